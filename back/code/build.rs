@@ -0,0 +1,4 @@
+// migrations are embedded by `sqlx::migrate!`, new files have to trigger a rebuild
+fn main() {
+    println!("cargo:rerun-if-changed=migrations");
+}
@@ -0,0 +1,35 @@
+-- Login sessions, one per signed in device, so they can be listed and revoked one by one.
+
+CREATE TABLE IF NOT EXISTS routine_app.session (
+    id UUID NOT NULL DEFAULT gen_random_uuid() PRIMARY KEY, 
+    user_id UUID NOT NULL REFERENCES routine_app.customer (id) ON DELETE CASCADE, 
+    user_agent VARCHAR(512), 
+    ip_address VARCHAR(64), 
+    status_id INT NOT NULL, -- [0, 1] 0 - active, 1 - revoked
+    created_at TIMESTAMP NOT NULL DEFAULT now(),
+    last_seen_at TIMESTAMP NOT NULL DEFAULT now()
+);
+CREATE INDEX IF NOT EXISTS session_user_id_idx ON routine_app.session (user_id);
+
+-- session status table creation and update
+
+CREATE TABLE IF NOT EXISTS routine_app.session_status (
+    id INT,
+    description VARCHAR(256)
+);
+
+INSERT INTO routine_app.session_status
+    (id, description)
+SELECT 0, 'Active'
+WHERE
+    NOT EXISTS (
+        SELECT id FROM routine_app.session_status WHERE id = 0
+    );
+
+INSERT INTO routine_app.session_status
+    (id, description)
+SELECT 1, 'Revoked'
+WHERE
+    NOT EXISTS (
+        SELECT id FROM routine_app.session_status WHERE id = 1
+    );
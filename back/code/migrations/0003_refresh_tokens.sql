@@ -0,0 +1,44 @@
+-- Rotating refresh tokens, only hashes are stored. A token used twice revokes the whole session.
+
+CREATE TABLE IF NOT EXISTS routine_app.refresh_token (
+    id SERIAL PRIMARY KEY, 
+    user_id UUID NOT NULL REFERENCES routine_app.customer (id) ON DELETE CASCADE, 
+    session_id UUID NOT NULL REFERENCES routine_app.session (id) ON DELETE CASCADE, 
+    token_hash VARCHAR(64) NOT NULL UNIQUE, 
+    status_id INT NOT NULL, -- [0, 1, 2] 0 - active, 1 - used, 2 - revoked
+    created_at TIMESTAMP NOT NULL DEFAULT now(),
+    expires_at TIMESTAMP NOT NULL
+);
+CREATE INDEX IF NOT EXISTS refresh_token_session_id_idx ON routine_app.refresh_token (session_id);
+CREATE INDEX IF NOT EXISTS refresh_token_user_id_idx ON routine_app.refresh_token (user_id);
+
+-- refresh token status table creation and update
+
+CREATE TABLE IF NOT EXISTS routine_app.refresh_token_status (
+    id INT,
+    description VARCHAR(256)
+);
+
+INSERT INTO routine_app.refresh_token_status
+    (id, description)
+SELECT 0, 'Active'
+WHERE
+    NOT EXISTS (
+        SELECT id FROM routine_app.refresh_token_status WHERE id = 0
+    );
+
+INSERT INTO routine_app.refresh_token_status
+    (id, description)
+SELECT 1, 'Used'
+WHERE
+    NOT EXISTS (
+        SELECT id FROM routine_app.refresh_token_status WHERE id = 1
+    );
+
+INSERT INTO routine_app.refresh_token_status
+    (id, description)
+SELECT 2, 'Revoked'
+WHERE
+    NOT EXISTS (
+        SELECT id FROM routine_app.refresh_token_status WHERE id = 2
+    );
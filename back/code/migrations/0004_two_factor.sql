@@ -0,0 +1,16 @@
+-- Optional TOTP two-factor authentication and its single-use recovery codes.
+
+ALTER TABLE routine_app.customer ADD COLUMN IF NOT EXISTS totp_secret VARCHAR(64); -- base32 encoded, set on enrollment
+ALTER TABLE routine_app.customer ADD COLUMN IF NOT EXISTS totp_enabled BOOLEAN NOT NULL DEFAULT false;
+ALTER TABLE routine_app.customer ADD COLUMN IF NOT EXISTS totp_last_step BIGINT; -- last accepted time step, protects from code replay
+
+-- two-factor recovery codes table creation
+
+CREATE TABLE IF NOT EXISTS routine_app.recovery_code (
+    id SERIAL PRIMARY KEY, 
+    user_id UUID NOT NULL REFERENCES routine_app.customer (id) ON DELETE CASCADE, 
+    code_hash VARCHAR(256) NOT NULL, 
+    used_at TIMESTAMP, 
+    created_at TIMESTAMP NOT NULL DEFAULT now()
+);
+CREATE INDEX IF NOT EXISTS recovery_code_user_id_idx ON routine_app.recovery_code (user_id);
@@ -0,0 +1,11 @@
+-- One-time password reset links replace emailed temporary passwords.
+
+CREATE TABLE IF NOT EXISTS routine_app.password_reset (
+    id SERIAL PRIMARY KEY, 
+    user_id UUID NOT NULL REFERENCES routine_app.customer (id) ON DELETE CASCADE, 
+    token_hash VARCHAR(256) NOT NULL UNIQUE, -- sha256 of the token sent by email
+    used_at TIMESTAMP, 
+    created_at TIMESTAMP NOT NULL DEFAULT now(), 
+    expires_at TIMESTAMP NOT NULL
+);
+CREATE INDEX IF NOT EXISTS password_reset_user_id_idx ON routine_app.password_reset (user_id);
@@ -0,0 +1,3 @@
+-- Deleted accounts (status 3) are kept for a grace period before they are removed.
+
+ALTER TABLE routine_app.customer ADD COLUMN IF NOT EXISTS deleted_at TIMESTAMP; -- set with status 3
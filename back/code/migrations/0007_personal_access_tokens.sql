@@ -0,0 +1,37 @@
+-- Scoped personal access tokens for scripts, only hashes are stored.
+
+CREATE TABLE IF NOT EXISTS routine_app.personal_access_token (
+    id SERIAL PRIMARY KEY, 
+    user_id UUID NOT NULL REFERENCES routine_app.customer (id) ON DELETE CASCADE, 
+    name VARCHAR(64), 
+    token_hash VARCHAR(256) NOT NULL UNIQUE, -- sha256 of the token, token itself is shown once
+    scopes TEXT[] NOT NULL DEFAULT '{}', 
+    status_id INT NOT NULL, -- [0, 1] 0 - active, 1 - revoked
+    created_at TIMESTAMP NOT NULL DEFAULT now(), 
+    expires_at TIMESTAMP, -- never expires if not set
+    last_used_at TIMESTAMP
+);
+CREATE INDEX IF NOT EXISTS personal_access_token_user_id_idx ON routine_app.personal_access_token (user_id);
+
+-- personal access token status table creation and update
+
+CREATE TABLE IF NOT EXISTS routine_app.personal_access_token_status (
+    id INT,
+    description VARCHAR(256)
+);
+
+INSERT INTO routine_app.personal_access_token_status
+    (id, description)
+SELECT 0, 'Active'
+WHERE
+    NOT EXISTS (
+        SELECT id FROM routine_app.personal_access_token_status WHERE id = 0
+    );
+
+INSERT INTO routine_app.personal_access_token_status
+    (id, description)
+SELECT 1, 'Revoked'
+WHERE
+    NOT EXISTS (
+        SELECT id FROM routine_app.personal_access_token_status WHERE id = 1
+    );
@@ -0,0 +1,3 @@
+-- Access tokens carry the generation they were issued with, bumping it invalidates all of them.
+
+ALTER TABLE routine_app.customer ADD COLUMN IF NOT EXISTS token_generation BIGINT NOT NULL DEFAULT 0;
@@ -0,0 +1,13 @@
+-- Accounts linked to an OpenID Connect provider.
+
+CREATE TABLE IF NOT EXISTS routine_app.external_identity (
+    id SERIAL PRIMARY KEY, 
+    user_id UUID NOT NULL REFERENCES routine_app.customer (id) ON DELETE CASCADE, 
+    issuer VARCHAR(512) NOT NULL, -- single sign-on provider
+    subject VARCHAR(256) NOT NULL, -- user id at the provider
+    email VARCHAR(256), -- email verified by the provider when identity was linked
+    created_at TIMESTAMP NOT NULL DEFAULT now(), 
+    last_login_at TIMESTAMP, 
+    UNIQUE (issuer, subject)
+);
+CREATE INDEX IF NOT EXISTS external_identity_user_id_idx ON routine_app.external_identity (user_id);
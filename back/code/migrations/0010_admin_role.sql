@@ -0,0 +1,35 @@
+-- Admin role and accounts disabled by an admin (status 4).
+-- Admins are appointed manually: UPDATE routine_app.customer SET role_id = 1 WHERE email = '...';
+
+ALTER TABLE routine_app.customer ADD COLUMN IF NOT EXISTS role_id INT NOT NULL DEFAULT 0; -- [0, 1] 0 - user, 1 - admin
+
+INSERT INTO routine_app.customer_status
+    (id, description)
+SELECT 4, 'Disabled'
+WHERE
+    NOT EXISTS (
+        SELECT id FROM routine_app.customer_status WHERE id = 4
+    );
+
+-- customer role table creation and update
+
+CREATE TABLE IF NOT EXISTS routine_app.customer_role (
+    id INT,
+    description VARCHAR(256)
+);
+
+INSERT INTO routine_app.customer_role
+    (id, description)
+SELECT 0, 'User'
+WHERE
+    NOT EXISTS (
+        SELECT id FROM routine_app.customer_role WHERE id = 0
+    );
+
+INSERT INTO routine_app.customer_role
+    (id, description)
+SELECT 1, 'Admin'
+WHERE
+    NOT EXISTS (
+        SELECT id FROM routine_app.customer_role WHERE id = 1
+    );
@@ -0,0 +1,31 @@
+-- Account security events. Append-only, rows outlive the accounts they refer to,
+-- so there are no foreign keys.
+
+CREATE TABLE IF NOT EXISTS routine_app.audit_event (
+    id BIGSERIAL PRIMARY KEY, 
+    user_id UUID, -- account the event is about, not set for unknown emails
+    actor_id UUID, -- who made the change, differs from user_id for admin actions
+    event_type VARCHAR(64) NOT NULL, 
+    ip_address VARCHAR(64), 
+    user_agent VARCHAR(512), 
+    metadata JSONB NOT NULL DEFAULT '{}', 
+    created_at TIMESTAMP NOT NULL DEFAULT now()
+);
+CREATE INDEX IF NOT EXISTS audit_event_user_id_idx ON routine_app.audit_event (user_id, created_at);
+CREATE INDEX IF NOT EXISTS audit_event_event_type_idx ON routine_app.audit_event (event_type, created_at);
+
+CREATE OR REPLACE FUNCTION routine_app.forbid_audit_event_change()
+ RETURNS trigger
+ LANGUAGE plpgsql
+AS $function$
+BEGIN
+    RAISE EXCEPTION 'audit events are append-only';
+END;
+$function$
+;
+
+DROP TRIGGER IF EXISTS trigger_append_only_audit_event ON routine_app.audit_event;
+CREATE TRIGGER trigger_append_only_audit_event
+BEFORE UPDATE OR DELETE ON routine_app.audit_event
+FOR EACH ROW
+EXECUTE FUNCTION routine_app.forbid_audit_event_change();
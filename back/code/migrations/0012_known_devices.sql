@@ -0,0 +1,12 @@
+-- Devices users logged in from, a login from a new one is reported by email.
+
+CREATE TABLE IF NOT EXISTS routine_app.known_device (
+    id SERIAL PRIMARY KEY, 
+    user_id UUID NOT NULL REFERENCES routine_app.customer (id) ON DELETE CASCADE, 
+    fingerprint VARCHAR(256) NOT NULL, -- sha256 of user agent and ip network
+    user_agent VARCHAR(512), 
+    ip_address VARCHAR(64), -- last address the device logged in from
+    created_at TIMESTAMP NOT NULL DEFAULT now(), 
+    last_seen_at TIMESTAMP NOT NULL DEFAULT now(), 
+    UNIQUE (user_id, fingerprint)
+);
@@ -0,0 +1,6 @@
+-- Recovery codes get a plain lookup prefix, so a login attempt verifies one Argon2 hash
+-- instead of every unused code of the user. Codes issued before have no prefix and are
+-- still checked one by one until 2FA is enrolled again.
+
+ALTER TABLE routine_app.recovery_code ADD COLUMN IF NOT EXISTS lookup_prefix VARCHAR(8);
+CREATE INDEX IF NOT EXISTS recovery_code_lookup_idx ON routine_app.recovery_code (user_id, lookup_prefix);
@@ -0,0 +1,9 @@
+-- Nonces of used action tokens, kept until the token expires, so an emailed link works once
+-- even if the cache was flushed meanwhile.
+
+CREATE TABLE IF NOT EXISTS routine_app.used_action_token (
+    nonce UUID PRIMARY KEY, 
+    expires_at TIMESTAMP NOT NULL, 
+    used_at TIMESTAMP NOT NULL DEFAULT now()
+);
+CREATE INDEX IF NOT EXISTS used_action_token_expires_at_idx ON routine_app.used_action_token (expires_at);
@@ -0,0 +1,6 @@
+-- Rotated refresh tokens remember when they were used and the salt their successor was derived with,
+-- so the same token presented again within the grace period gets the same successor
+-- instead of revoking the session. Tokens rotated before have no salt and are treated as reused.
+
+ALTER TABLE routine_app.refresh_token ADD COLUMN IF NOT EXISTS rotated_at TIMESTAMP;
+ALTER TABLE routine_app.refresh_token ADD COLUMN IF NOT EXISTS successor_salt VARCHAR(64);
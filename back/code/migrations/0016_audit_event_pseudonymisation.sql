@@ -0,0 +1,23 @@
+-- Audit events stay append-only, except that keys may be dropped from metadata,
+-- so personal data of purged accounts can be removed from their events.
+
+CREATE OR REPLACE FUNCTION routine_app.forbid_audit_event_change()
+ RETURNS trigger
+ LANGUAGE plpgsql
+AS $function$
+BEGIN
+    IF TG_OP = 'UPDATE'
+        AND NEW.id = OLD.id
+        AND NEW.user_id IS NOT DISTINCT FROM OLD.user_id
+        AND NEW.actor_id IS NOT DISTINCT FROM OLD.actor_id
+        AND NEW.event_type = OLD.event_type
+        AND NEW.ip_address IS NOT DISTINCT FROM OLD.ip_address
+        AND NEW.user_agent IS NOT DISTINCT FROM OLD.user_agent
+        AND NEW.created_at = OLD.created_at
+        AND OLD.metadata @> NEW.metadata THEN
+        RETURN NEW;
+    END IF;
+    RAISE EXCEPTION 'audit events are append-only';
+END;
+$function$
+;
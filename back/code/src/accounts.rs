@@ -0,0 +1,356 @@
+use sqlx::{self, Postgres, Pool, Row};
+use uuid::Uuid;
+
+use crate::{APP_SCHEMA, USERS_TABLE, BOARDS_TABLE, TASKS_TABLE};
+use crate::audit::pseudonymise_audit_events;
+use crate::models::{
+    AccountExport, ExportedProfile, ExportedBoard, StoredBoard, StoredTask, 
+    AdminUserSummary, AdminUserDetails
+};
+use crate::passwords::hash_password;
+use crate::roles::Role;
+use crate::tools::generate_random_token;
+
+// Deleted accounts are kept with status 3 for a grace period with all boards archived,
+// after that the cleanup job removes them together with boards and tasks,
+// their audit events are kept without personal data.
+
+pub struct DeletedAccount {
+    pub email: String, 
+    pub board_ids: Vec<i32>
+}
+
+pub async fn soft_delete_account(
+    db_link: &Pool<Postgres>, 
+    user_id: Uuid) -> Result<Option<DeletedAccount>, sqlx::Error> {
+
+    let mut transaction = db_link.begin().await?;
+
+    let query = format!(
+        "UPDATE {}.{}
+            SET status_id = 3, deleted_at = now()
+          WHERE id = $1
+            AND status_id = 1
+      RETURNING email", 
+        APP_SCHEMA, 
+        USERS_TABLE
+    );
+    let email: Option<String> = sqlx::query(&query)
+        .bind(user_id)
+        .map(|row| row.get("email"))
+        .fetch_optional(&mut transaction)
+        .await?;
+
+    let email = match email {
+        Some(email) => email, 
+        None => return Ok(None)
+    };
+
+    // tasks are reachable only through active boards, so they get archived together with them
+    let boards_query = format!(
+        "UPDATE {}.{}
+            SET status_id = 1
+          WHERE owner_id = $1
+      RETURNING id", 
+        APP_SCHEMA, 
+        BOARDS_TABLE
+    );
+    let board_ids = sqlx::query(&boards_query)
+        .bind(user_id)
+        .map(|row| row.get::<i32, &str>("id"))
+        .fetch_all(&mut transaction)
+        .await?;
+
+    transaction.commit().await?;
+    Ok(Some(DeletedAccount { email, board_ids }))
+}
+
+// removes accounts deleted earlier than grace period ago, returns count of removed accounts
+pub async fn purge_deleted_accounts(
+    db_link: &Pool<Postgres>, 
+    grace_period: i64) -> Result<u64, sqlx::Error> {
+
+    let mut transaction = db_link.begin().await?;
+
+    let tasks_query = format!(
+        "DELETE FROM {APP_SCHEMA}.{TASKS_TABLE} t
+          USING {APP_SCHEMA}.{BOARDS_TABLE} b, {APP_SCHEMA}.{USERS_TABLE} c
+          WHERE t.board_id = b.id
+            AND b.owner_id = c.id
+            AND c.status_id = 3
+            AND c.deleted_at < now() - make_interval(secs => $1)"
+    );
+    sqlx::query(&tasks_query)
+        .bind(grace_period as f64)
+        .execute(&mut transaction)
+        .await?;
+
+    let boards_query = format!(
+        "DELETE FROM {APP_SCHEMA}.{BOARDS_TABLE} b
+          USING {APP_SCHEMA}.{USERS_TABLE} c
+          WHERE b.owner_id = c.id
+            AND c.status_id = 3
+            AND c.deleted_at < now() - make_interval(secs => $1)"
+    );
+    sqlx::query(&boards_query)
+        .bind(grace_period as f64)
+        .execute(&mut transaction)
+        .await?;
+
+    let users_query = format!(
+        "DELETE FROM {}.{}
+          WHERE status_id = 3
+            AND deleted_at < now() - make_interval(secs => $1)
+      RETURNING id, email", 
+        APP_SCHEMA, 
+        USERS_TABLE
+    );
+    let (user_ids, emails): (Vec<Uuid>, Vec<Option<String>>) = sqlx::query(&users_query)
+        .bind(grace_period as f64)
+        .map(|row| (row.get::<Uuid, &str>("id"), row.get::<Option<String>, &str>("email")))
+        .fetch_all(&mut transaction)
+        .await?
+        .into_iter()
+        .unzip();
+
+    if !user_ids.is_empty() {
+        let emails: Vec<String> = emails.into_iter().flatten().collect();
+        pseudonymise_audit_events(&mut transaction, &user_ids, &emails).await?;
+    }
+
+    transaction.commit().await?;
+    Ok(user_ids.len() as u64)
+}
+
+pub async fn export_account(
+    db_link: &Pool<Postgres>, 
+    user_id: Uuid) -> Result<Option<AccountExport>, sqlx::Error> {
+
+    let profile_query = format!(
+        "SELECT
+            id, name, email, totp_enabled, created_at, updated_at
+           FROM {}.{}
+          WHERE id = $1
+            AND status_id = 1", 
+        APP_SCHEMA, 
+        USERS_TABLE
+    );
+    let profile = sqlx::query(&profile_query)
+        .bind(user_id)
+        .map(|row| {
+            ExportedProfile {
+                id: row.get("id"), 
+                name: row.get::<Option<String>, &str>("name").unwrap_or_default(), 
+                email: row.get::<Option<String>, &str>("email").unwrap_or_default(), 
+                two_factor_enabled: row.get("totp_enabled"), 
+                created_at: row.get::<chrono::NaiveDateTime, &str>("created_at").timestamp(), 
+                updated_at: row.get::<chrono::NaiveDateTime, &str>("updated_at").timestamp()
+            }
+        })
+        .fetch_optional(db_link)
+        .await?;
+
+    let profile = match profile {
+        Some(profile) => profile, 
+        None => return Ok(None)
+    };
+
+    let boards_query = format!(
+        "SELECT
+            id, title, description, status_id, creation_time
+           FROM {}.{}
+          WHERE owner_id = $1
+          ORDER BY creation_time", 
+        APP_SCHEMA, 
+        BOARDS_TABLE
+    );
+    let stored_boards = sqlx::query(&boards_query)
+        .bind(user_id)
+        .map(|row| {
+            let stored_board = StoredBoard {
+                id: row.get("id"), 
+                title: row.get("title"), 
+                description: row.get("description"), 
+                creation_time: row.get("creation_time")
+            };
+            (stored_board, row.get::<Option<i32>, &str>("status_id").unwrap_or(0))
+        })
+        .fetch_all(db_link)
+        .await?;
+
+    let tasks_query = format!(
+        "SELECT
+            t.id, t.title, t.description, t.board_id, t.status_id, t.creation_time, t.last_status_change_time
+           FROM {APP_SCHEMA}.{TASKS_TABLE} t
+           JOIN {APP_SCHEMA}.{BOARDS_TABLE} b ON b.id = t.board_id
+          WHERE b.owner_id = $1
+          ORDER BY t.creation_time"
+    );
+    let tasks: Vec<_> = sqlx::query(&tasks_query)
+        .bind(user_id)
+        .map(|row| {
+            StoredTask {
+                id: row.get("id"), 
+                title: row.get("title"), 
+                description: row.get("description"), 
+                board_id: row.get("board_id"), 
+                status_id: row.get("status_id"), 
+                creation_time: row.get("creation_time"), 
+                last_status_change_time: row.get("last_status_change_time")
+            }.get_task()
+        })
+        .fetch_all(db_link)
+        .await?;
+
+    let boards = stored_boards
+        .into_iter()
+        .map(|(stored_board, status_id)| {
+            let board = stored_board.get_board();
+            ExportedBoard {
+                tasks: tasks.iter().filter(|task| task.board_id == board.id).cloned().collect(), 
+                id: board.id, 
+                title: board.title, 
+                description: board.description, 
+                status_id, 
+                creation_time: board.creation_time
+            }
+        })
+        .collect();
+
+    Ok(Some(AccountExport {
+        exported_at: chrono::offset::Utc::now().naive_utc().timestamp(), 
+        profile, 
+        boards
+    }))
+}
+
+// Admin API queries
+
+const ADMIN_USER_FIELDS: &str = "id, name, email, status_id, role_id, \
+    EXTRACT(EPOCH FROM created_at)::BIGINT AS created_at";
+
+fn admin_user_summary(row: &sqlx::postgres::PgRow) -> AdminUserSummary {
+    AdminUserSummary {
+        id: row.get("id"), 
+        name: row.get::<Option<String>, &str>("name").unwrap_or_default(), 
+        email: row.get::<Option<String>, &str>("email").unwrap_or_default(), 
+        status_id: row.get::<Option<i32>, &str>("status_id").unwrap_or_default(), 
+        role: Role::from_id(row.get("role_id")), 
+        created_at: row.get("created_at")
+    }
+}
+
+// search is a case insensitive substring of name or email
+pub async fn search_users(
+    db_link: &Pool<Postgres>, 
+    search: Option<&str>, 
+    status_id: Option<i32>, 
+    limit: i64, 
+    offset: i64) -> Result<Vec<AdminUserSummary>, sqlx::Error> {
+
+    let pattern = search.map(|search| {
+        let escaped = search
+            .replace('\\', "\\\\")
+            .replace('%', "\\%")
+            .replace('_', "\\_");
+        format!("%{}%", escaped)
+    });
+
+    let query = format!(
+        "SELECT
+            {ADMIN_USER_FIELDS}
+           FROM {APP_SCHEMA}.{USERS_TABLE}
+          WHERE ($1::VARCHAR IS NULL OR name ILIKE $1 OR email ILIKE $1)
+            AND ($2::INT IS NULL OR status_id = $2)
+          ORDER BY created_at DESC, id
+          LIMIT $3 OFFSET $4"
+    );
+    sqlx::query(&query)
+        .bind(pattern)
+        .bind(status_id)
+        .bind(limit)
+        .bind(offset)
+        .map(|row| admin_user_summary(&row))
+        .fetch_all(db_link)
+        .await
+}
+
+pub async fn get_user_details(
+    db_link: &Pool<Postgres>, 
+    user_id: Uuid) -> Result<Option<AdminUserDetails>, sqlx::Error> {
+
+    let query = format!(
+        "SELECT
+            {ADMIN_USER_FIELDS}, totp_enabled, 
+            (SELECT count(*)
+               FROM {APP_SCHEMA}.{BOARDS_TABLE} b
+              WHERE b.owner_id = c.id
+                AND b.status_id = 0) AS boards_count, 
+            (SELECT count(*)
+               FROM {APP_SCHEMA}.{TASKS_TABLE} t
+               JOIN {APP_SCHEMA}.{BOARDS_TABLE} b ON b.id = t.board_id
+              WHERE b.owner_id = c.id
+                AND b.status_id = 0) AS tasks_count
+           FROM {APP_SCHEMA}.{USERS_TABLE} c
+          WHERE id = $1"
+    );
+    sqlx::query(&query)
+        .bind(user_id)
+        .map(|row| AdminUserDetails {
+            user: admin_user_summary(&row), 
+            two_factor_enabled: row.get("totp_enabled"), 
+            boards_count: row.get("boards_count"), 
+            tasks_count: row.get("tasks_count")
+        })
+        .fetch_optional(db_link)
+        .await
+}
+
+// moves account from one status to another, false if it isn't in `from_status`
+pub async fn set_account_status(
+    db_link: &Pool<Postgres>, 
+    user_id: Uuid, 
+    from_status: i32, 
+    to_status: i32) -> Result<bool, sqlx::Error> {
+
+    let query = format!(
+        "UPDATE {}.{}
+            SET status_id = $3
+          WHERE id = $1
+            AND status_id = $2", 
+        APP_SCHEMA, 
+        USERS_TABLE
+    );
+    let result = sqlx::query(&query)
+        .bind(user_id)
+        .bind(from_status)
+        .bind(to_status)
+        .execute(db_link)
+        .await?;
+
+    Ok(result.rows_affected() == 1)
+}
+
+// replaces password of an active account with a random one nobody knows,
+// returns email of the account to send the reset link to
+pub async fn scramble_password(
+    db_link: &Pool<Postgres>, 
+    user_id: Uuid) -> Result<Option<String>, sqlx::Error> {
+
+    let query = format!(
+        "UPDATE {}.{}
+            SET passwd = $2
+          WHERE id = $1
+            AND status_id = 1
+      RETURNING email", 
+        APP_SCHEMA, 
+        USERS_TABLE
+    );
+    let unusable_password = hash_password(&generate_random_token()).await;
+    sqlx::query(&query)
+        .bind(user_id)
+        .bind(unusable_password)
+        .map(|row| row.get("email"))
+        .fetch_optional(db_link)
+        .await
+}
@@ -0,0 +1,187 @@
+use jsonwebtoken::{self, Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use serde::{Serialize, Deserialize};
+use sqlx::{self, Postgres, Pool};
+use std::sync::OnceLock;
+use uuid::Uuid;
+
+use crate::{APP_SCHEMA, USED_ACTION_TOKENS_TABLE};
+use crate::settings::settings;
+
+// Action tokens are short-lived signed links sent by email (account activation, email change, etc).
+// They are signed with a key separate from access tokens, carry the purpose they were issued for,
+// so a token of one kind can't be used for another action, and a nonce which is recorded
+// in Postgres once the token is used, until the token expires.
+// The key is built once at startup from `tokens.action_token_secret`, its length is checked with other settings.
+
+static ACTION_TOKEN_KEYS: OnceLock<ActionTokenKeys> = OnceLock::new();
+
+struct ActionTokenKeys {
+    encoding_key: EncodingKey, 
+    decoding_key: DecodingKey
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum ActionPurpose {
+    UserVerification, 
+    EmailVerification, 
+    DeviceReport
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ActionToken {
+    pub purpose: ActionPurpose, 
+    pub sub: Uuid, 
+    pub exp: i64, 
+    pub nonce: Uuid, 
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub data: Option<String>
+}
+
+impl ActionToken {
+    pub fn new(purpose: ActionPurpose, sub: Uuid, lifetime: i64, data: Option<String>) -> Self {
+        let current_time = chrono::offset::Utc::now().naive_utc().timestamp();
+        ActionToken { purpose, sub, exp: current_time + lifetime, nonce: Uuid::new_v4(), data }
+    }
+
+    pub fn remaining_lifetime(&self) -> i64 {
+        let current_time = chrono::offset::Utc::now().naive_utc().timestamp();
+        self.exp - current_time
+    }
+}
+
+impl ActionTokenKeys {
+    fn from_secret(secret: &str) -> Self {
+        ActionTokenKeys {
+            encoding_key: EncodingKey::from_secret(secret.as_bytes()), 
+            decoding_key: DecodingKey::from_secret(secret.as_bytes())
+        }
+    }
+}
+
+pub fn init_action_token_keys() {
+    let secret = settings().tokens.action_token_secret.as_deref().expect("Action token secret is not set");
+
+    ACTION_TOKEN_KEYS.get_or_init(|| ActionTokenKeys::from_secret(secret));
+    log::info!("Action token key loaded");
+}
+
+// built-in settings have no secret, tests sign links with a fixed one
+#[cfg(test)]
+pub fn init_test_action_token_keys() {
+    ACTION_TOKEN_KEYS.get_or_init(|| ActionTokenKeys::from_secret("test action token secret, 32 bytes at least"));
+}
+
+// keys built by `init_action_token_keys`, which has to be called first
+fn action_token_keys() -> &'static ActionTokenKeys {
+    ACTION_TOKEN_KEYS.get().expect("Action token key is not initialized")
+}
+
+pub fn create_action_token(token_body: ActionToken) -> String {
+    let encoding_key = &action_token_keys().encoding_key;
+    jsonwebtoken::encode(&Header::new(Algorithm::HS256), &token_body, encoding_key).unwrap()
+}
+
+// returns token body only if the signature is valid, purpose matches and token isn't expired
+pub fn check_action_token(action_token: &str, purpose: ActionPurpose) -> Option<ActionToken> {
+    let decoding_key = &action_token_keys().decoding_key;
+    let mut validation = Validation::new(Algorithm::HS256);
+    validation.leeway = 0;
+
+    let token_body = jsonwebtoken::decode::<ActionToken>(action_token, decoding_key, &validation)
+        .ok()?
+        .claims;
+
+    if token_body.purpose != purpose {
+        return None;
+    }
+    Some(token_body)
+}
+
+// Database handlers
+
+// returns `false` if the token was already used
+pub async fn consume_action_token(
+    db_link: &Pool<Postgres>, 
+    action_token: &ActionToken) -> Result<bool, sqlx::Error> {
+
+    let query = format!(
+        "INSERT INTO {}.{} (nonce, expires_at)
+              VALUES ($1, now() + make_interval(secs => $2))
+         ON CONFLICT (nonce) DO NOTHING
+           RETURNING nonce", 
+        APP_SCHEMA, 
+        USED_ACTION_TOKENS_TABLE
+    );
+    let inserted = sqlx::query(&query)
+        .bind(action_token.nonce)
+        .bind(action_token.remaining_lifetime().max(1) as f64)
+        .fetch_optional(db_link)
+        .await?;
+
+    Ok(inserted.is_some())
+}
+
+// nonces of expired tokens aren't needed, the tokens are refused anyway
+pub async fn purge_used_action_tokens(db_link: &Pool<Postgres>) -> Result<u64, sqlx::Error> {
+    let query = format!(
+        "DELETE FROM {}.{} WHERE expires_at < now()", 
+        APP_SCHEMA, 
+        USED_ACTION_TOKENS_TABLE
+    );
+    let deleted = sqlx::query(&query)
+        .execute(db_link)
+        .await?;
+
+    Ok(deleted.rows_affected())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn verification_token(lifetime: i64) -> ActionToken {
+        ActionToken::new(ActionPurpose::UserVerification, Uuid::new_v4(), lifetime, None)
+    }
+
+    #[test]
+    fn token_is_checked_for_its_own_purpose_only() {
+        init_test_action_token_keys();
+        let token_body = ActionToken::new(ActionPurpose::EmailVerification, Uuid::new_v4(), 60, Some(String::from("ann@example.com")));
+        let (user_id, nonce) = (token_body.sub, token_body.nonce);
+        let action_token = create_action_token(token_body);
+
+        assert!(check_action_token(&action_token, ActionPurpose::UserVerification).is_none());
+        assert!(check_action_token(&action_token, ActionPurpose::DeviceReport).is_none());
+
+        let checked = check_action_token(&action_token, ActionPurpose::EmailVerification).unwrap();
+        assert_eq!(checked.sub, user_id);
+        assert_eq!(checked.nonce, nonce);
+        assert_eq!(checked.data.as_deref(), Some("ann@example.com"));
+    }
+
+    #[test]
+    fn refuses_expired_and_forged_tokens() {
+        init_test_action_token_keys();
+        let expired_token = create_action_token(verification_token(-1));
+        assert!(check_action_token(&expired_token, ActionPurpose::UserVerification).is_none());
+
+        let action_token = create_action_token(verification_token(60));
+        let (signed_part, _) = action_token.rsplit_once('.').unwrap();
+        let forged_token = format!("{}.{}", signed_part, "A".repeat(43));
+        assert!(check_action_token(&forged_token, ActionPurpose::UserVerification).is_none());
+
+        let foreign_key = EncodingKey::from_secret(b"some other secret of 32 bytes or more");
+        let foreign_token = jsonwebtoken::encode(&Header::new(Algorithm::HS256), &verification_token(60), &foreign_key).unwrap();
+        assert!(check_action_token(&foreign_token, ActionPurpose::UserVerification).is_none());
+    }
+
+    #[test]
+    fn every_token_gets_its_own_nonce() {
+        let user_id = Uuid::new_v4();
+        let first = ActionToken::new(ActionPurpose::UserVerification, user_id, 60, None);
+        let second = ActionToken::new(ActionPurpose::UserVerification, user_id, 60, None);
+
+        assert_ne!(first.nonce, second.nonce);
+    }
+}
@@ -1,26 +1,44 @@
-// main urls
-pub const HOST: &'static str = "0.0.0.0:5000";
-pub const SERVICE_URL: &'static str = "https://dev-home-project-r001.site";
+// Compile-time constants, values which differ between deployments
+// are read at startup into `settings::Settings`
 
-// threads count
-pub const THREADS_COUNT: usize = 3;
+// postgres data model
+pub const APP_SCHEMA: &str = "routine_app";
+pub const USERS_TABLE: &str = "customer";
+pub const BOARDS_TABLE: &str = "board";
+pub const TASKS_TABLE: &str = "task";
+pub const SESSIONS_TABLE: &str = "session";
+pub const REFRESH_TOKENS_TABLE: &str = "refresh_token";
+pub const RECOVERY_CODES_TABLE: &str = "recovery_code";
+pub const PASSWORD_RESETS_TABLE: &str = "password_reset";
+pub const PERSONAL_TOKENS_TABLE: &str = "personal_access_token";
+pub const EXTERNAL_IDENTITIES_TABLE: &str = "external_identity";
+pub const AUDIT_EVENTS_TABLE: &str = "audit_event";
+pub const KNOWN_DEVICES_TABLE: &str = "known_device";
+pub const USED_ACTION_TOKENS_TABLE: &str = "used_action_token";
 
-// postgres connections count
-pub const POSTGRESQL_CONNECTIONS_LIMIT: u32 = 5;
+// cookies
+pub const ACCESS_TOKEN_COOKIE: &str = "x-auth";
+pub const REFRESH_TOKEN_COOKIE: &str = "x-refresh";
+pub const REFRESH_TOKEN_COOKIE_PATH: &str = "/token"; // sent only to refresh endpoint
+pub const CSRF_COOKIE: &str = "x-csrf"; // the only cookie readable by frontend scripts
+pub const CSRF_HEADER: &str = "x-csrf-token"; // has to repeat CSRF cookie value
+pub const OIDC_STATE_COOKIE: &str = "x-oidc-state"; // binds single sign-on callback to the browser
 
-// postgres data model
-pub const APP_SCHEMA: &'static str = "routine_app";
-pub const USERS_TABLE: &'static str = "customer";
-pub const BOARDS_TABLE: &'static str = "board";
-pub const TASKS_TABLE: &'static str = "task";
+// access tokens signing
+pub const DEFAULT_JWT_KEY_ID: &str = "default"; // key for tokens issued without `kid`
+
+// personal access tokens
+pub const PERSONAL_TOKEN_PREFIX: &str = "rtn_pat_"; // tells personal tokens apart from JWTs
+pub const PERSONAL_TOKENS_LIMIT: i64 = 20; // active tokens per user
 
-// redis ttls
-pub const STORED_DATA_EXPIRATION_TIME: usize = 86_400; // 1 day cache lifetime
-pub const USER_DATA_EXPIRATION_TIME: usize = 259_200; // 3 days cache lifetime
+// audit events
+pub const AUDIT_EVENTS_PAGE_SIZE: i64 = 50; // events per page unless `limit` is set
+pub const AUDIT_EVENTS_PAGE_LIMIT: i64 = 500; // max events per page
 
-// token lifetime
-pub const TOKEN_LIFETIME: i64 = 86_400; // 24 hours lifetime
-pub const TOKEN_UPDATE_LIFETIME_THRESHOLD: i64 = 64_800; // 18 hours lifetime
+// two-factor authentication
+pub const LOGIN_CHALLENGE_ATTEMPTS: i64 = 5; // codes accepted per challenge
+pub const RECOVERY_CODES_COUNT: usize = 10;
+pub const TOTP_ISSUER: &str = "Routine";
 
 // logs
-pub const LOGS_CONFIG_FILE: &'static str = "log_config.yml";
\ No newline at end of file
+pub const LOGS_CONFIG_FILE: &str = "log_config.yml";
\ No newline at end of file
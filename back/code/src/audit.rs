@@ -0,0 +1,229 @@
+use actix_web::HttpRequest;
+use hmac::{Hmac, Mac};
+use serde_json::Value;
+use sha2::Sha256;
+use sqlx::{self, Executor, Postgres, Pool, Row};
+use uuid::Uuid;
+
+use crate::{APP_SCHEMA, AUDIT_EVENTS_TABLE};
+use crate::models::{AuditEventRecord, AuditEventsQuery};
+use crate::settings::settings;
+
+// Security relevant account events are appended to the audit table next to the free text log.
+// Recording never fails the request, a failed insert is only logged.
+// `user_id` is the account the event is about, `actor_id` is who caused it:
+// the user itself, an admin, or nobody for requests made without credentials.
+// Rows outlive accounts, so metadata never holds emails: the account is known by `user_id`,
+// submitted emails are kept as `hash_email` only. Rows of purged accounts keep the event itself,
+// personal data is stripped from their metadata by `pseudonymise_audit_events`.
+
+// metadata keys holding personal data, events recorded before emails were hashed have raw `email`
+const PERSONAL_METADATA_KEYS: [&str; 2] = ["email", "name"];
+
+#[derive(Clone, Copy)]
+pub enum AuditEvent {
+    UserCreated, 
+    UserVerified, 
+    VerificationResent, 
+    Login, 
+    LoginFailed, 
+    NewDeviceLogin, 
+    DeviceReported, 
+    SecondFactorFailed, 
+    RefreshTokenReused, 
+    Logout, 
+    LogoutEverywhere, 
+    SessionRevoked, 
+    UsernameChanged, 
+    PasswordChanged, 
+    PasswordResetRequested, 
+    PasswordReset, 
+    EmailChangeRequested, 
+    EmailChanged, 
+    TwoFactorEnabled, 
+    TwoFactorDisabled, 
+    PersonalTokenCreated, 
+    PersonalTokenRevoked, 
+    AccountExported, 
+    AccountDeleted, 
+    UserDisabled, 
+    UserEnabled, 
+    PasswordResetForced
+}
+
+impl AuditEvent {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AuditEvent::UserCreated => "user_created", 
+            AuditEvent::UserVerified => "user_verified", 
+            AuditEvent::VerificationResent => "verification_resent", 
+            AuditEvent::Login => "login", 
+            AuditEvent::LoginFailed => "login_failed", 
+            AuditEvent::NewDeviceLogin => "new_device_login", 
+            AuditEvent::DeviceReported => "device_reported", 
+            AuditEvent::SecondFactorFailed => "second_factor_failed", 
+            AuditEvent::RefreshTokenReused => "refresh_token_reused", 
+            AuditEvent::Logout => "logout", 
+            AuditEvent::LogoutEverywhere => "logout_everywhere", 
+            AuditEvent::SessionRevoked => "session_revoked", 
+            AuditEvent::UsernameChanged => "username_changed", 
+            AuditEvent::PasswordChanged => "password_changed", 
+            AuditEvent::PasswordResetRequested => "password_reset_requested", 
+            AuditEvent::PasswordReset => "password_reset", 
+            AuditEvent::EmailChangeRequested => "email_change_requested", 
+            AuditEvent::EmailChanged => "email_changed", 
+            AuditEvent::TwoFactorEnabled => "two_factor_enabled", 
+            AuditEvent::TwoFactorDisabled => "two_factor_disabled", 
+            AuditEvent::PersonalTokenCreated => "personal_token_created", 
+            AuditEvent::PersonalTokenRevoked => "personal_token_revoked", 
+            AuditEvent::AccountExported => "account_exported", 
+            AuditEvent::AccountDeleted => "account_deleted", 
+            AuditEvent::UserDisabled => "user_disabled", 
+            AuditEvent::UserEnabled => "user_enabled", 
+            AuditEvent::PasswordResetForced => "password_reset_forced"
+        }
+    }
+}
+
+// keyed by the server secret, an email can be matched against events but not read from them
+pub fn hash_email(email: &str) -> String {
+    let secret = settings().tokens.action_token_secret.as_deref().unwrap_or_default();
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).unwrap();
+    mac.update(b"audit email:");
+    mac.update(email.trim().to_lowercase().as_bytes());
+    format!("{:x}", mac.finalize().into_bytes())
+}
+
+// event made by the user on own account
+pub async fn record_user_event(
+    db_link: &Pool<Postgres>, 
+    request: &HttpRequest, 
+    event: AuditEvent, 
+    user_id: Uuid, 
+    metadata: Value) {
+
+    record_audit_event(db_link, request, event, Some(user_id), Some(user_id), metadata).await
+}
+
+pub async fn record_audit_event(
+    db_link: &Pool<Postgres>, 
+    request: &HttpRequest, 
+    event: AuditEvent, 
+    user_id: Option<Uuid>, 
+    actor_id: Option<Uuid>, 
+    metadata: Value) {
+
+    let user_agent = request
+        .headers()
+        .get("user-agent")
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.chars().take(512).collect::<String>());
+    let ip_address = request
+        .connection_info()
+        .realip_remote_addr()
+        .map(|value| value.to_string());
+
+    let query = format!(
+        "INSERT INTO {}.{} (user_id, actor_id, event_type, ip_address, user_agent, metadata)
+              VALUES ($1, $2, $3, $4, $5, $6)", 
+        APP_SCHEMA, 
+        AUDIT_EVENTS_TABLE
+    );
+    let result = sqlx::query(&query)
+        .bind(user_id)
+        .bind(actor_id)
+        .bind(event.as_str())
+        .bind(ip_address)
+        .bind(user_agent)
+        .bind(metadata)
+        .execute(db_link)
+        .await;
+
+    if let Err(db_error) = result {
+        log::error!("Database issue: {:?}", db_error);
+    }
+}
+
+// the append-only trigger lets metadata keys be dropped and nothing else
+pub async fn pseudonymise_audit_events<'c, E: Executor<'c, Database = Postgres>>(
+    executor: E, 
+    user_ids: &[Uuid], 
+    emails: &[String]) -> Result<u64, sqlx::Error> {
+
+    let keys = PERSONAL_METADATA_KEYS.map(String::from).to_vec();
+    let query = format!(
+        "UPDATE {}.{}
+            SET metadata = metadata - $3::TEXT[]
+          WHERE (user_id = ANY($1) OR metadata->>'email' = ANY($2))
+            AND metadata ?| $3::TEXT[]", 
+        APP_SCHEMA, 
+        AUDIT_EVENTS_TABLE
+    );
+    let updated = sqlx::query(&query)
+        .bind(user_ids)
+        .bind(emails)
+        .bind(keys)
+        .execute(executor)
+        .await?;
+    Ok(updated.rows_affected())
+}
+
+// newest events first, every filter is optional
+pub async fn get_audit_events(
+    db_link: &Pool<Postgres>, 
+    filter: &AuditEventsQuery, 
+    limit: i64, 
+    offset: i64) -> Result<Vec<AuditEventRecord>, sqlx::Error> {
+
+    let query = format!(
+        "SELECT
+            id, user_id, actor_id, event_type, ip_address, user_agent, metadata, 
+            EXTRACT(EPOCH FROM created_at)::BIGINT AS created_at
+           FROM {}.{}
+          WHERE ($1::UUID IS NULL OR user_id = $1)
+            AND ($2::UUID IS NULL OR actor_id = $2)
+            AND ($3::VARCHAR IS NULL OR event_type = $3)
+            AND ($4::BIGINT IS NULL OR created_at >= to_timestamp($4) AT TIME ZONE 'UTC')
+            AND ($5::BIGINT IS NULL OR created_at < to_timestamp($5) AT TIME ZONE 'UTC')
+          ORDER BY created_at DESC, id DESC
+          LIMIT $6 OFFSET $7", 
+        APP_SCHEMA, 
+        AUDIT_EVENTS_TABLE
+    );
+    sqlx::query(&query)
+        .bind(filter.user_id)
+        .bind(filter.actor_id)
+        .bind(filter.event_type.as_deref())
+        .bind(filter.since)
+        .bind(filter.until)
+        .bind(limit)
+        .bind(offset)
+        .map(|row| AuditEventRecord {
+            id: row.get("id"), 
+            user_id: row.get("user_id"), 
+            actor_id: row.get("actor_id"), 
+            event_type: row.get("event_type"), 
+            ip_address: row.get("ip_address"), 
+            user_agent: row.get("user_agent"), 
+            metadata: row.get("metadata"), 
+            created_at: row.get("created_at")
+        })
+        .fetch_all(db_link)
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::settings::init_test_settings;
+
+    #[test]
+    fn emails_are_hashed_case_insensitive() {
+        init_test_settings();
+        let email_hash = hash_email("Ann@Example.com");
+        assert_eq!(email_hash, hash_email(" ann@example.com"));
+        assert_ne!(email_hash, hash_email("bob@example.com"));
+        assert!(!email_hash.contains("example"));
+        assert_eq!(email_hash.len(), 64);
+    }
+}
@@ -0,0 +1,204 @@
+use actix_web::{
+    dev::{forward_ready, Payload, Service, ServiceRequest, ServiceResponse, Transform}, 
+    web::Data, 
+    Error, FromRequest, HttpMessage, HttpRequest
+};
+use std::future::{ready, Future, Ready};
+use std::pin::Pin;
+use uuid::Uuid;
+
+use crate::ACCESS_TOKEN_COOKIE;
+use crate::autorization::{JWToken, JwtKeyring, create_jwt};
+use crate::cookies::access_token_cookie;
+use crate::errors::AppError;
+use crate::personal_tokens::RequiredScope;
+use crate::settings::settings;
+
+// Identity verified by `validate_user`, kept in request extensions where the client can't reach it.
+// Handlers behind the authorization middleware take `AuthUser` as an argument.
+// Session id and access token are known for session tokens only, personal tokens have none.
+// Personal tokens carry their scopes instead, the endpoint is refused unless its `RequiredScope` is among them.
+#[derive(Clone)]
+pub struct AuthUser {
+    pub user_id: Uuid, 
+    pub session_id: Option<Uuid>, 
+    pub access_token: Option<JWToken>, 
+    pub scopes: Option<Vec<String>>
+}
+
+impl AuthUser {
+    pub fn from_access_token(access_token: JWToken) -> Self {
+        AuthUser {
+            user_id: access_token.user_id, 
+            session_id: Some(access_token.sid), 
+            access_token: Some(access_token), 
+            scopes: None
+        }
+    }
+
+    pub fn from_personal_token(user_id: Uuid, scopes: Vec<String>) -> Self {
+        AuthUser {
+            user_id, 
+            session_id: None, 
+            access_token: None, 
+            scopes: Some(scopes)
+        }
+    }
+
+    // identity set by `validate_user`, if it's allowed to call the matched endpoint
+    pub fn from_http_request(request: &HttpRequest) -> Result<Self, AppError> {
+        let auth_user = request
+            .extensions()
+            .get::<AuthUser>()
+            .cloned()
+            .ok_or_else(|| AppError::Unauthorized(String::from("Unauthorized")))?;
+
+        if let Some(scopes) = &auth_user.scopes {
+            let required_scope = request.app_data::<RequiredScope>().map(|RequiredScope(scope)| *scope);
+            if !required_scope.is_some_and(|required_scope| scopes.iter().any(|scope| scope == required_scope)) {
+                log::warn!(
+                    "Personal token of user: `{}` used for `{} {}` without required scope", 
+                    auth_user.user_id, 
+                    request.method(), 
+                    request.path()
+                );
+                return Err(AppError::Forbidden(String::from("Insufficient scope")));
+            }
+        }
+        Ok(auth_user)
+    }
+}
+
+impl FromRequest for AuthUser {
+    type Error = AppError;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(request: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(AuthUser::from_http_request(request))
+    }
+}
+
+// Sliding expiration of access token cookie. A request authenticated by the cookie gets
+// a fresh access token of the same session once the current one is about to expire,
+// so handlers never deal with cookie renewal. Responses setting access token cookie
+// themselves (logout, password change) are left as they are.
+
+pub struct AccessTokenRenewal;
+
+impl<S, B> Transform<S, ServiceRequest> for AccessTokenRenewal
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>, 
+    S::Future: 'static, 
+    B: 'static
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Transform = AccessTokenRenewalMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(AccessTokenRenewalMiddleware { service }))
+    }
+}
+
+pub struct AccessTokenRenewalMiddleware<S> {
+    service: S
+}
+
+impl<S, B> Service<ServiceRequest> for AccessTokenRenewalMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>, 
+    S::Future: 'static, 
+    B: 'static
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    forward_ready!(service);
+
+    fn call(&self, request: ServiceRequest) -> Self::Future {
+        let has_access_cookie = request.cookie(ACCESS_TOKEN_COOKIE).is_some();
+
+        let response = self.service.call(request);
+        Box::pin(async move {
+            let mut response = response.await?;
+            if has_access_cookie {
+                renew_access_token(&mut response);
+            }
+            Ok(response)
+        })
+    }
+}
+
+fn renew_access_token<B>(response: &mut ServiceResponse<B>) {
+    if response.response().cookies().any(|cookie| cookie.name() == ACCESS_TOKEN_COOKIE) {
+        return;
+    }
+
+    let renewed_token = {
+        let request = response.request();
+        let access_token = match request.extensions().get::<AuthUser>().and_then(|auth_user| auth_user.access_token.clone()) {
+            Some(access_token) => access_token, 
+            None => return
+        };
+        if access_token.remaining_lifetime() > settings().tokens.access_token_renewal {
+            return;
+        }
+        let jwt_keyring = match request.app_data::<Data<JwtKeyring>>() {
+            Some(jwt_keyring) => jwt_keyring, 
+            None => {
+                log::error!("JWT keyring is not configured, access token isn't renewed");
+                return;
+            }
+        };
+
+        log::info!("Access token of user: `{}` renewed in session `{}`", access_token.user_id, access_token.sid);
+        create_jwt(jwt_keyring, JWToken::new(access_token.user_id, access_token.sid, access_token.token_generation))
+    };
+
+    if let Err(cookie_error) = response.response_mut().add_cookie(&access_token_cookie(renewed_token)) {
+        log::error!("Unable to set renewed access token cookie: {:?}", cookie_error);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::test::TestRequest;
+
+    use super::*;
+    use crate::personal_tokens::{AVAILABLE_SCOPES, SCOPE_BOARDS_READ, SCOPE_TASKS_READ};
+    use crate::settings::init_test_settings;
+
+    fn extract(auth_user: AuthUser, required_scope: Option<RequiredScope>) -> Result<AuthUser, AppError> {
+        let request = match required_scope {
+            Some(required_scope) => TestRequest::default().app_data(required_scope), 
+            None => TestRequest::default()
+        }.to_http_request();
+        request.extensions_mut().insert(auth_user);
+        AuthUser::from_http_request(&request)
+    }
+
+    fn personal_token(scopes: &[&str]) -> AuthUser {
+        AuthUser::from_personal_token(Uuid::new_v4(), scopes.iter().map(|scope| scope.to_string()).collect())
+    }
+
+    #[test]
+    fn personal_token_needs_scope_of_the_route() {
+        assert!(extract(personal_token(&[SCOPE_BOARDS_READ]), Some(RequiredScope(SCOPE_BOARDS_READ))).is_ok());
+        assert!(matches!(
+            extract(personal_token(&[SCOPE_TASKS_READ]), Some(RequiredScope(SCOPE_BOARDS_READ))), 
+            Err(AppError::Forbidden(_))
+        ));
+    }
+
+    #[test]
+    fn routes_without_scope_refuse_personal_tokens() {
+        assert!(matches!(extract(personal_token(&AVAILABLE_SCOPES), None), Err(AppError::Forbidden(_))));
+
+        init_test_settings();
+        let session_user = AuthUser::from_access_token(JWToken::new(Uuid::new_v4(), Uuid::new_v4(), 0));
+        assert!(extract(session_user, None).is_ok());
+    }
+}
@@ -1,6 +1,8 @@
 use actix_web::{
     dev::ServiceRequest,
-    error::Error
+    error::Error, 
+    web::{self, Data}, 
+    HttpMessage
 };
 use actix_web_httpauth::{
     extractors::{
@@ -9,100 +11,678 @@ use actix_web_httpauth::{
     }
 };
 
-use http::header::{HeaderName, HeaderValue};
-use jwt::{self, SignWithKey, VerifyWithKey};
+use base64::{Engine as _, engine::general_purpose};
+use jsonwebtoken::{
+    self, Algorithm, DecodingKey, EncodingKey, Header, Validation, 
+    jwk::{
+        AlgorithmParameters, CommonParameters, EllipticCurve, Jwk, JwkSet, 
+        OctetKeyPairParameters, OctetKeyPairType, PublicKeyUse, RSAKeyParameters, RSAKeyType
+    }
+};
+use openssl::pkey::{Id, PKey};
 use serde::{Serialize, Deserialize};
-use hmac::{Hmac, Mac};
-use sha2::Sha256;
-use std::str::FromStr;
-use chrono;
 use uuid::Uuid;
 
-use crate::{TOKEN_LIFETIME, TOKEN_UPDATE_LIFETIME_THRESHOLD};
+use sqlx::{Postgres, Pool};
+
+use crate::{PersistentDB, CacheDB, DEFAULT_JWT_KEY_ID};
+use crate::auth_user::AuthUser;
+use crate::errors::AppError;
+use crate::settings::settings;
+use crate::personal_tokens::{is_personal_token, authenticate_personal_token};
+use crate::redis_handlers::{
+    check_token_revoked_in_redis, check_session_revoked_in_redis, 
+    put_token_generation_to_redis, cache_token_generation_in_redis, get_token_generation_from_redis
+};
+use crate::repositories::{Repository, SessionRepository};
+use crate::sessions::bump_token_generation;
 
+// Access tokens carry registered claims (`sub`, `iss`, `aud`, `iat`, `nbf`, `exp`) and 
+// `gen` - token generation of the user at the moment of issue. Generation is bumped on 
+// password change or logout from everywhere, tokens of older generations are rejected.
 #[derive(Serialize, Deserialize, Clone)]
 pub struct JWToken {
-    user_id: Uuid, 
-    iat: i64
+    #[serde(rename = "sub")]
+    pub user_id: Uuid, 
+    pub iss: String, 
+    pub aud: String, 
+    pub iat: i64, 
+    pub nbf: i64, 
+    pub exp: i64, 
+    pub jti: Uuid, 
+    pub sid: Uuid, 
+    #[serde(rename = "gen")]
+    pub token_generation: i64
 }
 
 impl JWToken {
-    pub fn new(user_id: Uuid, iat: i64) -> Self {
-        JWToken { user_id, iat }
+    pub fn new(user_id: Uuid, sid: Uuid, token_generation: i64) -> Self {
+        let settings = settings();
+        let current_time = chrono::offset::Utc::now().naive_utc().timestamp();
+        JWToken {
+            user_id, 
+            iss: settings.jwt_issuer().to_string(), 
+            aud: settings.jwt.audience.clone(), 
+            iat: current_time, 
+            nbf: current_time, 
+            exp: current_time + settings.tokens.access_token_lifetime, 
+            jti: Uuid::new_v4(), 
+            sid, 
+            token_generation
+        }
+    }
+
+    pub fn remaining_lifetime(&self) -> i64 {
+        let current_time = chrono::offset::Utc::now().naive_utc().timestamp();
+        self.exp + settings().jwt.clock_skew as i64 - current_time
+    }
+}
+
+// Keyring holds every key access tokens may be signed with. Tokens are signed by the current
+// signing key and carry its `kid` header, so several keys can be valid at once. A retired key
+// stays valid for verification during `jwt.key_grace_period`, which lets issued tokens live out
+// their lifetime after rotation. Public parts of asymmetric keys are published as JWKS.
+//
+// Keys are described in a json file referenced by JWT_KEYRING_FILE env var:
+// {
+//     "signing_kid": "2023-10-ed",
+//     "keys": [
+//         { "kid": "2023-10-ed", "algorithm": "EdDSA", "private_key_file": "/keys/2023-10-ed.pem" },
+//         { "kid": "default", "algorithm": "HS256", "secret_env": "JWT_SECRET_KEY", "retired_at": 1696118400 }
+//     ]
+// }
+// Without the file a single HS256 key is built from JWT_SECRET_KEY env var.
+// Tokens issued without `kid` header are verified with the key named DEFAULT_JWT_KEY_ID.
+
+#[derive(Deserialize)]
+struct KeyringConfig {
+    signing_kid: String, 
+    keys: Vec<KeyConfig>
+}
+
+#[derive(Deserialize)]
+struct KeyConfig {
+    kid: String, 
+    algorithm: Algorithm, 
+    secret_env: Option<String>, // HS256, HS384, HS512
+    private_key_file: Option<String>, // EdDSA, RS256, RS384, RS512 in PEM format
+    retired_at: Option<i64>
+}
+
+pub struct JwtKey {
+    kid: String, 
+    algorithm: Algorithm, 
+    encoding_key: EncodingKey, 
+    decoding_key: DecodingKey, 
+    public_jwk: Option<Jwk>, 
+    retired_at: Option<i64>
+}
+
+impl JwtKey {
+    fn from_config(key_config: KeyConfig) -> Result<Self, String> {
+        let KeyConfig { kid, algorithm, secret_env, private_key_file, retired_at } = key_config;
+        let (encoding_key, decoding_key, public_jwk) = match algorithm {
+            Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512 => {
+                let secret_env = secret_env
+                    .ok_or(format!("Key `{}` requires `secret_env`", kid))?;
+                let secret = std::env::var(&secret_env)
+                    .map_err(|_| format!("Unable to read {} env var for key `{}`", secret_env, kid))?;
+                (
+                    EncodingKey::from_secret(secret.as_bytes()), 
+                    DecodingKey::from_secret(secret.as_bytes()), 
+                    None
+                )
+            }, 
+            Algorithm::EdDSA | Algorithm::RS256 | Algorithm::RS384 | Algorithm::RS512 => {
+                let private_key_file = private_key_file
+                    .ok_or(format!("Key `{}` requires `private_key_file`", kid))?;
+                let private_key = std::fs::read(&private_key_file)
+                    .map_err(|error| format!("Unable to read key file {}: {}", private_key_file, error))?;
+                let public_jwk = public_jwk(&kid, algorithm, &private_key)?;
+                let encoding_key = match algorithm {
+                    Algorithm::EdDSA => EncodingKey::from_ed_pem(&private_key), 
+                    _ => EncodingKey::from_rsa_pem(&private_key)
+                }.map_err(|error| format!("Invalid private key `{}`: {}", kid, error))?;
+                let decoding_key = DecodingKey::from_jwk(&public_jwk)
+                    .map_err(|error| format!("Invalid public key `{}`: {}", kid, error))?;
+                (encoding_key, decoding_key, Some(public_jwk))
+            }, 
+            _ => return Err(format!("Unsupported algorithm {:?} of key `{}`", algorithm, kid))
+        };
+
+        Ok(JwtKey { kid, algorithm, encoding_key, decoding_key, public_jwk, retired_at })
+    }
+
+    fn is_valid_for_verification(&self, current_time: i64) -> bool {
+        match self.retired_at {
+            Some(retired_at) => current_time < retired_at + settings().jwt.key_grace_period, 
+            None => true
+        }
+    }
+}
+
+// public part of asymmetric key in JWK format, derived from its private key
+fn public_jwk(kid: &str, algorithm: Algorithm, private_key: &[u8]) -> Result<Jwk, String> {
+    let key = PKey::private_key_from_pem(private_key)
+        .map_err(|error| format!("Invalid private key `{}`: {}", kid, error))?;
+    let encode = |bytes: Vec<u8>| general_purpose::URL_SAFE_NO_PAD.encode(bytes);
+
+    let algorithm_parameters = match (algorithm, key.id()) {
+        (Algorithm::EdDSA, Id::ED25519) => {
+            let public_key = key
+                .raw_public_key()
+                .map_err(|error| format!("Invalid private key `{}`: {}", kid, error))?;
+            AlgorithmParameters::OctetKeyPair(OctetKeyPairParameters {
+                key_type: OctetKeyPairType::OctetKeyPair, 
+                curve: EllipticCurve::Ed25519, 
+                x: encode(public_key)
+            })
+        }, 
+        (Algorithm::RS256 | Algorithm::RS384 | Algorithm::RS512, Id::RSA) => {
+            let rsa = key
+                .rsa()
+                .map_err(|error| format!("Invalid private key `{}`: {}", kid, error))?;
+            AlgorithmParameters::RSA(RSAKeyParameters {
+                key_type: RSAKeyType::RSA, 
+                n: encode(rsa.n().to_vec()), 
+                e: encode(rsa.e().to_vec())
+            })
+        }, 
+        _ => return Err(format!("Key `{}` doesn't match algorithm {:?}", kid, algorithm))
+    };
+
+    Ok(Jwk {
+        common: CommonParameters {
+            public_key_use: Some(PublicKeyUse::Signature), 
+            algorithm: Some(algorithm), 
+            key_id: Some(kid.to_string()), 
+            ..Default::default()
+        }, 
+        algorithm: algorithm_parameters
+    })
+}
+
+pub struct JwtKeyring {
+    keys: Vec<JwtKey>, 
+    signing_kid: String
+}
+
+impl JwtKeyring {
+    fn from_config(keyring_config: KeyringConfig) -> Result<Self, String> {
+        let keys = keyring_config.keys
+            .into_iter()
+            .map(JwtKey::from_config)
+            .collect::<Result<Vec<JwtKey>, String>>()?;
+
+        let signing_key = keys
+            .iter()
+            .find(|key| key.kid == keyring_config.signing_kid)
+            .ok_or(format!("Signing key `{}` is not in the keyring", keyring_config.signing_kid))?;
+        if signing_key.retired_at.is_some() {
+            return Err(format!("Signing key `{}` is retired", signing_key.kid));
+        }
+
+        Ok(JwtKeyring { keys, signing_kid: keyring_config.signing_kid })
+    }
+
+    fn signing_key(&self) -> &JwtKey {
+        self.keys.iter().find(|key| key.kid == self.signing_kid).unwrap()
+    }
+
+    fn verification_key(&self, kid: Option<&str>) -> Option<&JwtKey> {
+        let kid = kid.unwrap_or(DEFAULT_JWT_KEY_ID);
+        let current_time = chrono::offset::Utc::now().naive_utc().timestamp();
+        self.keys
+            .iter()
+            .find(|key| key.kid == kid && key.is_valid_for_verification(current_time))
+    }
+
+    // public keys of asymmetric algorithms, HMAC secrets are never published
+    pub fn jwks(&self) -> JwkSet {
+        let current_time = chrono::offset::Utc::now().naive_utc().timestamp();
+        JwkSet {
+            keys: self.keys
+                .iter()
+                .filter(|key| key.is_valid_for_verification(current_time))
+                .filter_map(|key| key.public_jwk.clone())
+                .collect()
+        }
+    }
+}
+
+pub fn init_jwt_keyring() -> web::Data<JwtKeyring> {
+    let keyring_file = std::env::var("JWT_KEYRING_FILE")
+        .ok()
+        .filter(|keyring_file| !keyring_file.is_empty());
+    let keyring_config = match keyring_file {
+        Some(keyring_file) => {
+            let keyring_json = std::fs::read_to_string(&keyring_file)
+                .expect("Unable to read JWT_KEYRING_FILE");
+            serde_json::from_str(&keyring_json)
+                .expect("Invalid JWT keyring file")
+        }, 
+        None => KeyringConfig {
+            signing_kid: DEFAULT_JWT_KEY_ID.to_string(), 
+            keys: vec![KeyConfig {
+                kid: DEFAULT_JWT_KEY_ID.to_string(), 
+                algorithm: Algorithm::HS256, 
+                secret_env: Some("JWT_SECRET_KEY".to_string()), 
+                private_key_file: None, 
+                retired_at: None
+            }]
+        }
+    };
+
+    let jwt_keyring = JwtKeyring::from_config(keyring_config)
+        .unwrap_or_else(|error| panic!("Unable to load JWT keyring: {}", error));
+    log::info!("JWT keyring loaded, tokens signed with key `{}`", jwt_keyring.signing_kid);
+
+    web::Data::new(jwt_keyring)
+}
+
+// single HS256 key, for tests of handlers issuing or checking access tokens
+#[cfg(test)]
+pub fn test_jwt_keyring() -> JwtKeyring {
+    let secret = b"test_secret_of_the_access_tokens";
+    JwtKeyring {
+        keys: vec![JwtKey {
+            kid: DEFAULT_JWT_KEY_ID.to_string(), 
+            algorithm: Algorithm::HS256, 
+            encoding_key: EncodingKey::from_secret(secret), 
+            decoding_key: DecodingKey::from_secret(secret), 
+            public_jwk: None, 
+            retired_at: None
+        }], 
+        signing_kid: DEFAULT_JWT_KEY_ID.to_string()
+    }
+}
+
+pub fn create_jwt(jwt_keyring: &JwtKeyring, token_body: JWToken) -> String {
+    let signing_key = jwt_keyring.signing_key();
+    let mut header = Header::new(signing_key.algorithm);
+    header.kid = Some(signing_key.kid.clone());
+
+    jsonwebtoken::encode(&header, &token_body, &signing_key.encoding_key).unwrap()
+}
+
+pub fn check_jwt(jwt_keyring: &JwtKeyring, jwtoken: String) -> Result<JWToken, String> {
+    let header = jsonwebtoken::decode_header(&jwtoken)
+        .map_err(|_| "Invalid token".to_string())?;
+    let key = jwt_keyring
+        .verification_key(header.kid.as_deref())
+        .ok_or("Unknown signing key".to_string())?;
+    if header.alg != key.algorithm {
+        return Err("Invalid token".to_string());
+    }
+
+    let settings = settings();
+    let mut validation = Validation::new(key.algorithm);
+    validation.set_required_spec_claims(&["sub", "iss", "aud", "nbf", "exp"]);
+    validation.set_issuer(&[settings.jwt_issuer()]);
+    validation.set_audience(&[&settings.jwt.audience]);
+    validation.validate_nbf = true;
+    validation.leeway = settings.jwt.clock_skew;
+
+    jsonwebtoken::decode::<JWToken>(&jwtoken, &key.decoding_key, &validation)
+        .map(|token_data| token_data.claims)
+        .map_err(|_| "Invalid token".to_string())
+}
+
+// Revocation state is read from the cache. While the cache is unavailable session status and token
+// generation are read from Postgres instead. The `jti` denylist is kept in the cache only and is skipped
+// then, logout revokes the session in Postgres too, so a logged out token is still rejected by its session.
+async fn is_token_revoked(cache: &CacheDB, repository: &Repository, token: &JWToken) -> bool {
+    match check_token_revoked_in_redis(cache, token.jti).await {
+        Ok(true) => {
+            log::warn!("Revoked token `{}` received from user: `{}`", token.jti, token.user_id);
+            return true;
+        }, 
+        Ok(false) => (), 
+        Err(redis_error) => {
+            log::warn!("Cache database issue, revoked tokens aren't checked: {:?}", redis_error);
+        }
+    }
+
+    let session_revoked = match check_session_revoked_in_redis(cache, token.sid).await {
+        Ok(session_revoked) => Ok(session_revoked), 
+        Err(redis_error) => {
+            log::warn!("Cache database issue, session is checked in the database: {:?}", redis_error);
+            repository
+                .is_session_active(token.sid)
+                .await
+                .map(|session_active| !session_active)
+        }
+    };
+    match session_revoked {
+        Ok(true) => {
+            log::warn!("Token of revoked session `{}` received from user: `{}`", token.sid, token.user_id);
+            true
+        }, 
+        Ok(false) => false, 
+        Err(db_error) => {
+            log::error!("Database issue: {:?}", db_error);
+            true
+        }
+    }
+}
+
+async fn is_token_generation_current(cache: &CacheDB, repository: &Repository, token: &JWToken) -> bool {
+    let token_generation = match get_token_generation_from_redis(cache, token.user_id).await {
+        Ok(Some(token_generation)) => Ok(Some(token_generation)), 
+        Ok(None) => {
+            let stored_generation = repository.get_token_generation(token.user_id).await;
+            if let Ok(Some(token_generation)) = stored_generation {
+                if let Err(redis_error) = cache_token_generation_in_redis(cache, token.user_id, token_generation).await {
+                    log::warn!("Cache database issue: {:?}", redis_error);
+                }
+            }
+            stored_generation
+        }, 
+        Err(redis_error) => {
+            log::warn!("Cache database issue, token generation is read from the database: {:?}", redis_error);
+            repository.get_token_generation(token.user_id).await
+        }
+    };
+
+    match token_generation {
+        Ok(Some(token_generation)) if token_generation == token.token_generation => true, 
+        Ok(Some(_)) => {
+            log::warn!("Token `{}` of outdated generation received from user: `{}`", token.jti, token.user_id);
+            false
+        }, 
+        Ok(None) => {
+            log::warn!("Token `{}` of non-active user: `{}` received", token.jti, token.user_id);
+            false
+        }, 
+        Err(db_error) => {
+            log::error!("Database issue: {:?}", db_error);
+            false
+        }
     }
 }
 
-pub fn create_jwt(token_body: JWToken) -> String {
-    let jwt_secret: Hmac<Sha256> = Hmac::new_from_slice(
-        std::env::var("JWT_SECRET_KEY")
-            .expect("JWT_SECRET must be set!")
-            .as_bytes(),
-    ).unwrap();
-    let token = token_body.sign_with_key(&jwt_secret).unwrap();
+async fn is_token_accepted(request: &ServiceRequest, token: &JWToken) -> bool {
+    let (repository, redis_db) = match (request.app_data::<Data<Repository>>(), request.app_data::<Data<CacheDB>>()) {
+        (Some(repository), Some(redis_db)) => (repository.get_ref(), redis_db.get_ref()), 
+        _ => {
+            log::error!("Databases are not configured, token `{}` rejected", token.jti);
+            return false;
+        }
+    };
 
-    token
+    !is_token_revoked(redis_db, repository, token).await
+        && is_token_generation_current(redis_db, repository, token).await
 }
 
-pub fn check_jwt(jwtoken: String) -> Result<JWToken, String> {
-    let jwt_secret: Hmac<Sha256> = Hmac::new_from_slice(
-        std::env::var("JWT_SECRET_KEY")
-            .expect("JWT_SECRET must be set!")
-            .as_bytes(),
-    ).unwrap();
-    let user_data = jwtoken
-        .verify_with_key(&jwt_secret)
-        .map_err(|_| "Invalid token".to_string());
+// invalidates every access token issued to the user so far
+pub async fn revoke_user_access_tokens(
+    db_link: &Pool<Postgres>, 
+    cache: &CacheDB, 
+    user_id: Uuid) -> Result<(), AppError> {
 
-    user_data
+    if let Some(token_generation) = bump_token_generation(db_link, user_id).await? {
+        put_token_generation_to_redis(cache, user_id, token_generation).await?;
+    }
+    Ok(())
 }
 
 pub async fn validate_user(
-    mut request: ServiceRequest, 
+    request: ServiceRequest, 
     credentials: BearerAuth
 ) ->  Result<ServiceRequest, (Error, ServiceRequest)> {
 
     let jwtoken = credentials.token().to_string();
+    if is_personal_token(&jwtoken) {
+        return validate_personal_token(request, &jwtoken).await;
+    }
 
-    match check_jwt(jwtoken) {
+    let jwt_keyring = match request.app_data::<Data<JwtKeyring>>() {
+        Some(jwt_keyring) => jwt_keyring.clone(), 
+        None => {
+            log::error!("JWT keyring is not configured, token rejected");
+            return unauthorized(request, "Unable to check token");
+        }
+    };
+
+    match check_jwt(&jwt_keyring, jwtoken) {
         Ok(token) => {
-            let current_time = chrono::offset::Utc::now().naive_utc().timestamp();
-            let time_delta = current_time - token.iat;
-            if time_delta > TOKEN_LIFETIME {
-                let config = request
-                    .app_data::<bearer::Config>()
-                    .cloned()
-                    .unwrap_or_default()
-                    .scope("");
-
-                Err((AuthenticationError::from(config).into(), request))
+            if !is_token_accepted(&request, &token).await {
+                unauthorized(request, "Token is revoked")
             } else {
-                let headers = request.headers_mut();
-                let user_key = HeaderName::from_str("user_id").unwrap();
-                let user_value = HeaderValue::from_str(&(token.user_id.to_string())).unwrap();
-                headers.append(user_key, user_value);
-
-                if time_delta > TOKEN_UPDATE_LIFETIME_THRESHOLD {
-                    let updated_token = create_jwt(JWToken::new(
-                        token.user_id, 
-                        chrono::offset::Utc::now().naive_utc().timestamp()
-                    ));
-                    
-                    let token_key = HeaderName::from_str("new_token").unwrap();
-                    let token_value = HeaderValue::from_str(&updated_token).unwrap();
-                    headers.append(token_key, token_value);
-                } 
-
+                request.extensions_mut().insert(AuthUser::from_access_token(token));
                 Ok(request)
             }
         },
-        Err(_) => {
-            let config = request
-                .app_data::<bearer::Config>()
-                .cloned()
-                .unwrap_or_default()
-                .scope("");
+        Err(message) => unauthorized(request, &message)
+    }
+}
+
+async fn validate_personal_token(
+    request: ServiceRequest, 
+    personal_token: &str
+) -> Result<ServiceRequest, (Error, ServiceRequest)> {
+
+    let authenticated_token = match request.app_data::<Data<PersistentDB>>() {
+        Some(postgres_db) => authenticate_personal_token(&postgres_db.pool(), personal_token).await, 
+        None => {
+            log::error!("Persistent database is not configured, personal token rejected");
+            return unauthorized(request, "Unable to check token");
+        }
+    };
+
+    match authenticated_token {
+        // scopes are checked against the matched endpoint by the `AuthUser` extractor
+        Ok(Some(token)) => {
+            request.extensions_mut().insert(AuthUser::from_personal_token(token.user_id, token.scopes));
+            Ok(request)
+        }, 
+        Ok(None) => {
+            log::warn!("Invalid, expired or revoked personal token received");
+            unauthorized(request, "Invalid token")
+        }, 
+        Err(db_error) => {
+            log::error!("Database issue: {:?}", db_error);
+            unauthorized(request, "Unable to check token")
+        }
+    }
+}
+
+// 401 with bearer challenge, the message is sent as its `error_description`
+fn unauthorized(request: ServiceRequest, message: &str) -> Result<ServiceRequest, (Error, ServiceRequest)> {
+    let config = request
+        .app_data::<bearer::Config>()
+        .cloned()
+        .unwrap_or_default()
+        .scope("");
+    let error = AuthenticationError::from(config).with_error_description(message.to_string());
+
+    Err((error.into(), request))
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{test, App};
+    use actix_web_httpauth::middleware::HttpAuthentication;
+
+    use super::*;
+    use crate::repositories::{BoardRepository, MemoryRepository, UserRepository};
+    use crate::services::boards_managing;
+    use crate::settings::init_test_settings;
+
+    // nothing listens on the port, so every Redis command fails as during an outage
+    fn unavailable_cache() -> CacheDB {
+        CacheDB::new(Some(redis::Client::open("redis://127.0.0.1:1/").unwrap()))
+    }
 
-            Err((AuthenticationError::from(config).into(), request))
+    fn hmac_key(kid: &str, algorithm: Algorithm, secret: &[u8], retired_at: Option<i64>) -> JwtKey {
+        JwtKey {
+            kid: kid.to_string(), 
+            algorithm, 
+            encoding_key: EncodingKey::from_secret(secret), 
+            decoding_key: DecodingKey::from_secret(secret), 
+            public_jwk: None, 
+            retired_at
         }
     }
+
+    // `new` signs, `old` was retired the given number of seconds ago
+    fn rotated_keyring(retired_ago: i64) -> JwtKeyring {
+        init_test_settings();
+        let current_time = chrono::offset::Utc::now().naive_utc().timestamp();
+        JwtKeyring {
+            keys: vec![
+                hmac_key("old", Algorithm::HS256, b"old_secret_of_the_access_tokens_", Some(current_time - retired_ago)), 
+                hmac_key("new", Algorithm::HS512, b"new_secret_of_the_access_tokens_", None)
+            ], 
+            signing_kid: "new".to_string()
+        }
+    }
+
+    fn sign_with(key: &JwtKey, header_kid: Option<&str>, header_algorithm: Algorithm) -> String {
+        let mut header = Header::new(header_algorithm);
+        header.kid = header_kid.map(str::to_string);
+        let token_body = JWToken::new(Uuid::new_v4(), Uuid::new_v4(), 0);
+        jsonwebtoken::encode(&header, &token_body, &key.encoding_key).unwrap()
+    }
+
+    async fn active_user(memory: &MemoryRepository) -> Uuid {
+        init_test_settings();
+        let user_id = memory.create_user("Ann", "ann@example.com", "hash").await.unwrap();
+        memory.activate_user(user_id).await.unwrap();
+        user_id
+    }
+
+    #[actix_web::test]
+    async fn tokens_are_signed_with_current_key() {
+        let jwt_keyring = rotated_keyring(60);
+        let access_token = create_jwt(&jwt_keyring, JWToken::new(Uuid::new_v4(), Uuid::new_v4(), 0));
+        let header = jsonwebtoken::decode_header(&access_token).unwrap();
+
+        assert_eq!(header.kid.as_deref(), Some("new"));
+        assert_eq!(header.alg, Algorithm::HS512);
+        assert!(check_jwt(&jwt_keyring, access_token).is_ok());
+    }
+
+    #[actix_web::test]
+    async fn retired_key_verifies_within_grace_period() {
+        let jwt_keyring = rotated_keyring(60);
+        let old_key = &jwt_keyring.keys[0];
+        assert!(check_jwt(&jwt_keyring, sign_with(old_key, Some("old"), Algorithm::HS256)).is_ok());
+
+        let jwt_keyring = rotated_keyring(settings().jwt.key_grace_period + 1);
+        let old_key = &jwt_keyring.keys[0];
+        let expired_token = sign_with(old_key, Some("old"), Algorithm::HS256);
+        assert_eq!(check_jwt(&jwt_keyring, expired_token).err().as_deref(), Some("Unknown signing key"));
+    }
+
+    #[actix_web::test]
+    async fn unknown_kid_is_rejected() {
+        let jwt_keyring = rotated_keyring(60);
+        let signing_key = jwt_keyring.signing_key();
+
+        let unknown_kid = sign_with(signing_key, Some("other"), Algorithm::HS512);
+        assert_eq!(check_jwt(&jwt_keyring, unknown_kid).err().as_deref(), Some("Unknown signing key"));
+        // tokens without `kid` are checked with the default key, which this keyring lacks
+        let missing_kid = sign_with(signing_key, None, Algorithm::HS512);
+        assert_eq!(check_jwt(&jwt_keyring, missing_kid).err().as_deref(), Some("Unknown signing key"));
+    }
+
+    #[actix_web::test]
+    async fn algorithm_of_the_key_is_enforced() {
+        let jwt_keyring = rotated_keyring(60);
+        let old_key = &jwt_keyring.keys[0];
+        let new_key = &jwt_keyring.keys[1];
+
+        // the right secret with another algorithm than the key is configured for
+        let other_algorithm = sign_with(old_key, Some("old"), Algorithm::HS512);
+        assert_eq!(check_jwt(&jwt_keyring, other_algorithm).err().as_deref(), Some("Invalid token"));
+        // the kid of one key and the signature of another
+        let swapped_key = sign_with(old_key, Some("new"), Algorithm::HS512);
+        assert_eq!(check_jwt(&jwt_keyring, swapped_key).err().as_deref(), Some("Invalid token"));
+        assert!(check_jwt(&jwt_keyring, sign_with(new_key, Some("new"), Algorithm::HS512)).is_ok());
+    }
+
+    #[actix_web::test]
+    async fn session_is_checked_in_database_while_cache_is_unavailable() {
+        let memory = MemoryRepository::new();
+        let user_id = active_user(&memory).await;
+        let session_id = memory.start_session();
+        let revoked_session_id = memory.start_session();
+        memory.end_session(revoked_session_id);
+        let repository = Repository::Memory(memory);
+        let cache = unavailable_cache();
+
+        let token = JWToken::new(user_id, session_id, 0);
+        assert!(!is_token_revoked(&cache, &repository, &token).await);
+        let revoked_token = JWToken::new(user_id, revoked_session_id, 0);
+        assert!(is_token_revoked(&cache, &repository, &revoked_token).await);
+    }
+
+    #[actix_web::test]
+    async fn generation_is_checked_in_database_while_cache_is_unavailable() {
+        let memory = MemoryRepository::new();
+        let user_id = active_user(&memory).await;
+        memory.bump_token_generation(user_id);
+        let repository = Repository::Memory(memory);
+        let cache = unavailable_cache();
+        let session_id = Uuid::new_v4();
+
+        assert!(is_token_generation_current(&cache, &repository, &JWToken::new(user_id, session_id, 1)).await);
+        assert!(!is_token_generation_current(&cache, &repository, &JWToken::new(user_id, session_id, 0)).await);
+        let unknown_user = JWToken::new(Uuid::new_v4(), session_id, 0);
+        assert!(!is_token_generation_current(&cache, &repository, &unknown_user).await);
+    }
+
+    #[actix_web::test]
+    async fn boards_are_served_while_cache_is_unavailable() {
+        let memory = MemoryRepository::new();
+        let user_id = active_user(&memory).await;
+        let session_id = memory.start_session();
+        let repository = Data::new(Repository::Memory(memory));
+        repository.create_board(user_id, "Home", "").await.unwrap();
+        let jwt_keyring = Data::new(test_jwt_keyring());
+        let access_token = create_jwt(&jwt_keyring, JWToken::new(user_id, session_id, 0));
+
+        let app = test::init_service(
+            App::new()
+                .app_data(repository.clone())
+                .app_data(Data::new(unavailable_cache()))
+                .app_data(jwt_keyring.clone())
+                .wrap(HttpAuthentication::bearer(validate_user))
+                .configure(boards_managing)
+        ).await;
+        let request = test::TestRequest::get()
+            .uri("/user_boards")
+            .insert_header(("Authorization", format!("Bearer {}", access_token)))
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), 200);
+        let boards: Vec<crate::models::Board> = test::read_body_json(response).await;
+        assert_eq!(boards.len(), 1);
+    }
+
+    #[actix_web::test]
+    async fn rejected_token_gets_bearer_challenge() {
+        init_test_settings();
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(Repository::memory()))
+                .app_data(Data::new(CacheDB::new(None)))
+                .app_data(Data::new(test_jwt_keyring()))
+                .wrap(HttpAuthentication::bearer(validate_user))
+                .configure(boards_managing)
+        ).await;
+        let foreign_keyring = rotated_keyring(60);
+        let access_token = create_jwt(&foreign_keyring, JWToken::new(Uuid::new_v4(), Uuid::new_v4(), 0));
+        let request = test::TestRequest::get()
+            .uri("/user_boards")
+            .insert_header(("Authorization", format!("Bearer {}", access_token)))
+            .to_request();
+        let response = test::call_service(&app, request).await;
+
+        assert_eq!(response.status(), 401);
+        let challenge = response.headers().get("www-authenticate").unwrap().to_str().unwrap();
+        assert!(challenge.starts_with("Bearer"));
+        assert!(challenge.contains("error_description=\"Unknown signing key\""), "{}", challenge);
+    }
 }
@@ -0,0 +1,901 @@
+use actix_web::rt::time::timeout;
+use redis::{self, aio::ConnectionManager, AsyncCommands, RedisResult};
+use std::collections::{HashMap, HashSet};
+use std::future::Future;
+use std::sync::{Mutex, RwLock};
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+use crate::settings::settings;
+
+// Cache layer of the service. Redis is the primary store, an in-memory store takes over while
+// Redis is unreachable, so the service keeps working on Postgres alone: cached reads miss and
+// handlers go to Postgres.
+//
+// Calls to Redis are guarded by a circuit breaker. After `cache.failure_threshold` failed
+// or timed out commands in a row Redis is bypassed, every `cache.retry_interval` seconds one
+// call is let through to probe it. The first successful call closes the breaker, then keys
+// touched during the outage are removed from Redis and replaced by their in-memory values,
+// so Redis doesn't serve data which was changed or dropped meanwhile.
+//
+// Revocation state (revoked tokens and sessions, token generations) goes through `CacheDB::security`.
+// The in-memory store doesn't see what was written to Redis before the outage or by other instances,
+// so its reads fail while Redis is unreachable and callers check Postgres instead. Writes are still
+// kept in memory and reach Redis with the sync once it's back. Login throttling, login challenges and
+// other short-lived markers use the in-memory store during an outage like any cached data, so they
+// are counted per instance until Redis is back.
+// Without REDIS_URL the in-memory store is the only one, which suits a single instance only.
+
+pub trait CacheStore {
+    // `None` if the key is missing
+    async fn get(&self, key: &str) -> RedisResult<Option<String>>;
+
+    async fn set(&self, key: &str, value: &str, lifetime: usize) -> RedisResult<()>;
+
+    // sets the value only if the key is missing, returns `false` if it exists
+    async fn set_if_missing(&self, key: &str, value: &str, lifetime: usize) -> RedisResult<bool>;
+
+    // returns the value and removes the key at once
+    async fn take(&self, key: &str) -> RedisResult<Option<String>>;
+
+    async fn delete(&self, keys: &[String]) -> RedisResult<()>;
+
+    async fn exists(&self, key: &str) -> RedisResult<bool>;
+
+    // returns the new value, lifetime is renewed on every call
+    async fn increment(&self, key: &str, lifetime: usize) -> RedisResult<i64>;
+
+    // seconds left before the key expires, `None` if it's missing
+    async fn remaining_lifetime(&self, key: &str) -> RedisResult<Option<i64>>;
+
+    // appends values to the list, lifetime is renewed on every call
+    async fn push(&self, key: &str, values: &[String], lifetime: usize) -> RedisResult<()>;
+
+    async fn list(&self, key: &str) -> RedisResult<Vec<String>>;
+
+    // adds the time of an event to the sliding window, lifetime is renewed on every call
+    async fn add_event(&self, key: &str, event_time: i64, lifetime: usize) -> RedisResult<()>;
+
+    // drops events which happened at `since` or earlier, returns times of the rest
+    async fn events_after(&self, key: &str, since: i64) -> RedisResult<Vec<i64>>;
+}
+
+// Redis store
+
+pub struct RedisCache {
+    client: redis::Client, 
+    // connection is opened by the first command, so the service starts when Redis is down
+    connection: RwLock<Option<ConnectionManager>>
+}
+
+impl RedisCache {
+    pub fn new(client: redis::Client) -> Self {
+        RedisCache {
+            client, 
+            connection: RwLock::new(None)
+        }
+    }
+
+    // manager reconnects by itself once it's opened, commands fail until Redis is back
+    async fn connection(&self) -> RedisResult<ConnectionManager> {
+        if let Some(connection) = self.connection.read().unwrap().as_ref() {
+            return Ok(connection.clone());
+        }
+
+        let connection = ConnectionManager::new(self.client.clone()).await?;
+        log::info!("Connection to Redis opened");
+        *self.connection.write().unwrap() = Some(connection.clone());
+        Ok(connection)
+    }
+}
+
+impl CacheStore for RedisCache {
+    async fn get(&self, key: &str) -> RedisResult<Option<String>> {
+        self.connection().await?.get(key).await
+    }
+
+    async fn set(&self, key: &str, value: &str, lifetime: usize) -> RedisResult<()> {
+        self.connection().await?.set_ex(key, value, lifetime).await
+    }
+
+    async fn set_if_missing(&self, key: &str, value: &str, lifetime: usize) -> RedisResult<bool> {
+        let result: Option<String> = redis::cmd("SET")
+            .arg(key)
+            .arg(value)
+            .arg("NX")
+            .arg("EX")
+            .arg(lifetime)
+            .query_async(&mut self.connection().await?)
+            .await?;
+
+        Ok(result.is_some())
+    }
+
+    async fn take(&self, key: &str) -> RedisResult<Option<String>> {
+        let (value, _): (Option<String>, i32) = redis::pipe()
+            .atomic()
+            .get(key)
+            .del(key)
+            .query_async(&mut self.connection().await?)
+            .await?;
+
+        Ok(value)
+    }
+
+    async fn delete(&self, keys: &[String]) -> RedisResult<()> {
+        if keys.is_empty() {
+            return Ok(());
+        }
+        self.connection().await?.del(keys).await
+    }
+
+    async fn exists(&self, key: &str) -> RedisResult<bool> {
+        self.connection().await?.exists(key).await
+    }
+
+    async fn increment(&self, key: &str, lifetime: usize) -> RedisResult<i64> {
+        let (value, _): (i64, i32) = redis::pipe()
+            .atomic()
+            .incr(key, 1)
+            .expire(key, lifetime)
+            .query_async(&mut self.connection().await?)
+            .await?;
+
+        Ok(value)
+    }
+
+    async fn remaining_lifetime(&self, key: &str) -> RedisResult<Option<i64>> {
+        let lifetime: i64 = self.connection().await?.ttl(key).await?;
+        Ok(Some(lifetime).filter(|lifetime| *lifetime > 0))
+    }
+
+    async fn push(&self, key: &str, values: &[String], lifetime: usize) -> RedisResult<()> {
+        if values.is_empty() {
+            return Ok(());
+        }
+        redis::pipe()
+            .atomic()
+            .rpush(key, values)
+            .ignore()
+            .expire(key, lifetime)
+            .ignore()
+            .query_async(&mut self.connection().await?)
+            .await
+    }
+
+    async fn list(&self, key: &str) -> RedisResult<Vec<String>> {
+        self.connection().await?.lrange(key, 0, -1).await
+    }
+
+    async fn add_event(&self, key: &str, event_time: i64, lifetime: usize) -> RedisResult<()> {
+        redis::pipe()
+            .atomic()
+            .zadd(key, Uuid::new_v4().to_string(), event_time)
+            .ignore()
+            .expire(key, lifetime)
+            .ignore()
+            .query_async(&mut self.connection().await?)
+            .await
+    }
+
+    async fn events_after(&self, key: &str, since: i64) -> RedisResult<Vec<i64>> {
+        let (_, events): (i32, Vec<(String, i64)>) = redis::pipe()
+            .atomic()
+            .zrembyscore(key, "-inf", since)
+            .zrange_withscores(key, 0, -1)
+            .query_async(&mut self.connection().await?)
+            .await?;
+
+        Ok(events.into_iter().map(|(_, event_time)| event_time).collect())
+    }
+}
+
+// In-memory store
+
+#[derive(Clone)]
+enum MemoryValue {
+    Text(String), 
+    List(Vec<String>), 
+    Events(Vec<i64>)
+}
+
+struct MemoryEntry {
+    value: MemoryValue, 
+    expires_at: Instant
+}
+
+impl MemoryEntry {
+    fn new(value: MemoryValue, lifetime: usize) -> Self {
+        MemoryEntry {
+            value, 
+            expires_at: Instant::now() + Duration::from_secs(lifetime as u64)
+        }
+    }
+
+    fn is_alive(&self, now: Instant) -> bool {
+        self.expires_at > now
+    }
+}
+
+#[derive(Default)]
+struct MemoryState {
+    entries: HashMap<String, MemoryEntry>, 
+    // keys written or deleted since the last sync with Redis
+    touched_keys: HashSet<String>
+}
+
+pub struct MemoryCache {
+    state: Mutex<MemoryState>, 
+    capacity: usize
+}
+
+impl MemoryCache {
+    fn new(capacity: usize) -> Self {
+        MemoryCache {
+            state: Mutex::new(MemoryState::default()), 
+            capacity
+        }
+    }
+
+    // runs the operation on the live entry of the key, a missing entry is created from `default`
+    // and the operation is told it's new
+    fn update<T>(
+        &self, 
+        key: &str, 
+        default: Option<(MemoryValue, usize)>, 
+        operation: impl FnOnce(&mut MemoryEntry, bool) -> T) -> Option<T> {
+
+        let mut state = self.state.lock().unwrap();
+        let now = Instant::now();
+        if state.entries.get(key).is_some_and(|entry| !entry.is_alive(now)) {
+            state.entries.remove(key);
+        }
+        let created = !state.entries.contains_key(key);
+        if created {
+            let (value, lifetime) = default?;
+            if state.entries.len() >= self.capacity {
+                evict_entries(&mut state.entries, now);
+            }
+            state.entries.insert(key.to_string(), MemoryEntry::new(value, lifetime));
+        }
+        state.touched_keys.insert(key.to_string());
+        state.entries.get_mut(key).map(|entry| operation(entry, created))
+    }
+
+    fn read<T>(&self, key: &str, operation: impl FnOnce(&MemoryEntry) -> T) -> Option<T> {
+        let state = self.state.lock().unwrap();
+        state.entries
+            .get(key)
+            .filter(|entry| entry.is_alive(Instant::now()))
+            .map(operation)
+    }
+
+    fn remove(&self, keys: &[String]) -> Vec<MemoryValue> {
+        let mut state = self.state.lock().unwrap();
+        let now = Instant::now();
+        let mut values = Vec::new();
+        for key in keys {
+            if let Some(entry) = state.entries.remove(key) {
+                if entry.is_alive(now) {
+                    values.push(entry.value);
+                }
+            }
+            state.touched_keys.insert(key.clone());
+        }
+        values
+    }
+
+    // hands over keys touched since the last call and live values of them, the store is left empty
+    fn drain(&self) -> (Vec<String>, Vec<(String, MemoryValue, usize)>) {
+        let mut state = self.state.lock().unwrap();
+        let now = Instant::now();
+        let touched_keys = state.touched_keys.drain().collect();
+        let entries = state.entries
+            .drain()
+            .filter(|(_, entry)| entry.is_alive(now))
+            .map(|(key, entry)| {
+                let lifetime = entry.expires_at.duration_since(now).as_secs().max(1) as usize;
+                (key, entry.value, lifetime)
+            })
+            .collect();
+
+        (touched_keys, entries)
+    }
+}
+
+// drops expired entries, if there are none the ones closest to expiry are dropped
+fn evict_entries(entries: &mut HashMap<String, MemoryEntry>, now: Instant) {
+    let entries_count = entries.len();
+    entries.retain(|_, entry| entry.is_alive(now));
+    if entries.len() < entries_count {
+        return;
+    }
+
+    log::warn!("In-memory cache is full, {} keys held", entries_count);
+    let closest_expiry = entries
+        .iter()
+        .min_by_key(|(_, entry)| entry.expires_at)
+        .map(|(key, _)| key.clone());
+    if let Some(key) = closest_expiry {
+        entries.remove(&key);
+    }
+}
+
+impl CacheStore for MemoryCache {
+    async fn get(&self, key: &str) -> RedisResult<Option<String>> {
+        Ok(self.read(key, |entry| match &entry.value {
+            MemoryValue::Text(value) => Some(value.clone()), 
+            _ => None
+        }).flatten())
+    }
+
+    async fn set(&self, key: &str, value: &str, lifetime: usize) -> RedisResult<()> {
+        let value = MemoryValue::Text(value.to_string());
+        self.update(key, Some((value.clone(), lifetime)), |entry, _| {
+            *entry = MemoryEntry::new(value, lifetime);
+        });
+        Ok(())
+    }
+
+    async fn set_if_missing(&self, key: &str, value: &str, lifetime: usize) -> RedisResult<bool> {
+        let created = self.update(key, Some((MemoryValue::Text(value.to_string()), lifetime)), |_, created| created);
+        Ok(created.unwrap_or(false))
+    }
+
+    async fn take(&self, key: &str) -> RedisResult<Option<String>> {
+        Ok(self.remove(&[key.to_string()]).into_iter().find_map(|value| match value {
+            MemoryValue::Text(value) => Some(value), 
+            _ => None
+        }))
+    }
+
+    async fn delete(&self, keys: &[String]) -> RedisResult<()> {
+        self.remove(keys);
+        Ok(())
+    }
+
+    async fn exists(&self, key: &str) -> RedisResult<bool> {
+        Ok(self.read(key, |_| ()).is_some())
+    }
+
+    async fn increment(&self, key: &str, lifetime: usize) -> RedisResult<i64> {
+        let value = self.update(key, Some((MemoryValue::Text("0".to_string()), lifetime)), |entry, _| {
+            let value = match &entry.value {
+                MemoryValue::Text(value) => value.parse::<i64>().unwrap_or(0) + 1, 
+                _ => 1
+            };
+            *entry = MemoryEntry::new(MemoryValue::Text(value.to_string()), lifetime);
+            value
+        });
+        Ok(value.unwrap_or(1))
+    }
+
+    async fn remaining_lifetime(&self, key: &str) -> RedisResult<Option<i64>> {
+        Ok(self
+            .read(key, |entry| entry.expires_at.duration_since(Instant::now()).as_secs() as i64)
+            .filter(|lifetime| *lifetime > 0))
+    }
+
+    async fn push(&self, key: &str, values: &[String], lifetime: usize) -> RedisResult<()> {
+        if values.is_empty() {
+            return Ok(());
+        }
+        self.update(key, Some((MemoryValue::List(Vec::new()), lifetime)), |entry, _| {
+            let mut list = match std::mem::replace(&mut entry.value, MemoryValue::List(Vec::new())) {
+                MemoryValue::List(list) => list, 
+                _ => Vec::new()
+            };
+            list.extend_from_slice(values);
+            *entry = MemoryEntry::new(MemoryValue::List(list), lifetime);
+        });
+        Ok(())
+    }
+
+    async fn list(&self, key: &str) -> RedisResult<Vec<String>> {
+        Ok(self.read(key, |entry| match &entry.value {
+            MemoryValue::List(list) => list.clone(), 
+            _ => Vec::new()
+        }).unwrap_or_default())
+    }
+
+    async fn add_event(&self, key: &str, event_time: i64, lifetime: usize) -> RedisResult<()> {
+        self.update(key, Some((MemoryValue::Events(Vec::new()), lifetime)), |entry, _| {
+            let mut events = match std::mem::replace(&mut entry.value, MemoryValue::Events(Vec::new())) {
+                MemoryValue::Events(events) => events, 
+                _ => Vec::new()
+            };
+            events.push(event_time);
+            *entry = MemoryEntry::new(MemoryValue::Events(events), lifetime);
+        });
+        Ok(())
+    }
+
+    async fn events_after(&self, key: &str, since: i64) -> RedisResult<Vec<i64>> {
+        let events = self.update(key, None, |entry, _| match &mut entry.value {
+            MemoryValue::Events(events) => {
+                events.retain(|event_time| *event_time > since);
+                events.clone()
+            }, 
+            _ => Vec::new()
+        });
+        Ok(events.unwrap_or_default())
+    }
+}
+
+// Redis with in-memory fallback
+
+#[derive(Default)]
+struct BreakerState {
+    failures_count: u32, 
+    open_until: Option<Instant>, 
+    // set while Redis is bypassed, the in-memory store has to be synced when it's back
+    memory_used: bool
+}
+
+pub struct CacheDB {
+    redis: Option<RedisCache>, 
+    memory: MemoryCache, 
+    breaker: Mutex<BreakerState>
+}
+
+impl CacheDB {
+    pub fn new(redis_client: Option<redis::Client>) -> Self {
+        CacheDB {
+            redis: redis_client.map(RedisCache::new), 
+            memory: MemoryCache::new(settings().cache.memory_capacity), 
+            breaker: Mutex::new(BreakerState::default())
+        }
+    }
+
+    // Redis is skipped while the breaker is open, except for a probe call after the retry interval
+    fn available_redis(&self) -> Option<&RedisCache> {
+        let redis = self.redis.as_ref()?;
+        let mut breaker = self.breaker.lock().unwrap();
+        match breaker.open_until {
+            Some(open_until) if Instant::now() < open_until => None, 
+            Some(_) => {
+                // the next probe waits for another interval if this one fails
+                breaker.open_until = Some(Instant::now() + Duration::from_secs(settings().cache.retry_interval));
+                Some(redis)
+            }, 
+            None => Some(redis)
+        }
+    }
+
+    // revocation state is never read from the in-memory store while Redis is configured
+    pub fn security(&self) -> SecurityCache<'_> {
+        SecurityCache { cache: self }
+    }
+
+    // runs the command on Redis, `None` if Redis is bypassed or didn't answer
+    async fn run_on_redis<'a, T, R, RF>(&'a self, on_redis: R) -> Option<RedisResult<T>>
+    where
+        R: FnOnce(&'a RedisCache) -> RF, 
+        RF: Future<Output = RedisResult<T>> {
+
+        let redis = self.available_redis()?;
+        let command_timeout = Duration::from_millis(settings().cache.command_timeout);
+        match timeout(command_timeout, on_redis(redis)).await {
+            Ok(Ok(result)) => {
+                self.register_success(redis).await;
+                Some(Ok(result))
+            }, 
+            Ok(Err(redis_error)) if !is_unavailable(&redis_error) => {
+                self.register_success(redis).await;
+                Some(Err(redis_error))
+            }, 
+            Ok(Err(redis_error)) => {
+                self.register_failure(&format!("{:?}", redis_error));
+                None
+            }, 
+            Err(_) => {
+                self.register_failure("command timed out");
+                None
+            }
+        }
+    }
+
+    // runs the command on Redis if it's available, on the in-memory store otherwise
+    async fn run<'a, T, R, RF, M, MF>(&'a self, on_redis: R, on_memory: M) -> RedisResult<T>
+    where
+        R: FnOnce(&'a RedisCache) -> RF, 
+        RF: Future<Output = RedisResult<T>>, 
+        M: FnOnce(&'a MemoryCache) -> MF, 
+        MF: Future<Output = RedisResult<T>> {
+
+        if let Some(result) = self.run_on_redis(on_redis).await {
+            return result;
+        }
+
+        self.breaker.lock().unwrap().memory_used = true;
+        on_memory(&self.memory).await
+    }
+
+    // runs the command on Redis only and fails if it's unavailable, the in-memory store
+    // is used only when Redis isn't configured at all
+    async fn run_strict<'a, T, R, RF, M, MF>(&'a self, on_redis: R, on_memory: M) -> RedisResult<T>
+    where
+        R: FnOnce(&'a RedisCache) -> RF, 
+        RF: Future<Output = RedisResult<T>>, 
+        M: FnOnce(&'a MemoryCache) -> MF, 
+        MF: Future<Output = RedisResult<T>> {
+
+        if self.redis.is_none() {
+            return on_memory(&self.memory).await;
+        }
+        self.run_on_redis(on_redis).await.unwrap_or_else(|| Err(redis_unavailable()))
+    }
+
+    fn register_failure(&self, reason: &str) {
+        let mut breaker = self.breaker.lock().unwrap();
+        breaker.failures_count += 1;
+        if breaker.open_until.is_none() && breaker.failures_count >= settings().cache.failure_threshold {
+            breaker.open_until = Some(Instant::now() + Duration::from_secs(settings().cache.retry_interval));
+            log::error!("Redis is unavailable, in-memory cache is used instead: {}", reason);
+        } else {
+            log::warn!("Cache database issue: {}", reason);
+        }
+    }
+
+    async fn register_success(&self, redis: &RedisCache) {
+        let memory_used = {
+            let mut breaker = self.breaker.lock().unwrap();
+            if breaker.open_until.is_some() {
+                log::info!("Redis is available again");
+            }
+            breaker.failures_count = 0;
+            breaker.open_until = None;
+            std::mem::take(&mut breaker.memory_used)
+        };
+        if memory_used {
+            self.sync_memory_to_redis(redis).await;
+        }
+    }
+
+    // keys touched while Redis was bypassed are rewritten, values cached before the outage may be stale
+    async fn sync_memory_to_redis(&self, redis: &RedisCache) {
+        let (touched_keys, entries) = self.memory.drain();
+        let mut sync_result = redis.delete(&touched_keys).await;
+        for (key, value, lifetime) in entries {
+            if sync_result.is_err() {
+                break;
+            }
+            sync_result = match value {
+                MemoryValue::Text(value) => redis.set(&key, &value, lifetime).await, 
+                MemoryValue::List(list) => redis.push(&key, &list, lifetime).await, 
+                MemoryValue::Events(events) => {
+                    let mut result = Ok(());
+                    for event_time in events {
+                        result = result.and(redis.add_event(&key, event_time, lifetime).await);
+                    }
+                    result
+                }
+            };
+        }
+
+        match sync_result {
+            Ok(_) => log::info!("{} keys touched during Redis outage synced", touched_keys.len()), 
+            Err(redis_error) => log::error!("Unable to sync in-memory cache to Redis: {:?}", redis_error)
+        }
+    }
+}
+
+fn redis_unavailable() -> redis::RedisError {
+    redis::RedisError::from(std::io::Error::new(std::io::ErrorKind::NotConnected, "Redis is unavailable"))
+}
+
+pub fn is_unavailable(redis_error: &redis::RedisError) -> bool {
+    redis_error.is_io_error()
+        || redis_error.is_connection_refusal()
+        || redis_error.is_connection_dropped()
+        || redis_error.is_timeout()
+}
+
+impl CacheStore for CacheDB {
+    async fn get(&self, key: &str) -> RedisResult<Option<String>> {
+        self.run(|redis| redis.get(key), |memory| memory.get(key)).await
+    }
+
+    async fn set(&self, key: &str, value: &str, lifetime: usize) -> RedisResult<()> {
+        self.run(|redis| redis.set(key, value, lifetime), |memory| memory.set(key, value, lifetime)).await
+    }
+
+    async fn set_if_missing(&self, key: &str, value: &str, lifetime: usize) -> RedisResult<bool> {
+        self.run(
+            |redis| redis.set_if_missing(key, value, lifetime), 
+            |memory| memory.set_if_missing(key, value, lifetime)
+        ).await
+    }
+
+    async fn take(&self, key: &str) -> RedisResult<Option<String>> {
+        self.run(|redis| redis.take(key), |memory| memory.take(key)).await
+    }
+
+    async fn delete(&self, keys: &[String]) -> RedisResult<()> {
+        self.run(|redis| redis.delete(keys), |memory| memory.delete(keys)).await
+    }
+
+    async fn exists(&self, key: &str) -> RedisResult<bool> {
+        self.run(|redis| redis.exists(key), |memory| memory.exists(key)).await
+    }
+
+    async fn increment(&self, key: &str, lifetime: usize) -> RedisResult<i64> {
+        self.run(|redis| redis.increment(key, lifetime), |memory| memory.increment(key, lifetime)).await
+    }
+
+    async fn remaining_lifetime(&self, key: &str) -> RedisResult<Option<i64>> {
+        self.run(|redis| redis.remaining_lifetime(key), |memory| memory.remaining_lifetime(key)).await
+    }
+
+    async fn push(&self, key: &str, values: &[String], lifetime: usize) -> RedisResult<()> {
+        self.run(|redis| redis.push(key, values, lifetime), |memory| memory.push(key, values, lifetime)).await
+    }
+
+    async fn list(&self, key: &str) -> RedisResult<Vec<String>> {
+        self.run(|redis| redis.list(key), |memory| memory.list(key)).await
+    }
+
+    async fn add_event(&self, key: &str, event_time: i64, lifetime: usize) -> RedisResult<()> {
+        self.run(
+            |redis| redis.add_event(key, event_time, lifetime), 
+            |memory| memory.add_event(key, event_time, lifetime)
+        ).await
+    }
+
+    async fn events_after(&self, key: &str, since: i64) -> RedisResult<Vec<i64>> {
+        self.run(|redis| redis.events_after(key, since), |memory| memory.events_after(key, since)).await
+    }
+}
+
+// Revocation state store, see `CacheDB::security`
+
+pub struct SecurityCache<'a> {
+    cache: &'a CacheDB
+}
+
+impl CacheStore for SecurityCache<'_> {
+    async fn get(&self, key: &str) -> RedisResult<Option<String>> {
+        self.cache.run_strict(|redis| redis.get(key), |memory| memory.get(key)).await
+    }
+
+    async fn set(&self, key: &str, value: &str, lifetime: usize) -> RedisResult<()> {
+        self.cache.set(key, value, lifetime).await
+    }
+
+    async fn set_if_missing(&self, key: &str, value: &str, lifetime: usize) -> RedisResult<bool> {
+        self.cache.run_strict(
+            |redis| redis.set_if_missing(key, value, lifetime), 
+            |memory| memory.set_if_missing(key, value, lifetime)
+        ).await
+    }
+
+    async fn take(&self, key: &str) -> RedisResult<Option<String>> {
+        self.cache.run_strict(|redis| redis.take(key), |memory| memory.take(key)).await
+    }
+
+    async fn delete(&self, keys: &[String]) -> RedisResult<()> {
+        self.cache.delete(keys).await
+    }
+
+    async fn exists(&self, key: &str) -> RedisResult<bool> {
+        self.cache.run_strict(|redis| redis.exists(key), |memory| memory.exists(key)).await
+    }
+
+    async fn increment(&self, key: &str, lifetime: usize) -> RedisResult<i64> {
+        self.cache.run_strict(|redis| redis.increment(key, lifetime), |memory| memory.increment(key, lifetime)).await
+    }
+
+    async fn remaining_lifetime(&self, key: &str) -> RedisResult<Option<i64>> {
+        self.cache.run_strict(|redis| redis.remaining_lifetime(key), |memory| memory.remaining_lifetime(key)).await
+    }
+
+    async fn push(&self, key: &str, values: &[String], lifetime: usize) -> RedisResult<()> {
+        self.cache.push(key, values, lifetime).await
+    }
+
+    async fn list(&self, key: &str) -> RedisResult<Vec<String>> {
+        self.cache.run_strict(|redis| redis.list(key), |memory| memory.list(key)).await
+    }
+
+    async fn add_event(&self, key: &str, event_time: i64, lifetime: usize) -> RedisResult<()> {
+        self.cache.add_event(key, event_time, lifetime).await
+    }
+
+    async fn events_after(&self, key: &str, since: i64) -> RedisResult<Vec<i64>> {
+        self.cache.run_strict(|redis| redis.events_after(key, since), |memory| memory.events_after(key, since)).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::settings::init_test_settings;
+
+    // Redis stand-in speaking just enough RESP for the commands used by the sync,
+    // every received command is recorded
+    struct FakeRedis {
+        commands: Arc<Mutex<Vec<Vec<String>>>>
+    }
+
+    impl FakeRedis {
+        fn start(port: u16) -> Self {
+            let listener = TcpListener::bind(("127.0.0.1", port)).unwrap();
+            let commands = Arc::new(Mutex::new(Vec::new()));
+            let recorded = commands.clone();
+            std::thread::spawn(move || {
+                for stream in listener.incoming().flatten() {
+                    let recorded = recorded.clone();
+                    std::thread::spawn(move || serve(stream, recorded));
+                }
+            });
+            FakeRedis { commands }
+        }
+
+        fn received(&self, matches: impl Fn(&[String]) -> bool) -> bool {
+            self.commands.lock().unwrap().iter().any(|command| matches(command))
+        }
+    }
+
+    fn serve(stream: TcpStream, recorded: Arc<Mutex<Vec<Vec<String>>>>) {
+        let mut writer = stream.try_clone().unwrap();
+        let mut reader = BufReader::new(stream);
+        let mut stored: HashMap<String, String> = HashMap::new();
+        let mut read_line = || {
+            let mut line = String::new();
+            match reader.read_line(&mut line) {
+                Ok(0) | Err(_) => None, 
+                Ok(_) => Some(line.trim_end().to_string())
+            }
+        };
+
+        while let Some(header) = read_line() {
+            let arguments_count: usize = header.trim_start_matches('*').parse().unwrap();
+            let command: Vec<String> = (0..arguments_count)
+                .map(|_| {
+                    read_line();
+                    read_line().unwrap()
+                })
+                .collect();
+
+            let reply = match command[0].as_str() {
+                "GET" => match stored.get(&command[1]) {
+                    Some(value) => format!("${}\r\n{}\r\n", value.len(), value), 
+                    None => "$-1\r\n".to_string()
+                }, 
+                "SETEX" => {
+                    stored.insert(command[1].clone(), command[3].clone());
+                    "+OK\r\n".to_string()
+                }, 
+                "DEL" => format!(":{}\r\n", command[1..].iter().filter(|key| stored.remove(*key).is_some()).count()), 
+                _ => "+OK\r\n".to_string()
+            };
+            recorded.lock().unwrap().push(command);
+            if writer.write_all(reply.as_bytes()).is_err() {
+                return;
+            }
+        }
+    }
+
+    fn unused_port() -> u16 {
+        TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port()
+    }
+
+    fn cache_on(port: u16) -> CacheDB {
+        init_test_settings();
+        CacheDB::new(Some(redis::Client::open(format!("redis://127.0.0.1:{}/", port)).unwrap()))
+    }
+
+    fn is_breaker_open(cache: &CacheDB) -> bool {
+        cache.breaker.lock().unwrap().open_until.is_some()
+    }
+
+    // as if the retry interval has passed
+    fn expire_retry_interval(cache: &CacheDB) {
+        cache.breaker.lock().unwrap().open_until = Some(Instant::now() - Duration::from_secs(1));
+    }
+
+    #[actix_web::test]
+    async fn memory_entries_expire() {
+        let memory = MemoryCache::new(10);
+
+        memory.set("expired", "value", 0).await.unwrap();
+        memory.set("alive", "value", 60).await.unwrap();
+        assert_eq!(memory.get("expired").await.unwrap(), None);
+        assert!(!memory.exists("expired").await.unwrap());
+        assert_eq!(memory.get("alive").await.unwrap(), Some("value".to_string()));
+        assert!(matches!(memory.remaining_lifetime("alive").await.unwrap(), Some(59..=60)));
+        assert_eq!(memory.remaining_lifetime("expired").await.unwrap(), None);
+
+        // expired counter starts over
+        memory.increment("attempts", 0).await.unwrap();
+        assert_eq!(memory.increment("attempts", 60).await.unwrap(), 1);
+        assert_eq!(memory.increment("attempts", 60).await.unwrap(), 2);
+    }
+
+    #[actix_web::test]
+    async fn full_memory_evicts_expired_entries_first() {
+        let memory = MemoryCache::new(2);
+
+        memory.set("soon", "value", 10).await.unwrap();
+        memory.set("expired", "value", 0).await.unwrap();
+        memory.set("new", "value", 60).await.unwrap();
+
+        assert!(memory.exists("soon").await.unwrap());
+        assert!(memory.exists("new").await.unwrap());
+        assert_eq!(memory.state.lock().unwrap().entries.len(), 2);
+    }
+
+    #[actix_web::test]
+    async fn full_memory_evicts_entry_closest_to_expiry() {
+        let memory = MemoryCache::new(2);
+
+        memory.set("later", "value", 600).await.unwrap();
+        memory.set("soon", "value", 10).await.unwrap();
+        memory.set("new", "value", 60).await.unwrap();
+
+        assert!(!memory.exists("soon").await.unwrap());
+        assert!(memory.exists("later").await.unwrap());
+        assert!(memory.exists("new").await.unwrap());
+    }
+
+    #[actix_web::test]
+    async fn breaker_opens_after_failure_threshold() {
+        let cache = cache_on(unused_port());
+        let failure_threshold = settings().cache.failure_threshold;
+
+        for _ in 1..failure_threshold {
+            cache.set("boards", "cached", 60).await.unwrap();
+            assert!(!is_breaker_open(&cache));
+        }
+        cache.set("boards", "cached", 60).await.unwrap();
+        assert!(is_breaker_open(&cache));
+
+        // plain reads are answered from memory, security state fails closed
+        assert_eq!(cache.get("boards").await.unwrap(), Some("cached".to_string()));
+        let security_read = cache.security().get("revoked").await;
+        assert!(security_read.is_err_and(|redis_error| is_unavailable(&redis_error)));
+    }
+
+    #[actix_web::test]
+    async fn failed_probe_keeps_breaker_open() {
+        let cache = cache_on(unused_port());
+        for _ in 0..settings().cache.failure_threshold {
+            cache.set("boards", "cached", 60).await.unwrap();
+        }
+
+        expire_retry_interval(&cache);
+        // the half-open breaker lets one call through, its failure arms the breaker again
+        assert_eq!(cache.get("boards").await.unwrap(), Some("cached".to_string()));
+        let open_until = cache.breaker.lock().unwrap().open_until.unwrap();
+        assert!(open_until > Instant::now());
+    }
+
+    #[actix_web::test]
+    async fn recovered_redis_closes_breaker_and_gets_memory_synced() {
+        let port = unused_port();
+        let cache = cache_on(port);
+        for _ in 0..settings().cache.failure_threshold {
+            cache.set("boards", "cached", 60).await.unwrap();
+        }
+        cache.delete(&["tasks".to_string()]).await.unwrap();
+        assert!(is_breaker_open(&cache));
+
+        let redis = FakeRedis::start(port);
+        expire_retry_interval(&cache);
+        assert_eq!(cache.get("user").await.unwrap(), None);
+
+        assert!(!is_breaker_open(&cache));
+        assert_eq!(cache.breaker.lock().unwrap().failures_count, 0);
+        // keys touched during the outage are dropped first, then live values are written with their lifetime left
+        assert!(redis.received(|command| command[0] == "DEL" && command[1..].contains(&"tasks".to_string())));
+        assert!(redis.received(|command| command[0] == "SETEX" && command[1] == "boards" && command[3] == "cached"));
+
+        // Redis serves the synced value, memory was drained
+        assert!(cache.memory.state.lock().unwrap().entries.is_empty());
+        assert_eq!(cache.get("boards").await.unwrap(), Some("cached".to_string()));
+    }
+}
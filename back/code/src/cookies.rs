@@ -0,0 +1,59 @@
+use actix_web::{
+    cookie::{time::Duration, Cookie, SameSite}, 
+    HttpResponseBuilder
+};
+
+use crate::{
+    ACCESS_TOKEN_COOKIE, REFRESH_TOKEN_COOKIE, REFRESH_TOKEN_COOKIE_PATH, CSRF_COOKIE, OIDC_STATE_COOKIE
+};
+use crate::settings::settings;
+
+// Every cookie of the service is built here. All of them are `Secure` and `SameSite=Strict`, 
+// tokens are `HttpOnly` as well. CSRF cookie is left readable, frontend scripts repeat its value 
+// in CSRF_HEADER of every request authenticated by cookies (see `csrf` module). 
+// Single sign-on state cookie is the only `SameSite=Lax` one, it has to come back with the 
+// redirect from the provider.
+
+fn hardened_cookie(
+    name: &'static str, 
+    value: String, 
+    path: &'static str, 
+    max_age: i64, 
+    http_only: bool) -> Cookie<'static> {
+
+    Cookie::build(name, value)
+        .path(path)
+        .secure(true)
+        .http_only(http_only)
+        .same_site(SameSite::Strict)
+        .max_age(Duration::seconds(max_age))
+        .finish()
+}
+
+pub fn oidc_state_cookie(state: String) -> Cookie<'static> {
+    let max_age = if state.is_empty() { 0 } else { settings().tokens.oidc_login_lifetime as i64 };
+    let mut cookie = hardened_cookie(OIDC_STATE_COOKIE, state, "/oidc", max_age, true);
+    cookie.set_same_site(SameSite::Lax);
+    cookie
+}
+
+pub fn access_token_cookie(access_token: String) -> Cookie<'static> {
+    hardened_cookie(ACCESS_TOKEN_COOKIE, access_token, "/", settings().tokens.access_token_lifetime, true)
+}
+
+pub fn refresh_token_cookie(refresh_token: String) -> Cookie<'static> {
+    hardened_cookie(REFRESH_TOKEN_COOKIE, refresh_token, REFRESH_TOKEN_COOKIE_PATH, settings().tokens.refresh_token_lifetime, true)
+}
+
+// lives as long as refresh token, so cookie refresh requests can be protected too
+pub fn csrf_cookie(csrf_token: String) -> Cookie<'static> {
+    hardened_cookie(CSRF_COOKIE, csrf_token, "/", settings().tokens.refresh_token_lifetime, false)
+}
+
+// expires all auth cookies, used on logout and whenever sessions of the user are revoked
+pub fn drop_auth_cookies(response: &mut HttpResponseBuilder) -> &mut HttpResponseBuilder {
+    response
+        .cookie(hardened_cookie(ACCESS_TOKEN_COOKIE, String::new(), "/", 0, true))
+        .cookie(hardened_cookie(REFRESH_TOKEN_COOKIE, String::new(), REFRESH_TOKEN_COOKIE_PATH, 0, true))
+        .cookie(hardened_cookie(CSRF_COOKIE, String::new(), "/", 0, false))
+}
@@ -0,0 +1,206 @@
+use actix_web::{
+    body::EitherBody, 
+    dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform}, 
+    http::{header::{self, HeaderValue}, Method}, 
+    Error, ResponseError
+};
+use std::future::{ready, Future, Ready};
+use std::pin::Pin;
+
+use crate::{ACCESS_TOKEN_COOKIE, REFRESH_TOKEN_COOKIE, CSRF_COOKIE, CSRF_HEADER};
+use crate::errors::AppError;
+
+// Double-submit CSRF protection for browser requests authenticated by cookies.
+// A request without `Authorization` header carrying access or refresh token cookie
+// has to repeat CSRF cookie value in CSRF_HEADER unless its method is safe.
+// Access token cookie of such request is then passed to `validate_user` as bearer token.
+// Requests with `Authorization` header are left untouched, foreign sites can't set it.
+
+pub struct CsrfProtection;
+
+impl<S, B> Transform<S, ServiceRequest> for CsrfProtection
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>, 
+    S::Future: 'static, 
+    B: 'static
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Transform = CsrfProtectionMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(CsrfProtectionMiddleware { service }))
+    }
+}
+
+pub struct CsrfProtectionMiddleware<S> {
+    service: S
+}
+
+impl<S, B> Service<ServiceRequest> for CsrfProtectionMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>, 
+    S::Future: 'static, 
+    B: 'static
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    forward_ready!(service);
+
+    fn call(&self, mut request: ServiceRequest) -> Self::Future {
+        if !request.headers().contains_key(header::AUTHORIZATION) {
+            let access_token = request.cookie(ACCESS_TOKEN_COOKIE).map(|cookie| cookie.value().to_string());
+            let has_auth_cookies = access_token.is_some() || request.cookie(REFRESH_TOKEN_COOKIE).is_some();
+
+            if has_auth_cookies && !is_safe_method(request.method()) && !is_csrf_token_valid(&request) {
+                log::warn!("Missing or invalid CSRF token for `{} {}`", request.method(), request.path());
+                let response = AppError::Forbidden(String::from("Invalid CSRF token")).error_response();
+                let response = request.into_response(response).map_into_right_body();
+                return Box::pin(async move { Ok(response) });
+            }
+
+            if let Some(access_token) = access_token {
+                if let Ok(value) = HeaderValue::from_str(&format!("Bearer {}", access_token)) {
+                    request.headers_mut().insert(header::AUTHORIZATION, value);
+                }
+            }
+        }
+
+        let response = self.service.call(request);
+        Box::pin(async move {
+            response.await.map(ServiceResponse::map_into_left_body)
+        })
+    }
+}
+
+fn is_safe_method(method: &Method) -> bool {
+    matches!(*method, Method::GET | Method::HEAD | Method::OPTIONS)
+}
+
+fn is_csrf_token_valid(request: &ServiceRequest) -> bool {
+    let csrf_cookie = match request.cookie(CSRF_COOKIE) {
+        Some(csrf_cookie) => csrf_cookie, 
+        None => return false
+    };
+    let csrf_header = match request.headers().get(CSRF_HEADER) {
+        Some(csrf_header) => csrf_header.as_bytes(), 
+        None => return false
+    };
+    tokens_match(csrf_cookie.value().as_bytes(), csrf_header)
+}
+
+// constant-time, the time spent doesn't tell how many leading bytes were guessed right
+fn tokens_match(csrf_token: &[u8], csrf_header: &[u8]) -> bool {
+    !csrf_token.is_empty()
+        && csrf_token.len() == csrf_header.len()
+        && openssl::memcmp::eq(csrf_token, csrf_header)
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::test::{call_service, init_service, read_body, TestRequest};
+    use actix_web::{cookie::Cookie, web, App, HttpRequest, HttpResponse};
+
+    use super::*;
+
+    // answers with the authorization header seen by handlers
+    async fn echo_authorization(request: HttpRequest) -> HttpResponse {
+        let authorization = request
+            .headers()
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default()
+            .to_string();
+        HttpResponse::Ok().body(authorization)
+    }
+
+    async fn call(request: TestRequest) -> (u16, String) {
+        let app = init_service(
+            App::new()
+                .wrap(CsrfProtection)
+                .route("/", web::to(echo_authorization))
+        ).await;
+        let response = call_service(&app, request.uri("/").to_request()).await;
+        let status = response.status().as_u16();
+        let body = read_body(response).await;
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    fn with_cookies(request: TestRequest) -> TestRequest {
+        request
+            .cookie(Cookie::new(ACCESS_TOKEN_COOKIE, "access"))
+            .cookie(Cookie::new(CSRF_COOKIE, "csrf-token"))
+    }
+
+    #[actix_web::test]
+    async fn unsafe_method_with_cookies_needs_header() {
+        let (status, _) = call(with_cookies(TestRequest::post())).await;
+        assert_eq!(status, 403);
+
+        let (status, _) = call(TestRequest::delete().cookie(Cookie::new(REFRESH_TOKEN_COOKIE, "refresh"))).await;
+        assert_eq!(status, 403);
+    }
+
+    #[actix_web::test]
+    async fn mismatched_token_is_rejected() {
+        let (status, _) = call(with_cookies(TestRequest::put()).insert_header((CSRF_HEADER, "csrf-tokex"))).await;
+        assert_eq!(status, 403);
+
+        let (status, _) = call(with_cookies(TestRequest::put()).insert_header((CSRF_HEADER, "csrf"))).await;
+        assert_eq!(status, 403);
+
+        let (status, _) = call(with_cookies(TestRequest::delete()).insert_header((CSRF_HEADER, "csrf-token-and-more"))).await;
+        assert_eq!(status, 403);
+    }
+
+    #[actix_web::test]
+    async fn matching_token_passes_cookie_as_bearer() {
+        let (status, authorization) = call(with_cookies(TestRequest::post()).insert_header((CSRF_HEADER, "csrf-token"))).await;
+        assert_eq!(status, 200);
+        assert_eq!(authorization, "Bearer access");
+    }
+
+    #[actix_web::test]
+    async fn bearer_request_is_left_untouched() {
+        let request = with_cookies(TestRequest::post()).insert_header((header::AUTHORIZATION, "Bearer header"));
+        let (status, authorization) = call(request).await;
+        assert_eq!(status, 200);
+        assert_eq!(authorization, "Bearer header");
+    }
+
+    #[actix_web::test]
+    async fn safe_methods_pass() {
+        for method in [Method::GET, Method::HEAD, Method::OPTIONS] {
+            let (status, _) = call(with_cookies(TestRequest::default().method(method.clone()))).await;
+            assert_eq!(status, 200, "{}", method);
+        }
+    }
+
+    #[actix_web::test]
+    async fn request_without_cookies_passes() {
+        let (status, authorization) = call(TestRequest::post()).await;
+        assert_eq!(status, 200);
+        assert!(authorization.is_empty());
+    }
+
+    #[actix_web::test]
+    async fn header_without_csrf_cookie_is_rejected() {
+        let request = TestRequest::patch()
+            .cookie(Cookie::new(ACCESS_TOKEN_COOKIE, "access"))
+            .insert_header((CSRF_HEADER, "csrf-token"));
+        let (status, _) = call(request).await;
+        assert_eq!(status, 403);
+
+        // an empty cookie can't be matched by an empty header
+        let request = TestRequest::post()
+            .cookie(Cookie::new(ACCESS_TOKEN_COOKIE, "access"))
+            .cookie(Cookie::new(CSRF_COOKIE, ""))
+            .insert_header((CSRF_HEADER, ""));
+        let (status, _) = call(request).await;
+        assert_eq!(status, 403);
+    }
+}
@@ -1,16 +1,19 @@
 use std::sync::Mutex;
 use sqlx::{postgres::PgPoolOptions, Postgres, Pool};
-use redis::{self, Connection as RedisConnection};
 use actix_web::web;
 
-use crate::POSTGRESQL_CONNECTIONS_LIMIT;
+use crate::cache::CacheDB;
+use crate::settings::settings;
 
 pub struct PersistentDB {
     pub db: Mutex<Pool<Postgres>>
 }
 
-pub struct CacheDB {
-    pub db: Mutex<RedisConnection>
+impl PersistentDB {
+    // pool is a cheap handle, a clone is awaited on instead of holding the guard across `.await`
+    pub fn pool(&self) -> Pool<Postgres> {
+        self.db.lock().unwrap().clone()
+    }
 }
 
 pub async fn init_persistent_database() -> web::Data<PersistentDB> {
@@ -18,33 +21,29 @@ pub async fn init_persistent_database() -> web::Data<PersistentDB> {
     let db_url = std::env::var("DATABASE_URL")
         .expect("Unable to read DATABASE_URL env var");
     let postgres_pool = PgPoolOptions::new()
-        .max_connections(POSTGRESQL_CONNECTIONS_LIMIT)
+        .max_connections(settings().postgres.connections_limit)
         .connect(&db_url)
         .await
         .expect("Unable to connect to Postgres");
 
-    let postgres_db = web::Data::new(
+    web::Data::new(
         PersistentDB {
             db: Mutex::new(postgres_pool)
         }
-    );
-
-    postgres_db
+    )
 }
 
+// Redis isn't required, the in-memory cache is used alone if REDIS_URL isn't set.
+// Connection is opened by the first command, so the service starts while Redis is down.
 pub fn init_cache_database() -> web::Data<CacheDB> {
 
-    let cache_db_url = std::env::var("REDIS_URL")
-        .expect("Unable to read REDIS_URL env var");
-
-    let redis_client = redis::Client::open(cache_db_url).unwrap();
-    let redis_connection = redis_client.get_connection().unwrap();
-
-    let redis_db = web::Data::new(
-        CacheDB {
-            db: Mutex::new(redis_connection)
+    let redis_client = match std::env::var("REDIS_URL").ok().filter(|value| !value.is_empty()) {
+        Some(cache_db_url) => Some(redis::Client::open(cache_db_url).expect("Invalid REDIS_URL env var")), 
+        None => {
+            log::warn!("REDIS_URL is not set, in-memory cache is used instead of Redis");
+            None
         }
-    );
+    };
 
-    redis_db
+    web::Data::new(CacheDB::new(redis_client))
 }
\ No newline at end of file
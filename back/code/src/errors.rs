@@ -0,0 +1,147 @@
+use actix_web::{
+    http::{header, StatusCode}, 
+    HttpResponse, HttpResponseBuilder, ResponseError
+};
+use redis::RedisError;
+use std::fmt;
+
+use crate::cache::is_unavailable;
+use crate::models::ProblemDetails;
+use crate::password_policy::PasswordViolation;
+use crate::settings::settings;
+
+// Handlers return `Result<HttpResponse, AppError>` and leave early with `?`.
+// Every error is answered with RFC 7807 problem details (`application/problem+json`):
+// `code` is stable and meant for the frontend to switch on, `detail` can be shown to the user.
+// Database and cache errors are logged here, mail ones by `send_email`;
+// details of internal errors never reach the client. Unreachable Redis is answered with 503,
+// requests depending on security state are refused until it's back.
+
+#[derive(Debug)]
+pub enum AppError {
+    Validation(String), 
+    PasswordRejected(Vec<PasswordViolation>), 
+    Unauthorized(String), 
+    Forbidden(String), 
+    NotFound(String), 
+    Conflict(String), 
+    TooManyRequests { detail: String, retry_after: i64 }, 
+    Database(sqlx::Error), 
+    Cache(RedisError), 
+    Mail(String)
+}
+
+impl AppError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            AppError::Validation(_) => "validation_error", 
+            AppError::PasswordRejected(_) => "password_rejected", 
+            AppError::Unauthorized(_) => "unauthorized", 
+            AppError::Forbidden(_) => "forbidden", 
+            AppError::NotFound(_) => "not_found", 
+            AppError::Conflict(_) => "conflict", 
+            AppError::TooManyRequests { .. } => "too_many_requests", 
+            AppError::Database(_) => "database_error", 
+            AppError::Cache(_) => "cache_error", 
+            AppError::Mail(_) => "mail_error"
+        }
+    }
+
+    fn detail(&self) -> String {
+        match self {
+            AppError::Validation(detail)
+            | AppError::Unauthorized(detail)
+            | AppError::Forbidden(detail)
+            | AppError::NotFound(detail)
+            | AppError::Conflict(detail)
+            | AppError::TooManyRequests { detail, .. } => detail.clone(), 
+            AppError::PasswordRejected(violations) => violations
+                .iter()
+                .map(PasswordViolation::description)
+                .collect::<Vec<String>>()
+                .join(". "), 
+            AppError::Cache(cache_error) if is_unavailable(cache_error) => {
+                String::from("Service is temporarily unavailable, try again later")
+            }, 
+            AppError::Database(_) | AppError::Cache(_) => String::from("Internal server error"), 
+            AppError::Mail(_) => String::from("Email couldn't be sent, try again later")
+        }
+    }
+
+    // finishes the prepared response with problem details, lets handlers attach cookies to errors
+    pub fn respond_with(&self, response: &mut HttpResponseBuilder) -> HttpResponse {
+        match self {
+            AppError::Database(db_error) => log::error!("Database issue: {:?}", db_error), 
+            AppError::Cache(cache_error) => log::error!("Cache issue: {:?}", cache_error), 
+            _ => ()
+        }
+
+        let status = self.status_code();
+        let reasons = match self {
+            AppError::PasswordRejected(violations) => Some(violations), 
+            _ => None
+        };
+        let problem = ProblemDetails {
+            problem_type: "about:blank", 
+            title: status.canonical_reason().unwrap_or_default(), 
+            status: status.as_u16(), 
+            detail: self.detail(), 
+            code: self.code(), 
+            reasons
+        };
+
+        response.status(status);
+        response.content_type("application/problem+json");
+        match self {
+            AppError::TooManyRequests { retry_after, .. } => {
+                response.insert_header((header::RETRY_AFTER, retry_after.to_string()));
+            }, 
+            AppError::Cache(_) if status == StatusCode::SERVICE_UNAVAILABLE => {
+                response.insert_header((header::RETRY_AFTER, settings().cache.retry_interval.to_string()));
+            }, 
+            _ => ()
+        }
+        response.json(problem)
+    }
+}
+
+impl fmt::Display for AppError {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AppError::Mail(reason) => write!(formatter, "{}: {}", self.code(), reason), 
+            _ => write!(formatter, "{}: {}", self.code(), self.detail())
+        }
+    }
+}
+
+impl From<sqlx::Error> for AppError {
+    fn from(db_error: sqlx::Error) -> Self {
+        AppError::Database(db_error)
+    }
+}
+
+impl From<RedisError> for AppError {
+    fn from(cache_error: RedisError) -> Self {
+        AppError::Cache(cache_error)
+    }
+}
+
+impl ResponseError for AppError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            AppError::Validation(_) | AppError::PasswordRejected(_) => StatusCode::BAD_REQUEST, 
+            AppError::Unauthorized(_) => StatusCode::UNAUTHORIZED, 
+            AppError::Forbidden(_) => StatusCode::FORBIDDEN, 
+            AppError::NotFound(_) => StatusCode::NOT_FOUND, 
+            AppError::Conflict(_) => StatusCode::CONFLICT, 
+            AppError::TooManyRequests { .. } => StatusCode::TOO_MANY_REQUESTS, 
+            AppError::Cache(cache_error) if is_unavailable(cache_error) => StatusCode::SERVICE_UNAVAILABLE, 
+            AppError::Database(_) | AppError::Cache(_) => StatusCode::INTERNAL_SERVER_ERROR, 
+            AppError::Mail(_) => StatusCode::BAD_GATEWAY
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        self.respond_with(&mut HttpResponse::build(self.status_code()))
+    }
+}
@@ -0,0 +1,123 @@
+use sqlx::{self, Postgres, Pool, Row};
+use uuid::Uuid;
+
+use crate::{APP_SCHEMA, USERS_TABLE, EXTERNAL_IDENTITIES_TABLE};
+use crate::oidc::IdTokenClaims;
+use crate::passwords::hash_password;
+use crate::tools::generate_random_token;
+
+// External identity is an account at the single sign-on provider (issuer + subject) linked to a customer.
+// On the first login the identity is linked to the customer with the same email if the provider
+// verified it, otherwise a new customer is created. Customers created or activated this way get
+// a random password, they can set their own one with the forgotten password flow.
+
+pub enum LinkOutcome {
+    Linked(Uuid), 
+    EmailNotVerified, 
+    AccountInactive
+}
+
+pub async fn find_or_link_user(
+    db_link: &Pool<Postgres>, 
+    claims: &IdTokenClaims) -> Result<LinkOutcome, sqlx::Error> {
+
+    let mut transaction = db_link.begin().await?;
+
+    let identity_query = format!(
+        "UPDATE {APP_SCHEMA}.{EXTERNAL_IDENTITIES_TABLE} e
+            SET last_login_at = now()
+           FROM {APP_SCHEMA}.{USERS_TABLE} c
+          WHERE c.id = e.user_id
+            AND e.issuer = $1
+            AND e.subject = $2
+      RETURNING e.user_id, c.status_id"
+    );
+    let linked_user = sqlx::query(&identity_query)
+        .bind(&claims.iss)
+        .bind(&claims.sub)
+        .map(|row| (row.get::<Uuid, &str>("user_id"), row.get::<Option<i32>, &str>("status_id")))
+        .fetch_optional(&mut transaction)
+        .await?;
+
+    if let Some((user_id, status_id)) = linked_user {
+        transaction.commit().await?;
+        return match status_id {
+            Some(1) => Ok(LinkOutcome::Linked(user_id)), 
+            _ => Ok(LinkOutcome::AccountInactive)
+        };
+    }
+
+    let email = match &claims.email {
+        Some(email) if claims.email_verified => email, 
+        _ => return Ok(LinkOutcome::EmailNotVerified)
+    };
+
+    let user_query = format!(
+        "SELECT
+            id, status_id
+           FROM {}.{}
+          WHERE email = $1", 
+        APP_SCHEMA, 
+        USERS_TABLE
+    );
+    let existing_user = sqlx::query(&user_query)
+        .bind(email)
+        .map(|row| (row.get::<Uuid, &str>("id"), row.get::<Option<i32>, &str>("status_id")))
+        .fetch_optional(&mut transaction)
+        .await?;
+
+    let user_id = match existing_user {
+        Some((user_id, Some(1))) => user_id, 
+        // email of never verified account is confirmed by the provider now,
+        // password chosen on registration is replaced as nobody proved owning it
+        Some((user_id, Some(0))) => {
+            let activate_query = format!(
+                "UPDATE {}.{}
+                    SET verification_status_id = 1, status_id = 1, passwd = $2
+                  WHERE id = $1", 
+                APP_SCHEMA, 
+                USERS_TABLE
+            );
+            sqlx::query(&activate_query)
+                .bind(user_id)
+                .bind(hash_password(&generate_random_token()).await)
+                .execute(&mut transaction)
+                .await?;
+            user_id
+        }, 
+        Some(_) => return Ok(LinkOutcome::AccountInactive), 
+        None => {
+            let insert_query = format!(
+                "INSERT INTO {}.{} (name, email, passwd, verification_status_id, status_id)
+                      VALUES ($1, $2, $3, 1, 1)
+                   RETURNING id", 
+                APP_SCHEMA, 
+                USERS_TABLE
+            );
+            sqlx::query(&insert_query)
+                .bind(claims.name.clone().unwrap_or_default())
+                .bind(email)
+                .bind(hash_password(&generate_random_token()).await)
+                .map(|row| row.get::<Uuid, &str>("id"))
+                .fetch_one(&mut transaction)
+                .await?
+        }
+    };
+
+    let link_query = format!(
+        "INSERT INTO {}.{} (user_id, issuer, subject, email, last_login_at)
+              VALUES ($1, $2, $3, $4, now())", 
+        APP_SCHEMA, 
+        EXTERNAL_IDENTITIES_TABLE
+    );
+    sqlx::query(&link_query)
+        .bind(user_id)
+        .bind(&claims.iss)
+        .bind(&claims.sub)
+        .bind(email)
+        .execute(&mut transaction)
+        .await?;
+
+    transaction.commit().await?;
+    Ok(LinkOutcome::Linked(user_id))
+}
@@ -0,0 +1,81 @@
+use actix_web::{rt, web::Data};
+use std::time::Duration;
+
+use crate::{PersistentDB, APP_SCHEMA, USERS_TABLE};
+use crate::accounts::purge_deleted_accounts;
+use crate::action_tokens::purge_used_action_tokens;
+use crate::settings::settings;
+
+// Periodic maintenance tasks, spawned once on the main runtime before the server starts.
+
+pub fn start_background_jobs(postgres_db: Data<PersistentDB>) {
+    rt::spawn(async move {
+        let mut interval = rt::time::interval(Duration::from_secs(settings().accounts.cleanup_interval));
+        loop {
+            interval.tick().await;
+            remove_unverified_accounts(&postgres_db).await;
+            remove_deleted_accounts(&postgres_db).await;
+            remove_used_action_tokens(&postgres_db).await;
+        }
+    });
+}
+
+// accounts never activated by the verification link only hold the email,
+// removing them lets the address be registered again
+async fn remove_unverified_accounts(postgres_db: &Data<PersistentDB>) {
+    let db_link = &postgres_db.pool();
+
+    let query = format!(
+        "DELETE FROM {}.{}
+          WHERE status_id = 0
+            AND created_at < now() - make_interval(secs => $1)", 
+        APP_SCHEMA, 
+        USERS_TABLE
+    );
+    let result = sqlx::query(&query)
+        .bind(settings().accounts.unverified_account_lifetime as f64)
+        .execute(db_link)
+        .await;
+
+    match result {
+        Ok(deleted) => {
+            if deleted.rows_affected() > 0 {
+                log::info!("Removed {} never verified accounts", deleted.rows_affected());
+            }
+        }, 
+        Err(db_error) => {
+            log::error!("Database issue: {:?}", db_error);
+        }
+    }
+}
+
+// deleted accounts are kept for a grace period, then removed with all boards and tasks
+async fn remove_deleted_accounts(postgres_db: &Data<PersistentDB>) {
+    let db_link = &postgres_db.pool();
+
+    match purge_deleted_accounts(db_link, settings().accounts.deleted_account_grace_period).await {
+        Ok(deleted_count) => {
+            if deleted_count > 0 {
+                log::info!("Removed {} deleted accounts after grace period", deleted_count);
+            }
+        }, 
+        Err(db_error) => {
+            log::error!("Database issue: {:?}", db_error);
+        }
+    }
+}
+
+async fn remove_used_action_tokens(postgres_db: &Data<PersistentDB>) {
+    let db_link = &postgres_db.pool();
+
+    match purge_used_action_tokens(db_link).await {
+        Ok(deleted_count) => {
+            if deleted_count > 0 {
+                log::info!("Removed {} nonces of expired action tokens", deleted_count);
+            }
+        }, 
+        Err(db_error) => {
+            log::error!("Database issue: {:?}", db_error);
+        }
+    }
+}
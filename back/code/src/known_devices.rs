@@ -0,0 +1,147 @@
+use actix_web::HttpRequest;
+use sqlx::{self, Postgres, Pool, Row};
+use std::net::{IpAddr, SocketAddr};
+use uuid::Uuid;
+
+use crate::{APP_SCHEMA, USERS_TABLE, KNOWN_DEVICES_TABLE};
+use crate::convertations::AsHash;
+
+// A device is a client the user has already logged in from, it is told apart by the
+// user agent and the network of its ip address (/24 for IPv4, /48 for IPv6), so a client
+// moving inside its provider network isn't reported again. Only fingerprints are compared,
+// user agent and ip address are kept to be shown to the user.
+
+pub struct ClientInfo {
+    pub user_agent: String, 
+    pub ip_address: String, 
+    fingerprint: String
+}
+
+impl ClientInfo {
+    pub fn from_request(request: &HttpRequest) -> Self {
+        let user_agent = request
+            .headers()
+            .get("user-agent")
+            .and_then(|value| value.to_str().ok())
+            .map(|value| value.chars().take(512).collect::<String>())
+            .unwrap_or_default();
+        let ip_address = request
+            .connection_info()
+            .realip_remote_addr()
+            .map(|value| value.to_string())
+            .unwrap_or_default();
+        let fingerprint = format!("{}|{}", user_agent, ip_network(&ip_address)).as_hash();
+
+        ClientInfo { user_agent, ip_address, fingerprint }
+    }
+}
+
+fn ip_network(ip_address: &str) -> String {
+    let ip = ip_address
+        .parse::<IpAddr>()
+        .or_else(|_| ip_address.parse::<SocketAddr>().map(|socket| socket.ip()));
+
+    match ip {
+        Ok(IpAddr::V4(ip)) => {
+            let [a, b, c, _] = ip.octets();
+            format!("{}.{}.{}.0/24", a, b, c)
+        }, 
+        Ok(IpAddr::V6(ip)) => {
+            let segments = ip.segments();
+            format!("{:x}:{:x}:{:x}::/48", segments[0], segments[1], segments[2])
+        }, 
+        Err(_) => ip_address.to_string()
+    }
+}
+
+pub enum DeviceCheck {
+    Known, 
+    // the very first login of the user isn't worth a notification
+    First, 
+    New { device_id: i32, email: String }
+}
+
+// remembers the client of the login, tells if it was seen before
+pub async fn register_device(
+    db_link: &Pool<Postgres>, 
+    user_id: Uuid, 
+    client: &ClientInfo) -> Result<DeviceCheck, sqlx::Error> {
+
+    let mut transaction = db_link.begin().await?;
+
+    let count_query = format!(
+        "SELECT
+            count(*) AS devices_count
+           FROM {}.{}
+          WHERE user_id = $1", 
+        APP_SCHEMA, 
+        KNOWN_DEVICES_TABLE
+    );
+    let devices_count: i64 = sqlx::query(&count_query)
+        .bind(user_id)
+        .map(|row| row.get("devices_count"))
+        .fetch_one(&mut transaction)
+        .await?;
+
+    // `xmax` is zero only for rows inserted by the statement
+    let upsert_query = format!(
+        "INSERT INTO {}.{} (user_id, fingerprint, user_agent, ip_address, last_seen_at)
+              VALUES ($1, $2, $3, $4, now())
+         ON CONFLICT (user_id, fingerprint)
+       DO UPDATE SET ip_address = EXCLUDED.ip_address, last_seen_at = now()
+           RETURNING id, (xmax = 0) AS inserted", 
+        APP_SCHEMA, 
+        KNOWN_DEVICES_TABLE
+    );
+    let (device_id, inserted) = sqlx::query(&upsert_query)
+        .bind(user_id)
+        .bind(&client.fingerprint)
+        .bind(&client.user_agent)
+        .bind(&client.ip_address)
+        .map(|row| (row.get::<i32, &str>("id"), row.get::<bool, &str>("inserted")))
+        .fetch_one(&mut transaction)
+        .await?;
+
+    let email_query = format!(
+        "SELECT
+            email
+           FROM {}.{}
+          WHERE id = $1", 
+        APP_SCHEMA, 
+        USERS_TABLE
+    );
+    let email: Option<String> = sqlx::query(&email_query)
+        .bind(user_id)
+        .map(|row| row.get("email"))
+        .fetch_one(&mut transaction)
+        .await?;
+
+    transaction.commit().await?;
+    match (inserted, email) {
+        (false, _) => Ok(DeviceCheck::Known), 
+        (true, Some(email)) if devices_count > 0 => Ok(DeviceCheck::New { device_id, email }), 
+        _ => Ok(DeviceCheck::First)
+    }
+}
+
+// the device reported by the user is forgotten, so the next login from it is reported again
+pub async fn forget_device(
+    db_link: &Pool<Postgres>, 
+    user_id: Uuid, 
+    device_id: i32) -> Result<(), sqlx::Error> {
+
+    let query = format!(
+        "DELETE FROM {}.{}
+          WHERE id = $2
+            AND user_id = $1", 
+        APP_SCHEMA, 
+        KNOWN_DEVICES_TABLE
+    );
+    sqlx::query(&query)
+        .bind(user_id)
+        .bind(device_id)
+        .execute(db_link)
+        .await?;
+
+    Ok(())
+}
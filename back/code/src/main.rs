@@ -1,28 +1,61 @@
-#[macro_use]
 extern crate log;
 extern crate log4rs;
 
 use actix_web::{web, App, HttpServer};
 use actix_web_httpauth::middleware::HttpAuthentication;
 
+mod accounts;
+mod action_tokens;
+mod audit;
+mod auth_user;
 mod autorization;
 mod app_config;
+mod cache;
+mod cookies;
+mod csrf;
 mod databases;
+mod errors;
+mod external_identities;
+mod jobs;
+mod known_devices;
 mod logging;
+mod migrations;
 mod users_managing;
 mod convertations;
 mod models;
+mod oidc;
+mod password_policy;
+mod password_resets;
+mod personal_tokens;
+mod passwords;
 mod redis_handlers;
+mod repositories;
+mod roles;
 mod services;
+mod sessions;
+mod settings;
+mod throttling;
 mod tools;
+mod two_factor;
 
 pub use app_config::*;
-use autorization::validate_user;
-use users_managing::{authorized_users_managing, unauthorized_users_managing};
+use autorization::{validate_user, init_jwt_keyring};
+use action_tokens::init_action_token_keys;
+use auth_user::AccessTokenRenewal;
+use csrf::CsrfProtection;
+use errors::AppError;
+use oidc::init_oidc_provider;
+use password_policy::init_password_policy;
+use settings::init_settings;
+use users_managing::{admin_users_managing, authorized_users_managing, unauthorized_users_managing};
 use services::{boards_managing, tasks_managing};
 use databases::{init_persistent_database, init_cache_database};
-pub use databases::{PersistentDB, CacheDB};
+use repositories::init_repository;
+pub use databases::PersistentDB;
+pub use cache::CacheDB;
 use logging::init_logger;
+use migrations::run_migrations;
+use jobs::start_background_jobs;
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
@@ -31,26 +64,73 @@ async fn main() -> std::io::Result<()> {
     dotenv::dotenv().expect("Unable to load environment variables from .env file");
 
     init_logger();
+    let settings = init_settings();
     let postgres_db = init_persistent_database().await;
+
+    // `code migrate` applies migrations and exits without serving
+    let command = std::env::args().nth(1);
+    match command.as_deref() {
+        Some("migrate") => {
+            let postgres_pool = postgres_db.pool();
+            migrate_or_exit(&postgres_pool).await;
+            return Ok(());
+        }, 
+        Some(unknown_command) => {
+            log::error!("Unknown command `{}`, only `migrate` is supported", unknown_command);
+            std::process::exit(2);
+        }, 
+        None if settings.postgres.migrate_on_startup => {
+            let postgres_pool = postgres_db.pool();
+            migrate_or_exit(&postgres_pool).await;
+        }, 
+        None => log::warn!("Migrations on startup are turned off, database schema isn't checked")
+    }
+
+    let repository = init_repository(postgres_db.pool());
     let redis_db = init_cache_database();
+    let jwt_keyring = init_jwt_keyring();
+    init_action_token_keys();
+    let oidc_provider = init_oidc_provider();
+    let password_policy = init_password_policy();
+    start_background_jobs(postgres_db.clone());
+    let host = settings.server.host.clone();
+    let threads_count = settings.server.threads_count;
 
     HttpServer::new(move || {
         let authorization_middleware = HttpAuthentication::bearer(validate_user);
         App::new()
             .app_data(postgres_db.clone())
+            .app_data(repository.clone())
             .app_data(redis_db.clone())
+            .app_data(jwt_keyring.clone())
+            .app_data(oidc_provider.clone())
+            .app_data(password_policy.clone())
+            .app_data(web::JsonConfig::default().error_handler(|error, _| AppError::Validation(error.to_string()).into()))
+            .app_data(web::QueryConfig::default().error_handler(|error, _| AppError::Validation(error.to_string()).into()))
+            .app_data(web::PathConfig::default().error_handler(|error, _| AppError::Validation(error.to_string()).into()))
+            .wrap(CsrfProtection)
             .configure(unauthorized_users_managing)
             .service(
                 web::scope("")
                     .wrap(authorization_middleware)
+                    .wrap(AccessTokenRenewal)
                     .configure(authorized_users_managing)
+                    .configure(admin_users_managing)
                     .configure(boards_managing)
                     .configure(tasks_managing)
             )
     })
-        .bind(HOST)?
-        .workers(THREADS_COUNT)
+        .bind(host)?
+        .workers(threads_count)
         .run()
         .await
 
 }
+
+// the server isn't started on a schema it doesn't expect
+async fn migrate_or_exit(postgres_pool: &sqlx::Pool<sqlx::Postgres>) {
+    if let Err(error) = run_migrations(postgres_pool).await {
+        log::error!("Unable to apply database migrations: {}", error);
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,79 @@
+use sqlx::migrate::{MigrateError, Migrator};
+use sqlx::{self, Postgres, Pool};
+
+// Schema migrations are numbered files in `migrations/`, embedded into the binary at build time.
+// Applied versions and their checksums are kept in `public._sqlx_migrations`,
+// startup fails if an applied migration was edited or is missing from the binary.
+// Postgres advisory lock is held while migrating, so instances started together apply them once.
+// Run on startup unless `postgres.migrate_on_startup` is off, or alone with `code migrate`.
+// `0001_baseline` is the hand-run `db/db_setup.sql` as it was, databases set up by that script
+// get it recorded as applied without running it, later migrations are idempotent and adopt
+// whatever part of them was already set up by hand.
+
+static MIGRATOR: Migrator = sqlx::migrate!("./migrations");
+
+const BASELINE_VERSION: i64 = 1;
+
+pub async fn run_migrations(db_link: &Pool<Postgres>) -> Result<(), MigrateError> {
+
+    adopt_baseline(db_link).await?;
+
+    let applied_versions: Vec<i64> = sqlx::query_scalar("SELECT version FROM _sqlx_migrations WHERE success")
+        .fetch_all(db_link)
+        .await?;
+    let pending_migrations = MIGRATOR
+        .iter()
+        .filter(|migration| !applied_versions.contains(&migration.version))
+        .map(|migration| format!("{}_{}", migration.version, migration.description))
+        .collect::<Vec<String>>();
+
+    if pending_migrations.is_empty() {
+        log::info!("Database schema is up to date");
+    } else {
+        log::info!("Applying migrations: {}", pending_migrations.join(", "));
+    }
+
+    MIGRATOR.run(db_link).await
+}
+
+// baseline statements aren't idempotent, so it's marked as applied on a database
+// which already has the schema but no migration history
+async fn adopt_baseline(db_link: &Pool<Postgres>) -> Result<(), MigrateError> {
+
+    let baseline = MIGRATOR
+        .iter()
+        .find(|migration| migration.version == BASELINE_VERSION)
+        .ok_or(MigrateError::VersionMissing(BASELINE_VERSION))?;
+
+    // same table as sqlx creates on first run
+    sqlx::query("
+        CREATE TABLE IF NOT EXISTS _sqlx_migrations (
+            version BIGINT PRIMARY KEY,
+            description TEXT NOT NULL,
+            installed_on TIMESTAMPTZ NOT NULL DEFAULT now(),
+            success BOOLEAN NOT NULL,
+            checksum BYTEA NOT NULL,
+            execution_time BIGINT NOT NULL
+        )")
+        .execute(db_link)
+        .await?;
+
+    let adopted = sqlx::query("
+        INSERT INTO _sqlx_migrations (version, description, success, checksum, execution_time)
+        SELECT $1, $2, TRUE, $3, 0
+        WHERE
+            NOT EXISTS (SELECT version FROM _sqlx_migrations)
+            AND to_regclass('routine_app.customer') IS NOT NULL
+        ON CONFLICT (version) DO NOTHING")
+        .bind(baseline.version)
+        .bind(baseline.description.as_ref())
+        .bind(baseline.checksum.as_ref())
+        .execute(db_link)
+        .await?
+        .rows_affected() > 0;
+
+    if adopted {
+        log::info!("Existing database schema is adopted as {}_{}", baseline.version, baseline.description);
+    }
+    Ok(())
+}
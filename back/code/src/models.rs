@@ -2,6 +2,9 @@ use serde::{Serialize, Deserialize};
 use chrono::{NaiveDateTime, NaiveDate};
 use uuid::Uuid;
 
+use crate::password_policy::PasswordViolation;
+use crate::roles::Role;
+
 // Common
 
 #[derive(Serialize)]
@@ -10,17 +13,30 @@ pub struct ServerResponse {
     pub message: String
 }
 
+// error body, see `errors::AppError`
+#[derive(Serialize)]
+pub struct ProblemDetails<'a> {
+    #[serde(rename = "type")]
+    pub problem_type: &'static str, 
+    pub title: &'static str, 
+    pub status: u16, 
+    pub detail: String, 
+    pub code: &'static str, 
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reasons: Option<&'a Vec<PasswordViolation>>
+}
+
 // Users
 
 #[derive(Serialize, Deserialize)]
 pub struct User {
     pub id: Uuid, 
-    pub name: String,
+    pub name: String, 
     pub email: String, 
-    pub passwd: String,
-    pub verification_status_id: i32,
+    pub passwd: String, 
+    pub verification_status_id: i32, 
     pub status_id: i32, 
-    pub created_at: i64,
+    pub created_at: i64, 
     pub updated_at: i64
 }
 
@@ -30,15 +46,154 @@ pub struct UserCredentials {
     pub password: String
 }
 
+#[derive(Serialize)]
+pub struct TokenPair {
+    pub access_token: String, 
+    pub refresh_token: String, 
+    pub expires_in: i64
+}
+
+#[derive(Deserialize)]
+pub struct RefreshTokenBody {
+    pub refresh_token: String
+}
+
+#[derive(Deserialize)]
+pub struct OidcCallbackQuery {
+    pub code: Option<String>, 
+    pub state: Option<String>, 
+    pub error: Option<String>
+}
+
+#[derive(Serialize)]
+pub struct LoginChallenge {
+    pub two_factor_required: bool, 
+    pub challenge_token: String, 
+    pub expires_in: usize
+}
+
+#[derive(Deserialize)]
+pub struct SecondFactorBody {
+    pub challenge_token: String, 
+    pub code: Option<String>, 
+    pub recovery_code: Option<String>
+}
+
+#[derive(Serialize)]
+pub struct TwoFactorEnrollment {
+    pub secret: String, 
+    pub otpauth_uri: String
+}
+
+#[derive(Deserialize)]
+pub struct ConfirmTwoFactorBody {
+    pub code: String
+}
+
+#[derive(Deserialize)]
+pub struct DisableTwoFactorBody {
+    pub password: String
+}
+
+#[derive(Serialize)]
+pub struct RecoveryCodes {
+    pub recovery_codes: Vec<String>
+}
+
+#[derive(Serialize)]
+pub struct Session {
+    pub id: Uuid, 
+    pub user_agent: String, 
+    pub ip_address: String, 
+    pub created_at: i64, 
+    pub last_seen_at: i64, 
+    pub current: bool
+}
+
 #[derive(Serialize, Deserialize)]
+pub struct StoredSession {
+    pub id: Uuid, 
+    pub user_agent: Option<String>, 
+    pub ip_address: Option<String>, 
+    pub created_at: Option<NaiveDateTime>, 
+    pub last_seen_at: Option<NaiveDateTime>
+}
+
+impl StoredSession {
+    pub fn get_session(&self, current_session_id: Uuid) -> Session {
+        Session {
+            id: self.id, 
+            user_agent: self.user_agent.clone().unwrap_or_else(|| {"Unknown client".to_string()}), 
+            ip_address: self.ip_address.clone().unwrap_or_else(|| {"".to_string()}), 
+            created_at: self.created_at.unwrap_or_else(|| {
+                NaiveDate::from_ymd_opt(2000, 1, 1).unwrap().and_hms_opt(0, 0, 0).unwrap()
+            }).timestamp(), 
+            last_seen_at: self.last_seen_at.unwrap_or_else(|| {
+                NaiveDate::from_ymd_opt(2000, 1, 1).unwrap().and_hms_opt(0, 0, 0).unwrap()
+            }).timestamp(), 
+            current: self.id == current_session_id
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct PersonalToken {
+    pub id: i32, 
+    pub name: String, 
+    pub scopes: Vec<String>, 
+    pub created_at: i64, 
+    pub expires_at: Option<i64>, 
+    pub last_used_at: Option<i64>
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct StoredPersonalToken {
+    pub id: i32, 
+    pub name: Option<String>, 
+    pub scopes: Vec<String>, 
+    pub created_at: Option<NaiveDateTime>, 
+    pub expires_at: Option<NaiveDateTime>, 
+    pub last_used_at: Option<NaiveDateTime>
+}
+
+impl StoredPersonalToken {
+    pub fn get_personal_token(&self) -> PersonalToken {
+        PersonalToken {
+            id: self.id, 
+            name: self.name.clone().unwrap_or_else(|| {"Unnamed token".to_string()}), 
+            scopes: self.scopes.clone(), 
+            created_at: self.created_at.unwrap_or_else(|| {
+                NaiveDate::from_ymd_opt(2000, 1, 1).unwrap().and_hms_opt(0, 0, 0).unwrap()
+            }).timestamp(), 
+            expires_at: self.expires_at.map(|expires_at| expires_at.timestamp()), 
+            last_used_at: self.last_used_at.map(|last_used_at| last_used_at.timestamp())
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct NewPersonalToken {
+    pub token: String, 
+    #[serde(flatten)]
+    pub details: PersonalToken
+}
+
+#[derive(Deserialize)]
+pub struct CreatePersonalTokenBody {
+    pub name: String, 
+    pub scopes: Vec<String>, 
+    pub expires_in: Option<i64> // seconds, token never expires if not set
+}
+
+#[derive(Serialize, Deserialize, Clone)]
 pub struct StoredUser {
     pub id: Uuid, 
     pub name: Option<String>, 
     pub email: Option<String>, 
-    pub passwd: Option<String>,
-    pub verification_status_id: Option<i32>,
+    pub passwd: Option<String>, 
+    pub verification_status_id: Option<i32>, 
     pub status_id: Option<i32>, 
-    pub created_at: Option<NaiveDateTime>,
+    pub created_at: Option<NaiveDateTime>, 
     pub updated_at: Option<NaiveDateTime>
 }
 
@@ -53,7 +208,7 @@ impl StoredUser {
             status_id: self.status_id.unwrap(), 
             created_at: self.created_at.unwrap_or_else(|| {
                 NaiveDate::from_ymd_opt(2000, 1, 1).unwrap().and_hms_opt(0, 0, 0).unwrap()
-            }).timestamp(),
+            }).timestamp(), 
             updated_at: self.updated_at.unwrap_or_else(|| {
                 NaiveDate::from_ymd_opt(2000, 1, 1).unwrap().and_hms_opt(0, 0, 0).unwrap()
             }).timestamp()
@@ -63,7 +218,7 @@ impl StoredUser {
 
 #[derive(Serialize, Deserialize)]
 pub struct Profile {
-    pub id: Uuid,
+    pub id: Uuid, 
     pub name: String, 
     pub email: String
 }
@@ -77,7 +232,7 @@ pub struct CreateUserBody {
 
 #[derive(Deserialize)]
 pub struct ChangePasswordBody {
-    pub old_password: String,
+    pub old_password: String, 
     pub new_password: String
 }
 
@@ -86,6 +241,21 @@ pub struct ChangeForgottenPasswordBody {
     pub email: String
 }
 
+#[derive(Deserialize)]
+pub struct ResendVerificationBody {
+    pub email: String
+}
+
+#[derive(Deserialize)]
+pub struct ResetPasswordBody {
+    pub new_password: String
+}
+
+#[derive(Deserialize)]
+pub struct DeleteAccountBody {
+    pub password: String
+}
+
 #[derive(Deserialize)]
 pub struct ChangeEmailBody {
     pub new_email: String
@@ -96,21 +266,104 @@ pub struct ChangeUsernameBody {
     pub new_name: String
 }
 
+// Account export
+
+#[derive(Serialize)]
+pub struct AccountExport {
+    pub exported_at: i64, 
+    pub profile: ExportedProfile, 
+    pub boards: Vec<ExportedBoard>
+}
+
+#[derive(Serialize)]
+pub struct ExportedProfile {
+    pub id: Uuid, 
+    pub name: String, 
+    pub email: String, 
+    pub two_factor_enabled: bool, 
+    pub created_at: i64, 
+    pub updated_at: i64
+}
+
+#[derive(Serialize)]
+pub struct ExportedBoard {
+    pub id: i32, 
+    pub title: String, 
+    pub description: String, 
+    pub status_id: i32, 
+    pub creation_time: i64, 
+    pub tasks: Vec<Task>
+}
+
+// Admin
+
+#[derive(Deserialize)]
+pub struct AdminUsersQuery {
+    pub search: Option<String>, // part of name or email
+    pub status_id: Option<i32>, 
+    pub limit: Option<i64>, 
+    pub offset: Option<i64>
+}
+
+#[derive(Serialize)]
+pub struct AdminUserSummary {
+    pub id: Uuid, 
+    pub name: String, 
+    pub email: String, 
+    pub status_id: i32, 
+    pub role: Role, 
+    pub created_at: i64
+}
+
+#[derive(Serialize)]
+pub struct AdminUserDetails {
+    #[serde(flatten)]
+    pub user: AdminUserSummary, 
+    pub two_factor_enabled: bool, 
+    pub boards_count: i64, // active boards
+    pub tasks_count: i64 // tasks of active boards
+}
+
+// Audit events
+
+#[derive(Deserialize)]
+pub struct AuditEventsQuery {
+    pub user_id: Option<Uuid>, 
+    pub actor_id: Option<Uuid>, 
+    pub event_type: Option<String>, 
+    pub since: Option<i64>, // unix timestamps
+    pub until: Option<i64>, 
+    pub limit: Option<i64>, 
+    pub offset: Option<i64>
+}
+
+#[derive(Serialize)]
+pub struct AuditEventRecord {
+    pub id: i64, 
+    pub user_id: Option<Uuid>, 
+    pub actor_id: Option<Uuid>, 
+    pub event_type: String, 
+    pub ip_address: Option<String>, 
+    pub user_agent: Option<String>, 
+    pub metadata: serde_json::Value, 
+    pub created_at: i64
+}
+
 // Boards
 
 #[derive(Serialize, Deserialize)]
 pub struct Board {
     pub id: i32, 
     pub title: String, 
-    pub description: String,
+    pub description: String, 
     pub creation_time: i64
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct StoredBoard {
     pub id: i32, 
     pub title: Option<String>, 
-    pub description: Option<String>,
+    pub description: Option<String>, 
     pub creation_time: Option<NaiveDateTime>
 }
 
@@ -152,18 +405,18 @@ pub struct DeleteBoardBody {
 pub struct Task {
     pub id: i32, 
     pub title: String, 
-    pub description: String,
+    pub description: String, 
     pub board_id: i32, 
     pub status_id: i32, 
     pub creation_time: i64, 
     pub last_status_change_time: i64
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct StoredTask {
     pub id: i32, 
     pub title: Option<String>, 
-    pub description: Option<String>,
+    pub description: Option<String>, 
     pub board_id: Option<i32>, 
     pub status_id: Option<i32>, 
     pub creation_time: Option<NaiveDateTime>, 
@@ -176,11 +429,11 @@ impl StoredTask {
             id: self.id, 
             title: self.title.clone().unwrap_or_else(|| {"Unnamed task".to_string()}), 
             description: self.description.clone().unwrap_or_else(|| {"".to_string()}), 
-            board_id: self.board_id.unwrap_or_else(|| {0}),
-            status_id: self.status_id.unwrap_or_else(|| {0}),
+            board_id: self.board_id.unwrap_or(0), 
+            status_id: self.status_id.unwrap_or(0), 
             creation_time: self.creation_time.unwrap_or_else(|| {
                 NaiveDate::from_ymd_opt(2000, 1, 1).unwrap().and_hms_opt(0, 0, 0).unwrap()
-            }).timestamp(),
+            }).timestamp(), 
             last_status_change_time: self.last_status_change_time.unwrap_or_else(|| {
                 NaiveDate::from_ymd_opt(2000, 1, 1).unwrap().and_hms_opt(0, 0, 0).unwrap()
             }).timestamp()
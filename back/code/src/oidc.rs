@@ -0,0 +1,436 @@
+use actix_web::web;
+use base64::{Engine as _, engine::general_purpose};
+use jsonwebtoken::{self, Algorithm, DecodingKey, Validation, jwk::{Jwk, JwkSet}};
+use serde::{Serialize, Deserialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+use crate::settings::settings;
+
+// Single sign-on with an external OpenID Connect provider, authorization code flow with PKCE.
+// Provider is configured by the `oidc` settings section: discovery document is read from
+// `{issuer}/.well-known/openid-configuration`, client id and secret are credentials of the app
+// registered at the provider, redirect url is `{server.service_url}/oidc/callback` unless set.
+// Login is disabled if issuer or client id isn't set.
+// Id tokens are accepted only when signed with an RSA or EC algorithm the provider announces,
+// by a key of its JWKS. Keys are cached by `kid` and read again when a token names an unknown one.
+
+// symmetric algorithms would let anyone knowing the client secret sign id tokens
+const ACCEPTED_ALGORITHMS: [Algorithm; 5] = [
+    Algorithm::RS256, 
+    Algorithm::RS384, 
+    Algorithm::RS512, 
+    Algorithm::ES256, 
+    Algorithm::ES384
+];
+
+pub struct OidcConfig {
+    pub issuer: String, 
+    pub client_id: String, 
+    pub client_secret: String, 
+    pub redirect_url: String
+}
+
+#[derive(Deserialize, Clone)]
+struct ProviderMetadata {
+    issuer: String, 
+    authorization_endpoint: String, 
+    token_endpoint: String, 
+    jwks_uri: String, 
+    #[serde(default = "default_signing_algorithms")]
+    id_token_signing_alg_values_supported: Vec<String>
+}
+
+// the field is required by the discovery spec, RS256 is the one every provider has to support
+fn default_signing_algorithms() -> Vec<String> {
+    vec![String::from("RS256")]
+}
+
+impl ProviderMetadata {
+    fn signing_algorithms(&self) -> Vec<Algorithm> {
+        self.id_token_signing_alg_values_supported
+            .iter()
+            .filter_map(|algorithm| algorithm.parse::<Algorithm>().ok())
+            .filter(|algorithm| ACCEPTED_ALGORITHMS.contains(algorithm))
+            .collect()
+    }
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    id_token: String
+}
+
+// login attempt kept in cache between redirect to provider and callback
+#[derive(Serialize, Deserialize)]
+pub struct OidcLoginState {
+    pub code_verifier: String, 
+    pub nonce: String
+}
+
+#[derive(Deserialize)]
+pub struct IdTokenClaims {
+    pub iss: String, 
+    pub sub: String, 
+    pub email: Option<String>, 
+    #[serde(default)]
+    pub email_verified: bool, 
+    pub name: Option<String>, 
+    pub nonce: Option<String>
+}
+
+pub struct OidcProvider {
+    config: Option<OidcConfig>, 
+    http_client: reqwest::Client, 
+    metadata: RwLock<Option<(ProviderMetadata, i64)>>, 
+    // provider keys by `kid`, a key without `kid` is kept under empty one
+    signing_keys: RwLock<HashMap<String, Jwk>>
+}
+
+impl OidcProvider {
+    pub fn config(&self) -> Option<&OidcConfig> {
+        self.config.as_ref()
+    }
+
+    async fn metadata(&self, config: &OidcConfig) -> Result<ProviderMetadata, String> {
+        let current_time = chrono::offset::Utc::now().naive_utc().timestamp();
+        if let Some((metadata, fetched_at)) = &*self.metadata.read().unwrap() {
+            if current_time - fetched_at < settings().oidc.discovery_cache_lifetime {
+                return Ok(metadata.clone());
+            }
+        }
+
+        let discovery_url = format!("{}/.well-known/openid-configuration", config.issuer.trim_end_matches('/'));
+        let metadata: ProviderMetadata = self.http_client
+            .get(&discovery_url)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|error| format!("Discovery request failed: {}", error))?
+            .json()
+            .await
+            .map_err(|error| format!("Invalid discovery document: {}", error))?;
+
+        if metadata.issuer != config.issuer {
+            return Err(format!("Discovery document issued by `{}`", metadata.issuer));
+        }
+
+        *self.metadata.write().unwrap() = Some((metadata.clone(), current_time));
+        Ok(metadata)
+    }
+
+    pub async fn authorization_url(
+        &self, 
+        config: &OidcConfig, 
+        state: &str, 
+        login_state: &OidcLoginState) -> Result<String, String> {
+
+        let metadata = self.metadata(config).await?;
+        let code_challenge = code_challenge(&login_state.code_verifier);
+        let params = [
+            ("response_type", "code"), 
+            ("client_id", config.client_id.as_str()), 
+            ("redirect_uri", config.redirect_url.as_str()), 
+            ("scope", settings().oidc.scopes.as_str()), 
+            ("state", state), 
+            ("nonce", login_state.nonce.as_str()), 
+            ("code_challenge", code_challenge.as_str()), 
+            ("code_challenge_method", "S256")
+        ];
+
+        reqwest::Url::parse_with_params(&metadata.authorization_endpoint, &params)
+            .map(String::from)
+            .map_err(|error| format!("Invalid authorization endpoint: {}", error))
+    }
+
+    // exchanges authorization code and returns verified claims of the id token
+    pub async fn complete_login(
+        &self, 
+        config: &OidcConfig, 
+        code: &str, 
+        login_state: &OidcLoginState) -> Result<IdTokenClaims, String> {
+
+        let metadata = self.metadata(config).await?;
+        let params = [
+            ("grant_type", "authorization_code"), 
+            ("code", code), 
+            ("redirect_uri", config.redirect_url.as_str()), 
+            ("client_id", config.client_id.as_str()), 
+            ("code_verifier", login_state.code_verifier.as_str())
+        ];
+        let token_response: TokenResponse = self.http_client
+            .post(&metadata.token_endpoint)
+            .basic_auth(&config.client_id, Some(&config.client_secret))
+            .form(&params)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|error| format!("Token request failed: {}", error))?
+            .json()
+            .await
+            .map_err(|error| format!("Invalid token response: {}", error))?;
+
+        self.verify_id_token(config, &metadata, &token_response.id_token, &login_state.nonce).await
+    }
+
+    async fn verify_id_token(
+        &self, 
+        config: &OidcConfig, 
+        metadata: &ProviderMetadata, 
+        id_token: &str, 
+        nonce: &str) -> Result<IdTokenClaims, String> {
+
+        let header = jsonwebtoken::decode_header(id_token)
+            .map_err(|error| format!("Invalid id token: {}", error))?;
+
+        if !metadata.signing_algorithms().contains(&header.alg) {
+            return Err(format!("Id token signed with not allowed algorithm {:?}", header.alg));
+        }
+        let jwk = self.signing_key(metadata, header.kid.as_deref()).await?;
+        if jwk.common.algorithm.is_some_and(|algorithm| algorithm != header.alg) {
+            return Err(format!("Id token algorithm {:?} doesn't match its key", header.alg));
+        }
+        let decoding_key = DecodingKey::from_jwk(&jwk)
+            .map_err(|error| format!("Unsupported provider key: {}", error))?;
+
+        let mut validation = Validation::new(header.alg);
+        validation.set_required_spec_claims(&["iss", "aud", "sub", "exp"]);
+        validation.set_issuer(&[&config.issuer]);
+        validation.set_audience(&[&config.client_id]);
+        validation.leeway = settings().jwt.clock_skew;
+
+        let claims = jsonwebtoken::decode::<IdTokenClaims>(id_token, &decoding_key, &validation)
+            .map_err(|error| format!("Invalid id token: {}", error))?
+            .claims;
+
+        if claims.nonce.as_deref() != Some(nonce) {
+            return Err("Id token nonce mismatch".to_string());
+        }
+        Ok(claims)
+    }
+
+    // unknown `kid` means the provider has rotated its keys, so they are read again once
+    async fn signing_key(&self, metadata: &ProviderMetadata, kid: Option<&str>) -> Result<Jwk, String> {
+        if let Some(jwk) = find_signing_key(&self.signing_keys.read().unwrap(), kid) {
+            return Ok(jwk);
+        }
+
+        let jwks: JwkSet = self.http_client
+            .get(&metadata.jwks_uri)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|error| format!("JWKS request failed: {}", error))?
+            .json()
+            .await
+            .map_err(|error| format!("Invalid JWKS: {}", error))?;
+
+        let signing_keys: HashMap<String, Jwk> = jwks.keys
+            .into_iter()
+            .map(|jwk| (jwk.common.key_id.clone().unwrap_or_default(), jwk))
+            .collect();
+        let jwk = find_signing_key(&signing_keys, kid);
+        *self.signing_keys.write().unwrap() = signing_keys;
+
+        jwk.ok_or("Id token signed with unknown key".to_string())
+    }
+}
+
+// token without `kid` is accepted only from a provider with a single key
+fn find_signing_key(signing_keys: &HashMap<String, Jwk>, kid: Option<&str>) -> Option<Jwk> {
+    match kid {
+        Some(kid) => signing_keys.get(kid).cloned(), 
+        None if signing_keys.len() == 1 => signing_keys.values().next().cloned(), 
+        None => None
+    }
+}
+
+// PKCE `S256` challenge for the code verifier
+fn code_challenge(code_verifier: &str) -> String {
+    general_purpose::URL_SAFE_NO_PAD.encode(Sha256::digest(code_verifier.as_bytes()))
+}
+
+pub fn init_oidc_provider() -> web::Data<OidcProvider> {
+    let settings = settings();
+    let oidc_settings = &settings.oidc;
+    let config = match (&oidc_settings.issuer, &oidc_settings.client_id) {
+        (Some(issuer), Some(client_id)) => {
+            log::info!("Single sign-on enabled with provider `{}`", issuer);
+            Some(OidcConfig {
+                issuer: issuer.clone(), 
+                client_id: client_id.clone(), 
+                client_secret: oidc_settings.client_secret.clone().unwrap_or_default(), 
+                redirect_url: oidc_settings.redirect_url
+                    .clone()
+                    .unwrap_or(format!("{}/oidc/callback", settings.server.service_url))
+            })
+        }, 
+        _ => None
+    };
+
+    web::Data::new(OidcProvider {
+        config, 
+        http_client: reqwest::Client::new(), 
+        metadata: RwLock::new(None), 
+        signing_keys: RwLock::new(HashMap::new())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{web, App, HttpResponse, HttpServer};
+    use jsonwebtoken::{EncodingKey, Header};
+    use openssl::rsa::Rsa;
+    use serde_json::{json, Value};
+    use std::sync::Mutex;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+    use crate::settings::init_test_settings;
+
+    const ISSUER: &str = "https://sso.example.org";
+    const CLIENT_ID: &str = "routine";
+    const NONCE: &str = "login-nonce";
+
+    struct ProviderKey {
+        kid: String, 
+        encoding_key: EncodingKey, 
+        jwk: Value
+    }
+
+    fn provider_key(kid: &str) -> ProviderKey {
+        let rsa = Rsa::generate(2048).unwrap();
+        let encode = |bytes: Vec<u8>| general_purpose::URL_SAFE_NO_PAD.encode(bytes);
+        ProviderKey {
+            kid: kid.to_string(), 
+            encoding_key: EncodingKey::from_rsa_pem(&rsa.private_key_to_pem().unwrap()).unwrap(), 
+            jwk: json!({ "kty": "RSA", "kid": kid, "alg": "RS256", "n": encode(rsa.n().to_vec()), "e": encode(rsa.e().to_vec()) })
+        }
+    }
+
+    // JWKS endpoint serving whatever keys are set, counts requests
+    struct MockJwks {
+        keys: Mutex<Vec<Value>>, 
+        requests: AtomicUsize
+    }
+
+    async fn start_provider(jwks: web::Data<MockJwks>) -> String {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = HttpServer::new(move || {
+            App::new()
+                .app_data(jwks.clone())
+                .route("/jwks", web::get().to(|jwks: web::Data<MockJwks>| async move {
+                    jwks.requests.fetch_add(1, Ordering::SeqCst);
+                    HttpResponse::Ok().json(json!({ "keys": *jwks.keys.lock().unwrap() }))
+                }))
+        })
+            .workers(1)
+            .listen(listener)
+            .unwrap()
+            .run();
+        actix_web::rt::spawn(server);
+        format!("http://{}/jwks", address)
+    }
+
+    fn provider(jwks_uri: &str, algorithms: &[&str]) -> (OidcProvider, OidcConfig, ProviderMetadata) {
+        let provider = OidcProvider {
+            config: None, 
+            http_client: reqwest::Client::new(), 
+            metadata: RwLock::new(None), 
+            signing_keys: RwLock::new(HashMap::new())
+        };
+        let config = OidcConfig {
+            issuer: ISSUER.to_string(), 
+            client_id: CLIENT_ID.to_string(), 
+            client_secret: String::from("client_secret"), 
+            redirect_url: String::from("https://routine.example.org/oidc/callback")
+        };
+        let metadata = ProviderMetadata {
+            issuer: ISSUER.to_string(), 
+            authorization_endpoint: format!("{}/authorize", ISSUER), 
+            token_endpoint: format!("{}/token", ISSUER), 
+            jwks_uri: jwks_uri.to_string(), 
+            id_token_signing_alg_values_supported: algorithms.iter().map(|algorithm| algorithm.to_string()).collect()
+        };
+        (provider, config, metadata)
+    }
+
+    fn id_token(algorithm: Algorithm, kid: Option<&str>, encoding_key: &EncodingKey) -> String {
+        let mut header = Header::new(algorithm);
+        header.kid = kid.map(String::from);
+        let expires_at = chrono::offset::Utc::now().naive_utc().timestamp() + 300;
+        let claims = json!({ "iss": ISSUER, "aud": CLIENT_ID, "sub": "subject", "exp": expires_at, "nonce": NONCE });
+        jsonwebtoken::encode(&header, &claims, encoding_key).unwrap()
+    }
+
+    #[test]
+    fn only_announced_asymmetric_algorithms_are_accepted() {
+        let (_, _, metadata) = provider("http://127.0.0.1:1/jwks", &["HS256", "RS256", "PS256", "ES256", "none"]);
+        assert_eq!(metadata.signing_algorithms(), vec![Algorithm::RS256, Algorithm::ES256]);
+
+        let metadata: ProviderMetadata = serde_json::from_value(json!({
+            "issuer": ISSUER, 
+            "authorization_endpoint": "a", 
+            "token_endpoint": "t", 
+            "jwks_uri": "j"
+        })).unwrap();
+        assert_eq!(metadata.signing_algorithms(), vec![Algorithm::RS256]);
+    }
+
+    #[actix_web::test]
+    async fn token_signed_with_client_secret_is_rejected() {
+        init_test_settings();
+        let (provider, config, metadata) = provider("http://127.0.0.1:1/jwks", &["RS256", "HS256"]);
+        let token = id_token(Algorithm::HS256, None, &EncodingKey::from_secret(config.client_secret.as_bytes()));
+
+        let error = provider.verify_id_token(&config, &metadata, &token, NONCE).await.err().unwrap();
+        assert!(error.contains("not allowed algorithm"), "{}", error);
+    }
+
+    #[actix_web::test]
+    async fn keys_are_cached_and_read_again_for_unknown_kid() {
+        init_test_settings();
+        let first_key = provider_key("first");
+        let jwks = web::Data::new(MockJwks {
+            keys: Mutex::new(vec![first_key.jwk.clone()]), 
+            requests: AtomicUsize::new(0)
+        });
+        let jwks_uri = start_provider(jwks.clone()).await;
+        let (provider, config, metadata) = provider(&jwks_uri, &["RS256"]);
+
+        let token = id_token(Algorithm::RS256, Some(&first_key.kid), &first_key.encoding_key);
+        assert!(provider.verify_id_token(&config, &metadata, &token, NONCE).await.is_ok());
+        assert!(provider.verify_id_token(&config, &metadata, &token, NONCE).await.is_ok());
+        assert_eq!(jwks.requests.load(Ordering::SeqCst), 1);
+
+        // provider rotates its key
+        let second_key = provider_key("second");
+        *jwks.keys.lock().unwrap() = vec![second_key.jwk.clone()];
+        let token = id_token(Algorithm::RS256, Some(&second_key.kid), &second_key.encoding_key);
+        assert!(provider.verify_id_token(&config, &metadata, &token, NONCE).await.is_ok());
+        assert_eq!(jwks.requests.load(Ordering::SeqCst), 2);
+
+        let token = id_token(Algorithm::RS256, Some("unknown"), &second_key.encoding_key);
+        let error = provider.verify_id_token(&config, &metadata, &token, NONCE).await.err().unwrap();
+        assert!(error.contains("unknown key"), "{}", error);
+    }
+
+    #[actix_web::test]
+    async fn algorithm_not_announced_by_provider_is_rejected() {
+        init_test_settings();
+        let key = provider_key("first");
+        let jwks = web::Data::new(MockJwks {
+            keys: Mutex::new(vec![key.jwk.clone()]), 
+            requests: AtomicUsize::new(0)
+        });
+        let jwks_uri = start_provider(jwks.clone()).await;
+        let (provider, config, metadata) = provider(&jwks_uri, &["ES256"]);
+
+        let token = id_token(Algorithm::RS256, Some(&key.kid), &key.encoding_key);
+        let error = provider.verify_id_token(&config, &metadata, &token, NONCE).await.err().unwrap();
+        assert!(error.contains("not allowed algorithm"), "{}", error);
+        assert_eq!(jwks.requests.load(Ordering::SeqCst), 0);
+    }
+}
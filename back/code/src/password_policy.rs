@@ -0,0 +1,282 @@
+use actix_web::web;
+use serde::{Deserialize, Serialize};
+use sha1::{Digest, Sha1};
+use std::fs;
+use std::path::PathBuf;
+
+use crate::settings::settings;
+
+// Password policy is configured by the `password_policy` settings section: length limits in characters,
+// required character classes, whether non-ASCII characters are accepted and an optional directory
+// with breached password hashes split by the k-anonymity range, as published by Have I Been Pwned:
+// file `ABCDE.txt` holds lines `SUFFIX:COUNT` for SHA-1 hashes starting with `ABCDE`.
+// Spaces are allowed, so passphrases pass as long as they are long enough.
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum CharacterClass {
+    Lowercase, 
+    Uppercase, 
+    Digit, 
+    Symbol
+}
+
+pub struct PasswordPolicy {
+    min_length: usize, 
+    max_length: usize, 
+    required_classes: Vec<CharacterClass>, 
+    allow_unicode: bool, 
+    breached_hashes_dir: Option<PathBuf>
+}
+
+#[derive(Serialize, PartialEq, Debug)]
+#[serde(tag = "rule", rename_all = "snake_case")]
+pub enum PasswordViolation {
+    TooShort { min_length: usize }, 
+    TooLong { max_length: usize }, 
+    MissingLowercase, 
+    MissingUppercase, 
+    MissingDigit, 
+    MissingSymbol, 
+    NonAsciiCharacter, 
+    ControlCharacter, 
+    SameAsEmail, 
+    SameAsName, 
+    Breached
+}
+
+impl PasswordViolation {
+    pub fn description(&self) -> String {
+        match self {
+            PasswordViolation::TooShort { min_length } => format!("Password should contain at least {} signs", min_length), 
+            PasswordViolation::TooLong { max_length } => format!("Password couldn't be longer than {} signs", max_length), 
+            PasswordViolation::MissingLowercase => "Password should contain a lowercase letter".to_string(), 
+            PasswordViolation::MissingUppercase => "Password should contain an uppercase letter".to_string(), 
+            PasswordViolation::MissingDigit => "Password should contain a digit".to_string(), 
+            PasswordViolation::MissingSymbol => "Password should contain a symbol".to_string(), 
+            PasswordViolation::NonAsciiCharacter => "Password should contain latin letters, digits and symbols only".to_string(), 
+            PasswordViolation::ControlCharacter => "Password couldn't contain control characters".to_string(), 
+            PasswordViolation::SameAsEmail => "Password couldn't be the same as email".to_string(), 
+            PasswordViolation::SameAsName => "Password couldn't be the same as user name".to_string(), 
+            PasswordViolation::Breached => "Password was found in a data breach, choose another one".to_string()
+        }
+    }
+}
+
+impl PasswordPolicy {
+    // returns every rule the password breaks, empty if it's acceptable;
+    // email and name of the account are compared with the password when known
+    pub fn check(&self, password: &str, email: Option<&str>, name: Option<&str>) -> Vec<PasswordViolation> {
+        let mut violations = Vec::new();
+
+        let length = password.chars().count();
+        if length < self.min_length {
+            violations.push(PasswordViolation::TooShort { min_length: self.min_length });
+        }
+        if length > self.max_length {
+            violations.push(PasswordViolation::TooLong { max_length: self.max_length });
+        }
+
+        for class in &self.required_classes {
+            if !password.chars().any(|sign| character_class(sign) == Some(*class)) {
+                violations.push(match class {
+                    CharacterClass::Lowercase => PasswordViolation::MissingLowercase, 
+                    CharacterClass::Uppercase => PasswordViolation::MissingUppercase, 
+                    CharacterClass::Digit => PasswordViolation::MissingDigit, 
+                    CharacterClass::Symbol => PasswordViolation::MissingSymbol
+                });
+            }
+        }
+        if !self.allow_unicode && !password.is_ascii() {
+            violations.push(PasswordViolation::NonAsciiCharacter);
+        }
+        if password.chars().any(char::is_control) {
+            violations.push(PasswordViolation::ControlCharacter);
+        }
+
+        let normalized_password = password.trim().to_lowercase();
+        if let Some(email) = email {
+            let email = email.trim().to_lowercase();
+            let local_part = email.split('@').next().unwrap_or_default();
+            if normalized_password == email || normalized_password == local_part {
+                violations.push(PasswordViolation::SameAsEmail);
+            }
+        }
+        if let Some(name) = name {
+            if normalized_password == name.trim().to_lowercase() {
+                violations.push(PasswordViolation::SameAsName);
+            }
+        }
+
+        // no reason to look up passwords rejected anyway
+        if violations.is_empty() && self.is_breached(password) {
+            violations.push(PasswordViolation::Breached);
+        }
+        violations
+    }
+
+    fn is_breached(&self, password: &str) -> bool {
+        let breached_hashes_dir = match &self.breached_hashes_dir {
+            Some(breached_hashes_dir) => breached_hashes_dir, 
+            None => return false
+        };
+
+        let password_hash = format!("{:X}", Sha1::digest(password.as_bytes()));
+        let (prefix, suffix) = password_hash.split_at(5);
+        let range_file = breached_hashes_dir.join(format!("{}.txt", prefix));
+
+        match fs::read_to_string(&range_file) {
+            Ok(range) => range
+                .lines()
+                .filter_map(|line| line.trim().split_once(':'))
+                .any(|(hash_suffix, count)| {
+                    hash_suffix.eq_ignore_ascii_case(suffix) && count.trim().parse::<u64>().is_ok_and(|count| count > 0)
+                }), 
+            Err(error) => {
+                log::error!("Unable to read breached passwords range `{}`: {:?}", range_file.display(), error);
+                false
+            }
+        }
+    }
+}
+
+// letters of scripts without case count as lowercase
+fn character_class(sign: char) -> Option<CharacterClass> {
+    if sign.is_uppercase() {
+        Some(CharacterClass::Uppercase)
+    } else if sign.is_alphabetic() {
+        Some(CharacterClass::Lowercase)
+    } else if sign.is_numeric() {
+        Some(CharacterClass::Digit)
+    } else if !sign.is_control() {
+        Some(CharacterClass::Symbol)
+    } else {
+        None
+    }
+}
+
+// settings are validated at startup, so the limits and the directory are usable as they are
+pub fn init_password_policy() -> web::Data<PasswordPolicy> {
+    let policy_settings = &settings().password_policy;
+    if let Some(breached_hashes_dir) = &policy_settings.breached_hashes_dir {
+        log::info!("Passwords are checked against breached hashes in `{}`", breached_hashes_dir.display());
+    }
+
+    web::Data::new(PasswordPolicy {
+        min_length: policy_settings.min_length, 
+        max_length: policy_settings.max_length, 
+        required_classes: policy_settings.required_classes.clone(), 
+        allow_unicode: policy_settings.allow_unicode, 
+        breached_hashes_dir: policy_settings.breached_hashes_dir.clone()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy() -> PasswordPolicy {
+        PasswordPolicy {
+            min_length: 8, 
+            max_length: 16, 
+            required_classes: Vec::new(), 
+            allow_unicode: true, 
+            breached_hashes_dir: None
+        }
+    }
+
+    // range files in the Have I Been Pwned layout, removed when dropped
+    struct BreachedRanges {
+        dir: PathBuf
+    }
+
+    impl BreachedRanges {
+        fn with_passwords(passwords: &[(&str, u64)]) -> Self {
+            let dir = std::env::temp_dir().join(format!("breached-ranges-{}", uuid::Uuid::new_v4()));
+            fs::create_dir(&dir).unwrap();
+            for (password, count) in passwords {
+                let password_hash = format!("{:X}", Sha1::digest(password.as_bytes()));
+                let (prefix, suffix) = password_hash.split_at(5);
+                let range = format!("0000000000000000000000000000000000A:3\r\n{}:{}\r\n", suffix, count);
+                fs::write(dir.join(format!("{}.txt", prefix)), range).unwrap();
+            }
+            BreachedRanges { dir }
+        }
+    }
+
+    impl Drop for BreachedRanges {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.dir);
+        }
+    }
+
+    #[test]
+    fn length_is_counted_in_characters() {
+        let policy = policy();
+        // 8 characters, 16 bytes
+        assert!(policy.check("пароль12", None, None).is_empty());
+        // 17 characters
+        assert_eq!(policy.check("ééééééééééééééééé", None, None), vec![PasswordViolation::TooLong { max_length: 16 }]);
+        assert_eq!(policy.check("short", None, None), vec![PasswordViolation::TooShort { min_length: 8 }]);
+        assert!(policy.check("exactly16signs!!", None, None).is_empty());
+    }
+
+    #[test]
+    fn every_required_class_is_checked() {
+        let policy = PasswordPolicy {
+            required_classes: vec![
+                CharacterClass::Lowercase, CharacterClass::Uppercase, CharacterClass::Digit, CharacterClass::Symbol
+            ], 
+            ..policy()
+        };
+        assert!(policy.check("Passw0rd!", None, None).is_empty());
+        assert_eq!(policy.check("PASSW0RD!", None, None), vec![PasswordViolation::MissingLowercase]);
+        assert_eq!(policy.check("passw0rd!", None, None), vec![PasswordViolation::MissingUppercase]);
+        assert_eq!(policy.check("Password!", None, None), vec![PasswordViolation::MissingDigit]);
+        assert_eq!(policy.check("Passw0rds", None, None), vec![PasswordViolation::MissingSymbol]);
+        // spaces count as symbols, so passphrases pass
+        assert!(policy.check("Correct h0rse", None, None).is_empty());
+        assert_eq!(
+            policy.check("        ", None, None), 
+            vec![PasswordViolation::MissingLowercase, PasswordViolation::MissingUppercase, PasswordViolation::MissingDigit]
+        );
+    }
+
+    #[test]
+    fn unicode_can_be_refused() {
+        let policy = PasswordPolicy { allow_unicode: false, ..policy() };
+        assert_eq!(policy.check("пароль123", None, None), vec![PasswordViolation::NonAsciiCharacter]);
+        assert!(policy.check("password123", None, None).is_empty());
+    }
+
+    #[test]
+    fn control_characters_are_refused() {
+        let policy = policy();
+        assert_eq!(policy.check("pass\tword", None, None), vec![PasswordViolation::ControlCharacter]);
+        assert_eq!(policy.check("pass\u{0}word", None, None), vec![PasswordViolation::ControlCharacter]);
+    }
+
+    #[test]
+    fn password_differs_from_email_and_name() {
+        let policy = policy();
+        let email = Some("Ann.Smith@ex.io");
+        assert_eq!(policy.check("ann.smith", email, None), vec![PasswordViolation::SameAsEmail]);
+        assert_eq!(policy.check("ANN.SMITH@ex.io ", email, None), vec![PasswordViolation::SameAsEmail]);
+        assert_eq!(policy.check("Ann Smith", None, Some("ann smith")), vec![PasswordViolation::SameAsName]);
+        assert!(policy.check("ann.smith1", email, Some("Ann Smith")).is_empty());
+    }
+
+    #[test]
+    fn breached_passwords_are_found_by_range() {
+        let ranges = BreachedRanges::with_passwords(&[("password123", 250), ("unlisted pass", 0)]);
+        let policy = PasswordPolicy { breached_hashes_dir: Some(ranges.dir.clone()), ..policy() };
+
+        assert_eq!(policy.check("password123", None, None), vec![PasswordViolation::Breached]);
+        // entries with zero count are padding
+        assert!(policy.check("unlisted pass", None, None).is_empty());
+        // range file is missing
+        assert!(policy.check("never breached", None, None).is_empty());
+        // rejected passwords aren't looked up
+        assert_eq!(policy.check("password123", Some("password123@example.com"), None), vec![PasswordViolation::SameAsEmail]);
+    }
+}
@@ -0,0 +1,122 @@
+use sqlx::{self, Postgres, Pool, Row};
+use uuid::Uuid;
+
+use crate::{APP_SCHEMA, USERS_TABLE, PASSWORD_RESETS_TABLE};
+use crate::convertations::AsHash;
+use crate::settings::settings;
+use crate::tools::generate_random_token;
+
+// Reset tokens are random strings sent by email, only their hashes are stored.
+// A token can be used once and only before it expires, requesting a new one
+// invalidates the previous unused tokens of the user.
+
+pub async fn create_password_reset(
+    db_link: &Pool<Postgres>, 
+    user_id: Uuid) -> Result<String, sqlx::Error> {
+
+    let mut transaction = db_link.begin().await?;
+
+    let delete_query = format!(
+        "DELETE FROM {}.{}
+          WHERE user_id = $1
+            AND used_at IS NULL", 
+        APP_SCHEMA, 
+        PASSWORD_RESETS_TABLE
+    );
+    sqlx::query(&delete_query)
+        .bind(user_id)
+        .execute(&mut transaction)
+        .await?;
+
+    let reset_token = generate_random_token();
+    let insert_query = format!(
+        "INSERT INTO {}.{} (user_id, token_hash, expires_at)
+              VALUES ($1, $2, now() + make_interval(secs => $3))", 
+        APP_SCHEMA, 
+        PASSWORD_RESETS_TABLE
+    );
+    sqlx::query(&insert_query)
+        .bind(user_id)
+        .bind(reset_token.as_hash())
+        .bind(settings().tokens.password_reset_lifetime as f64)
+        .execute(&mut transaction)
+        .await?;
+
+    transaction.commit().await?;
+    Ok(reset_token)
+}
+
+// email and name of the active account the unused token was issued for,
+// lets the new password be checked against them before the token is consumed
+pub async fn get_password_reset_owner(
+    db_link: &Pool<Postgres>, 
+    reset_token: &str) -> Result<Option<(Option<String>, Option<String>)>, sqlx::Error> {
+
+    let query = format!(
+        "SELECT
+            c.email, c.name
+           FROM {APP_SCHEMA}.{PASSWORD_RESETS_TABLE} r
+           JOIN {APP_SCHEMA}.{USERS_TABLE} c ON c.id = r.user_id
+          WHERE r.token_hash = $1
+            AND r.used_at IS NULL
+            AND r.expires_at > now()
+            AND c.status_id = 1"
+    );
+    sqlx::query(&query)
+        .bind(reset_token.to_string().as_hash())
+        .map(|row| (row.get("email"), row.get("name")))
+        .fetch_optional(db_link)
+        .await
+}
+
+// consumes the token and sets the new password hash, returns owner of the token
+pub async fn reset_password(
+    db_link: &Pool<Postgres>, 
+    reset_token: &str, 
+    password_hash: &str) -> Result<Option<Uuid>, sqlx::Error> {
+
+    let mut transaction = db_link.begin().await?;
+
+    let consume_query = format!(
+        "UPDATE {}.{}
+            SET used_at = now()
+          WHERE token_hash = $1
+            AND used_at IS NULL
+            AND expires_at > now()
+      RETURNING user_id", 
+        APP_SCHEMA, 
+        PASSWORD_RESETS_TABLE
+    );
+    let consumed = sqlx::query(&consume_query)
+        .bind(reset_token.to_string().as_hash())
+        .map(|row| row.get::<Uuid, &str>("user_id"))
+        .fetch_optional(&mut transaction)
+        .await?;
+
+    let user_id = match consumed {
+        Some(user_id) => user_id, 
+        None => return Ok(None)
+    };
+
+    let update_query = format!(
+        "UPDATE {}.{}
+            SET passwd = $2
+          WHERE id = $1
+            AND status_id = 1
+      RETURNING id", 
+        APP_SCHEMA, 
+        USERS_TABLE
+    );
+    let updated = sqlx::query(&update_query)
+        .bind(user_id)
+        .bind(password_hash)
+        .fetch_optional(&mut transaction)
+        .await?;
+
+    if updated.is_none() {
+        return Ok(None);
+    }
+
+    transaction.commit().await?;
+    Ok(Some(user_id))
+}
@@ -0,0 +1,79 @@
+use actix_web::web;
+use argon2::{
+    password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString}, 
+    Argon2
+};
+
+use crate::convertations::AsHash;
+
+// Passwords are stored as Argon2id PHC strings (`$argon2id$v=19$...`) with a random salt per hash. 
+// Accounts created before that keep a bare SHA-256 hex digest until the next successful login.
+// Argon2 is slow on purpose, so hashing and verification run on the blocking thread pool
+// instead of the worker serving requests.
+
+pub enum PasswordCheck {
+    Valid, 
+    ValidLegacy, 
+    Invalid
+}
+
+impl PasswordCheck {
+    pub fn is_valid(&self) -> bool {
+        !matches!(self, PasswordCheck::Invalid)
+    }
+
+    pub fn needs_rehash(&self) -> bool {
+        matches!(self, PasswordCheck::ValidLegacy)
+    }
+}
+
+pub async fn hash_password(password: &str) -> String {
+    let password = password.to_string();
+    web::block(move || hash_with_salt(&password))
+        .await
+        .expect("Unable to hash password")
+}
+
+pub async fn verify_password(password: &str, stored_hash: &str) -> PasswordCheck {
+    let (password, stored_hash) = (password.to_string(), stored_hash.to_string());
+    web::block(move || check_password(&password, &stored_hash))
+        .await
+        .unwrap_or_else(|blocking_error| {
+            log::error!("Password verification failed: {:?}", blocking_error);
+            PasswordCheck::Invalid
+        })
+}
+
+fn hash_with_salt(password: &str) -> String {
+    let salt = SaltString::generate(&mut OsRng);
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .expect("Unable to hash password")
+        .to_string()
+}
+
+fn check_password(password: &str, stored_hash: &str) -> PasswordCheck {
+    if is_legacy_hash(stored_hash) {
+        if password.to_string().as_hash() == stored_hash {
+            return PasswordCheck::ValidLegacy;
+        }
+        return PasswordCheck::Invalid;
+    }
+
+    match PasswordHash::new(stored_hash) {
+        Ok(parsed_hash) => {
+            match Argon2::default().verify_password(password.as_bytes(), &parsed_hash) {
+                Ok(_) => PasswordCheck::Valid, 
+                Err(_) => PasswordCheck::Invalid
+            }
+        }, 
+        Err(hash_error) => {
+            log::error!("Unable to parse stored password hash: {:?}", hash_error);
+            PasswordCheck::Invalid
+        }
+    }
+}
+
+fn is_legacy_hash(stored_hash: &str) -> bool {
+    stored_hash.len() == 64 && stored_hash.chars().all(|character| character.is_ascii_hexdigit())
+}
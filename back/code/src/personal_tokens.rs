@@ -0,0 +1,177 @@
+use sqlx::{self, Postgres, Pool, Row};
+use uuid::Uuid;
+
+use crate::{APP_SCHEMA, USERS_TABLE, PERSONAL_TOKENS_TABLE, PERSONAL_TOKEN_PREFIX};
+use crate::convertations::AsHash;
+use crate::models::StoredPersonalToken;
+use crate::tools::generate_random_token;
+
+// Personal access tokens are long-lived credentials for scripts and integrations.
+// They start with PERSONAL_TOKEN_PREFIX, only their hashes are stored, and each one
+// is limited to the scopes chosen on creation. Account, session and token management
+// endpoints are never available with a personal token.
+
+pub const SCOPE_BOARDS_READ: &str = "boards:read";
+pub const SCOPE_BOARDS_WRITE: &str = "boards:write";
+pub const SCOPE_TASKS_READ: &str = "tasks:read";
+pub const SCOPE_TASKS_WRITE: &str = "tasks:write";
+
+pub const AVAILABLE_SCOPES: [&str; 4] = [
+    SCOPE_BOARDS_READ, SCOPE_BOARDS_WRITE, SCOPE_TASKS_READ, SCOPE_TASKS_WRITE
+];
+
+pub struct AuthenticatedToken {
+    pub user_id: Uuid, 
+    pub scopes: Vec<String>
+}
+
+pub fn is_personal_token(token: &str) -> bool {
+    token.starts_with(PERSONAL_TOKEN_PREFIX)
+}
+
+// Scope needed to call the endpoint with a personal token, attached to the resource as app data
+// and checked by the `AuthUser` extractor. Endpoints without one aren't available with a personal token.
+#[derive(Clone, Copy)]
+pub struct RequiredScope(pub &'static str);
+
+pub async fn count_personal_tokens(
+    db_link: &Pool<Postgres>, 
+    user_id: Uuid) -> Result<i64, sqlx::Error> {
+
+    let query = format!(
+        "SELECT
+            count(*) AS tokens_count
+           FROM {}.{}
+          WHERE user_id = $1
+            AND status_id = 0
+            AND (expires_at IS NULL OR expires_at > now())", 
+        APP_SCHEMA, 
+        PERSONAL_TOKENS_TABLE
+    );
+    sqlx::query(&query)
+        .bind(user_id)
+        .map(|row| row.get("tokens_count"))
+        .fetch_one(db_link)
+        .await
+}
+
+// returns stored token and the token itself, which is shown to the user only once
+pub async fn create_personal_token(
+    db_link: &Pool<Postgres>, 
+    user_id: Uuid, 
+    name: &str, 
+    scopes: &[String], 
+    expires_in: Option<i64>) -> Result<(StoredPersonalToken, String), sqlx::Error> {
+
+    let personal_token = format!("{}{}", PERSONAL_TOKEN_PREFIX, generate_random_token());
+    let query = format!(
+        "INSERT INTO {}.{} (user_id, name, token_hash, scopes, status_id, expires_at)
+              VALUES ($1, $2, $3, $4, 0, now() + make_interval(secs => $5))
+           RETURNING id, name, scopes, created_at, expires_at, last_used_at", 
+        APP_SCHEMA, 
+        PERSONAL_TOKENS_TABLE
+    );
+    let stored_token = sqlx::query(&query)
+        .bind(user_id)
+        .bind(name)
+        .bind(personal_token.as_hash())
+        .bind(scopes)
+        .bind(expires_in.map(|expires_in| expires_in as f64))
+        .map(|row| {
+            StoredPersonalToken {
+                id: row.get("id"), 
+                name: row.get("name"), 
+                scopes: row.get("scopes"), 
+                created_at: row.get("created_at"), 
+                expires_at: row.get("expires_at"), 
+                last_used_at: row.get("last_used_at")
+            }
+        })
+        .fetch_one(db_link)
+        .await?;
+
+    Ok((stored_token, personal_token))
+}
+
+pub async fn get_personal_tokens(
+    db_link: &Pool<Postgres>, 
+    user_id: Uuid) -> Result<Vec<StoredPersonalToken>, sqlx::Error> {
+
+    let query = format!(
+        "SELECT
+            id, name, scopes, created_at, expires_at, last_used_at
+           FROM {}.{}
+          WHERE user_id = $1
+            AND status_id = 0
+            AND (expires_at IS NULL OR expires_at > now())
+          ORDER BY created_at", 
+        APP_SCHEMA, 
+        PERSONAL_TOKENS_TABLE
+    );
+    sqlx::query(&query)
+        .bind(user_id)
+        .map(|row| {
+            StoredPersonalToken {
+                id: row.get("id"), 
+                name: row.get("name"), 
+                scopes: row.get("scopes"), 
+                created_at: row.get("created_at"), 
+                expires_at: row.get("expires_at"), 
+                last_used_at: row.get("last_used_at")
+            }
+        })
+        .fetch_all(db_link)
+        .await
+}
+
+pub async fn revoke_personal_token(
+    db_link: &Pool<Postgres>, 
+    user_id: Uuid, 
+    token_id: i32) -> Result<bool, sqlx::Error> {
+
+    let query = format!(
+        "UPDATE {}.{}
+            SET status_id = 1
+          WHERE id = $1
+            AND user_id = $2
+            AND status_id = 0
+      RETURNING id", 
+        APP_SCHEMA, 
+        PERSONAL_TOKENS_TABLE
+    );
+    let revoked = sqlx::query(&query)
+        .bind(token_id)
+        .bind(user_id)
+        .fetch_all(db_link)
+        .await?;
+
+    Ok(!revoked.is_empty())
+}
+
+// finds active token of an active user and marks it as used
+pub async fn authenticate_personal_token(
+    db_link: &Pool<Postgres>, 
+    personal_token: &str) -> Result<Option<AuthenticatedToken>, sqlx::Error> {
+
+    let query = format!(
+        "UPDATE {APP_SCHEMA}.{PERSONAL_TOKENS_TABLE} p
+            SET last_used_at = now()
+           FROM {APP_SCHEMA}.{USERS_TABLE} c
+          WHERE c.id = p.user_id
+            AND p.token_hash = $1
+            AND p.status_id = 0
+            AND (p.expires_at IS NULL OR p.expires_at > now())
+            AND c.status_id = 1
+      RETURNING p.user_id, p.scopes"
+    );
+    sqlx::query(&query)
+        .bind(personal_token.to_string().as_hash())
+        .map(|row| {
+            AuthenticatedToken {
+                user_id: row.get("user_id"), 
+                scopes: row.get("scopes")
+            }
+        })
+        .fetch_optional(db_link)
+        .await
+}
@@ -1,172 +1,331 @@
-use redis::{Commands, RedisResult, RedisError};
+use redis::{ErrorKind, RedisResult, RedisError};
+use crate::cache::{CacheDB, CacheStore};
 use crate::models::{User, Board, Task};
-use serde_json;
+use crate::oidc::OidcLoginState;
+use serde::{de::DeserializeOwned, Serialize};
 use uuid::Uuid;
 
-use crate::{STORED_DATA_EXPIRATION_TIME, USER_DATA_EXPIRATION_TIME};
+use crate::settings::settings;
 
-// User handlers
-
-pub fn set_email_userid_map_to_redis(
-    conn: &mut redis::Connection, 
-    email: String, 
-    user_id: Uuid) -> RedisResult<()> {
-    let key = format!("user_email:{}:id", email);
+// Cached data is read through `CacheStore`, so it's served by the in-memory cache while Redis is down.
+// Security state takes `CacheDB` and goes through its security store, which fails instead.
+// Missing or unreadable cached data is returned as an error and treated by callers as cache miss.
 
-    conn.set(&key, user_id.to_string())?;
-    conn.expire(&key, USER_DATA_EXPIRATION_TIME)?;
+fn cache_miss() -> RedisError {
+    RedisError::from((ErrorKind::TypeError, "Value is not cached"))
+}
 
-    Ok(())
+fn encode<T: Serialize>(value: &T) -> RedisResult<String> {
+    serde_json::to_string(value)
+        .map_err(|_| RedisError::from((ErrorKind::TypeError, "Value couldn't be encoded")))
 }
 
-pub fn check_email_in_redis(
-    conn: &mut redis::Connection, 
-    email: &str) -> RedisResult<Uuid> {
-    let key = format!("user_email:{}:id", email);
-    
-    let user_id_from_redis: String = conn.get(key)?; 
-    match Uuid::parse_str(&user_id_from_redis) {
-        Ok(user_id) => {
-            Ok(user_id)
-        }, 
-        Err(_) => {
-            let error_message: &'static str = "Redis db error";
-            Err(RedisError::from(std::io::Error::new(std::io::ErrorKind::Other, error_message)))
-        }
-    }
+fn decode<T: DeserializeOwned>(value: &str) -> RedisResult<T> {
+    serde_json::from_str(value)
+        .map_err(|_| RedisError::from((ErrorKind::TypeError, "Cached value couldn't be decoded")))
 }
 
-pub fn put_user_data_to_redis(
-    conn: &mut redis::Connection, 
+// User handlers
+
+pub async fn put_user_data_to_redis(
+    cache: &impl CacheStore, 
     user: User, 
     lifetime: Option<usize>) -> RedisResult<()> {
     let key = format!("user_id:{}:data", user.id);
 
-    let json = serde_json::to_string(&user).unwrap();
-    conn.set(&key, json)?;
-    match lifetime {
-        Some(custom_time) => {
-            conn.expire(&key, custom_time)?;
-        }, 
-        None => {
-            conn.expire(&key, USER_DATA_EXPIRATION_TIME)?;
-        }
-    }
-    
-    set_email_userid_map_to_redis(conn, user.email.clone(), user.id)?;
+    let json = encode(&user)?;
+    cache.set(&key, &json, lifetime.unwrap_or(settings().cache.user_data_lifetime)).await?;
 
     Ok(())
 }
 
-pub fn get_user_data_by_email_from_redis(
-    conn: &mut redis::Connection, 
-    email: &str) -> RedisResult<User> {
-    let user_id = check_email_in_redis(conn, email)?;
-    let user = get_user_data_by_id_from_redis(conn, user_id)?;
+pub async fn get_user_data_by_id_from_redis(
+    cache: &impl CacheStore, 
+    user_id: Uuid) -> RedisResult<User> {
+    let key = format!("user_id:{}:data", user_id);
 
-    Ok(user)
+    let user_data = cache.get(&key).await?.ok_or_else(cache_miss)?;
+    decode(&user_data)
 }
 
-pub fn get_user_data_by_id_from_redis(
-    conn: &mut redis::Connection, 
-    user_id: Uuid) -> RedisResult<User> {
+pub async fn drop_user_data_from_redis(
+    cache: &impl CacheStore, 
+    user_id: Uuid) {
     let key = format!("user_id:{}:data", user_id);
 
-    let user_data: String = conn.get(&key)?;
-    let user: User = serde_json::from_str(&user_data).unwrap();
+    let _ = cache.delete(&[key]).await;
+}
+
+// drops everything cached for the user, used when account is deleted
+pub async fn purge_user_data_from_redis(
+    cache: &impl CacheStore, 
+    user_id: Uuid, 
+    email: &str, 
+    board_ids: &[i32]) {
+    let mut keys = vec![
+        format!("user_id:{}:data", user_id), 
+        format!("user_email:{}:id", email), 
+        format!("user:{}:boards", user_id), 
+        format!("login_failures:email:{}", email.to_lowercase()), 
+        format!("account_lockout:{}", email.to_lowercase()), 
+        format!("verification_resent:{}", email)
+    ];
+    for board_id in board_ids {
+        keys.push(format!("board:{}:tasks", board_id));
+    }
 
-    Ok(user)
+    let _ = cache.delete(&keys).await;
 }
 
-pub fn drop_user_data_from_redis(
-    conn: &mut redis::Connection, 
-    user_id: Uuid) {
-    let key = format!("user_id:{}:data", user_id);
+// Token handlers
+
+pub async fn revoke_token_in_redis(
+    cache: &CacheDB, 
+    token_id: Uuid, 
+    lifetime: usize) -> RedisResult<()> {
+    let key = format!("revoked_token:{}", token_id);
+
+    cache.security().set(&key, "1", lifetime).await
+}
+
+pub async fn check_token_revoked_in_redis(
+    cache: &CacheDB, 
+    token_id: Uuid) -> RedisResult<bool> {
+    let key = format!("revoked_token:{}", token_id);
+
+    cache.security().exists(&key).await
+}
+
+pub async fn revoke_session_in_redis(
+    cache: &CacheDB, 
+    session_id: Uuid) -> RedisResult<()> {
+    let key = format!("revoked_session:{}", session_id);
+
+    // refresh tokens of the session are revoked in postgres, 
+    // so only already issued access tokens have to be rejected
+    cache.security().set(&key, "1", settings().tokens.access_token_lifetime as usize).await
+}
+
+pub async fn check_session_revoked_in_redis(
+    cache: &CacheDB, 
+    session_id: Uuid) -> RedisResult<bool> {
+    let key = format!("revoked_session:{}", session_id);
+
+    cache.security().exists(&key).await
+}
+
+// token generation is set after it's bumped in postgres, so it always replaces cached value
+pub async fn put_token_generation_to_redis(
+    cache: &CacheDB, 
+    user_id: Uuid, 
+    token_generation: i64) -> RedisResult<()> {
+    let key = format!("user_id:{}:token_generation", user_id);
+
+    cache.security().set(&key, &token_generation.to_string(), settings().cache.user_data_lifetime).await
+}
+
+// cache is filled only if it's empty, so a value read from postgres before
+// a concurrent bump can't overwrite the new one
+pub async fn cache_token_generation_in_redis(
+    cache: &CacheDB, 
+    user_id: Uuid, 
+    token_generation: i64) -> RedisResult<()> {
+    let key = format!("user_id:{}:token_generation", user_id);
+
+    cache.security().set_if_missing(&key, &token_generation.to_string(), settings().cache.user_data_lifetime).await?;
+
+    Ok(())
+}
+
+pub async fn get_token_generation_from_redis(
+    cache: &CacheDB, 
+    user_id: Uuid) -> RedisResult<Option<i64>> {
+    let key = format!("user_id:{}:token_generation", user_id);
+
+    Ok(cache.security().get(&key).await?.and_then(|token_generation| token_generation.parse().ok()))
+}
+
+// Login challenge handlers
+
+pub async fn put_login_challenge_to_redis(
+    cache: &CacheDB, 
+    challenge_token: &str, 
+    user_id: Uuid) -> RedisResult<()> {
+    let key = format!("login_challenge:{}", challenge_token);
 
-    conn.del::<&std::string::String, std::string::String>(&key);
+    cache.set(&key, &user_id.to_string(), settings().tokens.login_challenge_lifetime).await
+}
+
+pub async fn get_login_challenge_from_redis(
+    cache: &CacheDB, 
+    challenge_token: &str) -> RedisResult<Option<Uuid>> {
+    let key = format!("login_challenge:{}", challenge_token);
+
+    let user_id = cache.get(&key).await?;
+    Ok(user_id.and_then(|user_id| Uuid::parse_str(&user_id).ok()))
+}
+
+pub async fn count_login_challenge_attempt_in_redis(
+    cache: &CacheDB, 
+    challenge_token: &str) -> RedisResult<i64> {
+    let key = format!("login_challenge:{}:attempts", challenge_token);
+
+    cache.increment(&key, settings().tokens.login_challenge_lifetime).await
+}
+
+pub async fn drop_login_challenge_from_redis(
+    cache: &CacheDB, 
+    challenge_token: &str) {
+    let key = format!("login_challenge:{}", challenge_token);
+    let attempts_key = format!("login_challenge:{}:attempts", challenge_token);
+
+    let _ = cache.delete(&[key, attempts_key]).await;
+}
+
+// Login throttling handlers
+
+pub async fn add_login_failure_to_redis(
+    cache: &CacheDB, 
+    scope: &str, 
+    identifier: &str, 
+    failure_time: i64) -> RedisResult<()> {
+    let key = format!("login_failures:{}:{}", scope, identifier);
+
+    cache.add_event(&key, failure_time, settings().login_throttling.failures_window as usize).await
+}
+
+pub async fn get_login_failures_from_redis(
+    cache: &CacheDB, 
+    scope: &str, 
+    identifier: &str, 
+    current_time: i64) -> RedisResult<Vec<i64>> {
+    let key = format!("login_failures:{}:{}", scope, identifier);
+
+    cache.events_after(&key, current_time - settings().login_throttling.failures_window).await
+}
+
+pub async fn drop_login_failures_from_redis(
+    cache: &CacheDB, 
+    scope: &str, 
+    identifier: &str) {
+    let key = format!("login_failures:{}:{}", scope, identifier);
+
+    let _ = cache.delete(&[key]).await;
+}
+
+pub async fn lock_account_in_redis(
+    cache: &CacheDB, 
+    email: &str, 
+    duration: i64) -> RedisResult<bool> {
+    let key = format!("account_lockout:{}", email);
+
+    cache.set_if_missing(&key, "1", duration as usize).await
+}
+
+pub async fn get_account_lockout_time_from_redis(
+    cache: &CacheDB, 
+    email: &str) -> RedisResult<Option<i64>> {
+    let key = format!("account_lockout:{}", email);
+
+    cache.remaining_lifetime(&key).await
+}
+
+// Verification email handlers
+
+// returns `false` if verification email was sent to the address recently
+pub async fn mark_verification_resent_in_redis(
+    cache: &CacheDB, 
+    email: &str, 
+    interval: i64) -> RedisResult<bool> {
+    let key = format!("verification_resent:{}", email);
+
+    cache.set_if_missing(&key, "1", interval as usize).await
+}
+
+// Single sign-on handlers
+
+pub async fn put_oidc_login_to_redis(
+    cache: &CacheDB, 
+    state: &str, 
+    login_state: &OidcLoginState) -> RedisResult<()> {
+    let key = format!("oidc_login:{}", state);
+
+    let json = encode(login_state)?;
+    cache.set(&key, &json, settings().tokens.oidc_login_lifetime).await
+}
+
+// login state can be taken only once, the callback can't be replayed
+pub async fn take_oidc_login_from_redis(
+    cache: &CacheDB, 
+    state: &str) -> RedisResult<Option<OidcLoginState>> {
+    let key = format!("oidc_login:{}", state);
+
+    let login_state = cache.take(&key).await?;
+    Ok(login_state.and_then(|login_state| serde_json::from_str(&login_state).ok()))
 }
 
 // Board handlers
 
-pub fn put_user_boards_to_redis(
-    conn: &mut redis::Connection, 
+pub async fn put_user_boards_to_redis(
+    cache: &impl CacheStore, 
     user_id: Uuid, 
     boards: &[Board]) -> RedisResult<()> {
     let key = format!("user:{}:boards", user_id);
 
-    for board in boards {
-        let json = serde_json::to_string(board).unwrap();
-        conn.rpush(&key, json)?;
-    }
-    conn.expire(&key, STORED_DATA_EXPIRATION_TIME)?;
-    Ok(())
+    let boards = boards.iter().map(encode).collect::<RedisResult<Vec<String>>>()?;
+    cache.push(&key, &boards, settings().cache.stored_data_lifetime).await
 }
 
-pub fn get_user_boards_from_redis(
-    conn: &mut redis::Connection, 
+pub async fn get_user_boards_from_redis(
+    cache: &impl CacheStore, 
     user_id: Uuid) -> RedisResult<Vec<Board>> {
     let key = format!("user:{}:boards", user_id);
 
-    let results: Vec<String> = conn.lrange(&key, 0, -1)?;
-
-    let mut boards_list = Vec::new();
-    for res in results.iter() {
-        let board: Board = serde_json::from_str(res).unwrap();
-        boards_list.push(board);
-    }
-
-    Ok(boards_list)
+    let results = cache.list(&key).await?;
+    results.iter().map(|res| decode(res)).collect()
 }
 
-pub fn drop_user_boards_from_redis(
-    conn: &mut redis::Connection, 
+pub async fn drop_user_boards_from_redis(
+    cache: &impl CacheStore, 
     user_id: Uuid) {
     let key = format!("user:{}:boards", user_id);
-    conn.del::<&std::string::String, i32>(&key);
+    let _ = cache.delete(&[key]).await;
 }
 
 // Task handlers
 
-pub fn put_board_tasks_to_redis(
-    conn: &mut redis::Connection, 
+pub async fn put_board_tasks_to_redis(
+    cache: &impl CacheStore, 
     board_id: i32, 
     tasks: &[Task]) -> RedisResult<()> {
     let key = format!("board:{}:tasks", board_id);
 
-    let stored_tasks = get_board_tasks_from_redis(conn, board_id)?;
+    let stored_tasks = get_board_tasks_from_redis(cache, board_id).await?;
     let stored_tasks_id_list: Vec<i32> = stored_tasks
         .into_iter()
         .map(|task| {task.id})
         .collect();
 
-    for task in tasks {
-        if !stored_tasks_id_list.contains(&task.id) {
-            let json = serde_json::to_string(task).unwrap();
-            conn.rpush(&key, json)?;
-        }
-    }
-    conn.expire(&key, STORED_DATA_EXPIRATION_TIME)?;
-    Ok(())
+    let new_tasks = tasks
+        .iter()
+        .filter(|task| !stored_tasks_id_list.contains(&task.id))
+        .map(encode)
+        .collect::<RedisResult<Vec<String>>>()?;
+    cache.push(&key, &new_tasks, settings().cache.stored_data_lifetime).await
 }
 
-pub fn get_board_tasks_from_redis(
-    conn: &mut redis::Connection, 
+pub async fn get_board_tasks_from_redis(
+    cache: &impl CacheStore, 
     board_id: i32) -> RedisResult<Vec<Task>> {
     let key = format!("board:{}:tasks", board_id);
 
-    let results: Vec<String> = conn.lrange(&key, 0, -1)?;
-    let mut tasks_list = Vec::new();
-    for res in results.iter() {
-        let task: Task = serde_json::from_str(res).unwrap();
-        tasks_list.push(task);
-    }
-
-    Ok(tasks_list)
+    let results = cache.list(&key).await?;
+    results.iter().map(|res| decode(res)).collect()
 }
 
-pub fn drop_board_tasks_from_redis(
-    conn: &mut redis::Connection, 
+pub async fn drop_board_tasks_from_redis(
+    cache: &impl CacheStore, 
     board_id: i32) {
     let key = format!("board:{}:tasks", board_id);
-    conn.del::<&std::string::String, i32>(&key);
-}
\ No newline at end of file
+    let _ = cache.delete(&[key]).await;
+}
@@ -0,0 +1,314 @@
+use actix_web::web;
+use sqlx::{Postgres, Pool};
+use uuid::Uuid;
+
+use crate::action_tokens::ActionToken;
+use crate::models::{StoredUser, StoredBoard, StoredTask};
+
+#[cfg(test)]
+mod memory;
+mod postgres;
+
+#[cfg(test)]
+pub use memory::MemoryRepository;
+pub use postgres::PostgresRepository;
+
+// Storage of users, boards and tasks, and the session state access tokens are checked against.
+// Handlers call the traits on `Repository`, which passes
+// calls to Postgres in the service or to process memory in tests, so handler logic can run
+// without a database. Rows are returned as `Stored*` models, statuses follow the database schema.
+// Boards and tasks are always looked up by their owner, a foreign id reads as a missing one.
+// Methods changing a single row return `false` if no matching row was found.
+
+pub trait UserRepository {
+    async fn get_active_user(&self, user_id: Uuid) -> Result<Option<StoredUser>, sqlx::Error>;
+
+    async fn find_active_user_by_email(&self, email: &str) -> Result<Option<StoredUser>, sqlx::Error>;
+
+    // account created but not activated yet
+    async fn find_idle_user_by_email(&self, email: &str) -> Result<Option<StoredUser>, sqlx::Error>;
+
+    // any account with the email, whatever its status
+    async fn email_exists(&self, email: &str) -> Result<bool, sqlx::Error>;
+
+    // new account waits for activation by the link from email
+    async fn create_user(&self, name: &str, email: &str, password_hash: &str) -> Result<Uuid, sqlx::Error>;
+
+    async fn activate_user(&self, user_id: Uuid) -> Result<bool, sqlx::Error>;
+
+    async fn change_username(&self, user_id: Uuid, name: &str) -> Result<bool, sqlx::Error>;
+
+    async fn change_password(&self, user_id: Uuid, password_hash: &str) -> Result<bool, sqlx::Error>;
+
+    // new email is kept in the verification link until it's followed
+    async fn request_email_change(&self, user_id: Uuid) -> Result<bool, sqlx::Error>;
+
+    async fn confirm_email_change(&self, user_id: Uuid, email: &str) -> Result<bool, sqlx::Error>;
+}
+
+pub trait BoardRepository {
+    async fn get_user_boards(&self, owner_id: Uuid) -> Result<Vec<StoredBoard>, sqlx::Error>;
+
+    async fn board_exists(&self, board_id: i32, owner_id: Uuid) -> Result<bool, sqlx::Error>;
+
+    async fn create_board(&self, owner_id: Uuid, title: &str, description: &str) -> Result<i32, sqlx::Error>;
+
+    async fn update_board(&self, board_id: i32, owner_id: Uuid, title: &str, description: &str) -> Result<bool, sqlx::Error>;
+
+    async fn delete_board(&self, board_id: i32, owner_id: Uuid) -> Result<bool, sqlx::Error>;
+}
+
+pub trait TaskRepository {
+    async fn get_board_tasks(&self, board_id: i32, owner_id: Uuid) -> Result<Vec<StoredTask>, sqlx::Error>;
+
+    async fn get_task(&self, task_id: i32, board_id: i32, owner_id: Uuid) -> Result<Option<StoredTask>, sqlx::Error>;
+
+    // board has to be checked by the caller
+    async fn create_task(&self, board_id: i32, title: &str, description: &str) -> Result<i32, sqlx::Error>;
+
+    // status change time is renewed only if the status differs from the stored one
+    async fn update_task(
+        &self, 
+        task_id: i32, 
+        board_id: i32, 
+        owner_id: Uuid, 
+        title: &str, 
+        description: &str, 
+        status_id: i32) -> Result<bool, sqlx::Error>;
+
+    async fn delete_task(&self, task_id: i32, board_id: i32, owner_id: Uuid) -> Result<bool, sqlx::Error>;
+}
+
+// read by access token checks when the cache can't answer
+pub trait SessionRepository {
+    // `None` for non-active users
+    async fn get_token_generation(&self, user_id: Uuid) -> Result<Option<i64>, sqlx::Error>;
+
+    async fn is_session_active(&self, session_id: Uuid) -> Result<bool, sqlx::Error>;
+}
+
+// nonces of used links from emails
+pub trait ActionTokenRepository {
+    // `false` if the token was already used
+    async fn consume_action_token(&self, action_token: &ActionToken) -> Result<bool, sqlx::Error>;
+}
+
+pub enum Repository {
+    Postgres(PostgresRepository), 
+    #[cfg(test)]
+    Memory(MemoryRepository)
+}
+
+pub fn init_repository(postgres_pool: Pool<Postgres>) -> web::Data<Repository> {
+    web::Data::new(Repository::Postgres(PostgresRepository::new(postgres_pool)))
+}
+
+impl Repository {
+    // empty storage for running handlers without a database, the service itself always uses Postgres
+    #[cfg(test)]
+    pub fn memory() -> Self {
+        Repository::Memory(MemoryRepository::new())
+    }
+}
+
+impl UserRepository for Repository {
+    async fn get_active_user(&self, user_id: Uuid) -> Result<Option<StoredUser>, sqlx::Error> {
+        match self {
+            Repository::Postgres(postgres) => postgres.get_active_user(user_id).await, 
+            #[cfg(test)]
+            Repository::Memory(memory) => memory.get_active_user(user_id).await
+        }
+    }
+
+    async fn find_active_user_by_email(&self, email: &str) -> Result<Option<StoredUser>, sqlx::Error> {
+        match self {
+            Repository::Postgres(postgres) => postgres.find_active_user_by_email(email).await, 
+            #[cfg(test)]
+            Repository::Memory(memory) => memory.find_active_user_by_email(email).await
+        }
+    }
+
+    async fn find_idle_user_by_email(&self, email: &str) -> Result<Option<StoredUser>, sqlx::Error> {
+        match self {
+            Repository::Postgres(postgres) => postgres.find_idle_user_by_email(email).await, 
+            #[cfg(test)]
+            Repository::Memory(memory) => memory.find_idle_user_by_email(email).await
+        }
+    }
+
+    async fn email_exists(&self, email: &str) -> Result<bool, sqlx::Error> {
+        match self {
+            Repository::Postgres(postgres) => postgres.email_exists(email).await, 
+            #[cfg(test)]
+            Repository::Memory(memory) => memory.email_exists(email).await
+        }
+    }
+
+    async fn create_user(&self, name: &str, email: &str, password_hash: &str) -> Result<Uuid, sqlx::Error> {
+        match self {
+            Repository::Postgres(postgres) => postgres.create_user(name, email, password_hash).await, 
+            #[cfg(test)]
+            Repository::Memory(memory) => memory.create_user(name, email, password_hash).await
+        }
+    }
+
+    async fn activate_user(&self, user_id: Uuid) -> Result<bool, sqlx::Error> {
+        match self {
+            Repository::Postgres(postgres) => postgres.activate_user(user_id).await, 
+            #[cfg(test)]
+            Repository::Memory(memory) => memory.activate_user(user_id).await
+        }
+    }
+
+    async fn change_username(&self, user_id: Uuid, name: &str) -> Result<bool, sqlx::Error> {
+        match self {
+            Repository::Postgres(postgres) => postgres.change_username(user_id, name).await, 
+            #[cfg(test)]
+            Repository::Memory(memory) => memory.change_username(user_id, name).await
+        }
+    }
+
+    async fn change_password(&self, user_id: Uuid, password_hash: &str) -> Result<bool, sqlx::Error> {
+        match self {
+            Repository::Postgres(postgres) => postgres.change_password(user_id, password_hash).await, 
+            #[cfg(test)]
+            Repository::Memory(memory) => memory.change_password(user_id, password_hash).await
+        }
+    }
+
+    async fn request_email_change(&self, user_id: Uuid) -> Result<bool, sqlx::Error> {
+        match self {
+            Repository::Postgres(postgres) => postgres.request_email_change(user_id).await, 
+            #[cfg(test)]
+            Repository::Memory(memory) => memory.request_email_change(user_id).await
+        }
+    }
+
+    async fn confirm_email_change(&self, user_id: Uuid, email: &str) -> Result<bool, sqlx::Error> {
+        match self {
+            Repository::Postgres(postgres) => postgres.confirm_email_change(user_id, email).await, 
+            #[cfg(test)]
+            Repository::Memory(memory) => memory.confirm_email_change(user_id, email).await
+        }
+    }
+}
+
+impl BoardRepository for Repository {
+    async fn get_user_boards(&self, owner_id: Uuid) -> Result<Vec<StoredBoard>, sqlx::Error> {
+        match self {
+            Repository::Postgres(postgres) => postgres.get_user_boards(owner_id).await, 
+            #[cfg(test)]
+            Repository::Memory(memory) => memory.get_user_boards(owner_id).await
+        }
+    }
+
+    async fn board_exists(&self, board_id: i32, owner_id: Uuid) -> Result<bool, sqlx::Error> {
+        match self {
+            Repository::Postgres(postgres) => postgres.board_exists(board_id, owner_id).await, 
+            #[cfg(test)]
+            Repository::Memory(memory) => memory.board_exists(board_id, owner_id).await
+        }
+    }
+
+    async fn create_board(&self, owner_id: Uuid, title: &str, description: &str) -> Result<i32, sqlx::Error> {
+        match self {
+            Repository::Postgres(postgres) => postgres.create_board(owner_id, title, description).await, 
+            #[cfg(test)]
+            Repository::Memory(memory) => memory.create_board(owner_id, title, description).await
+        }
+    }
+
+    async fn update_board(&self, board_id: i32, owner_id: Uuid, title: &str, description: &str) -> Result<bool, sqlx::Error> {
+        match self {
+            Repository::Postgres(postgres) => postgres.update_board(board_id, owner_id, title, description).await, 
+            #[cfg(test)]
+            Repository::Memory(memory) => memory.update_board(board_id, owner_id, title, description).await
+        }
+    }
+
+    async fn delete_board(&self, board_id: i32, owner_id: Uuid) -> Result<bool, sqlx::Error> {
+        match self {
+            Repository::Postgres(postgres) => postgres.delete_board(board_id, owner_id).await, 
+            #[cfg(test)]
+            Repository::Memory(memory) => memory.delete_board(board_id, owner_id).await
+        }
+    }
+}
+
+impl TaskRepository for Repository {
+    async fn get_board_tasks(&self, board_id: i32, owner_id: Uuid) -> Result<Vec<StoredTask>, sqlx::Error> {
+        match self {
+            Repository::Postgres(postgres) => postgres.get_board_tasks(board_id, owner_id).await, 
+            #[cfg(test)]
+            Repository::Memory(memory) => memory.get_board_tasks(board_id, owner_id).await
+        }
+    }
+
+    async fn get_task(&self, task_id: i32, board_id: i32, owner_id: Uuid) -> Result<Option<StoredTask>, sqlx::Error> {
+        match self {
+            Repository::Postgres(postgres) => postgres.get_task(task_id, board_id, owner_id).await, 
+            #[cfg(test)]
+            Repository::Memory(memory) => memory.get_task(task_id, board_id, owner_id).await
+        }
+    }
+
+    async fn create_task(&self, board_id: i32, title: &str, description: &str) -> Result<i32, sqlx::Error> {
+        match self {
+            Repository::Postgres(postgres) => postgres.create_task(board_id, title, description).await, 
+            #[cfg(test)]
+            Repository::Memory(memory) => memory.create_task(board_id, title, description).await
+        }
+    }
+
+    async fn update_task(
+        &self, 
+        task_id: i32, 
+        board_id: i32, 
+        owner_id: Uuid, 
+        title: &str, 
+        description: &str, 
+        status_id: i32) -> Result<bool, sqlx::Error> {
+
+        match self {
+            Repository::Postgres(postgres) => postgres.update_task(task_id, board_id, owner_id, title, description, status_id).await, 
+            #[cfg(test)]
+            Repository::Memory(memory) => memory.update_task(task_id, board_id, owner_id, title, description, status_id).await
+        }
+    }
+
+    async fn delete_task(&self, task_id: i32, board_id: i32, owner_id: Uuid) -> Result<bool, sqlx::Error> {
+        match self {
+            Repository::Postgres(postgres) => postgres.delete_task(task_id, board_id, owner_id).await, 
+            #[cfg(test)]
+            Repository::Memory(memory) => memory.delete_task(task_id, board_id, owner_id).await
+        }
+    }
+}
+
+impl SessionRepository for Repository {
+    async fn get_token_generation(&self, user_id: Uuid) -> Result<Option<i64>, sqlx::Error> {
+        match self {
+            Repository::Postgres(postgres) => postgres.get_token_generation(user_id).await, 
+            #[cfg(test)]
+            Repository::Memory(memory) => memory.get_token_generation(user_id).await
+        }
+    }
+
+    async fn is_session_active(&self, session_id: Uuid) -> Result<bool, sqlx::Error> {
+        match self {
+            Repository::Postgres(postgres) => postgres.is_session_active(session_id).await, 
+            #[cfg(test)]
+            Repository::Memory(memory) => memory.is_session_active(session_id).await
+        }
+    }
+}
+
+impl ActionTokenRepository for Repository {
+    async fn consume_action_token(&self, action_token: &ActionToken) -> Result<bool, sqlx::Error> {
+        match self {
+            Repository::Postgres(postgres) => postgres.consume_action_token(action_token).await, 
+            #[cfg(test)]
+            Repository::Memory(memory) => memory.consume_action_token(action_token).await
+        }
+    }
+}
@@ -0,0 +1,358 @@
+use chrono::NaiveDateTime;
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::Mutex;
+use uuid::Uuid;
+
+use crate::action_tokens::ActionToken;
+use crate::models::{StoredUser, StoredBoard, StoredTask};
+use super::{UserRepository, BoardRepository, TaskRepository, SessionRepository, ActionTokenRepository};
+
+// Keeps rows in process memory the way Postgres would: same statuses, ids of boards and tasks
+// come from sequences, time columns are set as by defaults and triggers of the schema.
+// Nothing fails and nothing outlives the process, meant for handler tests.
+
+struct MemoryBoard {
+    owner_id: Uuid, 
+    status_id: i32, 
+    board: StoredBoard
+}
+
+#[derive(Default)]
+struct MemoryTables {
+    users: BTreeMap<Uuid, StoredUser>, 
+    boards: BTreeMap<i32, MemoryBoard>, 
+    tasks: BTreeMap<i32, StoredTask>, 
+    // users without an entry have the default generation 0
+    token_generations: BTreeMap<Uuid, i64>, 
+    // session id to whether it's active
+    sessions: BTreeMap<Uuid, bool>, 
+    // nonces are kept for the process lifetime, expired tokens are refused before the lookup anyway
+    used_action_tokens: BTreeSet<Uuid>, 
+    last_board_id: i32, 
+    last_task_id: i32
+}
+
+impl MemoryTables {
+    fn active_user(&mut self, user_id: Uuid) -> Option<&mut StoredUser> {
+        self.users
+            .get_mut(&user_id)
+            .filter(|user| user.status_id == Some(1))
+    }
+
+    fn active_board(&mut self, board_id: i32, owner_id: Uuid) -> Option<&mut MemoryBoard> {
+        self.boards
+            .get_mut(&board_id)
+            .filter(|board| board.owner_id == owner_id && board.status_id == 0)
+    }
+
+    // tasks of deleted boards are still listed, as the join of tasks and boards does
+    fn owned_task(&self, task_id: i32, board_id: i32, owner_id: Uuid) -> Option<&StoredTask> {
+        self.tasks
+            .get(&task_id)
+            .filter(|task| task.board_id == Some(board_id) && task.status_id != Some(4))
+            .filter(|_| self.boards.get(&board_id).is_some_and(|board| board.owner_id == owner_id))
+    }
+
+    // changes are made to tasks of active boards only
+    fn editable_task(&mut self, task_id: i32, board_id: i32, owner_id: Uuid) -> Option<&mut StoredTask> {
+        self.active_board(board_id, owner_id)?;
+        self.owned_task(task_id, board_id, owner_id)?;
+        self.tasks.get_mut(&task_id)
+    }
+}
+
+pub struct MemoryRepository {
+    tables: Mutex<MemoryTables>
+}
+
+impl MemoryRepository {
+    pub fn new() -> Self {
+        MemoryRepository {
+            tables: Mutex::new(MemoryTables::default())
+        }
+    }
+
+    // sessions are opened by the login handlers, which work on Postgres directly
+    pub fn start_session(&self) -> Uuid {
+        let session_id = Uuid::new_v4();
+        self.tables.lock().unwrap().sessions.insert(session_id, true);
+        session_id
+    }
+
+    pub fn end_session(&self, session_id: Uuid) {
+        self.tables.lock().unwrap().sessions.insert(session_id, false);
+    }
+
+    // as the admin handlers do, which work on Postgres directly
+    pub fn disable_user(&self, user_id: Uuid) {
+        if let Some(user) = self.tables.lock().unwrap().users.get_mut(&user_id) {
+            user.status_id = Some(4);
+        }
+    }
+
+    pub fn bump_token_generation(&self, user_id: Uuid) {
+        *self.tables.lock().unwrap().token_generations.entry(user_id).or_default() += 1;
+    }
+
+    fn find_user_by_email(&self, email: &str, status_id: i32) -> Option<StoredUser> {
+        self.tables
+            .lock()
+            .unwrap()
+            .users
+            .values()
+            .find(|user| user.email.as_deref() == Some(email) && user.status_id == Some(status_id))
+            .cloned()
+    }
+}
+
+fn now() -> NaiveDateTime {
+    chrono::offset::Utc::now().naive_utc()
+}
+
+impl UserRepository for MemoryRepository {
+    async fn get_active_user(&self, user_id: Uuid) -> Result<Option<StoredUser>, sqlx::Error> {
+        Ok(self.tables.lock().unwrap().active_user(user_id).cloned())
+    }
+
+    async fn find_active_user_by_email(&self, email: &str) -> Result<Option<StoredUser>, sqlx::Error> {
+        Ok(self.find_user_by_email(email, 1))
+    }
+
+    async fn find_idle_user_by_email(&self, email: &str) -> Result<Option<StoredUser>, sqlx::Error> {
+        Ok(self.find_user_by_email(email, 0))
+    }
+
+    async fn email_exists(&self, email: &str) -> Result<bool, sqlx::Error> {
+        let tables = self.tables.lock().unwrap();
+        Ok(tables.users.values().any(|user| user.email.as_deref() == Some(email)))
+    }
+
+    async fn create_user(&self, name: &str, email: &str, password_hash: &str) -> Result<Uuid, sqlx::Error> {
+        let user_id = Uuid::new_v4();
+        let created_at = now();
+        self.tables.lock().unwrap().users.insert(user_id, StoredUser {
+            id: user_id, 
+            name: Some(name.to_string()), 
+            email: Some(email.to_string()), 
+            passwd: Some(password_hash.to_string()), 
+            verification_status_id: Some(0), 
+            status_id: Some(0), 
+            created_at: Some(created_at), 
+            updated_at: Some(created_at)
+        });
+        Ok(user_id)
+    }
+
+    async fn activate_user(&self, user_id: Uuid) -> Result<bool, sqlx::Error> {
+        let mut tables = self.tables.lock().unwrap();
+        match tables.users.get_mut(&user_id).filter(|user| user.status_id == Some(0)) {
+            Some(user) => {
+                user.status_id = Some(1);
+                user.verification_status_id = Some(1);
+                user.updated_at = Some(now());
+                Ok(true)
+            }, 
+            None => Ok(false)
+        }
+    }
+
+    async fn change_username(&self, user_id: Uuid, name: &str) -> Result<bool, sqlx::Error> {
+        let mut tables = self.tables.lock().unwrap();
+        match tables.active_user(user_id) {
+            Some(user) => {
+                user.name = Some(name.to_string());
+                user.updated_at = Some(now());
+                Ok(true)
+            }, 
+            None => Ok(false)
+        }
+    }
+
+    async fn change_password(&self, user_id: Uuid, password_hash: &str) -> Result<bool, sqlx::Error> {
+        let mut tables = self.tables.lock().unwrap();
+        match tables.active_user(user_id) {
+            Some(user) => {
+                user.passwd = Some(password_hash.to_string());
+                user.updated_at = Some(now());
+                Ok(true)
+            }, 
+            None => Ok(false)
+        }
+    }
+
+    async fn request_email_change(&self, user_id: Uuid) -> Result<bool, sqlx::Error> {
+        let mut tables = self.tables.lock().unwrap();
+        match tables.active_user(user_id) {
+            Some(user) => {
+                user.verification_status_id = Some(4);
+                user.updated_at = Some(now());
+                Ok(true)
+            }, 
+            None => Ok(false)
+        }
+    }
+
+    async fn confirm_email_change(&self, user_id: Uuid, email: &str) -> Result<bool, sqlx::Error> {
+        let mut tables = self.tables.lock().unwrap();
+        match tables.active_user(user_id).filter(|user| user.verification_status_id == Some(4)) {
+            Some(user) => {
+                user.email = Some(email.to_string());
+                user.verification_status_id = Some(1);
+                user.updated_at = Some(now());
+                Ok(true)
+            }, 
+            None => Ok(false)
+        }
+    }
+}
+
+impl BoardRepository for MemoryRepository {
+    async fn get_user_boards(&self, owner_id: Uuid) -> Result<Vec<StoredBoard>, sqlx::Error> {
+        let tables = self.tables.lock().unwrap();
+        let mut boards: Vec<StoredBoard> = tables.boards
+            .values()
+            .filter(|board| board.owner_id == owner_id && board.status_id == 0)
+            .map(|board| board.board.clone())
+            .collect();
+        boards.sort_by_key(|board| board.creation_time);
+        Ok(boards)
+    }
+
+    async fn board_exists(&self, board_id: i32, owner_id: Uuid) -> Result<bool, sqlx::Error> {
+        Ok(self.tables.lock().unwrap().active_board(board_id, owner_id).is_some())
+    }
+
+    async fn create_board(&self, owner_id: Uuid, title: &str, description: &str) -> Result<i32, sqlx::Error> {
+        let mut tables = self.tables.lock().unwrap();
+        tables.last_board_id += 1;
+        let board_id = tables.last_board_id;
+        tables.boards.insert(board_id, MemoryBoard {
+            owner_id, 
+            status_id: 0, 
+            board: StoredBoard {
+                id: board_id, 
+                title: Some(title.to_string()), 
+                description: Some(description.to_string()), 
+                creation_time: Some(now())
+            }
+        });
+        Ok(board_id)
+    }
+
+    async fn update_board(&self, board_id: i32, owner_id: Uuid, title: &str, description: &str) -> Result<bool, sqlx::Error> {
+        let mut tables = self.tables.lock().unwrap();
+        match tables.active_board(board_id, owner_id) {
+            Some(board) => {
+                board.board.title = Some(title.to_string());
+                board.board.description = Some(description.to_string());
+                Ok(true)
+            }, 
+            None => Ok(false)
+        }
+    }
+
+    async fn delete_board(&self, board_id: i32, owner_id: Uuid) -> Result<bool, sqlx::Error> {
+        let mut tables = self.tables.lock().unwrap();
+        match tables.active_board(board_id, owner_id) {
+            Some(board) => {
+                board.status_id = 1;
+                Ok(true)
+            }, 
+            None => Ok(false)
+        }
+    }
+}
+
+impl TaskRepository for MemoryRepository {
+    async fn get_board_tasks(&self, board_id: i32, owner_id: Uuid) -> Result<Vec<StoredTask>, sqlx::Error> {
+        let tables = self.tables.lock().unwrap();
+        let mut tasks: Vec<StoredTask> = tables.tasks
+            .keys()
+            .filter_map(|task_id| tables.owned_task(*task_id, board_id, owner_id))
+            .cloned()
+            .collect();
+        tasks.sort_by_key(|task| task.creation_time);
+        Ok(tasks)
+    }
+
+    async fn get_task(&self, task_id: i32, board_id: i32, owner_id: Uuid) -> Result<Option<StoredTask>, sqlx::Error> {
+        let mut tables = self.tables.lock().unwrap();
+        if tables.active_board(board_id, owner_id).is_none() {
+            return Ok(None);
+        }
+        Ok(tables.owned_task(task_id, board_id, owner_id).cloned())
+    }
+
+    async fn create_task(&self, board_id: i32, title: &str, description: &str) -> Result<i32, sqlx::Error> {
+        let mut tables = self.tables.lock().unwrap();
+        tables.last_task_id += 1;
+        let task_id = tables.last_task_id;
+        let created_at = now();
+        tables.tasks.insert(task_id, StoredTask {
+            id: task_id, 
+            title: Some(title.to_string()), 
+            description: Some(description.to_string()), 
+            board_id: Some(board_id), 
+            status_id: Some(0), 
+            creation_time: Some(created_at), 
+            last_status_change_time: Some(created_at)
+        });
+        Ok(task_id)
+    }
+
+    async fn update_task(
+        &self, 
+        task_id: i32, 
+        board_id: i32, 
+        owner_id: Uuid, 
+        title: &str, 
+        description: &str, 
+        status_id: i32) -> Result<bool, sqlx::Error> {
+
+        let mut tables = self.tables.lock().unwrap();
+        match tables.editable_task(task_id, board_id, owner_id) {
+            Some(task) => {
+                task.title = Some(title.to_string());
+                task.description = Some(description.to_string());
+                if task.status_id != Some(status_id) {
+                    task.status_id = Some(status_id);
+                    task.last_status_change_time = Some(now());
+                }
+                Ok(true)
+            }, 
+            None => Ok(false)
+        }
+    }
+
+    async fn delete_task(&self, task_id: i32, board_id: i32, owner_id: Uuid) -> Result<bool, sqlx::Error> {
+        let mut tables = self.tables.lock().unwrap();
+        match tables.editable_task(task_id, board_id, owner_id) {
+            Some(task) => {
+                task.status_id = Some(4);
+                task.last_status_change_time = Some(now());
+                Ok(true)
+            }, 
+            None => Ok(false)
+        }
+    }
+}
+
+impl SessionRepository for MemoryRepository {
+    async fn get_token_generation(&self, user_id: Uuid) -> Result<Option<i64>, sqlx::Error> {
+        let mut tables = self.tables.lock().unwrap();
+        if tables.active_user(user_id).is_none() {
+            return Ok(None);
+        }
+        Ok(Some(tables.token_generations.get(&user_id).copied().unwrap_or_default()))
+    }
+
+    async fn is_session_active(&self, session_id: Uuid) -> Result<bool, sqlx::Error> {
+        Ok(self.tables.lock().unwrap().sessions.get(&session_id).copied().unwrap_or_default())
+    }
+}
+
+impl ActionTokenRepository for MemoryRepository {
+    async fn consume_action_token(&self, action_token: &ActionToken) -> Result<bool, sqlx::Error> {
+        Ok(self.tables.lock().unwrap().used_action_tokens.insert(action_token.nonce))
+    }
+}
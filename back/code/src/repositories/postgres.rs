@@ -0,0 +1,421 @@
+use sqlx::{self, postgres::PgRow, Postgres, Pool, Row};
+use uuid::Uuid;
+
+use crate::{APP_SCHEMA, USERS_TABLE, BOARDS_TABLE, TASKS_TABLE};
+use crate::models::{StoredUser, StoredBoard, StoredTask};
+use crate::{action_tokens, sessions};
+use crate::action_tokens::ActionToken;
+use super::{UserRepository, BoardRepository, TaskRepository, SessionRepository, ActionTokenRepository};
+
+pub struct PostgresRepository {
+    pool: Pool<Postgres>
+}
+
+impl PostgresRepository {
+    pub fn new(pool: Pool<Postgres>) -> Self {
+        PostgresRepository { pool }
+    }
+}
+
+const USER_COLUMNS: &str = "id, name, email, passwd, verification_status_id, status_id, created_at, updated_at";
+const TASK_COLUMNS: &str = "t.id, t.title, t.description, t.board_id, t.status_id, t.creation_time, t.last_status_change_time";
+
+fn stored_user(row: PgRow) -> StoredUser {
+    StoredUser {
+        id: row.get("id"), 
+        name: row.get("name"), 
+        email: row.get("email"), 
+        passwd: row.get("passwd"), 
+        verification_status_id: row.get("verification_status_id"), 
+        status_id: row.get("status_id"), 
+        created_at: row.get("created_at"), 
+        updated_at: row.get("updated_at")
+    }
+}
+
+fn stored_board(row: PgRow) -> StoredBoard {
+    StoredBoard {
+        id: row.get("id"), 
+        title: row.get("title"), 
+        description: row.get("description"), 
+        creation_time: row.get("creation_time")
+    }
+}
+
+fn stored_task(row: PgRow) -> StoredTask {
+    StoredTask {
+        id: row.get("id"), 
+        title: row.get("title"), 
+        description: row.get("description"), 
+        board_id: row.get("board_id"), 
+        status_id: row.get("status_id"), 
+        creation_time: row.get("creation_time"), 
+        last_status_change_time: row.get("last_status_change_time")
+    }
+}
+
+impl PostgresRepository {
+    async fn find_user_by_email(&self, email: &str, status_id: i32) -> Result<Option<StoredUser>, sqlx::Error> {
+        let query = format!(
+            "SELECT
+                {USER_COLUMNS}
+               FROM {APP_SCHEMA}.{USERS_TABLE}
+              WHERE email = $1 AND status_id = $2"
+        );
+        sqlx::query(&query)
+            .bind(email)
+            .bind(status_id)
+            .map(stored_user)
+            .fetch_optional(&self.pool)
+            .await
+    }
+}
+
+impl UserRepository for PostgresRepository {
+    async fn get_active_user(&self, user_id: Uuid) -> Result<Option<StoredUser>, sqlx::Error> {
+        let query = format!(
+            "SELECT
+                {USER_COLUMNS}
+               FROM {APP_SCHEMA}.{USERS_TABLE}
+              WHERE id = $1 AND status_id = 1"
+        );
+        sqlx::query(&query)
+            .bind(user_id)
+            .map(stored_user)
+            .fetch_optional(&self.pool)
+            .await
+    }
+
+    async fn find_active_user_by_email(&self, email: &str) -> Result<Option<StoredUser>, sqlx::Error> {
+        self.find_user_by_email(email, 1).await
+    }
+
+    async fn find_idle_user_by_email(&self, email: &str) -> Result<Option<StoredUser>, sqlx::Error> {
+        self.find_user_by_email(email, 0).await
+    }
+
+    async fn email_exists(&self, email: &str) -> Result<bool, sqlx::Error> {
+        let query = format!(
+            "SELECT
+                id
+               FROM {APP_SCHEMA}.{USERS_TABLE}
+              WHERE email = $1"
+        );
+        let existed_user = sqlx::query(&query)
+            .bind(email)
+            .fetch_optional(&self.pool)
+            .await?;
+
+        Ok(existed_user.is_some())
+    }
+
+    async fn create_user(&self, name: &str, email: &str, password_hash: &str) -> Result<Uuid, sqlx::Error> {
+        let query = format!(
+            "INSERT INTO {APP_SCHEMA}.{USERS_TABLE} (name, email, passwd, verification_status_id, status_id)
+                  VALUES ($1, $2, $3, 0, 0)
+               RETURNING id"
+        );
+        sqlx::query(&query)
+            .bind(name)
+            .bind(email)
+            .bind(password_hash)
+            .map(|row| row.get("id"))
+            .fetch_one(&self.pool)
+            .await
+    }
+
+    async fn activate_user(&self, user_id: Uuid) -> Result<bool, sqlx::Error> {
+        let query = format!(
+            "UPDATE {APP_SCHEMA}.{USERS_TABLE}
+                SET status_id = 1, verification_status_id = 1
+              WHERE id = $1
+                AND status_id = 0"
+        );
+        let result = sqlx::query(&query)
+            .bind(user_id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn change_username(&self, user_id: Uuid, name: &str) -> Result<bool, sqlx::Error> {
+        let query = format!(
+            "UPDATE {APP_SCHEMA}.{USERS_TABLE}
+                SET name = $2
+              WHERE id = $1
+                AND status_id = 1"
+        );
+        let result = sqlx::query(&query)
+            .bind(user_id)
+            .bind(name)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn change_password(&self, user_id: Uuid, password_hash: &str) -> Result<bool, sqlx::Error> {
+        let query = format!(
+            "UPDATE {APP_SCHEMA}.{USERS_TABLE}
+                SET passwd = $2
+              WHERE id = $1
+                AND status_id = 1"
+        );
+        let result = sqlx::query(&query)
+            .bind(user_id)
+            .bind(password_hash)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn request_email_change(&self, user_id: Uuid) -> Result<bool, sqlx::Error> {
+        let query = format!(
+            "UPDATE {APP_SCHEMA}.{USERS_TABLE}
+                SET verification_status_id = 4
+              WHERE id = $1
+                AND status_id = 1"
+        );
+        let result = sqlx::query(&query)
+            .bind(user_id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn confirm_email_change(&self, user_id: Uuid, email: &str) -> Result<bool, sqlx::Error> {
+        let query = format!(
+            "UPDATE {APP_SCHEMA}.{USERS_TABLE}
+                SET email = $2, verification_status_id = 1
+              WHERE id = $1
+                AND status_id = 1
+                AND verification_status_id = 4"
+        );
+        let result = sqlx::query(&query)
+            .bind(user_id)
+            .bind(email)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+}
+
+impl BoardRepository for PostgresRepository {
+    async fn get_user_boards(&self, owner_id: Uuid) -> Result<Vec<StoredBoard>, sqlx::Error> {
+        let query = format!(
+            "SELECT
+                id, title, description, creation_time
+               FROM {APP_SCHEMA}.{BOARDS_TABLE}
+              WHERE status_id = 0 AND owner_id = $1
+              ORDER BY creation_time"
+        );
+        sqlx::query(&query)
+            .bind(owner_id)
+            .map(stored_board)
+            .fetch_all(&self.pool)
+            .await
+    }
+
+    async fn board_exists(&self, board_id: i32, owner_id: Uuid) -> Result<bool, sqlx::Error> {
+        let query = format!(
+            "SELECT
+                id
+               FROM {APP_SCHEMA}.{BOARDS_TABLE}
+              WHERE id = $1 AND owner_id = $2 AND status_id = 0"
+        );
+        let board = sqlx::query(&query)
+            .bind(board_id)
+            .bind(owner_id)
+            .fetch_optional(&self.pool)
+            .await?;
+
+        Ok(board.is_some())
+    }
+
+    async fn create_board(&self, owner_id: Uuid, title: &str, description: &str) -> Result<i32, sqlx::Error> {
+        let query = format!(
+            "INSERT INTO {APP_SCHEMA}.{BOARDS_TABLE} (title, description, status_id, owner_id)
+                  VALUES ($1, $2, 0, $3)
+               RETURNING id"
+        );
+        sqlx::query(&query)
+            .bind(title)
+            .bind(description)
+            .bind(owner_id)
+            .map(|row| row.get("id"))
+            .fetch_one(&self.pool)
+            .await
+    }
+
+    async fn update_board(&self, board_id: i32, owner_id: Uuid, title: &str, description: &str) -> Result<bool, sqlx::Error> {
+        let query = format!(
+            "UPDATE {APP_SCHEMA}.{BOARDS_TABLE}
+                SET title = $3, description = $4
+              WHERE id = $1
+                AND owner_id = $2
+                AND status_id = 0"
+        );
+        let result = sqlx::query(&query)
+            .bind(board_id)
+            .bind(owner_id)
+            .bind(title)
+            .bind(description)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn delete_board(&self, board_id: i32, owner_id: Uuid) -> Result<bool, sqlx::Error> {
+        let query = format!(
+            "UPDATE {APP_SCHEMA}.{BOARDS_TABLE}
+                SET status_id = 1
+              WHERE id = $1
+                AND owner_id = $2
+                AND status_id = 0"
+        );
+        let result = sqlx::query(&query)
+            .bind(board_id)
+            .bind(owner_id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+}
+
+impl TaskRepository for PostgresRepository {
+    async fn get_board_tasks(&self, board_id: i32, owner_id: Uuid) -> Result<Vec<StoredTask>, sqlx::Error> {
+        let query = format!(
+            "SELECT
+                {TASK_COLUMNS}
+               FROM {APP_SCHEMA}.{TASKS_TABLE} t
+         INNER JOIN {APP_SCHEMA}.{BOARDS_TABLE} b
+                 ON t.board_id = b.id
+              WHERE t.status_id != 4
+                AND t.board_id = $1
+                AND b.owner_id = $2
+              ORDER BY t.creation_time"
+        );
+        sqlx::query(&query)
+            .bind(board_id)
+            .bind(owner_id)
+            .map(stored_task)
+            .fetch_all(&self.pool)
+            .await
+    }
+
+    async fn get_task(&self, task_id: i32, board_id: i32, owner_id: Uuid) -> Result<Option<StoredTask>, sqlx::Error> {
+        let query = format!(
+            "SELECT
+                {TASK_COLUMNS}
+               FROM {APP_SCHEMA}.{TASKS_TABLE} t
+         INNER JOIN {APP_SCHEMA}.{BOARDS_TABLE} b
+                 ON t.board_id = b.id
+              WHERE t.id = $1
+                AND t.board_id = $2
+                AND b.owner_id = $3
+                AND t.status_id != 4
+                AND b.status_id = 0"
+        );
+        sqlx::query(&query)
+            .bind(task_id)
+            .bind(board_id)
+            .bind(owner_id)
+            .map(stored_task)
+            .fetch_optional(&self.pool)
+            .await
+    }
+
+    async fn create_task(&self, board_id: i32, title: &str, description: &str) -> Result<i32, sqlx::Error> {
+        let query = format!(
+            "INSERT INTO {APP_SCHEMA}.{TASKS_TABLE} (title, description, board_id, status_id)
+                  VALUES ($1, $2, $3, 0)
+               RETURNING id"
+        );
+        sqlx::query(&query)
+            .bind(title)
+            .bind(description)
+            .bind(board_id)
+            .map(|row| row.get("id"))
+            .fetch_one(&self.pool)
+            .await
+    }
+
+    // status change time is set by the trigger of the table
+    async fn update_task(
+        &self, 
+        task_id: i32, 
+        board_id: i32, 
+        owner_id: Uuid, 
+        title: &str, 
+        description: &str, 
+        status_id: i32) -> Result<bool, sqlx::Error> {
+
+        let query = format!(
+            "UPDATE {APP_SCHEMA}.{TASKS_TABLE} t
+                SET title = $4, description = $5, status_id = $6
+               FROM {APP_SCHEMA}.{BOARDS_TABLE} b
+              WHERE b.id = t.board_id
+                AND t.id = $1
+                AND t.board_id = $2
+                AND b.owner_id = $3
+                AND b.status_id = 0
+                AND t.status_id != 4"
+        );
+        let result = sqlx::query(&query)
+            .bind(task_id)
+            .bind(board_id)
+            .bind(owner_id)
+            .bind(title)
+            .bind(description)
+            .bind(status_id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn delete_task(&self, task_id: i32, board_id: i32, owner_id: Uuid) -> Result<bool, sqlx::Error> {
+        let query = format!(
+            "UPDATE {APP_SCHEMA}.{TASKS_TABLE} t
+                SET status_id = 4
+               FROM {APP_SCHEMA}.{BOARDS_TABLE} b
+              WHERE b.id = t.board_id
+                AND t.id = $1
+                AND t.board_id = $2
+                AND b.owner_id = $3
+                AND b.status_id = 0
+                AND t.status_id != 4"
+        );
+        let result = sqlx::query(&query)
+            .bind(task_id)
+            .bind(board_id)
+            .bind(owner_id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+}
+
+// session queries are shared with the session handlers
+impl SessionRepository for PostgresRepository {
+    async fn get_token_generation(&self, user_id: Uuid) -> Result<Option<i64>, sqlx::Error> {
+        sessions::get_token_generation(&self.pool, user_id).await
+    }
+
+    async fn is_session_active(&self, session_id: Uuid) -> Result<bool, sqlx::Error> {
+        sessions::is_session_active(&self.pool, session_id).await
+    }
+}
+
+impl ActionTokenRepository for PostgresRepository {
+    async fn consume_action_token(&self, action_token: &ActionToken) -> Result<bool, sqlx::Error> {
+        action_tokens::consume_action_token(&self.pool, action_token).await
+    }
+}
@@ -0,0 +1,90 @@
+use actix_web::{
+    dev::Payload, 
+    web::Data, 
+    FromRequest, HttpRequest
+};
+use serde::Serialize;
+use sqlx::{self, Postgres, Pool, Row};
+use std::future::Future;
+use std::pin::Pin;
+use uuid::Uuid;
+
+use crate::{PersistentDB, APP_SCHEMA, USERS_TABLE};
+use crate::auth_user::AuthUser;
+use crate::errors::AppError;
+
+// Every customer has a role, stored as `role_id`. Admins get access to the admin API.
+// Role is read from postgres on each admin request, so granting or taking it away
+// takes effect at once without waiting for access tokens to expire.
+
+#[derive(Serialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    User, 
+    Admin
+}
+
+impl Role {
+    pub fn from_id(role_id: i32) -> Self {
+        match role_id {
+            1 => Role::Admin, 
+            _ => Role::User
+        }
+    }
+}
+
+// returns `None` for non-active users
+pub async fn get_user_role(
+    db_link: &Pool<Postgres>, 
+    user_id: Uuid) -> Result<Option<Role>, sqlx::Error> {
+
+    let query = format!(
+        "SELECT
+            role_id
+           FROM {}.{}
+          WHERE id = $1
+            AND status_id = 1", 
+        APP_SCHEMA, 
+        USERS_TABLE
+    );
+    sqlx::query(&query)
+        .bind(user_id)
+        .map(|row| Role::from_id(row.get("role_id")))
+        .fetch_optional(db_link)
+        .await
+}
+
+// Extractor for handlers of the admin API, rejects the request with 403 unless it's made
+// by an active admin. Works on top of `validate_user`, which sets `AuthUser` of the request.
+pub struct AdminUser {
+    pub user_id: Uuid
+}
+
+impl FromRequest for AdminUser {
+    type Error = AppError;
+    type Future = Pin<Box<dyn Future<Output = Result<Self, Self::Error>>>>;
+
+    fn from_request(request: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let auth_user = AuthUser::from_http_request(request);
+        let postgres_db = request.app_data::<Data<PersistentDB>>().cloned();
+
+        Box::pin(async move {
+            let (user_id, postgres_db) = match (auth_user, postgres_db) {
+                (Ok(auth_user), Some(postgres_db)) => (auth_user.user_id, postgres_db), 
+                _ => return Err(forbidden())
+            };
+
+            match get_user_role(&postgres_db.pool(), user_id).await? {
+                Some(Role::Admin) => Ok(AdminUser { user_id }), 
+                _ => {
+                    log::warn!("User: `{}` tried to access admin API", user_id);
+                    Err(forbidden())
+                }
+            }
+        })
+    }
+}
+
+fn forbidden() -> AppError {
+    AppError::Forbidden(String::from("Forbidden"))
+}
@@ -1,20 +1,20 @@
 use actix_web::{
     web::{self, Data, Json}, 
-    Responder, HttpRequest, HttpResponse, 
-    cookie::{time::Duration, Cookie}
+    HttpResponse
 };
-use log;
-use sqlx::{self, Row};
-use uuid::Uuid;
 
-use crate::{PersistentDB, CacheDB, APP_SCHEMA, BOARDS_TABLE, TOKEN_LIFETIME};
+use crate::CacheDB;
+use crate::errors::AppError;
+use crate::auth_user::AuthUser;
+use crate::personal_tokens::{RequiredScope, SCOPE_BOARDS_READ, SCOPE_BOARDS_WRITE};
+use crate::repositories::{Repository, BoardRepository};
 use crate::redis_handlers::{
     get_user_boards_from_redis, 
     put_user_boards_to_redis, 
     drop_user_boards_from_redis
 };
 use crate::models::{
-    ServerResponse, Board, StoredBoard, 
+    ServerResponse, Board, 
     CreateBoardBody, UpdateBoardBody, DeleteBoardBody
 };
 
@@ -22,332 +22,300 @@ pub fn boards_managing(cfg: &mut web::ServiceConfig) {
     cfg
         .service(
             web::resource("/user_boards")
+                .app_data(RequiredScope(SCOPE_BOARDS_READ))
                 .route(web::get().to(handle_user_boards))
         )
         .service(
             web::resource("/create_board")
+                .app_data(RequiredScope(SCOPE_BOARDS_WRITE))
                 .route(web::post().to(handle_create_board))
         )
         .service(
             web::resource("/change_board")
+                .app_data(RequiredScope(SCOPE_BOARDS_WRITE))
                 .route(web::put().to(handle_change_board))
         )
         .service(
             web::resource("/delete_board")
+                .app_data(RequiredScope(SCOPE_BOARDS_WRITE))
                 .route(web::delete().to(handle_delete_board))
         );
 }
 
 async fn handle_user_boards(
-    request: HttpRequest,
-    postgres_db: Data<PersistentDB>, 
-    redis_db: Data<CacheDB>) -> impl Responder {
+    auth_user: AuthUser,
+    repository: Data<Repository>, 
+    redis_db: Data<CacheDB>) -> Result<HttpResponse, AppError> {
 
-    let headers = request.headers();
-    let user_id: Uuid = headers.get("user_id").unwrap().to_str().unwrap().parse().unwrap();
+    let user_id = auth_user.user_id;
 
     log::info!("Boards requested by user {}", user_id);
 
-    let db_link = &*postgres_db.db.lock().unwrap();
-    let redis_conn = &mut *redis_db.db.lock().unwrap();
+    let cache = redis_db.get_ref();
 
-    let redis_data = get_user_boards_from_redis(redis_conn, user_id);
+    let redis_data = get_user_boards_from_redis(cache, user_id).await;
     if let Ok(redis_boards_list) = redis_data {
-        if redis_boards_list.len() > 0 {
-            if headers.contains_key("new_token") {
-                let token = headers.get("new_token").unwrap().to_str().unwrap();
-                let mut cookie = Cookie::new("x-auth", token);
-                cookie.set_max_age(Duration::seconds(TOKEN_LIFETIME));
-
-                return HttpResponse::Ok().cookie(cookie).json(redis_boards_list);
-            } else {
-                return HttpResponse::Ok().json(redis_boards_list);
-            }
+        if !redis_boards_list.is_empty() {
+            return Ok(HttpResponse::Ok().json(redis_boards_list));
         }
     }
 
-    let query = format!(
-        "SELECT 
-            id, title, description, creation_time    
-            FROM {}.{}
-            WHERE status_id = 0 AND owner_id = $1
-            ORDER BY creation_time", 
-        APP_SCHEMA, 
-        BOARDS_TABLE
-    );
-    let result = sqlx::query(&query)
-        .bind(user_id)
-        .map(|row| {
-            StoredBoard{
-                id: row.get("id"),
-                title: row.get("title"),
-                description: row.get("description"),
-                creation_time: row.get("creation_time")
-            } 
-        })
-        .fetch_all(db_link)
-        .await;
-
-    match result {
-        Ok(stored_boards_list) => {
-            
-            let mut board_list: Vec<Board> = vec![];
-            for stored_board in stored_boards_list.iter() {
-                let board_to_return = stored_board.get_board();
-                board_list.push(board_to_return);
-            }
-            put_user_boards_to_redis(redis_conn, user_id, &board_list);
-
-            if headers.contains_key("new_token") {
-                let token = headers.get("new_token").unwrap().to_str().unwrap();
-                let mut cookie = Cookie::new("x-auth", token);
-                cookie.set_max_age(Duration::seconds(TOKEN_LIFETIME));
-
-                HttpResponse::Ok().cookie(cookie).json(board_list)
-            } else {
-                HttpResponse::Ok().json(board_list)
-            }
-        }, 
-        Err(db_error) => {
-            log::error!("Database issue: {:?}", db_error);
-            HttpResponse::InternalServerError().json(ServerResponse {
-                status: 500, 
-                message: String::from("Internal server error")
-            })
-        }
+    let stored_boards_list = repository.get_user_boards(user_id).await?;
+
+    let mut board_list: Vec<Board> = vec![];
+    for stored_board in stored_boards_list.iter() {
+        let board_to_return = stored_board.get_board();
+        board_list.push(board_to_return);
     }
-    
+    if let Err(redis_error) = put_user_boards_to_redis(cache, user_id, &board_list).await {
+        log::warn!("Cache database issue: {:?}", redis_error);
+    }
+
+    Ok(HttpResponse::Ok().json(board_list))
 }
 
 async fn handle_create_board(
-    request: HttpRequest,
-    postgres_db: Data<PersistentDB>, 
+    auth_user: AuthUser,
+    repository: Data<Repository>, 
     redis_db: Data<CacheDB>, 
-    board_data: Json<CreateBoardBody>) -> impl Responder {
+    board_data: Json<CreateBoardBody>) -> Result<HttpResponse, AppError> {
 
     let CreateBoardBody{title, description} = board_data.0;
-    let headers = request.headers();
-    let user_id: Uuid = headers.get("user_id").unwrap().to_str().unwrap().parse().unwrap();
+    let user_id = auth_user.user_id;
 
     log::info!("Creation new board by user {}", user_id);
 
-    let db_link = &*postgres_db.db.lock().unwrap();
-    let redis_conn = &mut *redis_db.db.lock().unwrap();
-
-    let query = format!(
-        "INSERT INTO {}.{} (title, description, status_id, owner_id) 
-                VALUES ($1, $2, 0, $3)", 
-        APP_SCHEMA, 
-        BOARDS_TABLE
-    );
-    let result = sqlx::query(&query)
-        .bind(title)
-        .bind(description)
-        .bind(user_id)
-        .execute(db_link)
-        .await;
-
-    drop_user_boards_from_redis(redis_conn, user_id);
-    match result {
-        Ok(_) => {
-
-            if headers.contains_key("new_token") {
-                let token = headers.get("new_token").unwrap().to_str().unwrap();
-                let mut cookie = Cookie::new("x-auth", token);
-                cookie.set_max_age(Duration::seconds(TOKEN_LIFETIME));
-
-                HttpResponse::Ok().cookie(cookie).json(ServerResponse {
-                    status: 200, 
-                    message: String::from("Board created")
-                })
-            } else {
-                HttpResponse::Ok().json(ServerResponse {
-                    status: 200, 
-                    message: String::from("Board created")
-                })
-            }
-        }, 
-        Err(db_error) => {
-            log::error!("Database issue: {:?}", db_error);
-            HttpResponse::InternalServerError().json(ServerResponse {
-                status: 500, 
-                message: String::from("Internal server error")
-            })
-        }
-    }
+    let cache = redis_db.get_ref();
+
+    let result = repository.create_board(user_id, &title, &description).await;
+
+    drop_user_boards_from_redis(cache, user_id).await;
+    result?;
+
+    Ok(HttpResponse::Ok().json(ServerResponse {
+        status: 200, 
+        message: String::from("Board created")
+    }))
 }
 
 async fn handle_change_board(
-    request: HttpRequest,
-    postgres_db: Data<PersistentDB>, 
+    auth_user: AuthUser,
+    repository: Data<Repository>, 
     redis_db: Data<CacheDB>, 
-    board_data: Json<UpdateBoardBody>) -> impl Responder {
+    board_data: Json<UpdateBoardBody>) -> Result<HttpResponse, AppError> {
 
     let UpdateBoardBody{id, title, description} = board_data.0;
-    let headers = request.headers();
-    let user_id: Uuid = headers.get("user_id").unwrap().to_str().unwrap().parse().unwrap();
+    let user_id = auth_user.user_id;
 
     log::info!("User {} tried to change board {}", user_id, id);
 
-    let db_link = &*postgres_db.db.lock().unwrap();
-    let redis_conn = &mut *redis_db.db.lock().unwrap();
-
-    let board_to_be_update_status = sqlx::query(
-        &format!("
-        SELECT 
-            id
-            FROM {}.{}
-            WHERE id = $1 AND owner_id = $2 AND status_id = 0
-        ", 
-        APP_SCHEMA, 
-        BOARDS_TABLE)
-    )
-        .bind(id)
-        .bind(user_id)
-        .fetch_one(db_link)
-        .await;
-
-    match board_to_be_update_status {
-        Ok(_) => {
-
-            let query = format!("
-                UPDATE {}.{}
-                    SET title = $2, description = $3
-                    WHERE id = $1 
-                    AND owner_id = $4 
-                    AND status_id = 0
-                ", 
-                APP_SCHEMA, 
-                BOARDS_TABLE
-            );
-            let result = sqlx::query(&query)
-                .bind(id)
-                .bind(title)
-                .bind(description)
-                .bind(user_id)
-                .execute(db_link)
-                .await;
-
-            drop_user_boards_from_redis(redis_conn, user_id);
-            match result {
-                Ok(_) => {
-                    if headers.contains_key("new_token") {
-                        let token = headers.get("new_token").unwrap().to_str().unwrap();
-                        let mut cookie = Cookie::new("x-auth", token);
-                        cookie.set_max_age(Duration::seconds(TOKEN_LIFETIME));
-
-                        HttpResponse::Ok().cookie(cookie).json(ServerResponse {
-                            status: 200, 
-                            message: String::from("Board updated")
-                        })
-                    } else {
-                        HttpResponse::Ok().json(ServerResponse {
-                            status: 200, 
-                            message: String::from("Board updated")
-                        })
-                    }
-                }, 
-                Err(db_error) => {
-                    log::error!("Database issue: {:?}", db_error);
-                    HttpResponse::InternalServerError().json(ServerResponse {
-                        status: 500, 
-                        message: String::from("Internal server error")
-                    })
-                }
-            }
-        }, 
-        Err(_) => {
-            HttpResponse::BadRequest().json(ServerResponse {
-                status: 400, 
-                message: String::from("Invalid request")
-            })
-        }
+    let cache = redis_db.get_ref();
+
+    let result = repository.update_board(id, user_id, &title, &description).await;
+
+    drop_user_boards_from_redis(cache, user_id).await;
+    if !result? {
+        return Err(AppError::NotFound(String::from("Board not found")));
     }
-    
+
+    Ok(HttpResponse::Ok().json(ServerResponse {
+        status: 200, 
+        message: String::from("Board updated")
+    }))
 }
 
 async fn handle_delete_board(
-    request: HttpRequest,
-    postgres_db: Data<PersistentDB>, 
+    auth_user: AuthUser,
+    repository: Data<Repository>, 
     redis_db: Data<CacheDB>, 
-    board_data: Json<DeleteBoardBody>) -> impl Responder {
+    board_data: Json<DeleteBoardBody>) -> Result<HttpResponse, AppError> {
 
     let DeleteBoardBody {id} = board_data.0;
-    let headers = request.headers();
-    let user_id: Uuid = headers.get("user_id").unwrap().to_str().unwrap().parse().unwrap();
+    let user_id = auth_user.user_id;
 
-    let db_link = &*postgres_db.db.lock().unwrap();
-    let redis_conn = &mut *redis_db.db.lock().unwrap();
+    let cache = redis_db.get_ref();
 
     log::info!("User {} tried to delete board {}", user_id, id);
 
-    let board_is_valid_check = sqlx::query(
-        &format!("
-        SELECT 
-            id
-            FROM {}.{}
-            WHERE id = $1 AND owner_id = $2 AND status_id = 0
-        ", 
-        APP_SCHEMA, 
-        BOARDS_TABLE)
-    )   
-        .bind(id)
-        .bind(user_id)
-        .fetch_one(db_link)
-        .await;
-
-    match board_is_valid_check {
-        Ok(_) => {
-
-            let query = format!("
-                UPDATE {}.{}
-                    SET status_id = 1
-                    WHERE id = $1 
-                    AND owner_id = $2 
-                    AND status_id = 0
-                ", 
-                APP_SCHEMA, 
-                BOARDS_TABLE
-            );
-            let result = sqlx::query(&query)
-                .bind(id)
-                .bind(user_id)
-                .execute(db_link)
-                .await;
-            
-            drop_user_boards_from_redis(redis_conn, user_id);
-            match result {
-                Ok(_) => {
-                    if headers.contains_key("new_token") {
-                        let token = headers.get("new_token").unwrap().to_str().unwrap();
-                        let mut cookie = Cookie::new("x-auth", token);
-                        cookie.set_max_age(Duration::seconds(TOKEN_LIFETIME));
-
-                        HttpResponse::Ok().cookie(cookie).json(ServerResponse {
-                            status: 200, 
-                            message: String::from("Board deleted")
-                        })
-                    } else {
-                        HttpResponse::Ok().json(ServerResponse {
-                            status: 200, 
-                            message: String::from("Board deleted")
-                        })
-                    }
-                }, 
-                Err(db_error) => {
-                    log::error!("Database issue: {:?}", db_error);
-                    HttpResponse::InternalServerError().json(ServerResponse {
-                        status: 500, 
-                        message: String::from("Internal server error")
-                    })
-                }
-            }
-        }, 
-        Err(_) => {
-            HttpResponse::BadRequest().json(ServerResponse {
-                status: 400, 
-                message: String::from("Invalid request")
-            })
+    let result = repository.delete_board(id, user_id).await;
+    
+    drop_user_boards_from_redis(cache, user_id).await;
+    if !result? {
+        return Err(AppError::NotFound(String::from("Board not found")));
+    }
+
+    Ok(HttpResponse::Ok().json(ServerResponse {
+        status: 200, 
+        message: String::from("Board deleted")
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{dev::Service, test, App, HttpMessage};
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::personal_tokens::AVAILABLE_SCOPES;
+    use crate::settings::init_test_settings;
+
+    // the authorization middleware is replaced by a fixed identity
+    async fn call_as(
+        user_id: Uuid, 
+        repository: &Data<Repository>, 
+        cache: &Data<CacheDB>, 
+        request: test::TestRequest) -> actix_web::dev::ServiceResponse {
+
+        call_with_scopes(user_id, &AVAILABLE_SCOPES, repository, cache, request).await
+    }
+
+    // as a personal token limited to the given scopes
+    async fn call_with_scopes(
+        user_id: Uuid, 
+        scopes: &[&str], 
+        repository: &Data<Repository>, 
+        cache: &Data<CacheDB>, 
+        request: test::TestRequest) -> actix_web::dev::ServiceResponse {
+
+        let scopes = scopes.iter().map(|scope| scope.to_string()).collect::<Vec<String>>();
+        let app = test::init_service(
+            App::new()
+                .app_data(repository.clone())
+                .app_data(cache.clone())
+                .wrap_fn(move |request, service| {
+                    request.extensions_mut().insert(AuthUser::from_personal_token(user_id, scopes.clone()));
+                    service.call(request)
+                })
+                .configure(boards_managing)
+        ).await;
+        test::call_service(&app, request.to_request()).await
+    }
+
+    fn storage() -> (Data<Repository>, Data<CacheDB>) {
+        init_test_settings();
+        (Data::new(Repository::memory()), Data::new(CacheDB::new(None)))
+    }
+
+    #[actix_web::test]
+    async fn created_board_is_listed() {
+        let (repository, cache) = storage();
+        let user_id = Uuid::new_v4();
+
+        let create = test::TestRequest::post()
+            .uri("/create_board")
+            .set_json(serde_json::json!({ "title": "Home", "description": "Chores" }));
+        assert_eq!(call_as(user_id, &repository, &cache, create).await.status(), 200);
+
+        let response = call_as(user_id, &repository, &cache, test::TestRequest::get().uri("/user_boards")).await;
+        assert_eq!(response.status(), 200);
+        let boards: Vec<Board> = test::read_body_json(response).await;
+        assert_eq!(boards.len(), 1);
+        assert_eq!(boards[0].title, "Home");
+        assert_eq!(boards[0].description, "Chores");
+    }
+
+    #[actix_web::test]
+    async fn changed_board_is_not_served_from_stale_cache() {
+        let (repository, cache) = storage();
+        let user_id = Uuid::new_v4();
+        let board_id = repository.create_board(user_id, "Home", "").await.unwrap();
+
+        // fills the cache
+        call_as(user_id, &repository, &cache, test::TestRequest::get().uri("/user_boards")).await;
+
+        let change = test::TestRequest::put()
+            .uri("/change_board")
+            .set_json(serde_json::json!({ "id": board_id, "title": "Work", "description": "Projects" }));
+        assert_eq!(call_as(user_id, &repository, &cache, change).await.status(), 200);
+
+        let response = call_as(user_id, &repository, &cache, test::TestRequest::get().uri("/user_boards")).await;
+        let boards: Vec<Board> = test::read_body_json(response).await;
+        assert_eq!(boards[0].title, "Work");
+    }
+
+    #[actix_web::test]
+    async fn foreign_board_reads_as_missing() {
+        let (repository, cache) = storage();
+        let owner_id = Uuid::new_v4();
+        let stranger_id = Uuid::new_v4();
+        let board_id = repository.create_board(owner_id, "Home", "").await.unwrap();
+
+        let change = test::TestRequest::put()
+            .uri("/change_board")
+            .set_json(serde_json::json!({ "id": board_id, "title": "Mine", "description": "" }));
+        assert_eq!(call_as(stranger_id, &repository, &cache, change).await.status(), 404);
+
+        let delete = test::TestRequest::delete()
+            .uri("/delete_board")
+            .set_json(serde_json::json!({ "id": board_id }));
+        assert_eq!(call_as(stranger_id, &repository, &cache, delete).await.status(), 404);
+
+        let response = call_as(stranger_id, &repository, &cache, test::TestRequest::get().uri("/user_boards")).await;
+        let boards: Vec<Board> = test::read_body_json(response).await;
+        assert!(boards.is_empty());
+    }
+
+    #[actix_web::test]
+    async fn deleted_board_disappears() {
+        let (repository, cache) = storage();
+        let user_id = Uuid::new_v4();
+        let board_id = repository.create_board(user_id, "Home", "").await.unwrap();
+
+        let delete = test::TestRequest::delete()
+            .uri("/delete_board")
+            .set_json(serde_json::json!({ "id": board_id }));
+        assert_eq!(call_as(user_id, &repository, &cache, delete).await.status(), 200);
+
+        let response = call_as(user_id, &repository, &cache, test::TestRequest::get().uri("/user_boards")).await;
+        let boards: Vec<Board> = test::read_body_json(response).await;
+        assert!(boards.is_empty());
+
+        let delete_again = test::TestRequest::delete()
+            .uri("/delete_board")
+            .set_json(serde_json::json!({ "id": board_id }));
+        assert_eq!(call_as(user_id, &repository, &cache, delete_again).await.status(), 404);
+    }
+
+    #[actix_web::test]
+    async fn request_without_identity_is_unauthorized() {
+        let (repository, cache) = storage();
+        let app = test::init_service(
+            App::new()
+                .app_data(repository.clone())
+                .app_data(cache.clone())
+                .configure(boards_managing)
+        ).await;
+        let response = test::call_service(&app, test::TestRequest::get().uri("/user_boards").to_request()).await;
+        assert_eq!(response.status(), 401);
+    }
+
+    #[actix_web::test]
+    async fn routes_need_matching_scope() {
+        let (repository, cache) = storage();
+        let user_id = Uuid::new_v4();
+        let routes = [
+            (SCOPE_BOARDS_READ, test::TestRequest::get().uri("/user_boards")), 
+            (SCOPE_BOARDS_WRITE, test::TestRequest::post().uri("/create_board")
+                .set_json(serde_json::json!({ "title": "Home", "description": "" }))), 
+            (SCOPE_BOARDS_WRITE, test::TestRequest::put().uri("/change_board")
+                .set_json(serde_json::json!({ "id": 1, "title": "Work", "description": "" }))), 
+            (SCOPE_BOARDS_WRITE, test::TestRequest::delete().uri("/delete_board")
+                .set_json(serde_json::json!({ "id": 1 })))
+        ];
+        for (required_scope, request) in routes {
+            let other_scopes = AVAILABLE_SCOPES
+                .into_iter()
+                .filter(|scope| *scope != required_scope)
+                .collect::<Vec<&str>>();
+            let refused = call_with_scopes(user_id, &other_scopes, &repository, &cache, request).await;
+            assert_eq!(refused.status(), 403, "{} {}", refused.request().method(), refused.request().path());
         }
+
+        let create = test::TestRequest::post()
+            .uri("/create_board")
+            .set_json(serde_json::json!({ "title": "Home", "description": "" }));
+        assert_eq!(call_with_scopes(user_id, &[SCOPE_BOARDS_WRITE], &repository, &cache, create).await.status(), 200);
+        let list = test::TestRequest::get().uri("/user_boards");
+        assert_eq!(call_with_scopes(user_id, &[SCOPE_BOARDS_READ], &repository, &cache, list).await.status(), 200);
     }
 }
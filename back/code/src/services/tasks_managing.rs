@@ -1,13 +1,14 @@
 use actix_web::{
     web::{self, Data, Json}, 
-    Responder, HttpRequest, HttpResponse, 
-    cookie::{time::Duration, Cookie}
+    HttpRequest, HttpResponse
 };
-use log;
-use sqlx::{self, Row};
-use uuid::Uuid;
 
-use crate::{PersistentDB, CacheDB, APP_SCHEMA, BOARDS_TABLE, TASKS_TABLE, TOKEN_LIFETIME, models::Board};
+use crate::{CacheDB, models::Board};
+use crate::errors::AppError;
+use crate::auth_user::AuthUser;
+use crate::personal_tokens::{RequiredScope, SCOPE_TASKS_READ, SCOPE_TASKS_WRITE};
+use crate::repositories::{Repository, BoardRepository, TaskRepository};
+use crate::tools::parse_header;
 use crate::redis_handlers::{
     get_board_tasks_from_redis, 
     put_board_tasks_to_redis, 
@@ -15,7 +16,7 @@ use crate::redis_handlers::{
     get_user_boards_from_redis
 };
 use crate::models::{
-    ServerResponse, Task, StoredTask, 
+    ServerResponse, Task, 
     CreateTaskBody, UpdateTaskBody, DeleteTaskBody
 };
 
@@ -23,154 +24,82 @@ pub fn tasks_managing(cfg: &mut web::ServiceConfig) {
     cfg
         .service(
             web::resource("/board_tasks")
+                .app_data(RequiredScope(SCOPE_TASKS_READ))
                 .route(web::get().to(handle_board_tasks))
         ).service(
             web::resource("/task/{task_id}")
+                .app_data(RequiredScope(SCOPE_TASKS_READ))
                 .route(web::get().to(handle_task))
         )
         .service(
             web::resource("/create_task")
+                .app_data(RequiredScope(SCOPE_TASKS_WRITE))
                 .route(web::post().to(handle_create_task))
         )
         .service(
             web::resource("/change_task")
+                .app_data(RequiredScope(SCOPE_TASKS_WRITE))
                 .route(web::put().to(handle_change_task))
         )
         .service(
             web::resource("/delete_task")
+                .app_data(RequiredScope(SCOPE_TASKS_WRITE))
                 .route(web::delete().to(handle_delete_task))
         );
 }
 
 async fn handle_board_tasks(
     request: HttpRequest,
-    postgres_db: Data<PersistentDB>, 
-    redis_db: Data<CacheDB>) -> impl Responder {
+    auth_user: AuthUser,
+    repository: Data<Repository>, 
+    redis_db: Data<CacheDB>) -> Result<HttpResponse, AppError> {
 
-    let headers = request.headers();
-    let user_id: Uuid = headers.get("user_id").unwrap().to_str().unwrap().parse().unwrap();
-    let board_id: i32 = headers.get("BoardId").unwrap().to_str().unwrap().parse().unwrap();
+    let user_id = auth_user.user_id;
+    let board_id: i32 = parse_header(&request, "BoardId")?;
 
-    let db_link = &*postgres_db.db.lock().unwrap();
-    let redis_conn = &mut *redis_db.db.lock().unwrap();
+    let cache = redis_db.get_ref();
 
     log::info!("Tasks from board {} requested by user {}", board_id, user_id);
 
-    let is_valid_board = sqlx::query(
-        &format!("
-            SELECT 
-                id
-            FROM {}.{} 
-            WHERE id = $1 
-            AND owner_id = $2 
-            AND status_id = 0", 
-            APP_SCHEMA, 
-            BOARDS_TABLE
-        )
-    )
-        .bind(board_id)
-        .bind(user_id)
-        .fetch_one(db_link)
-        .await;
-
-    match is_valid_board {
-        Ok(_) => {
-            let redis_data = get_board_tasks_from_redis(redis_conn, board_id);
-            if let Ok(redis_tasks_list) = redis_data {
-                if redis_tasks_list.len() > 0 {
-                    if headers.contains_key("new_token") {
-                        let token = headers.get("new_token").unwrap().to_str().unwrap();
-                        let mut cookie = Cookie::new("x-auth", token);
-                        cookie.set_max_age(Duration::seconds(TOKEN_LIFETIME));
-
-                        return HttpResponse::Ok().cookie(cookie).json(redis_tasks_list);
-                    } else {
-                        return HttpResponse::Ok().json(redis_tasks_list);
-                    }
-                }
-            }
+    if !repository.board_exists(board_id, user_id).await? {
+        return Err(AppError::NotFound(String::from("Board not found")));
+    }
 
-            let query = format!("
-                SELECT 
-                    t.id, t.title, t.description, t.board_id, t.status_id, t.creation_time, t.last_status_change_time
-                    FROM {APP_SCHEMA}.{TASKS_TABLE} t
-            INNER JOIN {APP_SCHEMA}.{BOARDS_TABLE} b
-                    ON t.board_id = b.id
-                    WHERE t.status_id != 4 
-                    AND t.board_id = $1 
-                    AND b.owner_id = $2
-                    ORDER BY creation_time
-            ");
-            let result = sqlx::query(&query)
-                .bind(board_id)
-                .bind(user_id)
-                .map(|row| {
-                    StoredTask {
-                        id: row.get("id"),
-                        title: row.get("title"),
-                        description: row.get("description"),
-                        board_id: row.get("board_id"), 
-                        status_id: row.get("status_id"), 
-                        creation_time: row.get("creation_time"),
-                        last_status_change_time: row.get("last_status_change_time")
-                    } 
-                })
-                .fetch_all(db_link)
-                .await; 
-
-            match result {
-                Ok(stored_task_list) => {
-                    
-                    let mut tasks_list: Vec<Task> = vec![];
-                    for stored_task in stored_task_list.iter() {
-                        let task_to_return = stored_task.get_task();
-                        tasks_list.push(task_to_return);
-                    }
-                    put_board_tasks_to_redis(redis_conn, board_id, &tasks_list);
+    let redis_data = get_board_tasks_from_redis(cache, board_id).await;
+    if let Ok(redis_tasks_list) = redis_data {
+        if !redis_tasks_list.is_empty() {
+            return Ok(HttpResponse::Ok().json(redis_tasks_list));
+        }
+    }
 
-                    if headers.contains_key("new_token") {
-                        let token = headers.get("new_token").unwrap().to_str().unwrap();
-                        let mut cookie = Cookie::new("x-auth", token);
-                        cookie.set_max_age(Duration::seconds(TOKEN_LIFETIME));
+    let stored_task_list = repository.get_board_tasks(board_id, user_id).await?; 
 
-                        HttpResponse::Ok().cookie(cookie).json(tasks_list)
-                    } else {
-                        HttpResponse::Ok().json(tasks_list)
-                    }
-                }, 
-                Err(db_error) => {
-                    log::error!("Database issue: {:?}", db_error);
-                    HttpResponse::InternalServerError().json(ServerResponse {
-                        status: 500, 
-                        message: String::from("Internal server error")
-                    })
-                }
-            }
-        }, 
-        Err(_) => {
-            HttpResponse::BadRequest().json(ServerResponse {
-                status: 400, 
-                message: String::from("Invalid request")
-            })
-        }
+    let mut tasks_list: Vec<Task> = vec![];
+    for stored_task in stored_task_list.iter() {
+        let task_to_return = stored_task.get_task();
+        tasks_list.push(task_to_return);
     }
+    if let Err(redis_error) = put_board_tasks_to_redis(cache, board_id, &tasks_list).await {
+        log::warn!("Cache database issue: {:?}", redis_error);
+    }
+
+    Ok(HttpResponse::Ok().json(tasks_list))
 }
 
 async fn handle_task(
     request: HttpRequest,
-    postgres_db: Data<PersistentDB>, 
+    auth_user: AuthUser,
+    repository: Data<Repository>, 
     redis_db: Data<CacheDB>,
-    request_path: web::Path<i32>) -> impl Responder {
+    request_path: web::Path<i32>) -> Result<HttpResponse, AppError> {
 
-    let headers = request.headers();
-    let user_id: Uuid = headers.get("user_id").unwrap().to_str().unwrap().parse().unwrap();
-    let board_id: i32 = headers.get("BoardId").unwrap().to_str().unwrap().parse().unwrap();
+    let user_id = auth_user.user_id;
+    let board_id: i32 = parse_header(&request, "BoardId")?;
     let task_id = request_path.into_inner();
 
-    let db_link = &*postgres_db.db.lock().unwrap();
-    let redis_conn = &mut *redis_db.db.lock().unwrap();
+    let cache = redis_db.get_ref();
 
-    if let Ok(user_boards) = get_user_boards_from_redis(redis_conn, user_id) {
+    if let Ok(user_boards) = get_user_boards_from_redis(cache, user_id).await {
         let mut target_board: Option<Board> = None;
         for board in user_boards {
             if board.id == board_id {
@@ -178,400 +107,287 @@ async fn handle_task(
                 break;
             }
         }
-        match target_board {
-            Some(_) => {
-                if let Ok(tasks) = get_board_tasks_from_redis(redis_conn, board_id) {
-                    for task in tasks {
-                        if task.id == task_id {
-                            if headers.contains_key("new_token") {
-                                let token = headers.get("new_token").unwrap().to_str().unwrap();
-                                let mut cookie = Cookie::new("x-auth", token);
-                                cookie.set_max_age(Duration::seconds(TOKEN_LIFETIME));
-        
-                                return HttpResponse::Ok().cookie(cookie).json(task);
-                            } else {
-                                return HttpResponse::Ok().json(task);
-                            }
-                        }
+        if target_board.is_some() {
+            if let Ok(tasks) = get_board_tasks_from_redis(cache, board_id).await {
+                for task in tasks {
+                    if task.id == task_id {
+                        return Ok(HttpResponse::Ok().json(task));
                     }
                 }
-            }, 
-            None => ()
             }
         }
+    }
 
-    let is_valid_task = sqlx::query(
-        &format!("
-            SELECT 
-                t.id
-            FROM {APP_SCHEMA}.{TASKS_TABLE} t
-            INNER JOIN {APP_SCHEMA}.{BOARDS_TABLE} b
-            ON t.board_id = b.id
-            WHERE t.id = $1 
-            AND t.board_id = $2
-            AND b.owner_id = $3 
-            AND t.status_id != 4
-            AND b.status_id = 0"
-        )
-    )
-        .bind(task_id)
-        .bind(board_id)
-        .bind(user_id)
-        .fetch_one(db_link)
-        .await;
-
-    match is_valid_task {
-        Ok(_) => {
-            let query = format!("
-                SELECT 
-                    t.id, t.title, t.description, t.board_id, t.status_id, t.creation_time, t.last_status_change_time
-                FROM {APP_SCHEMA}.{TASKS_TABLE} t
-                WHERE t.status_id != 4 
-                AND t.id = $1 
-            ");
-            let result = sqlx::query(&query)
-                .bind(task_id)
-                .map(|row| {
-                    StoredTask {
-                        id: row.get("id"),
-                        title: row.get("title"),
-                        description: row.get("description"),
-                        board_id: row.get("board_id"), 
-                        status_id: row.get("status_id"), 
-                        creation_time: row.get("creation_time"), 
-                        last_status_change_time: row.get("last_status_change_time")
-                    } 
-                })
-                .fetch_all(db_link)
-                .await; 
-
-            match result {
-                Ok(tasks) => {
-                    let target_task = tasks[0].get_task();
-
-                    put_board_tasks_to_redis(redis_conn, board_id, &[target_task.clone()]);
-
-                    if headers.contains_key("new_token") {
-                        let token = headers.get("new_token").unwrap().to_str().unwrap();
-                        let mut cookie = Cookie::new("x-auth", token);
-                        cookie.set_max_age(Duration::seconds(TOKEN_LIFETIME));
-
-                        HttpResponse::Ok().cookie(cookie).json(target_task)
-                    } else {
-                        HttpResponse::Ok().json(target_task)
-                    }
-                }, 
-                Err(db_error) => {
-                    log::error!("Database issue: {:?}", db_error);
-                    HttpResponse::InternalServerError().json(ServerResponse {
-                        status: 500, 
-                        message: String::from("Internal server error")
-                    })
-                }
-            }
-        }, 
-        Err(_) => {
+    let stored_task = repository
+        .get_task(task_id, board_id, user_id)
+        .await?
+        .ok_or_else(|| {
             log::warn!("User {} tried to request non-matching values: task {} from board {}", user_id, task_id, board_id);
-            HttpResponse::BadRequest().json(ServerResponse {
-                status: 400, 
-                message: String::from("Invalid request")
-            })
-        }
+            AppError::NotFound(String::from("Task not found"))
+        })?;
+
+    let target_task = stored_task.get_task();
+    if let Err(redis_error) = put_board_tasks_to_redis(cache, board_id, std::slice::from_ref(&target_task)).await {
+        log::warn!("Cache database issue: {:?}", redis_error);
     }
+
+    Ok(HttpResponse::Ok().json(target_task))
 }
 
 async fn handle_create_task(
-    request: HttpRequest,
-    postgres_db: Data<PersistentDB>, 
+    auth_user: AuthUser,
+    repository: Data<Repository>, 
     redis_db: Data<CacheDB>, 
-    task_data: Json<CreateTaskBody>) -> impl Responder {
+    task_data: Json<CreateTaskBody>) -> Result<HttpResponse, AppError> {
 
     let CreateTaskBody {board_id, title, description } = task_data.0;
-    let headers = request.headers();
-    let user_id: Uuid = headers.get("user_id").unwrap().to_str().unwrap().parse().unwrap();
+    let user_id = auth_user.user_id;
 
     log::info!("User {} tried to create new task on board {}", user_id, board_id);
 
-    let db_link = &*postgres_db.db.lock().unwrap();
-    let redis_conn = &mut *redis_db.db.lock().unwrap();
-    
-    let is_valid_board = sqlx::query(
-        &format!("
-            SELECT 
-                id
-            FROM {}.{} 
-            WHERE id = $1 
-            AND owner_id = $2 
-            AND status_id = 0", 
-            APP_SCHEMA, 
-            BOARDS_TABLE
-        )
-    )
-        .bind(board_id)
-        .bind(user_id)
-        .fetch_one(db_link)
-        .await;
+    let cache = redis_db.get_ref();
     
-    match is_valid_board {
-        Ok(_) => {
-            let query = format!("
-                INSERT INTO {}.{} 
-                    (title, description, board_id, status_id) 
-                VALUES ($1, $2, $3, 0)", 
-                APP_SCHEMA, 
-                TASKS_TABLE
-            );
-            let result = sqlx::query(&query)
-                .bind(title)
-                .bind(description)
-                .bind(board_id)
-                .execute(db_link)
-                .await;
-
-            drop_board_tasks_from_redis(redis_conn, board_id);
-            match result {
-                Ok(_) => {
-                    if headers.contains_key("new_token") {
-                        let token = headers.get("new_token").unwrap().to_str().unwrap();
-                        let mut cookie = Cookie::new("x-auth", token);
-                        cookie.set_max_age(Duration::seconds(TOKEN_LIFETIME));
-
-                        HttpResponse::Ok().cookie(cookie).json(ServerResponse {
-                            status: 200, 
-                            message: String::from("Task created")
-                        })
-                    } else {
-                        HttpResponse::Ok().json(ServerResponse {
-                            status: 200, 
-                            message: String::from("Task created")
-                        })
-                    }
-                },
-                Err(db_error) => {
-                    log::error!("Database issue: {:?}", db_error);
-                    HttpResponse::InternalServerError().json(ServerResponse {
-                        status: 500, 
-                        message: String::from("Internal server error")
-                    })
-                }
-            }
-        }, 
-        Err(_) => {
-            HttpResponse::BadRequest().json(ServerResponse {
-                status: 400, 
-                message: String::from("Invalid request")
-            })
-        }
+    if !repository.board_exists(board_id, user_id).await? {
+        return Err(AppError::NotFound(String::from("Board not found")));
     }
 
+    let result = repository.create_task(board_id, &title, &description).await;
+
+    drop_board_tasks_from_redis(cache, board_id).await;
+    result?;
+
+    Ok(HttpResponse::Ok().json(ServerResponse {
+        status: 200, 
+        message: String::from("Task created")
+    }))
 }
 
 async fn handle_change_task(
-    request: HttpRequest,
-    postgres_db: Data<PersistentDB>, 
+    auth_user: AuthUser,
+    repository: Data<Repository>, 
     redis_db: Data<CacheDB>, 
-    task_data: Json<UpdateTaskBody>) -> impl Responder {
+    task_data: Json<UpdateTaskBody>) -> Result<HttpResponse, AppError> {
 
     let UpdateTaskBody { 
         id, board_id, title, description, status_id 
     } = task_data.0;
-    let headers = request.headers();
-    let user_id: Uuid = headers.get("user_id").unwrap().to_str().unwrap().parse().unwrap();
+    let user_id = auth_user.user_id;
 
     log::info!("User {} tried to change task {}", user_id, id);
 
-    let db_link = &*postgres_db.db.lock().unwrap();
-    let redis_conn = &mut *redis_db.db.lock().unwrap();
-
-    let task_to_be_update_status = sqlx::query(
-        &format!("
-            SELECT 
-                t.status_id
-                FROM {APP_SCHEMA}.{TASKS_TABLE} t
-        INNER JOIN {APP_SCHEMA}.{BOARDS_TABLE} b
-                ON t.board_id = b.id
-                WHERE t.status_id != 4 
-                AND t.id = $1
-                AND t.board_id = $2 
-                AND b.owner_id = $3")
-    )
-        .bind(id)
-        .bind(board_id)
-        .bind(user_id)
-        .fetch_one(db_link)
-        .await;
-
-    match task_to_be_update_status {
-        Ok(record) => {
-            let result = if record.get::<i32, &str>("status_id") == status_id { // status wasn't changed
-                let query = format!("
-                    UPDATE {APP_SCHEMA}.{TASKS_TABLE} t
-                        SET title = $2, description = $3
-                        FROM {APP_SCHEMA}.{BOARDS_TABLE} b
-                        WHERE b.id = t.board_id 
-                        AND t.id = $1 
-                        AND t.board_id = $4 
-                        AND b.owner_id = $5 
-                        AND b.status_id = 0 
-                        AND t.status_id != 4"
-                );
-                sqlx::query(&query)
-                    .bind(id)
-                    .bind(title)
-                    .bind(description)
-                    .bind(board_id)
-                    .bind(user_id)
-                    .execute(db_link)
-                    .await
-
-            } else { // status was changed
-                let query = format!("
-                    UPDATE {APP_SCHEMA}.{TASKS_TABLE} t
-                        SET title = $2, description = $3, status_id = $6
-                        FROM {APP_SCHEMA}.{BOARDS_TABLE} b
-                        WHERE b.id = t.board_id 
-                        AND t.id = $1 
-                        AND t.board_id = $4 
-                        AND b.owner_id = $5 
-                        AND b.status_id = 0 
-                        AND t.status_id != 4"
-                );
-                sqlx::query(&query)
-                    .bind(id)
-                    .bind(title)
-                    .bind(description)
-                    .bind(board_id)
-                    .bind(user_id)
-                    .bind(status_id)
-                    .execute(db_link)
-                    .await
-            };
-
-            drop_board_tasks_from_redis(redis_conn, board_id);
-            match result {
-                Ok(_) => {
-                    if headers.contains_key("new_token") {
-                        let token = headers.get("new_token").unwrap().to_str().unwrap();
-                        let mut cookie = Cookie::new("x-auth", token);
-                        cookie.set_max_age(Duration::seconds(TOKEN_LIFETIME));
-
-                        HttpResponse::Ok().cookie(cookie).json(ServerResponse {
-                            status: 200, 
-                            message: String::from("Task updated")
-                        })
-                    } else {
-                        HttpResponse::Ok().json(ServerResponse {
-                            status: 200, 
-                            message: String::from("Task updated")
-                        })
-                    }
-                },
-                Err(db_error) => {
-                    log::error!("Database issue: {:?}", db_error);
-                    HttpResponse::InternalServerError().json(ServerResponse {
-                        status: 500, 
-                        message: String::from("Internal server error")
-                    })
-                }
-            }
-        }, 
-        Err(_) => {
-            HttpResponse::BadRequest().json(ServerResponse {
-                status: 400, 
-                message: String::from("Invalid request")
-            })
-        }
+    let cache = redis_db.get_ref();
+
+    let result = repository.update_task(id, board_id, user_id, &title, &description, status_id).await;
+
+    drop_board_tasks_from_redis(cache, board_id).await;
+    if !result? {
+        return Err(AppError::NotFound(String::from("Task not found")));
     }
 
+    Ok(HttpResponse::Ok().json(ServerResponse {
+        status: 200, 
+        message: String::from("Task updated")
+    }))
 }
 
 async fn handle_delete_task(
-    request: HttpRequest,
-    postgres_db: Data<PersistentDB>, 
+    auth_user: AuthUser,
+    repository: Data<Repository>, 
     redis_db: Data<CacheDB>, 
-    task_data: Json<DeleteTaskBody>) -> impl Responder {
+    task_data: Json<DeleteTaskBody>) -> Result<HttpResponse, AppError> {
 
     let DeleteTaskBody {id, board_id } = task_data.0;
-    let headers = request.headers();
-    let user_id: Uuid = headers.get("user_id").unwrap().to_str().unwrap().parse().unwrap();
+    let user_id = auth_user.user_id;
 
     log::info!("User {} tried to delete task {}", user_id, id);
 
-    let db_link = &*postgres_db.db.lock().unwrap();
-    let redis_conn = &mut *redis_db.db.lock().unwrap();
-
-    let task_is_valid_check = sqlx::query(
-        &format!("   
-            SELECT 
-                t.id
-                FROM {APP_SCHEMA}.{TASKS_TABLE} t
-        INNER JOIN {APP_SCHEMA}.{BOARDS_TABLE} b
-                ON t.board_id = b.id
-                WHERE t.status_id != 4 
-                AND t.id = $1
-                AND t.board_id = $2 
-                AND b.owner_id = $3")
-    )
-        .bind(id)
-        .bind(board_id)
-        .bind(user_id)
-        .fetch_one(db_link)
-        .await;
-
-    match task_is_valid_check {
-        Ok(_) => {
-            let query = format!("
-                UPDATE {APP_SCHEMA}.{TASKS_TABLE} t
-                    SET status_id = 4
-                    FROM {APP_SCHEMA}.{BOARDS_TABLE} b
-                    WHERE b.id = t.board_id 
-                    AND t.id = $1 
-                    AND t.board_id = $2 
-                    AND b.owner_id = $3 
-                    AND b.status_id = 0
-                    AND t.status_id != 4"
-            ); 
-            let result = sqlx::query(&query)
-                .bind(id)
-                .bind(board_id)
-                .bind(user_id)
-                .execute(db_link)
-                .await;
-
-            drop_board_tasks_from_redis(redis_conn, board_id);
-            match result {
-                Ok(_) => {
-                    if headers.contains_key("new_token") {
-                        let token = headers.get("new_token").unwrap().to_str().unwrap();
-                        let mut cookie = Cookie::new("x-auth", token);
-                        cookie.set_max_age(Duration::seconds(TOKEN_LIFETIME));
-
-                        HttpResponse::Ok().cookie(cookie).json(ServerResponse {
-                            status: 200, 
-                            message: String::from("Task deleted")
-                        })
-                    } else {
-                        HttpResponse::Ok().json(ServerResponse {
-                            status: 200, 
-                            message: String::from("Task deleted")
-                        })
-                    }
-                },
-                Err(db_error) => {
-                    log::error!("Database issue: {:?}", db_error);
-                    HttpResponse::InternalServerError().json(ServerResponse {
-                        status: 500, 
-                        message: String::from("Internal server error")
-                    })
-                }
-            }
-        }, 
-        Err(_) => {
-            HttpResponse::BadRequest().json(ServerResponse {
-                status: 400, 
-                message: String::from("Invalid request")
-            })
+    let cache = redis_db.get_ref();
+
+    let result = repository.delete_task(id, board_id, user_id).await;
+
+    drop_board_tasks_from_redis(cache, board_id).await;
+    if !result? {
+        return Err(AppError::NotFound(String::from("Task not found")));
+    }
+
+    Ok(HttpResponse::Ok().json(ServerResponse {
+        status: 200, 
+        message: String::from("Task deleted")
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{dev::Service, test, App, HttpMessage};
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::personal_tokens::AVAILABLE_SCOPES;
+    use crate::settings::init_test_settings;
+
+    // the authorization middleware is replaced by a fixed identity
+    async fn call_as(
+        user_id: Uuid, 
+        repository: &Data<Repository>, 
+        cache: &Data<CacheDB>, 
+        request: test::TestRequest) -> actix_web::dev::ServiceResponse {
+
+        call_with_scopes(user_id, &AVAILABLE_SCOPES, repository, cache, request).await
+    }
+
+    // as a personal token limited to the given scopes
+    async fn call_with_scopes(
+        user_id: Uuid, 
+        scopes: &[&str], 
+        repository: &Data<Repository>, 
+        cache: &Data<CacheDB>, 
+        request: test::TestRequest) -> actix_web::dev::ServiceResponse {
+
+        let scopes = scopes.iter().map(|scope| scope.to_string()).collect::<Vec<String>>();
+        let app = test::init_service(
+            App::new()
+                .app_data(repository.clone())
+                .app_data(cache.clone())
+                .wrap_fn(move |request, service| {
+                    request.extensions_mut().insert(AuthUser::from_personal_token(user_id, scopes.clone()));
+                    service.call(request)
+                })
+                .configure(tasks_managing)
+        ).await;
+        test::call_service(&app, request.to_request()).await
+    }
+
+    fn storage() -> (Data<Repository>, Data<CacheDB>) {
+        init_test_settings();
+        (Data::new(Repository::memory()), Data::new(CacheDB::new(None)))
+    }
+
+    async fn board_tasks(user_id: Uuid, board_id: i32, repository: &Data<Repository>, cache: &Data<CacheDB>) -> Vec<Task> {
+        let request = test::TestRequest::get()
+            .uri("/board_tasks")
+            .insert_header(("BoardId", board_id.to_string()));
+        let response = call_as(user_id, repository, cache, request).await;
+        assert_eq!(response.status(), 200);
+        test::read_body_json(response).await
+    }
+
+    #[actix_web::test]
+    async fn task_goes_through_its_lifecycle() {
+        let (repository, cache) = storage();
+        let user_id = Uuid::new_v4();
+        let board_id = repository.create_board(user_id, "Home", "").await.unwrap();
+
+        let create = test::TestRequest::post()
+            .uri("/create_task")
+            .set_json(serde_json::json!({ "board_id": board_id, "title": "Dishes", "description": "Tonight" }));
+        assert_eq!(call_as(user_id, &repository, &cache, create).await.status(), 200);
+
+        let tasks = board_tasks(user_id, board_id, &repository, &cache).await;
+        assert_eq!(tasks.len(), 1);
+        assert_eq!(tasks[0].title, "Dishes");
+        let task_id = tasks[0].id;
+
+        let change = test::TestRequest::put()
+            .uri("/change_task")
+            .set_json(serde_json::json!({
+                "id": task_id, "board_id": board_id, "title": "Dishes", "description": "Done", "status_id": 2
+            }));
+        assert_eq!(call_as(user_id, &repository, &cache, change).await.status(), 200);
+
+        let request = test::TestRequest::get()
+            .uri(&format!("/task/{}", task_id))
+            .insert_header(("BoardId", board_id.to_string()));
+        let response = call_as(user_id, &repository, &cache, request).await;
+        assert_eq!(response.status(), 200);
+        let task: Task = test::read_body_json(response).await;
+        assert_eq!(task.description, "Done");
+        assert_eq!(task.status_id, 2);
+
+        let delete = test::TestRequest::delete()
+            .uri("/delete_task")
+            .set_json(serde_json::json!({ "id": task_id, "board_id": board_id }));
+        assert_eq!(call_as(user_id, &repository, &cache, delete).await.status(), 200);
+        assert!(board_tasks(user_id, board_id, &repository, &cache).await.is_empty());
+    }
+
+    #[actix_web::test]
+    async fn tasks_of_foreign_board_read_as_missing() {
+        let (repository, cache) = storage();
+        let owner_id = Uuid::new_v4();
+        let stranger_id = Uuid::new_v4();
+        let board_id = repository.create_board(owner_id, "Home", "").await.unwrap();
+        let task_id = repository.create_task(board_id, "Dishes", "").await.unwrap();
+
+        let list = test::TestRequest::get()
+            .uri("/board_tasks")
+            .insert_header(("BoardId", board_id.to_string()));
+        assert_eq!(call_as(stranger_id, &repository, &cache, list).await.status(), 404);
+
+        let get = test::TestRequest::get()
+            .uri(&format!("/task/{}", task_id))
+            .insert_header(("BoardId", board_id.to_string()));
+        assert_eq!(call_as(stranger_id, &repository, &cache, get).await.status(), 404);
+
+        let create = test::TestRequest::post()
+            .uri("/create_task")
+            .set_json(serde_json::json!({ "board_id": board_id, "title": "Mine", "description": "" }));
+        assert_eq!(call_as(stranger_id, &repository, &cache, create).await.status(), 404);
+
+        let change = test::TestRequest::put()
+            .uri("/change_task")
+            .set_json(serde_json::json!({
+                "id": task_id, "board_id": board_id, "title": "Mine", "description": "", "status_id": 1
+            }));
+        assert_eq!(call_as(stranger_id, &repository, &cache, change).await.status(), 404);
+
+        let delete = test::TestRequest::delete()
+            .uri("/delete_task")
+            .set_json(serde_json::json!({ "id": task_id, "board_id": board_id }));
+        assert_eq!(call_as(stranger_id, &repository, &cache, delete).await.status(), 404);
+
+        assert_eq!(board_tasks(owner_id, board_id, &repository, &cache).await.len(), 1);
+    }
+
+    #[actix_web::test]
+    async fn missing_board_header_is_rejected() {
+        let (repository, cache) = storage();
+        let response = call_as(Uuid::new_v4(), &repository, &cache, test::TestRequest::get().uri("/board_tasks")).await;
+        assert_eq!(response.status(), 400);
+    }
+
+    #[actix_web::test]
+    async fn routes_need_matching_scope() {
+        let (repository, cache) = storage();
+        let user_id = Uuid::new_v4();
+        let board_id = repository.create_board(user_id, "Home", "").await.unwrap();
+        let task_id = repository.create_task(board_id, "Dishes", "").await.unwrap();
+        let routes = [
+            (SCOPE_TASKS_READ, test::TestRequest::get().uri("/board_tasks")
+                .insert_header(("BoardId", board_id.to_string()))), 
+            (SCOPE_TASKS_READ, test::TestRequest::get().uri(&format!("/task/{}", task_id))
+                .insert_header(("BoardId", board_id.to_string()))), 
+            (SCOPE_TASKS_WRITE, test::TestRequest::post().uri("/create_task")
+                .set_json(serde_json::json!({ "board_id": board_id, "title": "Laundry", "description": "" }))), 
+            (SCOPE_TASKS_WRITE, test::TestRequest::put().uri("/change_task")
+                .set_json(serde_json::json!({
+                    "id": task_id, "board_id": board_id, "title": "Dishes", "description": "", "status_id": 2
+                }))), 
+            (SCOPE_TASKS_WRITE, test::TestRequest::delete().uri("/delete_task")
+                .set_json(serde_json::json!({ "id": task_id, "board_id": board_id })))
+        ];
+        for (required_scope, request) in routes {
+            let other_scopes = AVAILABLE_SCOPES
+                .into_iter()
+                .filter(|scope| *scope != required_scope)
+                .collect::<Vec<&str>>();
+            let refused = call_with_scopes(user_id, &other_scopes, &repository, &cache, request).await;
+            assert_eq!(refused.status(), 403, "{} {}", refused.request().method(), refused.request().path());
         }
+        assert_eq!(repository.get_board_tasks(board_id, user_id).await.unwrap().len(), 1);
+
+        let request = test::TestRequest::get()
+            .uri("/board_tasks")
+            .insert_header(("BoardId", board_id.to_string()));
+        assert_eq!(call_with_scopes(user_id, &[SCOPE_TASKS_READ], &repository, &cache, request).await.status(), 200);
     }
-    
 }
@@ -0,0 +1,390 @@
+use actix_web::HttpRequest;
+use base64::{Engine as _, engine::general_purpose};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use sqlx::{self, Executor, Postgres, Pool, Row};
+use uuid::Uuid;
+
+use crate::{APP_SCHEMA, USERS_TABLE, SESSIONS_TABLE, REFRESH_TOKENS_TABLE};
+use crate::convertations::AsHash;
+use crate::models::StoredSession;
+use crate::settings::settings;
+use crate::tools::generate_random_token;
+
+// A session is created on every login and is identified by `sid` claim of access tokens. 
+// Refresh tokens are opaque random strings, only their hashes are stored. 
+// Each refresh marks the presented token as used and issues the next one for the same session. 
+// The next token is derived from the presented one and a random salt kept with the used token, 
+// so concurrent refreshes from two tabs or a retried request get the same pair back 
+// within `tokens.refresh_token_grace_period`, as long as the next token hasn't been used itself. 
+// Otherwise a used token presented again means it was stolen, so the whole session gets revoked. 
+// Access tokens carry token generation of the user, bumping it invalidates all of them at once.
+
+pub enum RefreshOutcome {
+    Rotated { user_id: Uuid, session_id: Uuid, refresh_token: String }, 
+    Reused { user_id: Uuid, session_id: Uuid }, 
+    Invalid
+}
+
+pub async fn create_session(
+    db_link: &Pool<Postgres>, 
+    user_id: Uuid, 
+    request: &HttpRequest) -> Result<Uuid, sqlx::Error> {
+
+    let user_agent = request
+        .headers()
+        .get("user-agent")
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.chars().take(512).collect::<String>());
+    let ip_address = request
+        .connection_info()
+        .realip_remote_addr()
+        .map(|value| value.to_string());
+
+    let query = format!(
+        "INSERT INTO {}.{} (user_id, user_agent, ip_address, status_id) 
+              VALUES ($1, $2, $3, 0) 
+           RETURNING id", 
+        APP_SCHEMA, 
+        SESSIONS_TABLE
+    );
+    let new_session = sqlx::query(&query)
+        .bind(user_id)
+        .bind(user_agent)
+        .bind(ip_address)
+        .fetch_one(db_link)
+        .await?;
+
+    Ok(new_session.get("id"))
+}
+
+pub async fn issue_refresh_token(
+    db_link: &Pool<Postgres>, 
+    user_id: Uuid, 
+    session_id: Uuid) -> Result<String, sqlx::Error> {
+
+    let refresh_token = generate_random_token();
+    store_refresh_token(db_link, user_id, session_id, &refresh_token).await?;
+    Ok(refresh_token)
+}
+
+async fn store_refresh_token<'c, E: Executor<'c, Database = Postgres>>(
+    executor: E, 
+    user_id: Uuid, 
+    session_id: Uuid, 
+    refresh_token: &str) -> Result<(), sqlx::Error> {
+
+    let query = format!(
+        "INSERT INTO {}.{} (user_id, session_id, token_hash, status_id, expires_at) 
+              VALUES ($1, $2, $3, 0, now() + make_interval(secs => $4))", 
+        APP_SCHEMA, 
+        REFRESH_TOKENS_TABLE
+    );
+    sqlx::query(&query)
+        .bind(user_id)
+        .bind(session_id)
+        .bind(refresh_token.to_string().as_hash())
+        .bind(settings().tokens.refresh_token_lifetime as f64)
+        .execute(executor)
+        .await?;
+
+    Ok(())
+}
+
+// next token can't be guessed from the used one without the salt stored in Postgres
+fn successor_token(refresh_token: &str, successor_salt: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(refresh_token.as_bytes()).unwrap();
+    mac.update(successor_salt.as_bytes());
+    general_purpose::URL_SAFE_NO_PAD.encode(mac.finalize().into_bytes())
+}
+
+pub async fn rotate_refresh_token(
+    db_link: &Pool<Postgres>, 
+    refresh_token: &str) -> Result<RefreshOutcome, sqlx::Error> {
+
+    let token_hash = refresh_token.to_string().as_hash();
+    let successor_salt = generate_random_token();
+
+    // concurrent refresh with the same token waits here until the next token is stored
+    let mut transaction = db_link.begin().await?;
+
+    let query = format!(
+        "UPDATE {APP_SCHEMA}.{REFRESH_TOKENS_TABLE} r
+            SET status_id = 1, rotated_at = now(), successor_salt = $2
+           FROM {APP_SCHEMA}.{SESSIONS_TABLE} s
+          WHERE s.id = r.session_id 
+            AND r.token_hash = $1 
+            AND r.status_id = 0
+            AND r.expires_at > now()
+            AND s.status_id = 0
+      RETURNING r.user_id, r.session_id"
+    );
+    let rotated = sqlx::query_as::<_, (Uuid, Uuid)>(&query)
+        .bind(&token_hash)
+        .bind(&successor_salt)
+        .fetch_optional(&mut transaction)
+        .await?;
+
+    if let Some((user_id, session_id)) = rotated {
+        // last activity is tracked with access token lifetime granularity
+        let touch_query = format!(
+            "UPDATE {}.{}
+                SET last_seen_at = now()
+              WHERE id = $1", 
+            APP_SCHEMA, 
+            SESSIONS_TABLE
+        );
+        sqlx::query(&touch_query)
+            .bind(session_id)
+            .execute(&mut transaction)
+            .await?;
+
+        let refresh_token = successor_token(refresh_token, &successor_salt);
+        store_refresh_token(&mut transaction, user_id, session_id, &refresh_token).await?;
+        transaction.commit().await?;
+        return Ok(RefreshOutcome::Rotated { user_id, session_id, refresh_token });
+    }
+    transaction.commit().await?;
+
+    let reuse_query = format!(
+        "SELECT 
+            user_id, session_id, successor_salt, 
+            COALESCE(rotated_at > now() - make_interval(secs => $2), false) AS within_grace_period
+           FROM {}.{}
+          WHERE token_hash = $1 
+            AND status_id = 1", 
+        APP_SCHEMA, 
+        REFRESH_TOKENS_TABLE
+    );
+    let reused = sqlx::query_as::<_, (Uuid, Uuid, Option<String>, bool)>(&reuse_query)
+        .bind(&token_hash)
+        .bind(settings().tokens.refresh_token_grace_period as f64)
+        .fetch_optional(db_link)
+        .await?;
+
+    let (user_id, session_id, successor_salt, within_grace_period) = match reused {
+        Some(reused) => reused, 
+        None => return Ok(RefreshOutcome::Invalid)
+    };
+    if let (true, Some(successor_salt)) = (within_grace_period, successor_salt) {
+        let refresh_token = successor_token(refresh_token, &successor_salt);
+        if is_active_refresh_token(db_link, &refresh_token).await? {
+            return Ok(RefreshOutcome::Rotated { user_id, session_id, refresh_token });
+        }
+    }
+
+    revoke_session(db_link, user_id, session_id).await?;
+    Ok(RefreshOutcome::Reused { user_id, session_id })
+}
+
+async fn is_active_refresh_token(
+    db_link: &Pool<Postgres>, 
+    refresh_token: &str) -> Result<bool, sqlx::Error> {
+
+    let query = format!(
+        "SELECT 
+            r.id
+           FROM {APP_SCHEMA}.{REFRESH_TOKENS_TABLE} r
+           JOIN {APP_SCHEMA}.{SESSIONS_TABLE} s ON s.id = r.session_id
+          WHERE r.token_hash = $1 
+            AND r.status_id = 0
+            AND r.expires_at > now()
+            AND s.status_id = 0"
+    );
+    let active_token = sqlx::query(&query)
+        .bind(refresh_token.to_string().as_hash())
+        .fetch_optional(db_link)
+        .await?;
+
+    Ok(active_token.is_some())
+}
+
+pub async fn get_user_sessions(
+    db_link: &Pool<Postgres>, 
+    user_id: Uuid) -> Result<Vec<StoredSession>, sqlx::Error> {
+
+    let query = format!(
+        "SELECT 
+            id, user_agent, ip_address, created_at, last_seen_at
+           FROM {}.{}
+          WHERE user_id = $1 
+            AND status_id = 0
+          ORDER BY last_seen_at DESC", 
+        APP_SCHEMA, 
+        SESSIONS_TABLE
+    );
+    sqlx::query(&query)
+        .bind(user_id)
+        .map(|row| {
+            StoredSession {
+                id: row.get("id"), 
+                user_agent: row.get("user_agent"), 
+                ip_address: row.get("ip_address"), 
+                created_at: row.get("created_at"), 
+                last_seen_at: row.get("last_seen_at")
+            }
+        })
+        .fetch_all(db_link)
+        .await
+}
+
+pub async fn is_session_active(
+    db_link: &Pool<Postgres>, 
+    session_id: Uuid) -> Result<bool, sqlx::Error> {
+
+    let query = format!(
+        "SELECT
+            id
+           FROM {}.{}
+          WHERE id = $1
+            AND status_id = 0", 
+        APP_SCHEMA, 
+        SESSIONS_TABLE
+    );
+    let active_session = sqlx::query(&query)
+        .bind(session_id)
+        .fetch_optional(db_link)
+        .await?;
+
+    Ok(active_session.is_some())
+}
+
+pub async fn revoke_session(
+    db_link: &Pool<Postgres>, 
+    user_id: Uuid, 
+    session_id: Uuid) -> Result<bool, sqlx::Error> {
+
+    let query = format!(
+        "UPDATE {}.{}
+            SET status_id = 1
+          WHERE id = $1 
+            AND user_id = $2 
+            AND status_id = 0
+      RETURNING id", 
+        APP_SCHEMA, 
+        SESSIONS_TABLE
+    );
+    let revoked = sqlx::query(&query)
+        .bind(session_id)
+        .bind(user_id)
+        .fetch_all(db_link)
+        .await?;
+
+    let tokens_query = format!(
+        "UPDATE {}.{}
+            SET status_id = 2
+          WHERE session_id = $1 
+            AND user_id = $2 
+            AND status_id != 2", 
+        APP_SCHEMA, 
+        REFRESH_TOKENS_TABLE
+    );
+    sqlx::query(&tokens_query)
+        .bind(session_id)
+        .bind(user_id)
+        .execute(db_link)
+        .await?;
+
+    Ok(!revoked.is_empty())
+}
+
+pub async fn revoke_user_sessions(
+    db_link: &Pool<Postgres>, 
+    user_id: Uuid) -> Result<Vec<Uuid>, sqlx::Error> {
+
+    let query = format!(
+        "UPDATE {}.{}
+            SET status_id = 1
+          WHERE user_id = $1 
+            AND status_id = 0
+      RETURNING id", 
+        APP_SCHEMA, 
+        SESSIONS_TABLE
+    );
+    let revoked_sessions = sqlx::query(&query)
+        .bind(user_id)
+        .map(|row| row.get::<Uuid, &str>("id"))
+        .fetch_all(db_link)
+        .await?;
+
+    let tokens_query = format!(
+        "UPDATE {}.{}
+            SET status_id = 2
+          WHERE user_id = $1 
+            AND status_id != 2", 
+        APP_SCHEMA, 
+        REFRESH_TOKENS_TABLE
+    );
+    sqlx::query(&tokens_query)
+        .bind(user_id)
+        .execute(db_link)
+        .await?;
+
+    Ok(revoked_sessions)
+}
+
+// returns `None` for non-active users
+pub async fn get_token_generation(
+    db_link: &Pool<Postgres>, 
+    user_id: Uuid) -> Result<Option<i64>, sqlx::Error> {
+
+    let query = format!(
+        "SELECT
+            token_generation
+           FROM {}.{}
+          WHERE id = $1
+            AND status_id = 1", 
+        APP_SCHEMA, 
+        USERS_TABLE
+    );
+    sqlx::query(&query)
+        .bind(user_id)
+        .map(|row| row.get("token_generation"))
+        .fetch_optional(db_link)
+        .await
+}
+
+pub async fn bump_token_generation(
+    db_link: &Pool<Postgres>, 
+    user_id: Uuid) -> Result<Option<i64>, sqlx::Error> {
+
+    let query = format!(
+        "UPDATE {}.{}
+            SET token_generation = token_generation + 1
+          WHERE id = $1
+      RETURNING token_generation", 
+        APP_SCHEMA, 
+        USERS_TABLE
+    );
+    sqlx::query(&query)
+        .bind(user_id)
+        .map(|row| row.get("token_generation"))
+        .fetch_optional(db_link)
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn successor_token_is_repeatable_with_same_salt() {
+        let refresh_token = generate_random_token();
+        let successor_salt = generate_random_token();
+
+        let successor = successor_token(&refresh_token, &successor_salt);
+        assert_eq!(successor, successor_token(&refresh_token, &successor_salt));
+        assert_eq!(successor.len(), refresh_token.len());
+        assert_ne!(successor, refresh_token);
+    }
+
+    #[test]
+    fn successor_token_depends_on_salt_and_used_token() {
+        let refresh_token = generate_random_token();
+        let successor_salt = generate_random_token();
+
+        let successor = successor_token(&refresh_token, &successor_salt);
+        assert_ne!(successor, successor_token(&refresh_token, &generate_random_token()));
+        assert_ne!(successor, successor_token(&generate_random_token(), &successor_salt));
+    }
+}
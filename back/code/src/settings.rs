@@ -0,0 +1,332 @@
+use config::{Config, Environment, File, FileFormat};
+use reqwest::Url;
+use serde::Deserialize;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+
+use crate::password_policy::CharacterClass;
+
+// Runtime settings are read once at startup from layered sources, later ones win:
+//   1. built-in `settings/default.toml`, compiled into the binary
+//   2. optional TOML file set by SETTINGS_FILE env var
+//   3. env vars ROUTINE__<SECTION>__<KEY>, e.g. ROUTINE__SERVER__SERVICE_URL
+// Invalid settings stop the service before it binds, every problem is listed at once.
+// Settings are read everywhere through `settings()`, they aren't passed as app data.
+// Secrets have no built-in values and are set by env vars as well, e.g. ROUTINE__TOKENS__ACTION_TOKEN_SECRET;
+// SMTP credentials and JWT keys keep their own env vars, table and cookie names stay in `app_config`.
+
+const DEFAULT_SETTINGS: &str = include_str!("../settings/default.toml");
+const ENV_PREFIX: &str = "ROUTINE";
+const MIN_ACTION_TOKEN_SECRET_LENGTH: usize = 32;
+
+static SETTINGS: OnceLock<Settings> = OnceLock::new();
+
+#[derive(Deserialize, Debug)]
+pub struct Settings {
+    pub server: ServerSettings, 
+    pub postgres: PostgresSettings, 
+    pub smtp: SmtpSettings, 
+    pub cache: CacheSettings, 
+    pub tokens: TokensSettings, 
+    pub jwt: JwtSettings, 
+    pub accounts: AccountsSettings, 
+    pub login_throttling: LoginThrottlingSettings, 
+    pub admin: AdminSettings, 
+    pub password_policy: PasswordPolicySettings, 
+    pub oidc: OidcSettings
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ServerSettings {
+    pub host: String, 
+    pub service_url: String, 
+    pub threads_count: usize
+}
+
+#[derive(Deserialize, Debug)]
+pub struct PostgresSettings {
+    pub connections_limit: u32, 
+    pub migrate_on_startup: bool
+}
+
+#[derive(Deserialize, Debug)]
+pub struct SmtpSettings {
+    pub relay: String
+}
+
+// redis ttls and fallback to in-memory cache
+#[derive(Deserialize, Debug)]
+pub struct CacheSettings {
+    pub stored_data_lifetime: usize, 
+    pub user_data_lifetime: usize, 
+    pub failure_threshold: u32, 
+    pub retry_interval: u64, 
+    pub command_timeout: u64, 
+    pub memory_capacity: usize
+}
+
+#[derive(Deserialize, Debug)]
+pub struct TokensSettings {
+    pub access_token_lifetime: i64, 
+    pub access_token_renewal: i64, 
+    pub refresh_token_lifetime: i64, 
+    pub refresh_token_grace_period: i64, 
+    pub password_reset_lifetime: i64, 
+    pub user_verification_lifetime: i64, 
+    pub email_verification_lifetime: i64, 
+    pub device_report_lifetime: i64, 
+    pub personal_token_max_lifetime: i64, 
+    pub login_challenge_lifetime: usize, 
+    pub oidc_login_lifetime: usize, 
+    pub action_token_secret: Option<String>
+}
+
+#[derive(Deserialize, Debug)]
+pub struct JwtSettings {
+    pub issuer: Option<String>, 
+    pub audience: String, 
+    pub clock_skew: u64, 
+    pub key_grace_period: i64
+}
+
+#[derive(Deserialize, Debug)]
+pub struct AccountsSettings {
+    pub verification_resend_interval: i64, 
+    pub unverified_account_lifetime: i64, 
+    pub deleted_account_grace_period: i64, 
+    pub cleanup_interval: u64
+}
+
+// failures are counted per account email and per client ip within the sliding window
+#[derive(Deserialize, Debug)]
+pub struct LoginThrottlingSettings {
+    pub failures_window: i64, 
+    pub free_attempts: usize, 
+    pub base_delay: i64, 
+    pub max_delay: i64, 
+    pub lockout_threshold: usize, 
+    pub lockout_duration: i64, 
+    pub ip_failures_limit: usize
+}
+
+#[derive(Deserialize, Debug)]
+pub struct AdminSettings {
+    pub users_page_size: i64, 
+    pub users_page_limit: i64
+}
+
+// lengths are in characters, not bytes
+#[derive(Deserialize, Debug)]
+pub struct PasswordPolicySettings {
+    pub min_length: usize, 
+    pub max_length: usize, 
+    pub required_classes: Vec<CharacterClass>, 
+    pub allow_unicode: bool, 
+    pub breached_hashes_dir: Option<PathBuf>
+}
+
+// single sign-on is enabled when issuer and client id are set
+#[derive(Deserialize, Debug)]
+pub struct OidcSettings {
+    pub issuer: Option<String>, 
+    pub client_id: Option<String>, 
+    pub client_secret: Option<String>, 
+    pub redirect_url: Option<String>, 
+    pub scopes: String, 
+    pub discovery_cache_lifetime: i64
+}
+
+impl Settings {
+    // `iss` claim of access tokens
+    pub fn jwt_issuer(&self) -> &str {
+        self.jwt.issuer.as_deref().unwrap_or(&self.server.service_url)
+    }
+
+    fn load() -> Result<Self, config::ConfigError> {
+        let mut builder = Config::builder()
+            .add_source(File::from_str(DEFAULT_SETTINGS, FileFormat::Toml));
+        if let Some(settings_file) = std::env::var("SETTINGS_FILE").ok().filter(|value| !value.is_empty()) {
+            log::info!("Settings are read from `{}`", settings_file);
+            builder = builder.add_source(File::new(&settings_file, FileFormat::Toml));
+        }
+
+        builder
+            .add_source(
+                Environment::with_prefix(ENV_PREFIX)
+                    .prefix_separator("__")
+                    .separator("__")
+                    .list_separator(",")
+                    .with_list_parse_key("password_policy.required_classes")
+                    .try_parsing(true)
+            )
+            .build()?
+            .try_deserialize()
+    }
+
+    fn normalize(&mut self) {
+        self.server.service_url = self.server.service_url.trim_end_matches('/').to_string();
+        if let Some(issuer) = &self.jwt.issuer {
+            self.jwt.issuer = Some(issuer.trim_end_matches('/').to_string()).filter(|issuer| !issuer.is_empty());
+        }
+
+        // empty env vars read as unset
+        let optional_values = [
+            &mut self.tokens.action_token_secret, 
+            &mut self.oidc.issuer, 
+            &mut self.oidc.client_id, 
+            &mut self.oidc.client_secret, 
+            &mut self.oidc.redirect_url
+        ];
+        for value in optional_values {
+            *value = value.take().filter(|value| !value.trim().is_empty());
+        }
+        self.password_policy.breached_hashes_dir = self.password_policy.breached_hashes_dir
+            .take()
+            .filter(|breached_hashes_dir| !breached_hashes_dir.as_os_str().is_empty());
+    }
+
+    // returns a message for every invalid value
+    fn validate(&self) -> Vec<String> {
+        let mut errors = Vec::new();
+
+        if self.server.host.parse::<SocketAddr>().is_err() {
+            errors.push(format!("server.host `{}` should be an address like `0.0.0.0:5000`", self.server.host));
+        }
+        match Url::parse(&self.server.service_url) {
+            Ok(url) if url.scheme() == "http" || url.scheme() == "https" => (), 
+            _ => errors.push(format!("server.service_url `{}` should be an http(s) url", self.server.service_url))
+        }
+        if self.server.threads_count == 0 {
+            errors.push("server.threads_count should be at least 1".to_string());
+        }
+        if self.postgres.connections_limit == 0 {
+            errors.push("postgres.connections_limit should be at least 1".to_string());
+        }
+        if self.smtp.relay.trim().is_empty() {
+            errors.push("smtp.relay couldn't be empty".to_string());
+        }
+        if self.jwt.audience.trim().is_empty() {
+            errors.push("jwt.audience couldn't be empty".to_string());
+        }
+
+        let positive_values = [
+            ("cache.stored_data_lifetime", self.cache.stored_data_lifetime as i64), 
+            ("cache.user_data_lifetime", self.cache.user_data_lifetime as i64), 
+            ("cache.failure_threshold", self.cache.failure_threshold as i64), 
+            ("cache.retry_interval", self.cache.retry_interval as i64), 
+            ("cache.command_timeout", self.cache.command_timeout as i64), 
+            ("cache.memory_capacity", self.cache.memory_capacity as i64), 
+            ("tokens.access_token_lifetime", self.tokens.access_token_lifetime), 
+            ("tokens.access_token_renewal", self.tokens.access_token_renewal), 
+            ("tokens.refresh_token_lifetime", self.tokens.refresh_token_lifetime), 
+            ("tokens.refresh_token_grace_period", self.tokens.refresh_token_grace_period), 
+            ("tokens.password_reset_lifetime", self.tokens.password_reset_lifetime), 
+            ("tokens.user_verification_lifetime", self.tokens.user_verification_lifetime), 
+            ("tokens.email_verification_lifetime", self.tokens.email_verification_lifetime), 
+            ("tokens.device_report_lifetime", self.tokens.device_report_lifetime), 
+            ("tokens.personal_token_max_lifetime", self.tokens.personal_token_max_lifetime), 
+            ("tokens.login_challenge_lifetime", self.tokens.login_challenge_lifetime as i64), 
+            ("tokens.oidc_login_lifetime", self.tokens.oidc_login_lifetime as i64), 
+            ("jwt.key_grace_period", self.jwt.key_grace_period), 
+            ("accounts.verification_resend_interval", self.accounts.verification_resend_interval), 
+            ("accounts.unverified_account_lifetime", self.accounts.unverified_account_lifetime), 
+            ("accounts.deleted_account_grace_period", self.accounts.deleted_account_grace_period), 
+            ("accounts.cleanup_interval", self.accounts.cleanup_interval as i64), 
+            ("login_throttling.failures_window", self.login_throttling.failures_window), 
+            ("login_throttling.base_delay", self.login_throttling.base_delay), 
+            ("login_throttling.lockout_threshold", self.login_throttling.lockout_threshold as i64), 
+            ("login_throttling.lockout_duration", self.login_throttling.lockout_duration), 
+            ("login_throttling.ip_failures_limit", self.login_throttling.ip_failures_limit as i64), 
+            ("admin.users_page_size", self.admin.users_page_size), 
+            ("oidc.discovery_cache_lifetime", self.oidc.discovery_cache_lifetime)
+        ];
+        for (name, value) in positive_values {
+            if value <= 0 {
+                errors.push(format!("{} should be greater than 0, got {}", name, value));
+            }
+        }
+
+        if self.tokens.access_token_lifetime >= self.tokens.refresh_token_lifetime {
+            errors.push("tokens.access_token_lifetime should be shorter than tokens.refresh_token_lifetime".to_string());
+        }
+        if self.tokens.access_token_renewal >= self.tokens.access_token_lifetime {
+            errors.push("tokens.access_token_renewal should be shorter than tokens.access_token_lifetime".to_string());
+        }
+        match &self.tokens.action_token_secret {
+            Some(secret) if secret.len() >= MIN_ACTION_TOKEN_SECRET_LENGTH => (), 
+            _ => errors.push(format!("tokens.action_token_secret should be set to at least {} bytes", MIN_ACTION_TOKEN_SECRET_LENGTH))
+        }
+
+        if self.login_throttling.base_delay > self.login_throttling.max_delay {
+            errors.push("login_throttling.base_delay couldn't be greater than login_throttling.max_delay".to_string());
+        }
+        if self.admin.users_page_size > self.admin.users_page_limit {
+            errors.push("admin.users_page_size couldn't be greater than admin.users_page_limit".to_string());
+        }
+
+        let password_policy = &self.password_policy;
+        if password_policy.min_length == 0 {
+            errors.push("password_policy.min_length should be at least 1".to_string());
+        }
+        if password_policy.min_length > password_policy.max_length {
+            errors.push("password_policy.min_length couldn't be greater than password_policy.max_length".to_string());
+        }
+        if let Some(breached_hashes_dir) = &password_policy.breached_hashes_dir {
+            if !breached_hashes_dir.is_dir() {
+                errors.push(format!("password_policy.breached_hashes_dir `{}` is not a directory", breached_hashes_dir.display()));
+            }
+        }
+
+        match (&self.oidc.issuer, &self.oidc.client_id) {
+            (Some(_), None) | (None, Some(_)) => {
+                errors.push("oidc.issuer and oidc.client_id should be set together".to_string());
+            }, 
+            _ => ()
+        }
+        if !self.oidc.scopes.split_whitespace().any(|scope| scope == "openid") {
+            errors.push(format!("oidc.scopes `{}` should include `openid`", self.oidc.scopes));
+        }
+        let oidc_urls = [("oidc.issuer", &self.oidc.issuer), ("oidc.redirect_url", &self.oidc.redirect_url)];
+        for (name, url) in oidc_urls {
+            match url.as_deref().map(Url::parse) {
+                None => (), 
+                Some(Ok(url)) if url.scheme() == "http" || url.scheme() == "https" => (), 
+                Some(_) => errors.push(format!("{} `{}` should be an http(s) url", name, url.as_deref().unwrap_or_default()))
+            }
+        }
+        errors
+    }
+}
+
+pub fn init_settings() -> &'static Settings {
+    let mut settings = Settings::load().unwrap_or_else(|error| panic!("Unable to load settings: {}", error));
+    settings.normalize();
+
+    let errors = settings.validate();
+    if !errors.is_empty() {
+        panic!("Invalid settings:\n  - {}", errors.join("\n  - "));
+    }
+
+    log::info!("Settings loaded, service url: `{}`", settings.server.service_url);
+    SETTINGS.get_or_init(|| settings)
+}
+
+// built-in settings only, for tests of code reading `settings()`
+#[cfg(test)]
+pub fn init_test_settings() -> &'static Settings {
+    SETTINGS.get_or_init(|| {
+        let mut settings: Settings = Config::builder()
+            .add_source(File::from_str(DEFAULT_SETTINGS, FileFormat::Toml))
+            .build()
+            .and_then(|config| config.try_deserialize())
+            .expect("Built-in settings are invalid");
+        settings.normalize();
+        settings
+    })
+}
+
+// settings loaded by `init_settings`, which has to be called first
+pub fn settings() -> &'static Settings {
+    SETTINGS.get().expect("Settings are not initialized")
+}
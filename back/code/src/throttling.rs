@@ -0,0 +1,183 @@
+use redis::RedisResult;
+
+use crate::CacheDB;
+use crate::redis_handlers::{
+    add_login_failure_to_redis, get_login_failures_from_redis, drop_login_failures_from_redis, 
+    lock_account_in_redis, get_account_lockout_time_from_redis
+};
+use crate::settings::settings;
+
+// Failed logins are counted in sliding windows per account email and per client ip. 
+// After a few free attempts every next one has to wait twice longer than the previous, 
+// too many failures lock the account for a while.
+
+const EMAIL_SCOPE: &str = "email";
+const IP_SCOPE: &str = "ip";
+
+pub enum LoginThrottle {
+    Allowed, 
+    Delayed(i64), 
+    Locked(i64)
+}
+
+pub async fn check_login_throttle(
+    cache: &CacheDB, 
+    email: &str, 
+    client_ip: &str) -> RedisResult<LoginThrottle> {
+
+    let throttling = &settings().login_throttling;
+    let email = email.to_lowercase();
+    let current_time = chrono::offset::Utc::now().naive_utc().timestamp();
+
+    if let Some(lockout_time) = get_account_lockout_time_from_redis(cache, &email).await? {
+        return Ok(LoginThrottle::Locked(lockout_time));
+    }
+
+    let ip_failures = get_login_failures_from_redis(cache, IP_SCOPE, client_ip, current_time).await?;
+    if ip_failures.len() >= throttling.ip_failures_limit {
+        let oldest_failure = ip_failures.iter().min().copied().unwrap_or(current_time);
+        return Ok(LoginThrottle::Delayed((oldest_failure + throttling.failures_window - current_time).max(1)));
+    }
+
+    let email_failures = get_login_failures_from_redis(cache, EMAIL_SCOPE, &email, current_time).await?;
+    if let Some(last_failure) = email_failures.iter().max() {
+        let delay = progressive_delay(email_failures.len());
+        let elapsed = current_time - last_failure;
+        if elapsed < delay {
+            return Ok(LoginThrottle::Delayed(delay - elapsed));
+        }
+    }
+
+    Ok(LoginThrottle::Allowed)
+}
+
+// returns `true` when this failure has just locked the account
+pub async fn register_login_failure(
+    cache: &CacheDB, 
+    email: &str, 
+    client_ip: &str) -> RedisResult<bool> {
+
+    let throttling = &settings().login_throttling;
+    let email = email.to_lowercase();
+    let current_time = chrono::offset::Utc::now().naive_utc().timestamp();
+
+    add_login_failure_to_redis(cache, IP_SCOPE, client_ip, current_time).await?;
+    add_login_failure_to_redis(cache, EMAIL_SCOPE, &email, current_time).await?;
+
+    let email_failures = get_login_failures_from_redis(cache, EMAIL_SCOPE, &email, current_time).await?;
+    if email_failures.len() >= throttling.lockout_threshold {
+        let newly_locked = lock_account_in_redis(cache, &email, throttling.lockout_duration).await?;
+        drop_login_failures_from_redis(cache, EMAIL_SCOPE, &email).await;
+        return Ok(newly_locked);
+    }
+
+    Ok(false)
+}
+
+pub async fn reset_login_failures(cache: &CacheDB, email: &str) {
+    drop_login_failures_from_redis(cache, EMAIL_SCOPE, &email.to_lowercase()).await;
+}
+
+fn progressive_delay(failures_count: usize) -> i64 {
+    let throttling = &settings().login_throttling;
+    if failures_count < throttling.free_attempts {
+        return 0;
+    }
+    let exponent = (failures_count - throttling.free_attempts).min(16) as u32;
+    (throttling.base_delay * 2_i64.pow(exponent)).min(throttling.max_delay)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::settings::init_test_settings;
+
+    const EMAIL: &str = "ann@example.com";
+    const CLIENT_IP: &str = "203.0.113.7";
+
+    // in-memory cache only, as while REDIS_URL isn't set
+    fn test_cache() -> CacheDB {
+        init_test_settings();
+        CacheDB::new(None)
+    }
+
+    async fn fail_logins(cache: &CacheDB, email: &str, client_ip: &str, count: usize) -> Vec<bool> {
+        let mut locks = Vec::new();
+        for _ in 0..count {
+            locks.push(register_login_failure(cache, email, client_ip).await.unwrap());
+        }
+        locks
+    }
+
+    #[test]
+    fn delay_doubles_after_free_attempts() {
+        init_test_settings();
+        let throttling = &settings().login_throttling;
+        let free_attempts = throttling.free_attempts;
+
+        assert_eq!(progressive_delay(0), 0);
+        assert_eq!(progressive_delay(free_attempts - 1), 0);
+        assert_eq!(progressive_delay(free_attempts), throttling.base_delay);
+        assert_eq!(progressive_delay(free_attempts + 1), throttling.base_delay * 2);
+        assert_eq!(progressive_delay(free_attempts + 2), throttling.base_delay * 4);
+        assert_eq!(progressive_delay(1_000), throttling.max_delay);
+    }
+
+    #[actix_web::test]
+    async fn login_is_delayed_after_free_attempts() {
+        let cache = test_cache();
+        let free_attempts = settings().login_throttling.free_attempts;
+
+        fail_logins(&cache, EMAIL, CLIENT_IP, free_attempts - 1).await;
+        assert!(matches!(check_login_throttle(&cache, EMAIL, CLIENT_IP).await.unwrap(), LoginThrottle::Allowed));
+
+        fail_logins(&cache, EMAIL, CLIENT_IP, 1).await;
+        let base_delay = settings().login_throttling.base_delay;
+        assert!(matches!(
+            check_login_throttle(&cache, EMAIL, CLIENT_IP).await.unwrap(), 
+            LoginThrottle::Delayed(retry_after) if retry_after == base_delay
+        ));
+
+        // counted per account, not per spelling of the email
+        assert!(matches!(check_login_throttle(&cache, "Ann@Example.com", "198.51.100.1").await.unwrap(), LoginThrottle::Delayed(_)));
+        assert!(matches!(check_login_throttle(&cache, "bob@example.com", CLIENT_IP).await.unwrap(), LoginThrottle::Allowed));
+    }
+
+    #[actix_web::test]
+    async fn successful_login_resets_delay() {
+        let cache = test_cache();
+        fail_logins(&cache, EMAIL, CLIENT_IP, settings().login_throttling.free_attempts).await;
+
+        reset_login_failures(&cache, "ANN@example.com").await;
+        assert!(matches!(check_login_throttle(&cache, EMAIL, CLIENT_IP).await.unwrap(), LoginThrottle::Allowed));
+    }
+
+    #[actix_web::test]
+    async fn account_is_locked_once_threshold_is_reached() {
+        let cache = test_cache();
+        let throttling = &settings().login_throttling;
+
+        let locks = fail_logins(&cache, EMAIL, CLIENT_IP, throttling.lockout_threshold).await;
+        assert_eq!(locks.iter().filter(|newly_locked| **newly_locked).count(), 1);
+        assert!(locks.last().unwrap());
+
+        // dropping failures doesn't lift the lockout, and it holds for any client ip
+        reset_login_failures(&cache, EMAIL).await;
+        assert!(matches!(
+            check_login_throttle(&cache, EMAIL, "198.51.100.1").await.unwrap(), 
+            LoginThrottle::Locked(retry_after) if retry_after > 0 && retry_after <= throttling.lockout_duration
+        ));
+    }
+
+    #[actix_web::test]
+    async fn client_ip_is_delayed_after_failures_for_many_accounts() {
+        let cache = test_cache();
+        let ip_failures_limit = settings().login_throttling.ip_failures_limit;
+
+        for attempt in 0..ip_failures_limit {
+            register_login_failure(&cache, &format!("user{}@example.com", attempt), CLIENT_IP).await.unwrap();
+        }
+        assert!(matches!(check_login_throttle(&cache, EMAIL, CLIENT_IP).await.unwrap(), LoginThrottle::Delayed(_)));
+        assert!(matches!(check_login_throttle(&cache, EMAIL, "198.51.100.1").await.unwrap(), LoginThrottle::Allowed));
+    }
+}
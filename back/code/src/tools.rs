@@ -1,21 +1,19 @@
+use actix_web::{rt, web, HttpRequest};
+use base64::{Engine as _, engine::general_purpose};
 use lettre::message::header::ContentType;
 use lettre::transport::smtp::authentication::Credentials;
 use lettre::{Message, SmtpTransport, Transport};
-use uuid::Uuid;
+use rand::RngCore;
 use regex::Regex;
-use log;
+use std::str::FromStr;
 
-pub fn generate_random_password() -> String {
-    let new_password = Uuid::new_v4().to_string();
-    new_password
-}
+use crate::errors::AppError;
+use crate::settings::settings;
 
-pub fn is_valid_password(password: &str) -> bool {
-    if password.len() < 10 || password.len() > 64 {
-        return false;
-    }
-    let re = Regex::new(r"^([a-zA-Z0-9._+\-!?]+)$").unwrap();
-    re.is_match(password)
+pub fn generate_random_token() -> String {
+    let mut token_bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut token_bytes);
+    general_purpose::URL_SAFE_NO_PAD.encode(token_bytes)
 }
 
 pub fn is_valid_email(email: &str) -> bool {
@@ -23,31 +21,70 @@ pub fn is_valid_email(email: &str) -> bool {
     re.is_match(email)
 }
 
-pub fn send_email(email: &str, title: &str, message: &str) {
+pub fn parse_header<T: FromStr>(request: &HttpRequest, name: &str) -> Result<T, AppError> {
+    request
+        .headers()
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse().ok())
+        .ok_or_else(|| AppError::Validation(format!("Header `{}` is missing or invalid", name)))
+}
+
+// failure is logged here, callers sending optional notifications may ignore it
+pub fn send_email(email: &str, title: &str, message: &str) -> Result<(), AppError> {
+    match deliver_email(email, title, message) {
+        Ok(_) => {
+            log::info!("Email to address {} successfully sent", email);
+            Ok(())
+        },
+        Err(e) => {
+            log::error!("Could not send email to address {}: {}", email, e);
+            Err(AppError::Mail(e))
+        }
+    }
+}
+
+// SMTP exchange is blocking, it runs on the thread pool instead of the worker
+pub async fn send_email_off_worker(email: String, title: &'static str, message: String) -> Result<(), AppError> {
+    web::block(move || send_email(&email, title, &message))
+        .await
+        .unwrap_or_else(|error| Err(AppError::Mail(format!("{:?}", error))))
+}
+
+// notifications the response doesn't wait on, failure is only logged
+pub fn send_email_in_background(email: String, title: &'static str, message: String) {
+    rt::spawn(async move {
+        let _ = send_email_off_worker(email, title, message).await;
+    });
+}
+
+fn deliver_email(email: &str, title: &str, message: &str) -> Result<(), String> {
     
     let login = std::env::var("LOGIN").expect("Unable to read LOGIN env var");
     let password = std::env::var("PASSWORD").expect("Unable to read PASSWORD env var");
 
+    let from = format!("Admin <{}>", login)
+        .parse()
+        .map_err(|error| format!("invalid sender address: {:?}", error))?;
+    let to = format!("User <{}>", email)
+        .parse()
+        .map_err(|error| format!("invalid address: {:?}", error))?;
     let common_message = Message::builder()
-        .from((&format!("Admin <{}>", login)).parse().unwrap())
-        .to((&format!("User <{}>", email)).parse().unwrap())
+        .from(from)
+        .to(to)
         .subject(title)
         .header(ContentType::TEXT_PLAIN)
         .body(String::from(message))
-        .unwrap();
+        .map_err(|error| format!("invalid message: {:?}", error))?;
 
     let creds = Credentials::new(login, password);
-    let mailer = SmtpTransport::relay("smtp.mail.ru")
-        .unwrap()
+    let mailer = SmtpTransport::relay(&settings().smtp.relay)
+        .map_err(|error| format!("invalid SMTP relay: {:?}", error))?
         .credentials(creds)
         .build();
 
-    match mailer.send(&common_message) {
-        Ok(_) => {
-            log::info!("Email to address {} successfully sent", email);
-        },
-        Err(e) => {
-            log::error!("Could not send email to address {}: {e:?}", email);
-        }
-    }
+    mailer
+        .send(&common_message)
+        .map(|_| ())
+        .map_err(|error| format!("{:?}", error))
 }
@@ -0,0 +1,414 @@
+use data_encoding::BASE32_NOPAD;
+use hmac::{Hmac, Mac};
+use rand::{Rng, RngCore};
+use sha1::Sha1;
+use sqlx::{self, Postgres, Pool, Row};
+use std::collections::HashSet;
+use uuid::Uuid;
+
+use crate::{APP_SCHEMA, USERS_TABLE, RECOVERY_CODES_TABLE, RECOVERY_CODES_COUNT, TOTP_ISSUER};
+use crate::passwords::{hash_password, verify_password};
+
+// RFC 6238 time-based one-time passwords with the parameters every authenticator app supports:
+// HMAC-SHA1, 6 digits, 30 seconds step. One step of clock drift is tolerated in both directions
+// and the last accepted step is stored, so the same code can't be used twice.
+// Recovery codes are stored as Argon2 hashes next to their first characters, the prefix is unique
+// among codes of the user and picks the only hash to verify.
+
+const TOTP_DIGITS: u32 = 6;
+const TOTP_PERIOD: i64 = 30;
+const TOTP_ALLOWED_DRIFT: i64 = 1;
+const TOTP_SECRET_LENGTH: usize = 20;
+const RECOVERY_CODE_ALPHABET: &[u8] = b"abcdefghjkmnpqrstuvwxyz23456789";
+const RECOVERY_CODE_PREFIX_LENGTH: usize = 4;
+
+pub struct TotpState {
+    pub secret: Option<String>, 
+    pub enabled: bool, 
+    pub last_step: Option<i64>
+}
+
+pub fn generate_totp_secret() -> String {
+    let mut secret_bytes = [0u8; TOTP_SECRET_LENGTH];
+    rand::thread_rng().fill_bytes(&mut secret_bytes);
+    BASE32_NOPAD.encode(&secret_bytes)
+}
+
+pub fn totp_uri(secret: &str, email: &str) -> String {
+    format!(
+        "otpauth://totp/{}:{}?secret={}&issuer={}&algorithm=SHA1&digits={}&period={}", 
+        percent_encode(TOTP_ISSUER), 
+        percent_encode(email), 
+        secret, 
+        percent_encode(TOTP_ISSUER), 
+        TOTP_DIGITS, 
+        TOTP_PERIOD
+    )
+}
+
+// returns time step the code belongs to, steps up to the last accepted one are refused
+pub fn verify_totp_code(secret: &str, code: &str, last_step: Option<i64>) -> Option<i64> {
+    let current_step = chrono::offset::Utc::now().naive_utc().timestamp() / TOTP_PERIOD;
+    verify_totp_code_at(secret, code, current_step, last_step)
+}
+
+fn verify_totp_code_at(secret: &str, code: &str, current_step: i64, last_step: Option<i64>) -> Option<i64> {
+    let key = BASE32_NOPAD.decode(secret.as_bytes()).ok()?;
+    let code = code.trim();
+    if code.len() != TOTP_DIGITS as usize {
+        return None;
+    }
+
+    (current_step - TOTP_ALLOWED_DRIFT..=current_step + TOTP_ALLOWED_DRIFT)
+        .filter(|step| last_step.is_none_or(|last_step| *step > last_step))
+        .find(|step| hotp(&key, *step as u64) == code)
+}
+
+pub fn generate_recovery_codes() -> Vec<String> {
+    let mut rng = rand::thread_rng();
+    let mut prefixes = HashSet::new();
+    let mut recovery_codes = Vec::new();
+    while recovery_codes.len() < RECOVERY_CODES_COUNT {
+        let code: String = (0..10)
+            .map(|_| RECOVERY_CODE_ALPHABET[rng.gen_range(0..RECOVERY_CODE_ALPHABET.len())] as char)
+            .collect();
+        if prefixes.insert(code[..RECOVERY_CODE_PREFIX_LENGTH].to_string()) {
+            recovery_codes.push(format!("{}-{}", &code[..5], &code[5..]));
+        }
+    }
+    recovery_codes
+}
+
+fn recovery_code_prefix(recovery_code: &str) -> String {
+    recovery_code.chars().take(RECOVERY_CODE_PREFIX_LENGTH).collect()
+}
+
+fn hotp(key: &[u8], counter: u64) -> String {
+    let mut mac = Hmac::<Sha1>::new_from_slice(key).unwrap();
+    mac.update(&counter.to_be_bytes());
+    let digest = mac.finalize().into_bytes();
+
+    let offset = (digest[digest.len() - 1] & 0x0f) as usize;
+    let binary = u32::from_be_bytes([
+        digest[offset] & 0x7f, 
+        digest[offset + 1], 
+        digest[offset + 2], 
+        digest[offset + 3]
+    ]);
+
+    format!("{:0width$}", binary % 10_u32.pow(TOTP_DIGITS), width = TOTP_DIGITS as usize)
+}
+
+fn percent_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|byte| {
+            if byte.is_ascii_alphanumeric() || b"-._~@".contains(&byte) {
+                (byte as char).to_string()
+            } else {
+                format!("%{:02X}", byte)
+            }
+        })
+        .collect()
+}
+
+// Database handlers
+
+pub async fn get_totp_state(
+    db_link: &Pool<Postgres>, 
+    user_id: Uuid) -> Result<Option<TotpState>, sqlx::Error> {
+
+    let query = format!(
+        "SELECT
+            totp_secret, totp_enabled, totp_last_step
+           FROM {}.{}
+          WHERE id = $1
+            AND status_id = 1", 
+        APP_SCHEMA, 
+        USERS_TABLE
+    );
+    sqlx::query(&query)
+        .bind(user_id)
+        .map(|row| {
+            TotpState {
+                secret: row.get("totp_secret"), 
+                enabled: row.get("totp_enabled"), 
+                last_step: row.get("totp_last_step")
+            }
+        })
+        .fetch_optional(db_link)
+        .await
+}
+
+// returns email of the user to build the otpauth uri, `None` if 2FA is already enabled
+pub async fn set_pending_totp_secret(
+    db_link: &Pool<Postgres>, 
+    user_id: Uuid, 
+    secret: &str) -> Result<Option<String>, sqlx::Error> {
+
+    let query = format!(
+        "UPDATE {}.{}
+            SET totp_secret = $2, totp_last_step = NULL
+          WHERE id = $1
+            AND status_id = 1
+            AND totp_enabled = false
+      RETURNING email", 
+        APP_SCHEMA, 
+        USERS_TABLE
+    );
+    sqlx::query(&query)
+        .bind(user_id)
+        .bind(secret)
+        .map(|row| row.get("email"))
+        .fetch_optional(db_link)
+        .await
+}
+
+pub async fn enable_two_factor(
+    db_link: &Pool<Postgres>, 
+    user_id: Uuid, 
+    step: i64, 
+    recovery_codes: &[String]) -> Result<(), sqlx::Error> {
+
+    let mut transaction = db_link.begin().await?;
+
+    let query = format!(
+        "UPDATE {}.{}
+            SET totp_enabled = true, totp_last_step = $2
+          WHERE id = $1
+            AND status_id = 1", 
+        APP_SCHEMA, 
+        USERS_TABLE
+    );
+    sqlx::query(&query)
+        .bind(user_id)
+        .bind(step)
+        .execute(&mut transaction)
+        .await?;
+
+    let delete_query = format!(
+        "DELETE FROM {}.{} WHERE user_id = $1", 
+        APP_SCHEMA, 
+        RECOVERY_CODES_TABLE
+    );
+    sqlx::query(&delete_query)
+        .bind(user_id)
+        .execute(&mut transaction)
+        .await?;
+
+    let insert_query = format!(
+        "INSERT INTO {}.{} (user_id, code_hash, lookup_prefix) VALUES ($1, $2, $3)", 
+        APP_SCHEMA, 
+        RECOVERY_CODES_TABLE
+    );
+    for recovery_code in recovery_codes {
+        sqlx::query(&insert_query)
+            .bind(user_id)
+            .bind(hash_password(recovery_code).await)
+            .bind(recovery_code_prefix(recovery_code))
+            .execute(&mut transaction)
+            .await?;
+    }
+
+    transaction.commit().await
+}
+
+pub async fn disable_two_factor(
+    db_link: &Pool<Postgres>, 
+    user_id: Uuid) -> Result<(), sqlx::Error> {
+
+    let mut transaction = db_link.begin().await?;
+
+    let query = format!(
+        "UPDATE {}.{}
+            SET totp_enabled = false, totp_secret = NULL, totp_last_step = NULL
+          WHERE id = $1", 
+        APP_SCHEMA, 
+        USERS_TABLE
+    );
+    sqlx::query(&query)
+        .bind(user_id)
+        .execute(&mut transaction)
+        .await?;
+
+    let delete_query = format!(
+        "DELETE FROM {}.{} WHERE user_id = $1", 
+        APP_SCHEMA, 
+        RECOVERY_CODES_TABLE
+    );
+    sqlx::query(&delete_query)
+        .bind(user_id)
+        .execute(&mut transaction)
+        .await?;
+
+    transaction.commit().await
+}
+
+pub async fn check_totp_code(
+    db_link: &Pool<Postgres>, 
+    user_id: Uuid, 
+    code: &str) -> Result<bool, sqlx::Error> {
+
+    match get_totp_state(db_link, user_id).await? {
+        Some(TotpState { secret: Some(secret), enabled: true, last_step }) => {
+            match verify_totp_code(&secret, code, last_step) {
+                Some(step) => consume_totp_step(db_link, user_id, step).await, 
+                None => Ok(false)
+            }
+        }, 
+        _ => Ok(false)
+    }
+}
+
+// marks the step as used, fails if the same or a later code was already accepted
+pub async fn consume_totp_step(
+    db_link: &Pool<Postgres>, 
+    user_id: Uuid, 
+    step: i64) -> Result<bool, sqlx::Error> {
+
+    let query = format!(
+        "UPDATE {}.{}
+            SET totp_last_step = $2
+          WHERE id = $1
+            AND (totp_last_step IS NULL OR totp_last_step < $2)
+      RETURNING id", 
+        APP_SCHEMA, 
+        USERS_TABLE
+    );
+    let updated = sqlx::query(&query)
+        .bind(user_id)
+        .bind(step)
+        .fetch_all(db_link)
+        .await?;
+
+    Ok(!updated.is_empty())
+}
+
+pub async fn use_recovery_code(
+    db_link: &Pool<Postgres>, 
+    user_id: Uuid, 
+    recovery_code: &str) -> Result<bool, sqlx::Error> {
+
+    let query = format!(
+        "SELECT
+            id, code_hash
+           FROM {}.{}
+          WHERE user_id = $1
+            AND used_at IS NULL
+            AND (lookup_prefix = $2 OR lookup_prefix IS NULL)", 
+        APP_SCHEMA, 
+        RECOVERY_CODES_TABLE
+    );
+    let recovery_code = recovery_code.trim().to_lowercase();
+    let stored_codes = sqlx::query(&query)
+        .bind(user_id)
+        .bind(recovery_code_prefix(&recovery_code))
+        .map(|row| (row.get::<i32, &str>("id"), row.get::<String, &str>("code_hash")))
+        .fetch_all(db_link)
+        .await?;
+
+    let mut matched_code = None;
+    for stored_code in &stored_codes {
+        if verify_password(&recovery_code, &stored_code.1).await.is_valid() {
+            matched_code = Some(stored_code);
+            break;
+        }
+    }
+
+    match matched_code {
+        Some((code_id, _)) => {
+            let update_query = format!(
+                "UPDATE {}.{}
+                    SET used_at = now()
+                  WHERE id = $1
+                    AND used_at IS NULL
+              RETURNING id", 
+                APP_SCHEMA, 
+                RECOVERY_CODES_TABLE
+            );
+            let updated = sqlx::query(&update_query)
+                .bind(code_id)
+                .fetch_all(db_link)
+                .await?;
+
+            Ok(!updated.is_empty())
+        }, 
+        None => Ok(false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // RFC 6238 Appendix B shared secret "12345678901234567890"
+    const RFC_SECRET: &str = "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ";
+
+    fn step_of(timestamp: i64) -> i64 {
+        timestamp / TOTP_PERIOD
+    }
+
+    #[test]
+    fn matches_rfc_6238_sha1_vectors() {
+        // the appendix lists 8 digit codes, 6 digit ones are their last digits
+        let vectors = [
+            (59, "94287082"), 
+            (1111111109, "07081804"), 
+            (1111111111, "14050471"), 
+            (1234567890, "89005924"), 
+            (2000000000, "69279037"), 
+            (20000000000, "65353130")
+        ];
+        let key = BASE32_NOPAD.decode(RFC_SECRET.as_bytes()).unwrap();
+        for (timestamp, expected) in vectors {
+            assert_eq!(hotp(&key, step_of(timestamp) as u64), &expected[2..], "time {}", timestamp);
+        }
+    }
+
+    #[test]
+    fn tolerates_one_step_of_drift() {
+        let current_step = step_of(1111111111);
+        let key = BASE32_NOPAD.decode(RFC_SECRET.as_bytes()).unwrap();
+
+        for drift in [-1, 0, 1] {
+            let code = hotp(&key, (current_step + drift) as u64);
+            assert_eq!(verify_totp_code_at(RFC_SECRET, &code, current_step, None), Some(current_step + drift));
+        }
+        for drift in [-2, 2] {
+            let code = hotp(&key, (current_step + drift) as u64);
+            assert_eq!(verify_totp_code_at(RFC_SECRET, &code, current_step, None), None);
+        }
+    }
+
+    #[test]
+    fn refuses_replay_of_last_accepted_step() {
+        let current_step = step_of(1234567890);
+        let key = BASE32_NOPAD.decode(RFC_SECRET.as_bytes()).unwrap();
+        let code = hotp(&key, current_step as u64);
+
+        assert_eq!(verify_totp_code_at(RFC_SECRET, &code, current_step, Some(current_step - 1)), Some(current_step));
+        assert_eq!(verify_totp_code_at(RFC_SECRET, &code, current_step, Some(current_step)), None);
+        assert_eq!(verify_totp_code_at(RFC_SECRET, &code, current_step, Some(current_step + 1)), None);
+    }
+
+    #[test]
+    fn refuses_malformed_codes() {
+        let current_step = step_of(59);
+        assert_eq!(verify_totp_code_at(RFC_SECRET, "28708", current_step, None), None);
+        assert_eq!(verify_totp_code_at(RFC_SECRET, "94287082", current_step, None), None);
+        assert_eq!(verify_totp_code_at("not base32!", "287082", current_step, None), None);
+        assert_eq!(verify_totp_code_at(RFC_SECRET, " 287082 ", current_step, None), Some(current_step));
+    }
+
+    #[test]
+    fn recovery_code_prefixes_are_unique() {
+        let recovery_codes = generate_recovery_codes();
+        let prefixes = recovery_codes
+            .iter()
+            .map(|recovery_code| recovery_code_prefix(recovery_code))
+            .collect::<HashSet<String>>();
+
+        assert_eq!(recovery_codes.len(), RECOVERY_CODES_COUNT);
+        assert_eq!(prefixes.len(), RECOVERY_CODES_COUNT);
+    }
+}
@@ -1,5 +1,7 @@
+mod admin_users_managing;
 mod authorized_users_managing;
 mod unauthorized_users_managing;
 
+pub use admin_users_managing::admin_users_managing;
 pub use authorized_users_managing::authorized_users_managing;
 pub use unauthorized_users_managing::unauthorized_users_managing;
\ No newline at end of file
@@ -0,0 +1,205 @@
+use actix_web::{
+    web::{self, Data, Query}, 
+    HttpRequest, HttpResponse
+};
+use serde_json::json;
+use uuid::Uuid;
+
+use crate::models::{ServerResponse, AdminUsersQuery, AuditEventsQuery};
+use crate::{
+    PersistentDB, CacheDB, 
+    AUDIT_EVENTS_PAGE_SIZE, AUDIT_EVENTS_PAGE_LIMIT
+};
+use crate::errors::AppError;
+use crate::audit::{AuditEvent, record_audit_event, get_audit_events};
+use crate::redis_handlers::drop_user_data_from_redis;
+use crate::autorization::revoke_user_access_tokens;
+use crate::roles::AdminUser;
+use crate::settings::settings;
+use crate::sessions::revoke_user_sessions;
+use crate::accounts::{search_users, get_user_details, set_account_status, scramble_password};
+use crate::password_resets::create_password_reset;
+use crate::tools::send_email_off_worker;
+
+// Admin API, every handler takes `AdminUser` so requests of other users are rejected with 403.
+// Disabled accounts get status 4, it blocks login, token refresh and personal tokens
+// the same way as any other non-active status.
+
+pub fn admin_users_managing(cfg: &mut web::ServiceConfig) {
+    cfg
+        .service(
+            web::resource("/admin/users")
+                .route(web::get().to(handle_search_users))
+        ).service(
+            web::resource("/admin/users/{user_id}")
+                .route(web::get().to(handle_get_user_details))
+        ).service(
+            web::resource("/admin/users/{user_id}/disable")
+                .route(web::post().to(handle_disable_user))
+        ).service(
+            web::resource("/admin/users/{user_id}/enable")
+                .route(web::post().to(handle_enable_user))
+        ).service(
+            web::resource("/admin/users/{user_id}/password_reset")
+                .route(web::post().to(handle_force_password_reset))
+        ).service(
+            web::resource("/admin/audit_events")
+                .route(web::get().to(handle_get_audit_events))
+        );
+}
+
+async fn handle_search_users(
+    admin: AdminUser, 
+    postgres_db: Data<PersistentDB>, 
+    request_query: Query<AdminUsersQuery>) -> Result<HttpResponse, AppError> {
+
+    let AdminUsersQuery { search, status_id, limit, offset } = request_query.into_inner();
+    log::info!("Users search `{:?}` requested by admin: `{}`", search, admin.user_id);
+
+    let limit = limit.unwrap_or(settings().admin.users_page_size).clamp(1, settings().admin.users_page_limit);
+    let offset = offset.unwrap_or(0).max(0);
+    let search = search.filter(|search| !search.trim().is_empty());
+
+    let db_link = &postgres_db.pool();
+    let users = search_users(db_link, search.as_deref().map(str::trim), status_id, limit, offset).await?;
+    Ok(HttpResponse::Ok().json(users))
+}
+
+async fn handle_get_user_details(
+    admin: AdminUser, 
+    postgres_db: Data<PersistentDB>, 
+    request_path: web::Path<Uuid>) -> Result<HttpResponse, AppError> {
+
+    let user_id = request_path.into_inner();
+    log::info!("Details of user: `{}` requested by admin: `{}`", user_id, admin.user_id);
+
+    let db_link = &postgres_db.pool();
+    let user_details = get_user_details(db_link, user_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound(String::from("User not found")))?;
+    Ok(HttpResponse::Ok().json(user_details))
+}
+
+async fn handle_disable_user(
+    request: HttpRequest, 
+    admin: AdminUser, 
+    postgres_db: Data<PersistentDB>, 
+    redis_db: Data<CacheDB>, 
+    request_path: web::Path<Uuid>) -> Result<HttpResponse, AppError> {
+
+    let user_id = request_path.into_inner();
+    log::info!("Admin: `{}` tried to disable user: `{}`", admin.user_id, user_id);
+
+    if user_id == admin.user_id {
+        return Err(AppError::Validation(String::from("Admin can't disable own account")));
+    }
+
+    let db_link = &postgres_db.pool();
+    let cache = redis_db.get_ref();
+
+    if !set_account_status(db_link, user_id, 1, 4).await? {
+        return Err(AppError::Conflict(String::from("Account is not active")));
+    }
+
+    drop_user_data_from_redis(cache, user_id).await;
+    if let Err(db_error) = revoke_user_sessions(db_link, user_id).await {
+        log::error!("Database issue: {:?}", db_error);
+    }
+    if let Err(error) = revoke_user_access_tokens(db_link, cache, user_id).await {
+        log::error!("Access tokens of user: `{}` weren't revoked: {:?}", user_id, error);
+    }
+
+    log::info!("User: `{}` disabled by admin: `{}`", user_id, admin.user_id);
+    record_audit_event(db_link, &request, AuditEvent::UserDisabled, Some(user_id), Some(admin.user_id), json!({})).await;
+    Ok(HttpResponse::Ok().json(ServerResponse {
+        status: 200, 
+        message: String::from("User disabled")
+    }))
+}
+
+async fn handle_enable_user(
+    request: HttpRequest, 
+    admin: AdminUser, 
+    postgres_db: Data<PersistentDB>, 
+    request_path: web::Path<Uuid>) -> Result<HttpResponse, AppError> {
+
+    let user_id = request_path.into_inner();
+    log::info!("Admin: `{}` tried to enable user: `{}`", admin.user_id, user_id);
+
+    let db_link = &postgres_db.pool();
+    if !set_account_status(db_link, user_id, 4, 1).await? {
+        return Err(AppError::Conflict(String::from("Account is not disabled")));
+    }
+
+    log::info!("User: `{}` enabled by admin: `{}`", user_id, admin.user_id);
+    record_audit_event(db_link, &request, AuditEvent::UserEnabled, Some(user_id), Some(admin.user_id), json!({})).await;
+    Ok(HttpResponse::Ok().json(ServerResponse {
+        status: 200, 
+        message: String::from("User enabled")
+    }))
+}
+
+// current password stops working at once, the user gets a reset link by email.
+// The reset holds even if the mail fails, the user can still ask for a new link by themselves
+async fn handle_force_password_reset(
+    request: HttpRequest, 
+    admin: AdminUser, 
+    postgres_db: Data<PersistentDB>, 
+    redis_db: Data<CacheDB>, 
+    request_path: web::Path<Uuid>) -> Result<HttpResponse, AppError> {
+
+    let user_id = request_path.into_inner();
+    log::info!("Admin: `{}` tried to force password reset of user: `{}`", admin.user_id, user_id);
+
+    let db_link = &postgres_db.pool();
+    let cache = redis_db.get_ref();
+
+    let email = scramble_password(db_link, user_id)
+        .await?
+        .ok_or_else(|| AppError::Conflict(String::from("Account is not active")))?;
+
+    drop_user_data_from_redis(cache, user_id).await;
+    if let Err(db_error) = revoke_user_sessions(db_link, user_id).await {
+        log::error!("Database issue: {:?}", db_error);
+    }
+    if let Err(error) = revoke_user_access_tokens(db_link, cache, user_id).await {
+        log::error!("Access tokens of user: `{}` weren't revoked: {:?}", user_id, error);
+    }
+    record_audit_event(db_link, &request, AuditEvent::PasswordResetForced, Some(user_id), Some(admin.user_id), json!({})).await;
+
+    let reset_token = create_password_reset(db_link, user_id).await?;
+    let message = format!(
+        "Password of your account was reset by administrator. \
+        To set a new password follow the link {}/password_reset/{} \
+        It would be valid in next {} minutes.", 
+        settings().server.service_url, 
+        reset_token, 
+        settings().tokens.password_reset_lifetime / 60
+    );
+    let response_message = match send_email_off_worker(email, "Password reset email", message).await {
+        Ok(_) => "Password reset, email with reset link sent", 
+        Err(_) => "Password reset, email with reset link wasn't sent"
+    };
+
+    log::info!("Password of user: `{}` reset by admin: `{}`", user_id, admin.user_id);
+    Ok(HttpResponse::Ok().json(ServerResponse {
+        status: 200, 
+        message: String::from(response_message)
+    }))
+}
+
+async fn handle_get_audit_events(
+    admin: AdminUser, 
+    postgres_db: Data<PersistentDB>, 
+    request_query: Query<AuditEventsQuery>) -> Result<HttpResponse, AppError> {
+
+    let filter = request_query.into_inner();
+    log::info!("Audit events requested by admin: `{}`", admin.user_id);
+
+    let limit = filter.limit.unwrap_or(AUDIT_EVENTS_PAGE_SIZE).clamp(1, AUDIT_EVENTS_PAGE_LIMIT);
+    let offset = filter.offset.unwrap_or(0).max(0);
+
+    let db_link = &postgres_db.pool();
+    let events = get_audit_events(db_link, &filter, limit, offset).await?;
+    Ok(HttpResponse::Ok().json(events))
+}
\ No newline at end of file
@@ -1,22 +1,45 @@
 use actix_web::{
     web::{self, Data, Json}, 
-    HttpRequest, Responder, HttpResponse, 
-    cookie::{time::Duration, Cookie}
+    HttpRequest, HttpResponse, 
+    http::header::{ContentDisposition, DispositionType, DispositionParam}
 };
-use log;
-use sqlx::{self, Row};
+use serde_json::json;
 use uuid::Uuid;
 
 use crate::models::{
     Profile, ServerResponse, ChangePasswordBody, 
-    ChangeEmailBody, ChangeUsernameBody, StoredUser
+    ChangeEmailBody, ChangeUsernameBody, DeleteAccountBody, Session, 
+    TwoFactorEnrollment, ConfirmTwoFactorBody, DisableTwoFactorBody, RecoveryCodes, 
+    PersonalToken, NewPersonalToken, CreatePersonalTokenBody, AuditEventsQuery
 };
-use crate::{PersistentDB, CacheDB, APP_SCHEMA, USERS_TABLE, TOKEN_LIFETIME};
+use crate::{
+    PersistentDB, CacheDB, 
+    PERSONAL_TOKENS_LIMIT, AUDIT_EVENTS_PAGE_SIZE, AUDIT_EVENTS_PAGE_LIMIT
+};
+use crate::errors::AppError;
+use crate::repositories::{Repository, UserRepository};
+use crate::settings::settings;
 use crate::redis_handlers::{
-    put_user_data_to_redis, get_user_data_by_id_from_redis, drop_user_data_from_redis
+    put_user_data_to_redis, get_user_data_by_id_from_redis, drop_user_data_from_redis, 
+    purge_user_data_from_redis, revoke_token_in_redis, revoke_session_in_redis
+};
+use crate::audit::{AuditEvent, hash_email, record_user_event, get_audit_events};
+use crate::autorization::revoke_user_access_tokens;
+use crate::auth_user::AuthUser;
+use crate::cookies::drop_auth_cookies;
+use crate::action_tokens::{ActionToken, ActionPurpose, create_action_token};
+use crate::sessions::{get_user_sessions, revoke_session, revoke_user_sessions};
+use crate::accounts::{soft_delete_account, export_account};
+use crate::personal_tokens::{
+    AVAILABLE_SCOPES, count_personal_tokens, create_personal_token, get_personal_tokens, revoke_personal_token
+};
+use crate::passwords::{hash_password, verify_password};
+use crate::two_factor::{
+    generate_totp_secret, totp_uri, verify_totp_code, generate_recovery_codes, 
+    get_totp_state, set_pending_totp_secret, enable_two_factor, disable_two_factor, TotpState
 };
-use crate::convertations::{AsHash, AsBase64};
-use crate::tools::{send_email, is_valid_password, is_valid_email};
+use crate::password_policy::PasswordPolicy;
+use crate::tools::{send_email_off_worker, is_valid_email};
 
 pub fn authorized_users_managing(cfg: &mut web::ServiceConfig) {
     cfg
@@ -35,417 +58,608 @@ pub fn authorized_users_managing(cfg: &mut web::ServiceConfig) {
         ).service(
             web::resource("/logout")
                 .route(web::delete().to(handle_logout))
+        ).service(
+            web::resource("/logout_everywhere")
+                .route(web::delete().to(handle_logout_everywhere))
+        ).service(
+            web::resource("/sessions")
+                .route(web::get().to(handle_get_sessions))
+        ).service(
+            web::resource("/sessions/{session_id}")
+                .route(web::delete().to(handle_delete_session))
+        ).service(
+            web::resource("/tokens")
+                .route(web::get().to(handle_get_personal_tokens))
+                .route(web::post().to(handle_create_personal_token))
+        ).service(
+            web::resource("/tokens/{token_id}")
+                .route(web::delete().to(handle_delete_personal_token))
+        ).service(
+            web::resource("/account")
+                .route(web::delete().to(handle_delete_account))
+        ).service(
+            web::resource("/account/export")
+                .route(web::get().to(handle_export_account))
+        ).service(
+            web::resource("/account/security_events")
+                .route(web::get().to(handle_get_security_events))
+        ).service(
+            web::resource("/two_factor")
+                .route(web::delete().to(handle_disable_two_factor))
+        ).service(
+            web::resource("/two_factor/enroll")
+                .route(web::post().to(handle_enroll_two_factor))
+        ).service(
+            web::resource("/two_factor/confirm")
+                .route(web::post().to(handle_confirm_two_factor))
         );
 }
 
+
 async fn handle_get_user(
-    request: HttpRequest,
-    postgres_db: Data<PersistentDB>, 
-    redis_db: Data<CacheDB>) -> impl Responder {
+    auth_user: AuthUser,
+    repository: Data<Repository>, 
+    redis_db: Data<CacheDB>) -> Result<HttpResponse, AppError> {
 
-    let headers = request.headers();
-    let user_id: Uuid = headers.get("user_id").unwrap().to_str().unwrap().parse().unwrap();
+    let user_id = auth_user.user_id;
     log::info!("Requested profile data for user: `{}`", user_id);
 
-    let db_link = &*postgres_db.db.lock().unwrap();
-    let redis_conn = &mut *redis_db.db.lock().unwrap();
+    let cache = redis_db.get_ref();
 
-    let redis_data = get_user_data_by_id_from_redis(redis_conn, user_id);
+    let redis_data = get_user_data_by_id_from_redis(cache, user_id).await;
     if let Ok(cached_user_data) = redis_data {
         let username: String = cached_user_data.name;
         let email: String = cached_user_data.email;
 
-        if headers.contains_key("new_token") {
-            let token = headers.get("new_token").unwrap().to_str().unwrap();
-            let mut cookie = Cookie::new("x-auth", token);
-            cookie.set_max_age(Duration::seconds(TOKEN_LIFETIME));
-
-            return HttpResponse::Ok().cookie(cookie).json(Profile {
-                id: user_id,
-                name: username, 
-                email: email
-            });
-        } else {
-            return HttpResponse::Ok().json(Profile {
-                id: user_id,
-                name: username, 
-                email: email
-            });
-        }
+        return Ok(HttpResponse::Ok().json(Profile {
+            id: user_id,
+            name: username, 
+            email
+        }));
     }
 
-    let query = format!("
-        SELECT 
-            id, name, email, passwd, verification_status_id, status_id, created_at, updated_at
-        FROM {}.{}
-        WHERE status_id = 1
-        ", 
-        APP_SCHEMA, 
-        USERS_TABLE
-    ); 
-
-    let result = sqlx::query(&query)
-        .bind(user_id)
-        .map(|row| {
-            StoredUser{
-                id: row.get("id"),
-                name: row.get("name"),
-                email: row.get("email"), 
-                passwd: row.get("passwd"), 
-                verification_status_id: row.get("verification_status_id"), 
-                status_id: row.get("status_id"), 
-                created_at: row.get("created_at"), 
-                updated_at: row.get("updated_at")
-            } 
-        })
-        .fetch_all(db_link)
-        .await;
-
-    match result {
-        Ok(stored_users) => {
-            if stored_users.len() > 0 {
-                let stored_user = &stored_users[0];
-                put_user_data_to_redis(redis_conn, stored_user.get_user(), None);
-
-                let user = stored_user.get_user();
-                let username: String = user.name;
-                let email: String = user.email;
-                if headers.contains_key("new_token") {
-                    let token = headers.get("new_token").unwrap().to_str().unwrap();
-                    let mut cookie = Cookie::new("x-auth", token);
-                    cookie.set_max_age(Duration::seconds(TOKEN_LIFETIME));
-        
-                    HttpResponse::Ok().cookie(cookie).json(Profile {
-                        id: user_id,
-                        name: username, 
-                        email: email
-                    })
-                } else {
-                    HttpResponse::Ok().json(Profile {
-                        id: user_id,
-                        name: username, 
-                        email: email
-                    })
-                }
-            } else {
-                HttpResponse::InternalServerError().json(ServerResponse {
-                    status: 500, 
-                    message: String::from("Internal server error")
-                })
-            }
-        }, 
-        Err(db_error) => {
-            log::error!("Database issue: {:?}", db_error);
-            HttpResponse::InternalServerError().json(ServerResponse {
-                status: 500, 
-                message: String::from("Internal server error")
-            })
-        }
+    let stored_user = repository
+        .get_active_user(user_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound(String::from("User not found")))?;
+
+    if let Err(redis_error) = put_user_data_to_redis(cache, stored_user.get_user(), None).await {
+        log::warn!("Cache database issue: {:?}", redis_error);
     }
+
+    let user = stored_user.get_user();
+    let username: String = user.name;
+    let email: String = user.email;
+    Ok(HttpResponse::Ok().json(Profile {
+        id: user_id, 
+        name: username, 
+        email
+    }))
 }
 
 async fn handle_change_username(
     request: HttpRequest,
+    auth_user: AuthUser,
     postgres_db: Data<PersistentDB>, 
+    repository: Data<Repository>, 
     redis_db: Data<CacheDB>, 
-    request_data: Json<ChangeUsernameBody>) -> impl Responder {
-    
+    request_data: Json<ChangeUsernameBody>) -> Result<HttpResponse, AppError> {
+
     let ChangeUsernameBody {new_name} = request_data.0;
-    let headers = request.headers();
-    let user_id: Uuid = headers.get("user_id").unwrap().to_str().unwrap().parse().unwrap();
+    let user_id = auth_user.user_id;
     log::info!("Request for changing name from user: `{}`", user_id);
 
-    let db_link = &*postgres_db.db.lock().unwrap();
-    let redis_conn = &mut *redis_db.db.lock().unwrap();
-
-    let query = format!("
-        UPDATE {}.{}
-            SET name = $2
-            WHERE id = $1 
-            AND status_id = 1
-        ", 
-        APP_SCHEMA, 
-        USERS_TABLE
-    );
-    let result = sqlx::query(&query)
-        .bind(user_id)
-        .bind(&new_name)
-        .execute(db_link)
-        .await;
-
-    match result {
-        Ok(_) => {
-            drop_user_data_from_redis(redis_conn, user_id);
-            log::info!("New name `{}` setted for user: `{}`", new_name, user_id);
-
-            if headers.contains_key("new_token") {
-                let token = headers.get("new_token").unwrap().to_str().unwrap();
-                let mut cookie = Cookie::new("x-auth", token);
-                cookie.set_max_age(Duration::seconds(TOKEN_LIFETIME));
-
-                HttpResponse::Ok().cookie(cookie).json(ServerResponse {
-                    status: 200, 
-                    message: String::from("User name updated")
-                })
-            } else {
-                HttpResponse::Ok().json(ServerResponse {
-                    status: 200, 
-                    message: String::from("User name updated")
-                })
-            }
-        }, 
-        Err(db_error) => {
-            log::error!("Database issue: {:?}", db_error);
-            HttpResponse::InternalServerError().json(ServerResponse {
-                status: 500, 
-                message: String::from("Internal server error")
-            })
-        }
-    }
+    let db_link = &postgres_db.pool();
+    let cache = redis_db.get_ref();
+
+    repository.change_username(user_id, &new_name).await?;
+
+    drop_user_data_from_redis(cache, user_id).await;
+    log::info!("New name `{}` setted for user: `{}`", new_name, user_id);
+    record_user_event(db_link, &request, AuditEvent::UsernameChanged, user_id, json!({ "name": new_name })).await;
+
+    Ok(HttpResponse::Ok().json(ServerResponse {
+        status: 200, 
+        message: String::from("User name updated")
+    }))
 }
 
 async fn handle_change_password(
     request: HttpRequest,
+    auth_user: AuthUser,
     postgres_db: Data<PersistentDB>, 
+    repository: Data<Repository>, 
     redis_db: Data<CacheDB>, 
-    request_data: Json<ChangePasswordBody>) -> impl Responder {
+    password_policy: Data<PasswordPolicy>, 
+    request_data: Json<ChangePasswordBody>) -> Result<HttpResponse, AppError> {
 
     let ChangePasswordBody {old_password, new_password} = request_data.0;
-    let headers = request.headers();
-    let user_id: Uuid = headers.get("user_id").unwrap().to_str().unwrap().parse().unwrap();
-    
+    let user_id = auth_user.user_id;
+
     log::info!("Request for changing password from user: `{}`", user_id);
-    if !is_valid_password(&new_password) {
-        log::warn!("Invalid new password received from user: `{}`", user_id);
-        return HttpResponse::BadRequest().body("Invalid password");
-    }
 
-    let old_password = old_password.as_hash();
-    let new_password = new_password.as_hash();
-    
-    let db_link = &*postgres_db.db.lock().unwrap();
-    let redis_conn = &mut *redis_db.db.lock().unwrap();
+    let db_link = &postgres_db.pool();
+    let cache = redis_db.get_ref();
 
-    let query: String;        
-    let redis_data = get_user_data_by_id_from_redis(redis_conn, user_id);
+    let email: Option<String>;
+    let name: Option<String>;
+    let redis_data = get_user_data_by_id_from_redis(cache, user_id).await;
     if let Ok(cached_user_data) = redis_data {
-        if old_password == cached_user_data.passwd {
-            query = format!("
-                UPDATE {}.{}
-                    SET passwd = $2, verification_status_id = 1
-                    WHERE id = $1 
-                    AND status_id = 1
-                RETURNING id
-                ", 
-                APP_SCHEMA, 
-                USERS_TABLE
-            );
+        if verify_password(&old_password, &cached_user_data.passwd).await.is_valid() {
+            email = Some(cached_user_data.email);
+            name = Some(cached_user_data.name);
         } else {
             log::warn!("Invalid current password received from user: `{}`", user_id);
-            return HttpResponse::BadRequest().json(ServerResponse {
-                status: 400, 
-                message: String::from("Invalid password")
-            });
+            return Err(AppError::Validation(String::from("Invalid password")));
         }
     } else {
-        query = format!("
-            UPDATE {}.{}
-                SET passwd = $2
-                WHERE id = $1 
-                AND status_id = 1
-                AND passwd = '{}'
-            RETURNING id
-            ", 
-            APP_SCHEMA, 
-            USERS_TABLE, 
-            old_password
-        ); 
+        let stored_user = repository.get_active_user(user_id).await?;
+        let is_valid = match &stored_user {
+            Some(stored_user) => verify_password(&old_password, stored_user.passwd.as_deref().unwrap_or_default()).await.is_valid(), 
+            None => false
+        };
+        match stored_user {
+            Some(stored_user) if is_valid => {
+                email = stored_user.email;
+                name = stored_user.name;
+            }, 
+            _ => {
+                log::warn!("Invalid current password received from user: `{}`", user_id);
+                return Err(AppError::Validation(String::from("Invalid password")));
+            }
+        }
     }
 
-    let result = sqlx::query(&query)
-        .bind(user_id)
-        .bind(new_password)
-        .fetch_all(db_link)
-        .await;
-
-    match result {
-        Ok(update_result) => {
-            if update_result.len() > 0 {
-                drop_user_data_from_redis(redis_conn, user_id);
-                log::info!("Password updated for user: `{}`", user_id);
-
-                let mut cookie = Cookie::new("x-auth", "");
-                cookie.set_max_age(Duration::seconds(0));
+    let violations = password_policy.check(&new_password, email.as_deref(), name.as_deref());
+    if !violations.is_empty() {
+        log::warn!("New password of user: `{}` rejected by policy: {:?}", user_id, violations);
+        return Err(AppError::PasswordRejected(violations));
+    }
 
-                HttpResponse::Ok().cookie(cookie).json(ServerResponse {
-                    status: 200, 
-                    message: String::from("Password updated")
-                })
+    let new_password_hash = hash_password(&new_password).await;
+    if !repository.change_password(user_id, &new_password_hash).await? {
+        log::warn!("Invalid current password received from user: `{}`", user_id);
+        return Err(AppError::Validation(String::from("Invalid password")));
+    }
 
-            } else {
-                log::warn!("Invalid current password received from user: `{}`", user_id);
-                HttpResponse::BadRequest().json(ServerResponse {
-                    status: 400, 
-                    message: String::from("Invalid password")
-                })
-            }
-        }, 
-        Err(db_error) => {
-            log::error!("Database issue: {:?}", db_error);
-            HttpResponse::InternalServerError().json(ServerResponse {
-                status: 500, 
-                message: String::from("Internal server error")
-            })
-        }
+    drop_user_data_from_redis(cache, user_id).await;
+    if let Err(db_error) = revoke_user_sessions(db_link, user_id).await {
+        log::error!("Database issue: {:?}", db_error);
     }
-    
+    if let Err(error) = revoke_user_access_tokens(db_link, cache, user_id).await {
+        log::error!("Access tokens of user: `{}` weren't revoked: {:?}", user_id, error);
+    }
+    log::info!("Password updated for user: `{}`", user_id);
+    record_user_event(db_link, &request, AuditEvent::PasswordChanged, user_id, json!({})).await;
+
+    Ok(drop_auth_cookies(&mut HttpResponse::Ok()).json(ServerResponse {
+        status: 200, 
+        message: String::from("Password updated")
+    }))
 }
 
 async fn handle_change_email(
     request: HttpRequest,
+    auth_user: AuthUser,
     postgres_db: Data<PersistentDB>, 
+    repository: Data<Repository>, 
     redis_db: Data<CacheDB>, 
-    request_data: Json<ChangeEmailBody>) -> impl Responder {
+    request_data: Json<ChangeEmailBody>) -> Result<HttpResponse, AppError> {
 
     let ChangeEmailBody {new_email } = request_data.0;
-    let headers = request.headers();
-    let user_id: Uuid = headers.get("user_id").unwrap().to_str().unwrap().parse().unwrap();
+    let user_id = auth_user.user_id;
 
     log::info!("Request for changing email to: `{}` from user: `{}`", new_email, user_id);
     if !is_valid_email(&new_email) {
         log::warn!("Invalid email: `{}` received from user: `{}`", new_email, user_id);
-        return HttpResponse::BadRequest().body("Invalid email");
+        return Err(AppError::Validation(String::from("Invalid email")));
     }
 
-    let db_link = &*postgres_db.db.lock().unwrap();
-    let redis_conn = &mut *redis_db.db.lock().unwrap();
+    let db_link = &postgres_db.pool();
+    let cache = redis_db.get_ref();
 
-    let check_query = format!(
-        "SELECT 
-            id
-           FROM {}.{}
-          WHERE email = $1", 
-        APP_SCHEMA, 
-        USERS_TABLE
-    );
-    let check_result = sqlx::query(&check_query)
-        .bind(new_email.clone())
-        .fetch_all(db_link)
-        .await;
-
-    match check_result {
-        Ok(existed_users) => {
-            if existed_users.len() > 0 {
-                log::warn!("User `{}` attempted to set email as new witch exists in DB: `{}`", user_id, new_email);
-                HttpResponse::BadRequest().json(ServerResponse {
-                    status: 400, 
-                    message: format!("User with email {} already exists", new_email)
-                })
-            } else {
-                let query = format!("
-                    UPDATE {}.{}
-                        SET verification_status_id = 4
-                        WHERE id = $1 
-                        AND status_id = 1
-                    RETURNING id, name, email, passwd, verification_status_id, status_id, created_at, updated_at
-                    ", 
-                    APP_SCHEMA, 
-                    USERS_TABLE
-                );
-                let result = sqlx::query(&query)
-                    .bind(user_id)
-                    .map(|row| {
-                        StoredUser{
-                            id: row.get("id"),
-                            name: row.get("name"),
-                            email: row.get("email"), 
-                            passwd: row.get("passwd"), 
-                            verification_status_id: row.get("verification_status_id"), 
-                            status_id: row.get("status_id"), 
-                            created_at: row.get("created_at"), 
-                            updated_at: row.get("updated_at")
-                        } 
-                    })
-                    .fetch_all(db_link)
-                    .await;
-
-                match result {
-                    Ok(stored_users) => {
-                        if stored_users.len() > 0 {
-                            let stored_user = &stored_users[0].get_user();
-                            let verification_token = format!(
-                                "{}{}{}", 
-                                new_email, 
-                                stored_user.email, 
-                                stored_user.created_at
-                            ).as_hash();
-
-                            drop_user_data_from_redis(redis_conn, user_id);
-                            let message = format!(
-                                "Click the link to verify your new email address {}/email_verification/{}/{}/{}", 
-                                crate::SERVICE_URL, 
-                                new_email.as_base64(), 
-                                user_id, 
-                                verification_token
-                            );
-                            send_email(&new_email, "New email address verification", &message);
-                            log::info!("Verification email for user `{}` sent to address: `{}`", user_id, new_email);
-
-                            if headers.contains_key("new_token") {
-                                let token = headers.get("new_token").unwrap().to_str().unwrap();
-                                let mut cookie = Cookie::new("x-auth", token);
-                                cookie.set_max_age(Duration::seconds(TOKEN_LIFETIME));
-
-                                HttpResponse::Ok().cookie(cookie).json(ServerResponse {
-                                    status: 200, 
-                                    message: String::from("Verification mail was sent")
-                                })
-                            } else {
-                                HttpResponse::Ok().json(ServerResponse {
-                                    status: 200, 
-                                    message: String::from("Verification mail was sent")
-                                })
-                            }
-                        } else {
-                            HttpResponse::InternalServerError().json(ServerResponse {
-                                status: 500, 
-                                message: String::from("Internal server error")
-                            })
-                        }
-                    }, 
-                    Err(db_error) => {
-                        log::error!("Database issue: {:?}", db_error);
-                        HttpResponse::InternalServerError().json(ServerResponse {
-                            status: 500, 
-                            message: String::from("Internal server error")
-                        })
-                    }
-                }
-            }
-        }, 
-        Err(db_error) => {
-            log::error!("Database issue: {:?}", db_error);
-            HttpResponse::InternalServerError().json(ServerResponse {
-                status: 500, 
-                message: String::from("Internal server error")
-            })
-        }
+    if repository.email_exists(&new_email).await? {
+        log::warn!("User `{}` attempted to set email as new witch exists in DB: `{}`", user_id, new_email);
+        return Err(AppError::Conflict(format!("User with email {} already exists", new_email)));
     }
+
+    if !repository.request_email_change(user_id).await? {
+        return Err(AppError::Conflict(String::from("Account is not active")));
+    }
+
+    let verification_token = create_action_token(ActionToken::new(
+        ActionPurpose::EmailVerification, 
+        user_id, 
+        settings().tokens.email_verification_lifetime, 
+        Some(new_email.clone())
+    ));
+
+    drop_user_data_from_redis(cache, user_id).await;
+    let message = format!(
+        "Click the link to verify your new email address {}/email_verification/{} \
+        It would be valid in next {} hours", 
+        settings().server.service_url, 
+        verification_token, 
+        settings().tokens.email_verification_lifetime / 3_600
+    );
+    send_email_off_worker(new_email.clone(), "New email address verification", message).await?;
+    log::info!("Verification email for user `{}` sent to address: `{}`", user_id, new_email);
+    let metadata = json!({ "email_hash": hash_email(&new_email) });
+    record_user_event(db_link, &request, AuditEvent::EmailChangeRequested, user_id, metadata).await;
+
+    Ok(HttpResponse::Ok().json(ServerResponse {
+        status: 200, 
+        message: String::from("Verification mail was sent")
+    }))
 }
 
 async fn handle_logout(
     request: HttpRequest,
-    redis_db: Data<CacheDB>) -> impl Responder {
-    
-    let headers = request.headers();
-    let user_id: Uuid = headers.get("user_id").unwrap().to_str().unwrap().parse().unwrap();
+    auth_user: AuthUser,
+    postgres_db: Data<PersistentDB>, 
+    redis_db: Data<CacheDB>) -> Result<HttpResponse, AppError> {
 
-    let redis_conn = &mut *redis_db.db.lock().unwrap();
-    drop_user_data_from_redis(redis_conn, user_id);
+    let user_id = auth_user.user_id;
 
-    let mut cookie = Cookie::new("x-auth", "");
-    cookie.set_max_age(Duration::seconds(0));
+    let db_link = &postgres_db.pool();
+    let cache = redis_db.get_ref();
+    if let Some(token) = auth_user.access_token {
+        revoke_session(db_link, user_id, token.sid).await?;
+        let lifetime = token.remaining_lifetime().max(1) as usize;
+        revoke_token_in_redis(cache, token.jti, lifetime).await?;
+        revoke_session_in_redis(cache, token.sid).await?;
+    }
+    drop_user_data_from_redis(cache, user_id).await;
 
     log::info!("Logout of user: `{}`", user_id);
-    HttpResponse::Ok().cookie(cookie).body("Logout")
+    record_user_event(db_link, &request, AuditEvent::Logout, user_id, json!({})).await;
+    Ok(drop_auth_cookies(&mut HttpResponse::Ok()).body("Logout"))
+}
+
+async fn handle_logout_everywhere(
+    request: HttpRequest,
+    auth_user: AuthUser,
+    postgres_db: Data<PersistentDB>, 
+    redis_db: Data<CacheDB>) -> Result<HttpResponse, AppError> {
+
+    let user_id = auth_user.user_id;
+
+    let db_link = &postgres_db.pool();
+    let cache = redis_db.get_ref();
+    revoke_user_sessions(db_link, user_id).await?;
+    revoke_user_access_tokens(db_link, cache, user_id).await?;
+    drop_user_data_from_redis(cache, user_id).await;
+
+    log::info!("All tokens of user: `{}` revoked", user_id);
+    record_user_event(db_link, &request, AuditEvent::LogoutEverywhere, user_id, json!({})).await;
+    Ok(drop_auth_cookies(&mut HttpResponse::Ok()).body("Logout"))
+}
+
+async fn handle_get_sessions(
+    auth_user: AuthUser,
+    postgres_db: Data<PersistentDB>) -> Result<HttpResponse, AppError> {
+
+    let user_id = auth_user.user_id;
+    let session_id = auth_user.session_id.ok_or_else(|| {
+        AppError::Forbidden(String::from("Sessions are available for session tokens only"))
+    })?;
+    log::info!("Active sessions requested by user: `{}`", user_id);
+
+    let db_link = &postgres_db.pool();
+    let sessions: Vec<Session> = get_user_sessions(db_link, user_id)
+        .await?
+        .iter()
+        .map(|stored_session| stored_session.get_session(session_id))
+        .collect();
+
+    Ok(HttpResponse::Ok().json(sessions))
+}
+
+async fn handle_delete_session(
+    request: HttpRequest,
+    auth_user: AuthUser,
+    postgres_db: Data<PersistentDB>, 
+    redis_db: Data<CacheDB>, 
+    request_path: web::Path<Uuid>) -> Result<HttpResponse, AppError> {
+
+    let user_id = auth_user.user_id;
+    let session_id = request_path.into_inner();
+    log::info!("User: `{}` tried to revoke session `{}`", user_id, session_id);
+
+    let db_link = &postgres_db.pool();
+    let cache = redis_db.get_ref();
+
+    if !revoke_session(db_link, user_id, session_id).await? {
+        log::warn!("User: `{}` tried to revoke unknown session `{}`", user_id, session_id);
+        return Err(AppError::NotFound(String::from("Session not found")));
+    }
+    revoke_session_in_redis(cache, session_id).await?;
+
+    log::info!("Session `{}` of user: `{}` revoked", session_id, user_id);
+    let metadata = json!({ "session_id": session_id });
+    record_user_event(db_link, &request, AuditEvent::SessionRevoked, user_id, metadata).await;
+    Ok(HttpResponse::Ok().json(ServerResponse {
+        status: 200, 
+        message: String::from("Session revoked")
+    }))
 }
+
+async fn handle_get_personal_tokens(
+    auth_user: AuthUser,
+    postgres_db: Data<PersistentDB>) -> Result<HttpResponse, AppError> {
+
+    let user_id = auth_user.user_id;
+    log::info!("Personal tokens requested by user: `{}`", user_id);
+
+    let db_link = &postgres_db.pool();
+
+    let tokens: Vec<PersonalToken> = get_personal_tokens(db_link, user_id)
+        .await?
+        .iter()
+        .map(|stored_token| stored_token.get_personal_token())
+        .collect();
+    Ok(HttpResponse::Ok().json(tokens))
+}
+
+async fn handle_create_personal_token(
+    request: HttpRequest,
+    auth_user: AuthUser,
+    postgres_db: Data<PersistentDB>, 
+    request_data: Json<CreatePersonalTokenBody>) -> Result<HttpResponse, AppError> {
+
+    let CreatePersonalTokenBody { name, mut scopes, expires_in } = request_data.0;
+    let user_id = auth_user.user_id;
+    log::info!("Personal token `{}` creation requested by user: `{}`", name, user_id);
+
+    let name = name.trim().to_string();
+    if name.is_empty() || name.len() > 64 {
+        log::warn!("Invalid personal token name received from user: `{}`", user_id);
+        return Err(AppError::Validation(String::from("Token name should contain from 1 to 64 signs")));
+    }
+    scopes.sort();
+    scopes.dedup();
+    if scopes.is_empty() || scopes.iter().any(|scope| !AVAILABLE_SCOPES.contains(&scope.as_str())) {
+        log::warn!("Invalid personal token scopes received from user: `{}`", user_id);
+        return Err(AppError::Validation(format!("Token scopes should be chosen from: {}", AVAILABLE_SCOPES.join(", "))));
+    }
+    let max_lifetime = settings().tokens.personal_token_max_lifetime;
+    if let Some(expires_in) = expires_in {
+        if expires_in <= 0 || expires_in > max_lifetime {
+            log::warn!("Invalid personal token lifetime received from user: `{}`", user_id);
+            return Err(AppError::Validation(format!("Token lifetime should be from 1 to {} seconds", max_lifetime)));
+        }
+    }
+
+    let db_link = &postgres_db.pool();
+
+    if count_personal_tokens(db_link, user_id).await? >= PERSONAL_TOKENS_LIMIT {
+        log::warn!("User: `{}` reached personal tokens limit", user_id);
+        return Err(AppError::Conflict(format!("No more than {} active tokens allowed", PERSONAL_TOKENS_LIMIT)));
+    }
+
+    let (stored_token, token) = create_personal_token(db_link, user_id, &name, &scopes, expires_in).await?;
+    log::info!("Personal token `{}` created for user: `{}`", stored_token.id, user_id);
+    let metadata = json!({ "token_id": stored_token.id, "name": name, "scopes": scopes });
+    record_user_event(db_link, &request, AuditEvent::PersonalTokenCreated, user_id, metadata).await;
+    Ok(HttpResponse::Ok().json(NewPersonalToken {
+        token, 
+        details: stored_token.get_personal_token()
+    }))
+}
+
+async fn handle_delete_personal_token(
+    request: HttpRequest,
+    auth_user: AuthUser,
+    postgres_db: Data<PersistentDB>, 
+    request_path: web::Path<i32>) -> Result<HttpResponse, AppError> {
+
+    let user_id = auth_user.user_id;
+    let token_id = request_path.into_inner();
+    log::info!("User: `{}` tried to revoke personal token `{}`", user_id, token_id);
+
+    let db_link = &postgres_db.pool();
+
+    if !revoke_personal_token(db_link, user_id, token_id).await? {
+        log::warn!("User: `{}` tried to revoke unknown personal token `{}`", user_id, token_id);
+        return Err(AppError::NotFound(String::from("Token not found")));
+    }
+
+    log::info!("Personal token `{}` of user: `{}` revoked", token_id, user_id);
+    let metadata = json!({ "token_id": token_id });
+    record_user_event(db_link, &request, AuditEvent::PersonalTokenRevoked, user_id, metadata).await;
+    Ok(HttpResponse::Ok().json(ServerResponse {
+        status: 200, 
+        message: String::from("Token revoked")
+    }))
+}
+
+async fn handle_delete_account(
+    request: HttpRequest,
+    auth_user: AuthUser,
+    postgres_db: Data<PersistentDB>, 
+    repository: Data<Repository>, 
+    redis_db: Data<CacheDB>, 
+    request_data: Json<DeleteAccountBody>) -> Result<HttpResponse, AppError> {
+
+    let DeleteAccountBody { password } = request_data.0;
+    let user_id = auth_user.user_id;
+    log::info!("Account deletion requested by user: `{}`", user_id);
+
+    let db_link = &postgres_db.pool();
+    let cache = redis_db.get_ref();
+
+    if !check_current_password(&repository, user_id, &password).await? {
+        log::warn!("Invalid current password received from user: `{}`", user_id);
+        return Err(AppError::Validation(String::from("Invalid password")));
+    }
+
+    let deleted_account = soft_delete_account(db_link, user_id)
+        .await?
+        .ok_or_else(|| {
+            log::warn!("User: `{}` attempted to delete non-active account", user_id);
+            AppError::Conflict(String::from("Account is not active"))
+        })?;
+
+    purge_user_data_from_redis(cache, user_id, &deleted_account.email, &deleted_account.board_ids).await;
+    if let Err(db_error) = revoke_user_sessions(db_link, user_id).await {
+        log::error!("Database issue: {:?}", db_error);
+    }
+    if let Err(error) = revoke_user_access_tokens(db_link, cache, user_id).await {
+        log::error!("Access tokens of user: `{}` weren't revoked: {:?}", user_id, error);
+    }
+    log::info!("Account of user: `{}` deleted", user_id);
+    record_user_event(db_link, &request, AuditEvent::AccountDeleted, user_id, json!({})).await;
+
+    Ok(drop_auth_cookies(&mut HttpResponse::Ok()).json(ServerResponse {
+        status: 200, 
+        message: String::from("Account deleted")
+    }))
+}
+
+async fn handle_export_account(
+    request: HttpRequest,
+    auth_user: AuthUser,
+    postgres_db: Data<PersistentDB>) -> Result<HttpResponse, AppError> {
+
+    let user_id = auth_user.user_id;
+    log::info!("Data export requested by user: `{}`", user_id);
+
+    let db_link = &postgres_db.pool();
+
+    let account_export = export_account(db_link, user_id)
+        .await?
+        .ok_or_else(|| {
+            log::warn!("Data export requested for non-active account of user: `{}`", user_id);
+            AppError::Conflict(String::from("Account is not active"))
+        })?;
+
+    record_user_event(db_link, &request, AuditEvent::AccountExported, user_id, json!({})).await;
+    let file_name = format!(
+        "routine_export_{}.json", 
+        chrono::offset::Utc::now().naive_utc().format("%Y%m%d")
+    );
+    Ok(HttpResponse::Ok()
+        .insert_header(ContentDisposition {
+            disposition: DispositionType::Attachment, 
+            parameters: vec![DispositionParam::Filename(file_name)]
+        })
+        .json(account_export))
+}
+
+// events about the own account, filters other than event type and time range are ignored
+async fn handle_get_security_events(
+    auth_user: AuthUser,
+    postgres_db: Data<PersistentDB>, 
+    request_query: web::Query<AuditEventsQuery>) -> Result<HttpResponse, AppError> {
+
+    let user_id = auth_user.user_id;
+    log::info!("Security events requested by user: `{}`", user_id);
+
+    let request_query = request_query.into_inner();
+    let limit = request_query.limit.unwrap_or(AUDIT_EVENTS_PAGE_SIZE).clamp(1, AUDIT_EVENTS_PAGE_LIMIT);
+    let offset = request_query.offset.unwrap_or(0).max(0);
+    let filter = AuditEventsQuery {
+        user_id: Some(user_id), 
+        actor_id: None, 
+        ..request_query
+    };
+
+    let db_link = &postgres_db.pool();
+    let events = get_audit_events(db_link, &filter, limit, offset).await?;
+    Ok(HttpResponse::Ok().json(events))
+}
+
+async fn check_current_password(
+    repository: &Repository, 
+    user_id: Uuid, 
+    password: &str) -> Result<bool, sqlx::Error> {
+
+    let stored_password = repository
+        .get_active_user(user_id)
+        .await?
+        .and_then(|stored_user| stored_user.passwd);
+
+    match stored_password {
+        Some(stored_password) => Ok(verify_password(password, &stored_password).await.is_valid()), 
+        None => Ok(false)
+    }
+}
+
+async fn handle_enroll_two_factor(
+    auth_user: AuthUser,
+    postgres_db: Data<PersistentDB>) -> Result<HttpResponse, AppError> {
+
+    let user_id = auth_user.user_id;
+    log::info!("Two-factor enrollment requested by user: `{}`", user_id);
+
+    let db_link = &postgres_db.pool();
+    let secret = generate_totp_secret();
+
+    let email = set_pending_totp_secret(db_link, user_id, &secret)
+        .await?
+        .ok_or_else(|| {
+            log::warn!("User: `{}` tried to enroll two-factor authentication twice", user_id);
+            AppError::Conflict(String::from("Two-factor authentication already enabled"))
+        })?;
+
+    Ok(HttpResponse::Ok().json(TwoFactorEnrollment {
+        otpauth_uri: totp_uri(&secret, &email), 
+        secret
+    }))
+}
+
+async fn handle_confirm_two_factor(
+    request: HttpRequest,
+    auth_user: AuthUser,
+    postgres_db: Data<PersistentDB>, 
+    request_data: Json<ConfirmTwoFactorBody>) -> Result<HttpResponse, AppError> {
+
+    let ConfirmTwoFactorBody { code } = request_data.0;
+    let user_id = auth_user.user_id;
+    log::info!("Two-factor enrollment confirmation from user: `{}`", user_id);
+
+    let db_link = &postgres_db.pool();
+
+    let verified_step = match get_totp_state(db_link, user_id).await? {
+        Some(TotpState { secret: Some(secret), enabled: false, last_step }) => verify_totp_code(&secret, &code, last_step), 
+        _ => None
+    };
+    let step = verified_step.ok_or_else(|| {
+        log::warn!("Invalid two-factor confirmation code received from user: `{}`", user_id);
+        AppError::Validation(String::from("Invalid verification code"))
+    })?;
+
+    let recovery_codes = generate_recovery_codes();
+    enable_two_factor(db_link, user_id, step, &recovery_codes).await?;
+
+    log::info!("Two-factor authentication enabled for user: `{}`", user_id);
+    record_user_event(db_link, &request, AuditEvent::TwoFactorEnabled, user_id, json!({})).await;
+    Ok(HttpResponse::Ok().json(RecoveryCodes { recovery_codes }))
+}
+
+async fn handle_disable_two_factor(
+    request: HttpRequest,
+    auth_user: AuthUser,
+    postgres_db: Data<PersistentDB>, 
+    repository: Data<Repository>, 
+    request_data: Json<DisableTwoFactorBody>) -> Result<HttpResponse, AppError> {
+
+    let DisableTwoFactorBody { password } = request_data.0;
+    let user_id = auth_user.user_id;
+    log::info!("Request for disabling two-factor authentication from user: `{}`", user_id);
+
+    let db_link = &postgres_db.pool();
+
+    if !check_current_password(&repository, user_id, &password).await? {
+        log::warn!("Invalid current password received from user: `{}`", user_id);
+        return Err(AppError::Validation(String::from("Invalid password")));
+    }
+
+    disable_two_factor(db_link, user_id).await?;
+    log::info!("Two-factor authentication disabled for user: `{}`", user_id);
+    record_user_event(db_link, &request, AuditEvent::TwoFactorDisabled, user_id, json!({})).await;
+    Ok(HttpResponse::Ok().json(ServerResponse {
+        status: 200, 
+        message: String::from("Two-factor authentication disabled")
+    }))
+}
\ No newline at end of file
@@ -1,26 +1,59 @@
 use actix_web::{
     web::{self, Data, Json}, 
-    Responder, HttpResponse, 
-    cookie::{time::Duration, Cookie}
+    HttpRequest, Responder, HttpResponse, HttpResponseBuilder, 
+    http::header
 };
-use chrono::{self, NaiveDateTime};
-use log;
-use sqlx::{self, Row};
+use serde_json::json;
 use uuid::Uuid;
 
+use sqlx::{Postgres, Pool};
+
 use crate::models::{
-    ServerResponse, UserCredentials, CreateUserBody, 
-    ChangeForgottenPasswordBody, StoredUser
+    ServerResponse, UserCredentials, CreateUserBody, OidcCallbackQuery, 
+    ChangeForgottenPasswordBody, ResendVerificationBody, ResetPasswordBody, TokenPair, RefreshTokenBody, 
+    LoginChallenge, SecondFactorBody
+};
+use crate::{
+    PersistentDB, CacheDB, OIDC_STATE_COOKIE, 
+    REFRESH_TOKEN_COOKIE, LOGIN_CHALLENGE_ATTEMPTS
 };
-use crate::{PersistentDB, CacheDB, APP_SCHEMA, USERS_TABLE, TOKEN_LIFETIME};
+use crate::errors::AppError;
+use crate::repositories::{Repository, UserRepository, ActionTokenRepository};
+use crate::settings::settings;
 use crate::redis_handlers::{
     put_user_data_to_redis, 
-    get_user_data_by_email_from_redis, 
-    drop_user_data_from_redis
+    drop_user_data_from_redis, 
+    revoke_session_in_redis, 
+    put_login_challenge_to_redis, 
+    get_login_challenge_from_redis, 
+    count_login_challenge_attempt_in_redis, 
+    drop_login_challenge_from_redis, 
+    mark_verification_resent_in_redis, 
+    put_oidc_login_to_redis, 
+    take_oidc_login_from_redis
+};
+use crate::audit::{AuditEvent, hash_email, record_audit_event, record_user_event};
+use crate::action_tokens::{ActionToken, ActionPurpose, create_action_token, check_action_token};
+use crate::autorization::{JWToken, JwtKeyring, create_jwt, revoke_user_access_tokens};
+use crate::cookies::{
+    access_token_cookie, refresh_token_cookie, csrf_cookie, oidc_state_cookie, drop_auth_cookies
+};
+use crate::oidc::{OidcProvider, OidcLoginState};
+use crate::external_identities::{LinkOutcome, find_or_link_user};
+use crate::known_devices::{ClientInfo, DeviceCheck, register_device, forget_device};
+use crate::accounts::scramble_password;
+use crate::sessions::{
+    RefreshOutcome, create_session, issue_refresh_token, rotate_refresh_token, revoke_user_sessions, 
+    get_token_generation
+};
+use crate::password_resets::{create_password_reset, get_password_reset_owner, reset_password};
+use crate::password_policy::PasswordPolicy;
+use crate::passwords::{hash_password, verify_password};
+use crate::throttling::{LoginThrottle, check_login_throttle, register_login_failure, reset_login_failures};
+use crate::two_factor::{get_totp_state, check_totp_code, use_recovery_code};
+use crate::tools::{
+    send_email_in_background, send_email_off_worker, generate_random_token, is_valid_email
 };
-use crate::autorization::{JWToken, create_jwt};
-use crate::convertations::{AsHash, FromBase64};
-use crate::tools::{send_email, generate_random_password, is_valid_password, is_valid_email};
 
 pub fn unauthorized_users_managing(cfg: &mut web::ServiceConfig) {
     cfg
@@ -30,455 +63,1032 @@ pub fn unauthorized_users_managing(cfg: &mut web::ServiceConfig) {
         ).service(
             web::resource("/authorization")
                 .route(web::post().to(handle_authorization))
+        ).service(
+            web::resource("/authorization/second_factor")
+                .route(web::post().to(handle_second_factor))
+        ).service(
+            web::resource("/token/refresh")
+                .route(web::post().to(handle_token_refresh))
+        ).service(
+            web::resource("/oidc/login")
+                .route(web::get().to(handle_oidc_login))
+        ).service(
+            web::resource("/oidc/callback")
+                .route(web::get().to(handle_oidc_callback))
+        ).service(
+            web::resource("/.well-known/jwks.json")
+                .route(web::get().to(handle_jwks))
         ).service(
             web::resource("/forgot_password")
                 .route(web::put().to(handle_forgot_password))
         ).service(
-            web::resource("/user_verification/{user_id}/{verification_token}")
+            web::resource("/reset_password/{reset_token}")
+                .route(web::put().to(handle_reset_password))
+        ).service(
+            web::resource("/resend_verification")
+                .route(web::post().to(handle_resend_verification))
+        ).service(
+            web::resource("/user_verification/{verification_token}")
                 .route(web::get().to(handle_user_verification))
         ).service(
-            web::resource("/email_verification/{email}/{user_id}/{verification_token}")
+            web::resource("/email_verification/{verification_token}")
                 .route(web::get().to(handle_email_verification))
+        ).service(
+            web::resource("/report_device/{report_token}")
+                .route(web::post().to(handle_device_report))
         );
 }
 
 async fn handle_create_user(
+    request: HttpRequest, 
     postgres_db: Data<PersistentDB>, 
-    user_data: Json<CreateUserBody>) -> impl Responder {
+    repository: Data<Repository>, 
+    password_policy: Data<PasswordPolicy>, 
+    user_data: Json<CreateUserBody>) -> Result<HttpResponse, AppError> {
 
     let CreateUserBody {name, email, password} = user_data.0;
     log::info!("New user creation request: name `{}`, email, `{}`", name, email);
 
-    if !is_valid_password(&password) {
-        log::warn!("Invalid password received");
-        return HttpResponse::BadRequest().json(ServerResponse {
-            status: 400, 
-            message: "Invalid password".to_string()
-        });
+    let violations = password_policy.check(&password, Some(&email), Some(&name));
+    if !violations.is_empty() {
+        log::warn!("Password rejected by policy: {:?}", violations);
+        return Err(AppError::PasswordRejected(violations));
     }
     if !is_valid_email(&email) {
         log::warn!("Invalid email received: `{}`", email);
-        return HttpResponse::BadRequest().json(ServerResponse {
-            status: 400, 
-            message: "Invalid email".to_string()
-        });
-    }
-
-    let password = password.as_hash();
-    let db_link = &*postgres_db.db.lock().unwrap();
-    let check_query = format!(
-        "SELECT 
-            id
-           FROM {}.{}
-          WHERE email = $1", 
-        APP_SCHEMA, 
-        USERS_TABLE
-    );
-    let check_result = sqlx::query(&check_query)
-        .bind(email.clone())
-        .fetch_one(db_link)
-        .await;
-
-    match check_result {
-        Ok(_) => {
-            log::warn!("Attempt to create new account with email existed in DB: `{}`", email);
-            HttpResponse::BadRequest().json(ServerResponse {
-                status: 400, 
-                message: format!("User with email {} already exists", email)
-            })
-        }, 
-        Err(_) => {
-            let insert_query = format!(
-                "INSERT INTO {}.{} (name, email, passwd, verification_status_id, status_id) 
-                      VALUES ($1, $2, $3, 0, 0) 
-                   RETURNING id, created_at", 
-                APP_SCHEMA, 
-                USERS_TABLE
-            );
-            let insert_result = sqlx::query(&insert_query)
-                .bind(name)
-                .bind(email.clone())
-                .bind(password.clone())
-                .fetch_one(db_link)
-                .await;
-
-            match insert_result {
-                Ok(new_user) => {
-                    let new_user_id: Uuid = new_user.get("id");
-                    let creation_time: NaiveDateTime = new_user.get("created_at");
-                    let verification_token = format!(
-                        "{}{}{}", 
-                        new_user_id, 
-                        password.clone(), 
-                        creation_time.timestamp()
-                    ).as_hash();
-                    let message = format!("
-                        Click this link to finish your verification {}/user_verification/{}/{}
-                        ", 
-                        crate::SERVICE_URL, 
-                        new_user_id, 
-                        verification_token
-                    );
-
-                    send_email(&email, "New user activation", &message);
-                    log::info!("Verification email for new user sent to address: `{}`", email);
-                    HttpResponse::Ok().json(ServerResponse {
-                        status: 200, 
-                        message: String::from("User created")
-                    })
-                }, 
-                Err(db_error) => {
-                    log::error!("Database issue: {:?}", db_error);
-                    HttpResponse::InternalServerError().json(ServerResponse {
-                        status: 500, 
-                        message: String::from("Internal server error")
-                    })
-                }
-            }
-        }
+        return Err(AppError::Validation("Invalid email".to_string()));
+    }
+
+    let password = hash_password(&password).await;
+    let db_link = &postgres_db.pool();
+    if repository.email_exists(&email).await? {
+        log::warn!("Attempt to create new account with email existed in DB: `{}`", email);
+        return Err(AppError::Conflict(format!("User with email {} already exists", email)));
     }
+
+    let new_user_id = repository.create_user(&name, &email, &password).await?;
+    let message = user_verification_message(new_user_id);
+
+    record_user_event(db_link, &request, AuditEvent::UserCreated, new_user_id, json!({})).await;
+    send_email_off_worker(email.clone(), "New user activation", message).await?;
+    log::info!("Verification email for new user sent to address: `{}`", email);
+    Ok(HttpResponse::Ok().json(ServerResponse {
+        status: 200, 
+        message: String::from("User created")
+    }))
 }
 
 async fn handle_authorization(
+    request: HttpRequest, 
+    jwt_keyring: Data<JwtKeyring>, 
     postgres_db: Data<PersistentDB>, 
+    repository: Data<Repository>, 
     redis_db: Data<CacheDB>, 
-    user_data: Json<UserCredentials>) -> impl Responder {
-    
+    user_data: Json<UserCredentials>) -> Result<HttpResponse, AppError> {
+
     let UserCredentials { email, password } = user_data.0;
-    let password = password.as_hash();
+    let client_ip = client_ip(&request);
 
     log::info!("User login request with email: `{}`", email);
 
-    let db_link = &*postgres_db.db.lock().unwrap();
-    let redis_conn = &mut *redis_db.db.lock().unwrap();
-
-    let redis_data = get_user_data_by_email_from_redis(redis_conn, &email);
-    if let Ok(cached_user_data) = redis_data {
-        if password == cached_user_data.passwd {
-
-            let user_id = cached_user_data.id;
-            let current_time = chrono::offset::Utc::now().naive_utc().timestamp();
-            let jwtoken = JWToken::new(user_id, current_time);
-            let token = create_jwt(jwtoken);
-            let mut cookie = Cookie::new("x-auth", &token);
-            cookie.set_max_age(Duration::seconds(TOKEN_LIFETIME));
-            
-            log::info!("User: `{}` have been authorized", user_id);
-            return HttpResponse::Ok().cookie(cookie).json(token);
+    let db_link = &postgres_db.pool();
+    let cache = redis_db.get_ref();
+
+    match check_login_throttle(cache, &email, &client_ip).await? {
+        LoginThrottle::Allowed => (), 
+        LoginThrottle::Delayed(retry_after) => {
+            log::warn!("Login attempt for email: `{}` from `{}` throttled for {} seconds", email, client_ip, retry_after);
+            record_login_failure(db_link, &request, None, &email, "throttled").await;
+            return Err(too_many_attempts(retry_after));
+        }, 
+        LoginThrottle::Locked(retry_after) => {
+            log::warn!("Login attempt for locked account with email: `{}` from `{}`", email, client_ip);
+            record_login_failure(db_link, &request, None, &email, "account_locked").await;
+            return Err(too_many_attempts(retry_after));
+        }
+    }
+
+    // password and status are read from Postgres, the cached copy may be stale
+    let stored_user = match repository.find_active_user_by_email(&email).await? {
+        Some(stored_user) => stored_user, 
+        None => {
+            log::warn!("Invalid email received: `{}`", email);
+            track_login_failure(cache, &email, &client_ip, false).await;
+            record_login_failure(db_link, &request, None, &email, "unknown_email").await;
+            return Err(AppError::Validation(String::from("Invalid user credentials")));
+        }
+    };
+
+    let password_check = verify_password(&password, stored_user.passwd.as_deref().unwrap_or_default()).await;
+    if !password_check.is_valid() {
+        log::warn!("Invalid password received from user with email: `{}`", email);
+        track_login_failure(cache, &email, &client_ip, true).await;
+        record_login_failure(db_link, &request, Some(stored_user.id), &email, "invalid_password").await;
+        return Err(AppError::Validation(String::from("Invalid user credentials")));
+    }
+
+    let mut authorized_user = stored_user.get_user();
+    if password_check.needs_rehash() {
+        if let Some(upgraded_hash) = upgrade_password_hash(&repository, stored_user.id, &password).await {
+            authorized_user.passwd = upgraded_hash;
+        }
+    }
+    if let Err(redis_error) = put_user_data_to_redis(cache, authorized_user, None).await {
+        log::warn!("Cache database issue: {:?}", redis_error);
+    }
+
+    log::info!("User: `{}` have been authorized", stored_user.id);
+    complete_login(&jwt_keyring, db_link, cache, stored_user.id, &email, &request).await
+}
+
+// failed codes count against the account like failed passwords, so a fresh challenge
+// from another password login doesn't give more attempts
+async fn handle_second_factor(
+    request: HttpRequest, 
+    jwt_keyring: Data<JwtKeyring>, 
+    postgres_db: Data<PersistentDB>, 
+    repository: Data<Repository>, 
+    redis_db: Data<CacheDB>, 
+    request_data: Json<SecondFactorBody>) -> Result<HttpResponse, AppError> {
+
+    let SecondFactorBody { challenge_token, code, recovery_code } = request_data.0;
+    let client_ip = client_ip(&request);
+
+    let db_link = &postgres_db.pool();
+    let cache = redis_db.get_ref();
+
+    let challenge_user_id = get_login_challenge_from_redis(cache, &challenge_token).await?;
+    let attempts = count_login_challenge_attempt_in_redis(cache, &challenge_token).await?;
+    let user_id = match challenge_user_id {
+        Some(user_id) if attempts <= LOGIN_CHALLENGE_ATTEMPTS => user_id, 
+        user_id => {
+            log::warn!("Invalid or exhausted login challenge received for user: `{:?}`", user_id);
+            drop_login_challenge_from_redis(cache, &challenge_token).await;
+            return Err(AppError::Unauthorized(String::from("Login challenge expired")));
+        }
+    };
+
+    let email = match repository.get_active_user(user_id).await?.and_then(|stored_user| stored_user.email) {
+        Some(email) => email, 
+        None => {
+            log::warn!("Login challenge of non-active user: `{}` received", user_id);
+            drop_login_challenge_from_redis(cache, &challenge_token).await;
+            return Err(AppError::Unauthorized(String::from("Login challenge expired")));
+        }
+    };
+    match check_login_throttle(cache, &email, &client_ip).await? {
+        LoginThrottle::Allowed => (), 
+        LoginThrottle::Delayed(retry_after) => {
+            log::warn!("Second factor of user: `{}` from `{}` throttled for {} seconds", user_id, client_ip, retry_after);
+            return Err(too_many_attempts(retry_after));
+        }, 
+        LoginThrottle::Locked(retry_after) => {
+            log::warn!("Second factor for locked account of user: `{}` from `{}`", user_id, client_ip);
+            drop_login_challenge_from_redis(cache, &challenge_token).await;
+            return Err(too_many_attempts(retry_after));
+        }
+    }
+
+    let is_verified = match (code, recovery_code) {
+        (Some(code), _) => check_totp_code(db_link, user_id, &code).await?, 
+        (None, Some(recovery_code)) => use_recovery_code(db_link, user_id, &recovery_code).await?, 
+        (None, None) => false
+    };
+
+    if !is_verified {
+        log::warn!("Invalid second factor received from user: `{}`", user_id);
+        track_login_failure(cache, &email, &client_ip, true).await;
+        record_audit_event(db_link, &request, AuditEvent::SecondFactorFailed, Some(user_id), None, json!({})).await;
+        return Err(AppError::Validation(String::from("Invalid verification code")));
+    }
+
+    drop_login_challenge_from_redis(cache, &challenge_token).await;
+    reset_login_failures(cache, &email).await;
+    log::info!("Second factor of user: `{}` verified", user_id);
+    start_session(&jwt_keyring, db_link, user_id, &request, "second_factor").await
+}
+
+async fn complete_login(
+    jwt_keyring: &JwtKeyring, 
+    db_link: &Pool<Postgres>, 
+    cache: &CacheDB, 
+    user_id: Uuid, 
+    email: &str, 
+    request: &HttpRequest) -> Result<HttpResponse, AppError> {
+
+    // failures are forgotten only once the login is complete, second factor failures count until then
+    match get_totp_state(db_link, user_id).await? {
+        Some(totp_state) if totp_state.enabled => {
+            let challenge_token = generate_random_token();
+            put_login_challenge_to_redis(cache, &challenge_token, user_id).await?;
+
+            log::info!("Second factor requested from user: `{}`", user_id);
+            Ok(HttpResponse::Ok().json(LoginChallenge {
+                two_factor_required: true, 
+                challenge_token, 
+                expires_in: settings().tokens.login_challenge_lifetime
+            }))
+        }, 
+        _ => {
+            reset_login_failures(cache, email).await;
+            start_session(jwt_keyring, db_link, user_id, request, "password").await
         }
     }
+}
+
+fn client_ip(request: &HttpRequest) -> String {
+    request
+        .connection_info()
+        .realip_remote_addr()
+        .unwrap_or("unknown")
+        .to_string()
+}
+
+async fn track_login_failure(
+    cache: &CacheDB, 
+    email: &str, 
+    client_ip: &str, 
+    account_exists: bool) {
 
-    let query = format!(
-        "SELECT 
-            id, name, email, passwd, verification_status_id, status_id, created_at, updated_at
-           FROM {}.{}
-          WHERE email = $1 AND status_id = 1", 
-        APP_SCHEMA, 
-        USERS_TABLE
+    match register_login_failure(cache, email, client_ip).await {
+        Ok(true) => {
+            log::warn!("Account with email: `{}` locked after too many failed login attempts", email);
+            if account_exists {
+                let message = format!(
+                    "Your account was locked for {} minutes after too many failed login attempts. 
+                    If it wasn't you, consider changing your password.", 
+                    settings().login_throttling.lockout_duration / 60
+                );
+                // failure is logged by `send_email`, the notification is optional
+                send_email_in_background(email.to_string(), "Account temporarily locked", message);
+            }
+        }, 
+        Ok(false) => (), 
+        Err(redis_error) => {
+            log::error!("Cache database issue: {:?}", redis_error);
+        }
+    }
+}
+
+// actor of a failed login is unknown, the account is set only if the email belongs to one
+async fn record_login_failure(
+    db_link: &Pool<Postgres>, 
+    request: &HttpRequest, 
+    user_id: Option<Uuid>, 
+    email: &str, 
+    reason: &str) {
+
+    let metadata = json!({ "email_hash": hash_email(email), "reason": reason });
+    record_audit_event(db_link, request, AuditEvent::LoginFailed, user_id, None, metadata).await;
+}
+
+fn too_many_attempts(retry_after: i64) -> AppError {
+    AppError::TooManyRequests {
+        detail: format!("Too many attempts, try again in {} seconds", retry_after), 
+        retry_after
+    }
+}
+
+async fn upgrade_password_hash(
+    repository: &Repository, 
+    user_id: Uuid, 
+    password: &str) -> Option<String> {
+
+    let upgraded_hash = hash_password(password).await;
+    match repository.change_password(user_id, &upgraded_hash).await {
+        Ok(true) => {
+            log::info!("Legacy password hash of user: `{}` upgraded", user_id);
+            Some(upgraded_hash)
+        }, 
+        Ok(false) => None, 
+        Err(db_error) => {
+            log::error!("Database issue: {:?}", db_error);
+            None
+        }
+    }
+}
+
+// creates session and issues its first token pair
+async fn open_session(
+    jwt_keyring: &JwtKeyring, 
+    db_link: &Pool<Postgres>, 
+    user_id: Uuid, 
+    request: &HttpRequest, 
+    login_method: &str) -> Result<(Uuid, TokenPair), sqlx::Error> {
+
+    let token_generation = get_token_generation(db_link, user_id)
+        .await?
+        .ok_or(sqlx::Error::RowNotFound)?;
+    let session_id = create_session(db_link, user_id, request).await?;
+    let refresh_token = issue_refresh_token(db_link, user_id, session_id).await?;
+    let metadata = json!({ "session_id": session_id, "method": login_method });
+    record_user_event(db_link, request, AuditEvent::Login, user_id, metadata).await;
+    notify_new_device(db_link, user_id, request).await;
+
+    Ok((session_id, issue_token_pair(jwt_keyring, user_id, session_id, token_generation, refresh_token)))
+}
+
+// emails the user about login from a client never seen before, failures don't stop the login
+async fn notify_new_device(
+    db_link: &Pool<Postgres>, 
+    user_id: Uuid, 
+    request: &HttpRequest) {
+
+    let client = ClientInfo::from_request(request);
+    let (device_id, email) = match register_device(db_link, user_id, &client).await {
+        Ok(DeviceCheck::New { device_id, email }) => (device_id, email), 
+        Ok(_) => return, 
+        Err(db_error) => {
+            log::error!("Database issue: {:?}", db_error);
+            return;
+        }
+    };
+
+    let report_token = create_action_token(ActionToken::new(
+        ActionPurpose::DeviceReport, 
+        user_id, 
+        settings().tokens.device_report_lifetime, 
+        Some(device_id.to_string())
+    ));
+    let message = format!(
+        "New login to your account at {} UTC. \
+        Client: {}, ip address: {}. \
+        If it wasn't you, follow the link {}/device_report/{} \
+        All sessions would be closed and the link to set a new password sent to this address.", 
+        chrono::offset::Utc::now().naive_utc().format("%Y-%m-%d %H:%M"), 
+        if client.user_agent.is_empty() { "unknown" } else { &client.user_agent }, 
+        if client.ip_address.is_empty() { "unknown" } else { &client.ip_address }, 
+        settings().server.service_url, 
+        report_token
     );
-    let result = sqlx::query(&query)
-        .bind(&email)
-        .map(|row| {
-            StoredUser{
-                id: row.get("id"),
-                name: row.get("name"),
-                email: row.get("email"), 
-                passwd: row.get("passwd"), 
-                verification_status_id: row.get("verification_status_id"), 
-                status_id: row.get("status_id"), 
-                created_at: row.get("created_at"), 
-                updated_at: row.get("updated_at")
-            } 
-        })
-        .fetch_all(db_link)
-        .await;
-
-    match result {
-        Ok(stored_users) => {
-            if stored_users.len() > 0 {
-                let stored_user = &stored_users[0];
-                if password == stored_user.passwd.clone().unwrap() {
-                    
-                    let authorized_user = stored_user.get_user();
-                    put_user_data_to_redis(redis_conn, authorized_user, None);
-
-                    let current_time = chrono::offset::Utc::now().naive_utc().timestamp();
-                    let jwtoken = JWToken::new(stored_user.id, current_time);
-                    let token = create_jwt(jwtoken);
-                    let mut cookie = Cookie::new("x-auth", &token);
-                    cookie.set_max_age(Duration::seconds(TOKEN_LIFETIME));
-
-                    log::info!("User: `{}` have been authorized", stored_user.id);
-                    HttpResponse::Ok().cookie(cookie).json(token)
-
-                } else {
-                    log::warn!("Invalid password received from user with email: `{}`", email);
-                    HttpResponse::BadRequest().json(ServerResponse {
-                        status: 400, 
-                        message: String::from("Invalid user credentials")
-                    })
+    log::info!("Login of user: `{}` from new device `{}` is reported by email", user_id, device_id);
+    send_email_in_background(email, "New login to your account", message);
+
+    let metadata = json!({ "device_id": device_id, "user_agent": client.user_agent });
+    record_user_event(db_link, request, AuditEvent::NewDeviceLogin, user_id, metadata).await;
+}
+
+async fn start_session(
+    jwt_keyring: &JwtKeyring, 
+    db_link: &Pool<Postgres>, 
+    user_id: Uuid, 
+    request: &HttpRequest, 
+    login_method: &str) -> Result<HttpResponse, AppError> {
+
+    let (session_id, token_pair) = open_session(jwt_keyring, db_link, user_id, request, login_method).await?;
+    log::info!("Session `{}` started for user: `{}`", session_id, user_id);
+    Ok(set_token_pair_cookies(&mut HttpResponse::Ok(), &token_pair).json(token_pair))
+}
+
+fn issue_token_pair(
+    jwt_keyring: &JwtKeyring, 
+    user_id: Uuid, 
+    session_id: Uuid, 
+    token_generation: i64, 
+    refresh_token: String) -> TokenPair {
+
+    TokenPair {
+        access_token: create_jwt(jwt_keyring, JWToken::new(user_id, session_id, token_generation)), 
+        refresh_token, 
+        expires_in: settings().tokens.access_token_lifetime
+    }
+}
+
+fn set_token_pair_cookies<'a>(
+    response: &'a mut HttpResponseBuilder, 
+    token_pair: &TokenPair) -> &'a mut HttpResponseBuilder {
+
+    response
+        .cookie(access_token_cookie(token_pair.access_token.clone()))
+        .cookie(refresh_token_cookie(token_pair.refresh_token.clone()))
+        .cookie(csrf_cookie(generate_random_token()))
+}
+
+async fn handle_token_refresh(
+    request: HttpRequest, 
+    jwt_keyring: Data<JwtKeyring>, 
+    postgres_db: Data<PersistentDB>, 
+    redis_db: Data<CacheDB>, 
+    request_data: Option<Json<RefreshTokenBody>>) -> Result<HttpResponse, AppError> {
+
+    let refresh_token = match request_data {
+        Some(body) => body.0.refresh_token, 
+        None => match request.cookie(REFRESH_TOKEN_COOKIE) {
+            Some(cookie) => cookie.value().to_string(), 
+            None => return Err(AppError::Unauthorized(String::from("Refresh token required")))
+        }
+    };
+
+    let db_link = &postgres_db.pool();
+    match rotate_refresh_token(db_link, &refresh_token).await? {
+        RefreshOutcome::Rotated { user_id, session_id, refresh_token } => {
+            let token_generation = get_token_generation(db_link, user_id)
+                .await?
+                .ok_or_else(|| {
+                    log::warn!("Refresh token of non-active user: `{}` received", user_id);
+                    AppError::Unauthorized(String::from("Invalid refresh token"))
+                })?;
+
+            log::info!("Tokens refreshed for user: `{}`", user_id);
+            let token_pair = issue_token_pair(&jwt_keyring, user_id, session_id, token_generation, refresh_token);
+            Ok(set_token_pair_cookies(&mut HttpResponse::Ok(), &token_pair).json(token_pair))
+        }, 
+        RefreshOutcome::Reused { user_id, session_id } => {
+            log::warn!("Reuse of refresh token detected for user: `{}`, session `{}` revoked", user_id, session_id);
+            let metadata = json!({ "session_id": session_id });
+            record_audit_event(db_link, &request, AuditEvent::RefreshTokenReused, Some(user_id), None, metadata).await;
+            let cache = redis_db.get_ref();
+            if let Err(redis_error) = revoke_session_in_redis(cache, session_id).await {
+                log::error!("Cache database issue: {:?}", redis_error);
+            }
+
+            Ok(AppError::Unauthorized(String::from("Invalid refresh token"))
+                .respond_with(drop_auth_cookies(&mut HttpResponse::Unauthorized())))
+        }, 
+        RefreshOutcome::Invalid => {
+            log::warn!("Invalid refresh token received");
+            Err(AppError::Unauthorized(String::from("Invalid refresh token")))
+        }
+    }
+}
+
+// redirects browser to the sign in page of single sign-on provider
+async fn handle_oidc_login(
+    redis_db: Data<CacheDB>, 
+    oidc_provider: Data<OidcProvider>) -> Result<HttpResponse, AppError> {
+
+    let config = oidc_provider
+        .config()
+        .ok_or_else(|| AppError::NotFound(String::from("Single sign-on is not configured")))?;
+
+    let state = generate_random_token();
+    let login_state = OidcLoginState {
+        code_verifier: generate_random_token(), 
+        nonce: generate_random_token()
+    };
+    let authorization_url = match oidc_provider.authorization_url(config, &state, &login_state).await {
+        Ok(authorization_url) => authorization_url, 
+        Err(provider_error) => {
+            log::error!("Single sign-on provider issue: {}", provider_error);
+            return Ok(oidc_redirect("/#sso_error=unavailable").finish());
+        }
+    };
+
+    let cache = redis_db.get_ref();
+    if let Err(redis_error) = put_oidc_login_to_redis(cache, &state, &login_state).await {
+        log::error!("Cache database issue: {:?}", redis_error);
+        return Ok(oidc_redirect("/#sso_error=unavailable").finish());
+    }
+
+    Ok(HttpResponse::Found()
+        .insert_header((header::LOCATION, authorization_url))
+        .cookie(oidc_state_cookie(state))
+        .finish())
+}
+
+// provider redirects back here with authorization code, the user is signed in 
+// or asked for the second factor, errors are shown by the frontend
+async fn handle_oidc_callback(
+    request: HttpRequest, 
+    jwt_keyring: Data<JwtKeyring>, 
+    postgres_db: Data<PersistentDB>, 
+    redis_db: Data<CacheDB>, 
+    oidc_provider: Data<OidcProvider>, 
+    query: web::Query<OidcCallbackQuery>) -> Result<HttpResponse, AppError> {
+
+    let config = oidc_provider
+        .config()
+        .ok_or_else(|| AppError::NotFound(String::from("Single sign-on is not configured")))?;
+
+    let OidcCallbackQuery { code, state, error } = query.into_inner();
+    if let Some(error) = error {
+        log::warn!("Single sign-on provider returned error: `{}`", error);
+        return Ok(oidc_redirect("/#sso_error=failed").finish());
+    }
+    let (code, state) = match (code, state) {
+        (Some(code), Some(state)) => (code, state), 
+        _ => {
+            log::warn!("Single sign-on callback without code or state received");
+            return Ok(oidc_redirect("/#sso_error=failed").finish());
+        }
+    };
+    if request.cookie(OIDC_STATE_COOKIE).map(|cookie| cookie.value().to_string()) != Some(state.clone()) {
+        log::warn!("Single sign-on callback with state not issued to this browser received");
+        return Ok(oidc_redirect("/#sso_error=failed").finish());
+    }
+
+    let login_state = {
+        let cache = redis_db.get_ref();
+        take_oidc_login_from_redis(cache, &state).await
+    };
+    let login_state = match login_state {
+        Ok(Some(login_state)) => login_state, 
+        Ok(None) => {
+            log::warn!("Unknown or expired single sign-on state received");
+            return Ok(oidc_redirect("/#sso_error=failed").finish());
+        }, 
+        Err(redis_error) => {
+            log::error!("Cache database issue: {:?}", redis_error);
+            return Ok(oidc_redirect("/#sso_error=unavailable").finish());
+        }
+    };
+
+    let claims = match oidc_provider.complete_login(config, &code, &login_state).await {
+        Ok(claims) => claims, 
+        Err(provider_error) => {
+            log::warn!("Single sign-on login failed: {}", provider_error);
+            return Ok(oidc_redirect("/#sso_error=failed").finish());
+        }
+    };
+
+    let db_link = &postgres_db.pool();
+    let user_id = match find_or_link_user(db_link, &claims).await {
+        Ok(LinkOutcome::Linked(user_id)) => user_id, 
+        Ok(LinkOutcome::EmailNotVerified) => {
+            log::warn!("Single sign-on identity `{}` of `{}` has no verified email", claims.sub, claims.iss);
+            return Ok(oidc_redirect("/#sso_error=email_not_verified").finish());
+        }, 
+        Ok(LinkOutcome::AccountInactive) => {
+            log::warn!("Single sign-on identity `{}` of `{}` belongs to inactive account", claims.sub, claims.iss);
+            return Ok(oidc_redirect("/#sso_error=account_inactive").finish());
+        }, 
+        Err(db_error) => {
+            log::error!("Database issue: {:?}", db_error);
+            return Ok(oidc_redirect("/#sso_error=unavailable").finish());
+        }
+    };
+    log::info!("Single sign-on login of user: `{}` via `{}`", user_id, claims.iss);
+
+    let response = match get_totp_state(db_link, user_id).await {
+        Ok(Some(totp_state)) if totp_state.enabled => {
+            let challenge_token = generate_random_token();
+            let cache = redis_db.get_ref();
+            match put_login_challenge_to_redis(cache, &challenge_token, user_id).await {
+                Ok(_) => {
+                    log::info!("Second factor requested from user: `{}`", user_id);
+                    oidc_redirect(&format!("/#second_factor={}", challenge_token)).finish()
+                }, 
+                Err(redis_error) => {
+                    log::error!("Cache database issue: {:?}", redis_error);
+                    oidc_redirect("/#sso_error=unavailable").finish()
                 }
-            } else {
-                log::warn!("Invalid email received: `{}`", email);
-                HttpResponse::BadRequest().json(ServerResponse {
-                    status: 400, 
-                    message: String::from("Invalid user credentials")
-                })
+            }
+        }, 
+        Ok(_) => match open_session(&jwt_keyring, db_link, user_id, &request, "single_sign_on").await {
+            Ok((session_id, token_pair)) => {
+                log::info!("Session `{}` started for user: `{}`", session_id, user_id);
+                set_token_pair_cookies(&mut oidc_redirect("/boards"), &token_pair).finish()
+            }, 
+            Err(db_error) => {
+                log::error!("Database issue: {:?}", db_error);
+                oidc_redirect("/#sso_error=unavailable").finish()
             }
         }, 
         Err(db_error) => {
             log::error!("Database issue: {:?}", db_error);
-            HttpResponse::InternalServerError().json(ServerResponse {
-                status: 500, 
-                message: String::from("Internal server error")
-            })
+            oidc_redirect("/#sso_error=unavailable").finish()
         }
-    }
+    };
+    Ok(response)
+}
+
+// single sign-on always ends with a redirect, state cookie isn't needed anymore
+fn oidc_redirect(location: &str) -> HttpResponseBuilder {
+    let mut response = HttpResponse::Found();
+    response
+        .insert_header((header::LOCATION, location.to_string()))
+        .cookie(oidc_state_cookie(String::new()));
+    response
+}
+
+async fn handle_jwks(jwt_keyring: Data<JwtKeyring>) -> impl Responder {
+    HttpResponse::Ok()
+        .insert_header((header::CACHE_CONTROL, "public, max-age=300"))
+        .json(jwt_keyring.jwks())
 }
 
 async fn handle_forgot_password(
+    request: HttpRequest, 
     postgres_db: Data<PersistentDB>, 
-    redis_db: Data<CacheDB>, 
-    request_data: Json<ChangeForgottenPasswordBody>) -> impl Responder {
-    
+    repository: Data<Repository>, 
+    request_data: Json<ChangeForgottenPasswordBody>) -> Result<HttpResponse, AppError> {
+
     let ChangeForgottenPasswordBody { email } = request_data.0;
     log::info!("Request for change forgotten password for user with email: `{}`", email);
     if !is_valid_email(&email) {
         log::warn!("Invalid email received: `{}`", email);
-        return HttpResponse::BadRequest().body("Invalid email");
+        return Err(AppError::Validation(String::from("Invalid email")));
     }
 
-    let db_link = &*postgres_db.db.lock().unwrap();
-    let redis_conn = &mut *redis_db.db.lock().unwrap();
+    let db_link = &postgres_db.pool();
 
-    let query = format!(
-        "SELECT 
-            id, name, email, passwd, verification_status_id, status_id, created_at, updated_at
-           FROM {}.{}
-          WHERE email = $1 AND status_id = 1", 
-        APP_SCHEMA, 
-        USERS_TABLE
-    );
-    let result = sqlx::query(&query)
-        .bind(email.clone())
-        .map(|row| {
-            StoredUser{
-                id: row.get("id"),
-                name: row.get("name"),
-                email: row.get("email"), 
-                passwd: row.get("passwd"), 
-                verification_status_id: row.get("verification_status_id"), 
-                status_id: row.get("status_id"), 
-                created_at: row.get("created_at"), 
-                updated_at: row.get("updated_at")
-            } 
-        })
-        .fetch_all(db_link)
-        .await;
-
-    match result {
-        Ok(stored_users) => {
-            if stored_users.len() > 0 {
-                let mut stored_user = stored_users[0].get_user();
-
-                let generated_password = generate_random_password();
-                stored_user.passwd = generated_password.clone().as_hash();
-                stored_user.verification_status_id = 3;
-
-                put_user_data_to_redis(redis_conn, stored_user, Some(43200));
+    let user_id = repository
+        .find_active_user_by_email(&email)
+        .await?
+        .map(|stored_user| stored_user.id);
 
-                let message = format!(
-                    "Your new temporary password {} Would be valid in next 12 hours", 
-                    generated_password
-                );
-                send_email(&email, "Password reset email", &message);
-
-                log::info!("Message with temporary password sent to address: {}", email);
-                HttpResponse::Ok().json(ServerResponse {
-                    status: 200, 
-                    message: String::from("Email with new password sent")
-                })
-                
-            } else {
-                log::warn!("Unexisted email received: `{}`", email);
-                HttpResponse::BadRequest().json(ServerResponse {
-                    status: 400, 
-                    message: String::from("Invalid email")
-                })
+    match user_id {
+        Some(user_id) => {
+            let reset_token = create_password_reset(db_link, user_id).await?;
+            let message = format!(
+                "To set a new password follow the link {}/password_reset/{} \
+                It would be valid in next {} minutes. \
+                If you didn't request password reset just ignore this email", 
+                settings().server.service_url, 
+                reset_token, 
+                settings().tokens.password_reset_lifetime / 60
+            );
+            // mail failure isn't reported, it would tell registered accounts apart
+            if send_email_off_worker(email.clone(), "Password reset email", message).await.is_ok() {
+                log::info!("Message with password reset link sent to address: {}", email);
             }
+            record_audit_event(db_link, &request, AuditEvent::PasswordResetRequested, Some(user_id), None, json!({})).await;
         }, 
-        Err(db_error) => {
-            log::error!("Database issue: {:?}", db_error);
-            HttpResponse::InternalServerError().json(ServerResponse {
-                status: 500, 
-                message: String::from("Internal server error")
-            })
+        None => {
+            log::warn!("Unexisted email received: `{}`", email);
         }
     }
+
+    // same response for unknown emails, so the endpoint can't be used to find registered accounts
+    Ok(HttpResponse::Ok().json(ServerResponse {
+        status: 200, 
+        message: String::from("If the account exists, email with password reset link sent")
+    }))
 }
 
-async fn handle_user_verification(
+async fn handle_reset_password(
+    request: HttpRequest, 
     postgres_db: Data<PersistentDB>, 
-    request_path: web::Path<(Uuid, String)>) -> impl Responder {
+    redis_db: Data<CacheDB>, 
+    password_policy: Data<PasswordPolicy>, 
+    request_path: web::Path<String>, 
+    request_data: Json<ResetPasswordBody>) -> Result<HttpResponse, AppError> {
 
-    let (user_id, verification_token) = request_path.into_inner();
-    log::info!("Account activation request from user: `{}`", user_id);
-    let db_link = &*postgres_db.db.lock().unwrap();
-
-    let check_query = format!("
-         SELECT 
-            passwd, created_at
-           FROM {}.{}
-          WHERE id = $1 
-            AND status_id = 0
-            ", 
-        APP_SCHEMA, 
-        USERS_TABLE
-    );
-    let check_result = sqlx::query(&check_query)
-        .bind(user_id)
-        .fetch_one(db_link)
-        .await;
-
-    match check_result {
-        Ok(row) => { // idle user found
-            let password: String = row.get("passwd");
-            let creation_time: NaiveDateTime = row.get("created_at");
-
-            let stored_token = format!(
-                "{}{}{}", 
-                user_id, 
-                password.clone(), 
-                creation_time.timestamp()
-            ).as_hash();
-
-            if stored_token == verification_token { // verification passed
-                let update_query = format!("
-                    UPDATE {}.{}
-                       SET status_id = 1, verification_status_id = 1
-                     WHERE id = $1 
-                       AND status_id = 0
-                    ", 
-                    APP_SCHEMA, 
-                    USERS_TABLE
-                ); 
-                let update_result = sqlx::query(&update_query)
-                    .bind(user_id)
-                    .execute(db_link)
-                    .await;
-                
-                match update_result {
-                    Ok(_) => {
-                        log::info!("Account of user `{}` activated", user_id);
-                        HttpResponse::Ok().body("Ok")
-                    }, 
-                    Err(db_error) => {
-                        log::error!("Database issue: {:?}", db_error);
-                        HttpResponse::InternalServerError().json(ServerResponse {
-                            status: 500, 
-                            message: String::from("Internal server error")
-                        })
-                    }
-                }
-            } else { // invalid token
-                log::warn!("Invalid verification token received from user: `{}`", user_id);
-                HttpResponse::BadRequest().body("BadRequest")
+    let reset_token = request_path.into_inner();
+    let ResetPasswordBody { new_password } = request_data.0;
+    log::info!("Password reset request");
+
+    let db_link = &postgres_db.pool();
+    let cache = redis_db.get_ref();
+
+    let (email, name) = get_password_reset_owner(db_link, &reset_token)
+        .await?
+        .ok_or_else(invalid_reset_link)?;
+    let violations = password_policy.check(&new_password, email.as_deref(), name.as_deref());
+    if !violations.is_empty() {
+        log::warn!("Password rejected by policy: {:?}", violations);
+        return Err(AppError::PasswordRejected(violations));
+    }
+
+    let new_password_hash = hash_password(&new_password).await;
+    let user_id = reset_password(db_link, &reset_token, &new_password_hash)
+        .await?
+        .ok_or_else(invalid_reset_link)?;
+
+    drop_user_data_from_redis(cache, user_id).await;
+    if let Err(db_error) = revoke_user_sessions(db_link, user_id).await {
+        log::error!("Database issue: {:?}", db_error);
+    }
+    if let Err(error) = revoke_user_access_tokens(db_link, cache, user_id).await {
+        log::error!("Access tokens of user: `{}` weren't revoked: {:?}", user_id, error);
+    }
+    log::info!("Password reset for user: `{}`", user_id);
+    record_user_event(db_link, &request, AuditEvent::PasswordReset, user_id, json!({})).await;
+
+    Ok(drop_auth_cookies(&mut HttpResponse::Ok()).json(ServerResponse {
+        status: 200, 
+        message: String::from("Password updated")
+    }))
+}
+
+fn invalid_reset_link() -> AppError {
+    log::warn!("Invalid or expired password reset token received");
+    AppError::Validation(String::from("Invalid or expired reset link"))
+}
+
+async fn handle_resend_verification(
+    request: HttpRequest, 
+    postgres_db: Data<PersistentDB>, 
+    repository: Data<Repository>, 
+    redis_db: Data<CacheDB>, 
+    request_data: Json<ResendVerificationBody>) -> Result<HttpResponse, AppError> {
+
+    let ResendVerificationBody { email } = request_data.0;
+    log::info!("Request for resending verification email to address: `{}`", email);
+    if !is_valid_email(&email) {
+        log::warn!("Invalid email received: `{}`", email);
+        return Err(AppError::Validation(String::from("Invalid email")));
+    }
+
+    let db_link = &postgres_db.pool();
+    let cache = redis_db.get_ref();
+
+    let resend_interval = settings().accounts.verification_resend_interval;
+    if !mark_verification_resent_in_redis(cache, &email, resend_interval).await? {
+        log::warn!("Verification email for address: `{}` requested too often", email);
+        return Err(too_many_attempts(resend_interval));
+    }
+
+    let user_id = repository
+        .find_idle_user_by_email(&email)
+        .await?
+        .map(|stored_user| stored_user.id);
+
+    match user_id {
+        Some(user_id) => {
+            // mail failure isn't reported, it would tell registered accounts apart
+            let message = user_verification_message(user_id);
+            if send_email_off_worker(email.clone(), "New user activation", message).await.is_ok() {
+                log::info!("Verification email for user `{}` resent to address: `{}`", user_id, email);
             }
+            record_audit_event(db_link, &request, AuditEvent::VerificationResent, Some(user_id), None, json!({})).await;
         }, 
-        Err(_) => { // no one idle user found
-            log::warn!("User `{}` attemted to activate its non-idle account", user_id);
-            HttpResponse::BadRequest().body("BadRequest")
+        None => {
+            log::warn!("No account waiting for verification with email: `{}`", email);
         }
     }
 
+    // same response for unknown emails, so the endpoint can't be used to find registered accounts
+    Ok(HttpResponse::Ok().json(ServerResponse {
+        status: 200, 
+        message: String::from("If the account waits for verification, email with activation link sent")
+    }))
+}
+
+fn user_verification_message(user_id: Uuid) -> String {
+    let verification_token = create_action_token(ActionToken::new(
+        ActionPurpose::UserVerification, 
+        user_id, 
+        settings().tokens.user_verification_lifetime, 
+        None
+    ));
+    format!(
+        "Click this link to finish your verification {}/user_verification/{} \
+        It would be valid in next {} hours", 
+        settings().server.service_url, 
+        verification_token, 
+        settings().tokens.user_verification_lifetime / 3_600
+    )
+}
+
+// marks the action token nonce as used, a token used before is rejected
+async fn use_action_token(repository: &Repository, action_token: &ActionToken) -> Result<(), AppError> {
+    if repository.consume_action_token(action_token).await? {
+        Ok(())
+    } else {
+        log::warn!("Already used action token received for user: `{}`", action_token.sub);
+        Err(invalid_link())
+    }
+}
+
+fn invalid_link() -> AppError {
+    AppError::Validation(String::from("Invalid or expired link"))
+}
+
+async fn handle_user_verification(
+    request: HttpRequest, 
+    postgres_db: Data<PersistentDB>, 
+    repository: Data<Repository>, 
+    request_path: web::Path<String>) -> Result<HttpResponse, AppError> {
+
+    let verification_token = request_path.into_inner();
+    let action_token = check_action_token(&verification_token, ActionPurpose::UserVerification)
+        .ok_or_else(|| {
+            log::warn!("Invalid or expired account activation token received");
+            invalid_link()
+        })?;
+    let user_id = action_token.sub;
+    log::info!("Account activation request from user: `{}`", user_id);
+
+    let db_link = &postgres_db.pool();
+    use_action_token(&repository, &action_token).await?;
+
+    if !repository.activate_user(user_id).await? { // no one idle user found
+        log::warn!("User `{}` attemted to activate its non-idle account", user_id);
+        return Err(AppError::Conflict(String::from("Account is already activated")));
+    }
+
+    log::info!("Account of user `{}` activated", user_id);
+    record_user_event(db_link, &request, AuditEvent::UserVerified, user_id, json!({})).await;
+    Ok(HttpResponse::Ok().body("Ok"))
 }
 
 async fn handle_email_verification(
+    request: HttpRequest, 
     postgres_db: Data<PersistentDB>, 
+    repository: Data<Repository>, 
     redis_db: Data<CacheDB>, 
-    request_path: web::Path<(String, Uuid, String)>) -> impl Responder {
+    request_path: web::Path<String>) -> Result<HttpResponse, AppError> {
 
-    let (encoded_email, user_id, verification_token) = request_path.into_inner();
+    let verification_token = request_path.into_inner();
+    let action_token = check_action_token(&verification_token, ActionPurpose::EmailVerification)
+        .ok_or_else(|| {
+            log::warn!("Invalid or expired email verification token received");
+            invalid_link()
+        })?;
+    let user_id = action_token.sub;
+    let new_email = action_token.data.clone().ok_or_else(|| {
+        log::warn!("Email verification token without email received from user: `{}`", user_id);
+        invalid_link()
+    })?;
     log::info!("New email verification request from user: `{}`", user_id);
-    match encoded_email.from_base64() {
-        Some(new_email) => {
-            let db_link = &*postgres_db.db.lock().unwrap();
-            let redis_conn = &mut *redis_db.db.lock().unwrap();
-
-            let check_query = format!("
-                SELECT 
-                    email, created_at
-                  FROM {}.{}
-                 WHERE id = $1 
-                   AND status_id = 1
-                   AND verification_status_id = 4
-                    ", 
-                APP_SCHEMA, 
-                USERS_TABLE
-            );
-            let check_result = sqlx::query(&check_query)
-                .bind(user_id)
-                .fetch_one(db_link)
-                .await;
-
-            match check_result {
-                Ok(row) => { // idle user found
-                    let current_email: String = row.get("email");
-                    let creation_time: NaiveDateTime = row.get("created_at");
-
-                    let stored_token = format!(
-                        "{}{}{}", 
-                        new_email, 
-                        current_email, 
-                        creation_time.timestamp()
-                    ).as_hash();
-
-                    if stored_token == verification_token { // verification passed
-                        let update_query = format!("
-                            UPDATE {}.{}
-                               SET email = $2, verification_status_id = 1
-                             WHERE id = $1 
-                               AND status_id = 1
-                               AND verification_status_id = 4
-                            ", 
-                            APP_SCHEMA, 
-                            USERS_TABLE
-                        ); 
-                        let update_result = sqlx::query(&update_query)
-                            .bind(user_id)
-                            .bind(&new_email)
-                            .execute(db_link)
-                            .await;
-                        
-                        match update_result {
-                            Ok(_) => {
-                                drop_user_data_from_redis(redis_conn, user_id);
-                                log::info!("New email `{}` setted for user: `{}`", new_email, user_id);
-                                HttpResponse::Ok().body("Ok")
-                            }, 
-                            Err(db_error) => {
-                                log::error!("Database issue: {:?}", db_error);
-                                HttpResponse::InternalServerError().json(ServerResponse {
-                                    status: 500, 
-                                    message: String::from("Internal server error")
-                                })
-                            }
-                        }
-                    } else { // invalid token
-                        log::warn!("Invalid verification token received from user: `{}`", user_id);
-                        HttpResponse::BadRequest().body("BadRequest")
-                    }
-                }, 
-                Err(_) => { // no one idle user found
-                    log::warn!("User `{}` attempted verify email: `{}`", user_id, new_email);
-                    HttpResponse::BadRequest().body("BadRequest")
-                }
-            }
-        }, 
-        None => {
-            log::warn!("Invalid base64 decoded email received: `{}`, user: `{}`", encoded_email, user_id);
-            HttpResponse::BadRequest().body("BadRequest")
+
+    let db_link = &postgres_db.pool();
+    let cache = redis_db.get_ref();
+
+    use_action_token(&repository, &action_token).await?;
+
+    if !repository.confirm_email_change(user_id, &new_email).await? { // no one user waiting for email change found
+        log::warn!("User `{}` attempted verify email: `{}`", user_id, new_email);
+        return Err(AppError::Conflict(String::from("No email change is waiting for verification")));
+    }
+
+    drop_user_data_from_redis(cache, user_id).await;
+    log::info!("New email `{}` setted for user: `{}`", new_email, user_id);
+    record_user_event(db_link, &request, AuditEvent::EmailChanged, user_id, json!({ "email_hash": hash_email(&new_email) })).await;
+    Ok(HttpResponse::Ok().body("Ok"))
+}
+
+// confirmed "this wasn't me" link of the new device email, locks the intruder out and lets the owner
+// set a new password. The link itself opens a frontend page, so mail scanners following it change nothing.
+async fn handle_device_report(
+    request: HttpRequest, 
+    postgres_db: Data<PersistentDB>, 
+    repository: Data<Repository>, 
+    redis_db: Data<CacheDB>, 
+    request_path: web::Path<String>) -> Result<HttpResponse, AppError> {
+
+    let report_token = request_path.into_inner();
+    let action_token = check_action_token(&report_token, ActionPurpose::DeviceReport)
+        .ok_or_else(|| {
+            log::warn!("Invalid or expired device report token received");
+            invalid_link()
+        })?;
+    let user_id = action_token.sub;
+    let device_id = action_token.data.as_deref().and_then(|device_id| device_id.parse::<i32>().ok());
+    log::info!("Unknown device reported by user: `{}`", user_id);
+
+    let db_link = &postgres_db.pool();
+    let cache = redis_db.get_ref();
+
+    use_action_token(&repository, &action_token).await?;
+
+    let email = scramble_password(db_link, user_id)
+        .await?
+        .ok_or_else(|| {
+            log::warn!("Device report for non-active account of user: `{}` received", user_id);
+            AppError::Conflict(String::from("Account is not active"))
+        })?;
+
+    drop_user_data_from_redis(cache, user_id).await;
+    if let Err(db_error) = revoke_user_sessions(db_link, user_id).await {
+        log::error!("Database issue: {:?}", db_error);
+    }
+    if let Err(error) = revoke_user_access_tokens(db_link, cache, user_id).await {
+        log::error!("Access tokens of user: `{}` weren't revoked: {:?}", user_id, error);
+    }
+    if let Some(device_id) = device_id {
+        if let Err(db_error) = forget_device(db_link, user_id, device_id).await {
+            log::error!("Database issue: {:?}", db_error);
         }
     }
+    record_user_event(db_link, &request, AuditEvent::DeviceReported, user_id, json!({ "device_id": device_id })).await;
+
+    let reset_token = create_password_reset(db_link, user_id).await?;
+    let message = format!(
+        "All sessions of your account were closed. \
+        To set a new password follow the link {}/password_reset/{} \
+        It would be valid in next {} minutes.", 
+        settings().server.service_url, 
+        reset_token, 
+        settings().tokens.password_reset_lifetime / 60
+    );
+    send_email_off_worker(email, "Password reset email", message).await?;
+
+    log::info!("Sessions of user: `{}` revoked after device report", user_id);
+    Ok(HttpResponse::Ok().body("Ok"))
 }
 
+#[cfg(test)]
+mod tests {
+    use actix_web::{test, App};
+    use sqlx::postgres::PgPoolOptions;
+    use std::sync::Mutex;
+    use std::time::Duration;
+
+    use super::*;
+    use crate::action_tokens::init_test_action_token_keys;
+    use crate::autorization::test_jwt_keyring;
+    use crate::repositories::MemoryRepository;
+    use crate::settings::init_test_settings;
+
+    const EMAIL: &str = "ann@example.com";
+    const PASSWORD: &str = "Sup3r-Secret-Pass!";
+
+    // refused connections, audit events of failed logins are only logged as database issues
+    fn unavailable_postgres() -> PersistentDB {
+        let pool = PgPoolOptions::new()
+            .acquire_timeout(Duration::from_millis(200))
+            .connect_lazy("postgres://postgres@127.0.0.1:1/routine")
+            .unwrap();
+        PersistentDB { db: Mutex::new(pool) }
+    }
+
+    // user who has logged in before, so the account is cached
+    async fn cached_user(memory: &MemoryRepository, cache: &CacheDB) -> Uuid {
+        init_test_settings();
+        let user_id = memory.create_user("Ann", EMAIL, &hash_password(PASSWORD).await).await.unwrap();
+        memory.activate_user(user_id).await.unwrap();
+        let stored_user = memory.get_active_user(user_id).await.unwrap().unwrap();
+        put_user_data_to_redis(cache, stored_user.get_user(), None).await.unwrap();
+        user_id
+    }
+
+    async fn login(repository: Repository, cache: CacheDB, password: &str) -> u16 {
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(repository))
+                .app_data(Data::new(cache))
+                .app_data(Data::new(unavailable_postgres()))
+                .app_data(Data::new(test_jwt_keyring()))
+                .configure(unauthorized_users_managing)
+        ).await;
+        let request = test::TestRequest::post()
+            .uri("/authorization")
+            .set_json(json!({ "email": EMAIL, "password": password }))
+            .to_request();
+        test::call_service(&app, request).await.status().as_u16()
+    }
+
+    // account waiting for activation
+    async fn idle_user(memory: &MemoryRepository) -> Uuid {
+        init_test_settings();
+        init_test_action_token_keys();
+        memory.create_user("Ann", EMAIL, &hash_password(PASSWORD).await).await.unwrap()
+    }
+
+    fn action_link(purpose: ActionPurpose, user_id: Uuid, data: Option<String>) -> String {
+        create_action_token(ActionToken::new(purpose, user_id, 600, data))
+    }
+
+    // statuses of the same link followed several times
+    async fn follow_link(repository: &Data<Repository>, uri: &str, times: usize) -> Vec<u16> {
+        let app = test::init_service(
+            App::new()
+                .app_data(repository.clone())
+                .app_data(Data::new(CacheDB::new(None)))
+                .app_data(Data::new(unavailable_postgres()))
+                .configure(unauthorized_users_managing)
+        ).await;
+        let mut statuses = Vec::new();
+        for _ in 0..times {
+            let request = test::TestRequest::get().uri(uri).to_request();
+            statuses.push(test::call_service(&app, request).await.status().as_u16());
+        }
+        statuses
+    }
+
+    #[actix_web::test]
+    async fn disabled_user_cant_log_in() {
+        let memory = MemoryRepository::new();
+        let cache = CacheDB::new(None);
+        let user_id = cached_user(&memory, &cache).await;
+        memory.disable_user(user_id);
+
+        assert_eq!(login(Repository::Memory(memory), cache, PASSWORD).await, 400);
+    }
+
+    #[actix_web::test]
+    async fn replaced_password_cant_be_used() {
+        let memory = MemoryRepository::new();
+        let cache = CacheDB::new(None);
+        let user_id = cached_user(&memory, &cache).await;
+        memory.change_password(user_id, &hash_password("An0ther-Secret-Pass!").await).await.unwrap();
+
+        assert_eq!(login(Repository::Memory(memory), cache, PASSWORD).await, 400);
+    }
+
+    #[actix_web::test]
+    async fn verification_link_is_used_once() {
+        let memory = MemoryRepository::new();
+        let user_id = idle_user(&memory).await;
+        let repository = Data::new(Repository::Memory(memory));
+        let uri = format!("/user_verification/{}", action_link(ActionPurpose::UserVerification, user_id, None));
+
+        // the second use is refused by its nonce, an already active account would give 409
+        assert_eq!(follow_link(&repository, &uri, 2).await, vec![200, 400]);
+        assert!(repository.get_active_user(user_id).await.unwrap().is_some());
+    }
+
+    #[actix_web::test]
+    async fn link_is_refused_for_other_action() {
+        let memory = MemoryRepository::new();
+        let user_id = idle_user(&memory).await;
+        let repository = Data::new(Repository::Memory(memory));
+        let email_link = action_link(ActionPurpose::EmailVerification, user_id, Some(String::from("new@example.com")));
+        let uri = format!("/user_verification/{}", email_link);
+
+        assert_eq!(follow_link(&repository, &uri, 1).await, vec![400]);
+        assert!(repository.get_active_user(user_id).await.unwrap().is_none());
+    }
+}
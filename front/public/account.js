@@ -1,13 +1,13 @@
 $(document).ready(async function() {
 
-    if (theCookieExist('x-auth')) {
+    if (theCookieExist('x-csrf')) {
 
-        let token = getCookieValue('x-auth');
+        let csrf_token = getCookieValue('x-csrf');
         let check_login = await fetch('/get_user', {
             method: 'GET',
             headers: {
                 'Content-Type': 'application/json;charset=utf-8', 
-                'Authorization': 'Bearer ' + token
+                'X-CSRF-Token': csrf_token
             }
         });
 
@@ -113,7 +113,7 @@ $(document).ready(async function() {
                 method: 'PUT',
                 headers: {
                     'Content-Type': 'application/json;charset=utf-8', 
-                    'Authorization': 'Bearer ' + token
+                    'X-CSRF-Token': csrf_token
                 }, 
                 body: JSON.stringify(changeNameRequestBody)
             });
@@ -148,7 +148,7 @@ $(document).ready(async function() {
                 method: 'PUT',
                 headers: {
                     'Content-Type': 'application/json;charset=utf-8', 
-                    'Authorization': 'Bearer ' + token
+                    'X-CSRF-Token': csrf_token
                 }, 
                 body: JSON.stringify(changeEmailRequestBody)
             });
@@ -162,9 +162,9 @@ $(document).ready(async function() {
 
                 newEmailInput.value = '';
 
-            } else if (changeEmailRequestStatus == 400) {
+            } else if (changeEmailRequestStatus == 400 || changeEmailRequestStatus == 409) {
                 let response = await changeEmailRequest.json();
-                let message = response['message'];
+                let message = response['detail'];
                 hideOverlay();
                 alert(message)
             } else {
@@ -186,73 +186,128 @@ $(document).ready(async function() {
             const newPassword = newPasswordInput.value;
             const repeatPassword = repeatPasswordInput.value;
 
-            if ((newPassword.length >= 10) && (newPassword.length <= 64)) {
+            if (newPassword == repeatPassword) {
+                if (oldPassword != newPassword) {
+            
+                    let changePassRequestBody = {
+                        "old_password": oldPassword,
+                        "new_password": newPassword
+                    };
+
+                    showOverlay();
+                    let changePassRequest = await fetch('/change_password', {
+                        method: 'PUT',
+                        headers: {
+                            'Content-Type': 'application/json;charset=utf-8', 
+                            'X-CSRF-Token': csrf_token
+                        }, 
+                        body: JSON.stringify(changePassRequestBody)
+                    });
+
+                    let changePassRequestStatus = changePassRequest.status;
+                    console.log(changePassRequestStatus);
+                    if (changePassRequestStatus == 200) {
+
+                        hideOverlay();
+                        alert("Password successfully changed.");
+
+                        $(".popup").css("display", "none");
+                        $(".modal").css("display", "none");
 
-                if (newPassword == repeatPassword) {
-                    if (oldPassword != newPassword) {
-                
-                        let changePassRequestBody = {
-                            "old_password": oldPassword,
-                            "new_password": newPassword
-                        };
-
-                        showOverlay();
-                        let changePassRequest = await fetch('/change_password', {
-                            method: 'PUT',
-                            headers: {
-                                'Content-Type': 'application/json;charset=utf-8', 
-                                'Authorization': 'Bearer ' + token
-                            }, 
-                            body: JSON.stringify(changePassRequestBody)
-                        });
-
-                        let changePassRequestStatus = changePassRequest.status;
-                        console.log(changePassRequestStatus);
-                        if (changePassRequestStatus == 200) {
-
-                            hideOverlay();
-                            alert("Password successfully changed.");
-
-                            $(".popup").css("display", "none");
-                            $(".modal").css("display", "none");
-
-                            
-                            oldPasswordInput.value = '';
-                            newPasswordInput.value = '';
-                            repeatPasswordInput.value = '';
-
-                            await logOut(token);
-
-                        } else {
-                            hideOverlay();
-                            alert("Something goes wrong.\nPlease try later.")
-                        }
                         
-
-                    } else {
-                        alert("New password couldn't be the same as current!");
+                        oldPasswordInput.value = '';
                         newPasswordInput.value = '';
                         repeatPasswordInput.value = '';
+
+                        await logOut(csrf_token);
+
+                    } else if (changePassRequestStatus == 400) {
+                        hideOverlay();
+                        let changePassResponse = await changePassRequest.json();
+                        alert(changePassResponse['detail']);
+                    } else {
+                        hideOverlay();
+                        alert("Something goes wrong.\nPlease try later.")
                     }
                     
+
                 } else {
-                    
-                    alert("Repeated password is not equal to new password!");
+                    alert("New password couldn't be the same as current!");
                     newPasswordInput.value = '';
                     repeatPasswordInput.value = '';
                 }
+                
             } else {
-                if (newPassword.length < 10) {
-                    alert("Password length couldn't be less than 10 signs");
-                } else {
-                    alert("Password length couldn't be greater than 64 signs");
+                
+                alert("Repeated password is not equal to new password!");
+                newPasswordInput.value = '';
+                repeatPasswordInput.value = '';
+            }
+        });
+
+
+        jQuery('#export-data-btn').on('click', async function(){
+            showOverlay();
+            let exportRequest = await fetch('/account/export', {
+                method: 'GET',
+                headers: {
+                    'X-CSRF-Token': csrf_token
                 }
+            });
+            hideOverlay();
+
+            if (exportRequest.status == 200) {
+                let exportBlob = await exportRequest.blob();
+                let link = document.createElement('a');
+                link.href = URL.createObjectURL(exportBlob);
+                link.download = 'routine_export.json';
+                link.click();
+                URL.revokeObjectURL(link.href);
+            } else {
+                alert("Something goes wrong.\nPlease try later.");
             }
         });
 
+        jQuery('#delete-account-btn').on('click', async function(){
+            $("#deleteAccountModal").css("display", "block");
+
+            $(".close").click(async function() {
+                $("#deleteAccountModal").css("display", "none");
+            });
+        });
+
+        jQuery('#delete-account-form').on('submit', async function(e){
+            e.preventDefault();
+
+            if (!confirm("Account, all boards and tasks will be deleted. Continue?")) {
+                return;
+            }
+
+            showOverlay();
+            let deleteRequest = await fetch('/account', {
+                method: 'DELETE',
+                headers: {
+                    'Content-Type': 'application/json;charset=utf-8', 
+                    'X-CSRF-Token': csrf_token
+                },
+                body: JSON.stringify({
+                    "password": document.getElementById('delete-password-input').value
+                })
+            });
+            hideOverlay();
+
+            if (deleteRequest.status == 200) {
+                alert("Account deleted.");
+                window.location.href = '/';
+            } else if (deleteRequest.status == 400) {
+                alert("Invalid password");
+            } else {
+                alert("Something goes wrong.\nPlease try later.");
+            }
+        });
 
         jQuery('#logout').on('click', async function(){
-            await logOut(token);
+            await logOut(csrf_token);
         });
     
 
@@ -289,13 +344,13 @@ function hideOverlay() {
     document.getElementById("overlay").style.display = "none";
 }
 
-async function logOut(auth_token) {
+async function logOut(csrf_token) {
     showOverlay();
     let out = await fetch('/logout', {
         method: 'DELETE',
         headers: {
             'Content-Type': 'application/json;charset=utf-8', 
-            'Authorization': 'Bearer ' + auth_token
+            'X-CSRF-Token': csrf_token
         }
     });
     hideOverlay();
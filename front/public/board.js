@@ -1,6 +1,6 @@
 $(document).ready(async function() {
 
-    if (theCookieExist('x-auth')) {
+    if (theCookieExist('x-csrf')) {
 
       const startTitleInput = document.getElementById('board-title');
       const startDescriptionInput = document.getElementById('board-description');
@@ -11,13 +11,13 @@ $(document).ready(async function() {
 
       var board_id = document.getElementsByClassName("message")[0].innerHTML;
 
-      let token = getCookieValue('x-auth');
+      let csrf_token = getCookieValue('x-csrf');
 
       let boards_data_request = await fetch('/user_boards', {
         method: 'GET',
         headers: {
             'Content-Type': 'application/json;charset=utf-8', 
-            'Authorization': 'Bearer ' + token
+            'X-CSRF-Token': csrf_token
         }
 
       });
@@ -42,7 +42,7 @@ $(document).ready(async function() {
           method: 'GET',
           headers: {
               'Content-Type': 'application/json;charset=utf-8', 
-              'Authorization': 'Bearer ' + getCookieValue('x-auth'),
+              'X-CSRF-Token': getCookieValue('x-csrf'),
               'BoardId': board_id
           }
 
@@ -87,7 +87,7 @@ $(document).ready(async function() {
               method: 'PUT',
               headers: {
                   'Content-Type': 'application/json;charset=utf-8', 
-                  'Authorization': 'Bearer ' + token,
+                  'X-CSRF-Token': csrf_token,
               },
               body: JSON.stringify(board_update_body)
           });
@@ -116,7 +116,7 @@ $(document).ready(async function() {
             method: 'DELETE',
             headers: {
                 'Content-Type': 'application/json;charset=utf-8', 
-                'Authorization': 'Bearer ' + token
+                'X-CSRF-Token': csrf_token
             },
             body: JSON.stringify({
                 "id": parseInt(board_id, 10)
@@ -189,7 +189,7 @@ $(document).ready(async function() {
             method: 'POST',
             headers: {
                 'Content-Type': 'application/json;charset=utf-8', 
-                'Authorization': 'Bearer ' + token
+                'X-CSRF-Token': csrf_token
             },
             body: JSON.stringify(new_task_data)
     
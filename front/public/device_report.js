@@ -0,0 +1,38 @@
+$(document).ready(function() {
+
+    $("#device-report-form").submit(async function(event) {
+        event.preventDefault();
+
+        showOverlay();
+        let report_request = await fetch(`/report_device/${reportToken}`, {
+            method: 'POST',
+            headers: {
+                'X-CSRF-Token': getCookieValue('x-csrf')
+            }
+        });
+        hideOverlay();
+
+        if (report_request.status == 200) {
+            alert("All sessions are closed. Check your email to set a new password.");
+            window.location.href = '/';
+        } else if (report_request.status == 400 || report_request.status == 409) {
+            let response = await report_request.json();
+            alert(response.detail);
+        } else {
+            alert("Something goes wrong.\nPlease try later.");
+        }
+    });
+});
+
+function getCookieValue(cookieName) {
+    const cookie = document.cookie.match('(^|;)\\s*' + cookieName + '\\s*=\\s*([^;]+)');
+    return cookie ? cookie.pop() : '';
+}
+
+function showOverlay() {
+    document.getElementById("overlay").style.display = "flex";
+}
+
+function hideOverlay() {
+    document.getElementById("overlay").style.display = "none";
+}
@@ -1,16 +1,37 @@
 jQuery('document').ready(async function(){
 
+  let sso_result = new URLSearchParams(window.location.hash.substring(1));
+  history.replaceState(null, '', window.location.pathname);
+  if (sso_result.has('second_factor')) {
+    if (await submitSecondFactor(sso_result.get('second_factor'))) {
+      window.location.replace("/boards");
+      return;
+    }
+  } else if (sso_result.has('sso_error')) {
+    alert(ssoErrorMessage(sso_result.get('sso_error')));
+  }
+
   let check_authorisation_request = await fetch('/get_user', {
     method: 'GET',
     headers: {
         'Content-Type': 'application/json;charset=utf-8', 
-        'Authorization': 'Bearer ' + getCookieValue('x-auth')
+        'X-CSRF-Token': getCookieValue('x-csrf')
     }
   });
 
   let check_authorisation_result = check_authorisation_request.status; 
   if (check_authorisation_result == 200) {
     window.location.replace("/boards");  
+  } else {
+    let refresh_request = await fetch('/token/refresh', {
+      method: 'POST',
+      headers: {
+          'X-CSRF-Token': getCookieValue('x-csrf')
+      }
+    });
+    if (refresh_request.status == 200) {
+      window.location.replace("/boards");
+    }
   }
 
   jQuery('document').ready( async function(){
@@ -36,7 +57,8 @@ jQuery('document').ready(async function(){
       let user_data_value = await fetch('/authorization', {
         method: 'POST',
         headers: {
-            'Content-Type': 'application/json;charset=utf-8'
+            'Content-Type': 'application/json;charset=utf-8', 
+            'X-CSRF-Token': getCookieValue('x-csrf')
         },
         body: JSON.stringify(user_data)
 
@@ -44,13 +66,21 @@ jQuery('document').ready(async function(){
       let login_result = user_data_value.status; 
 
       if (login_result == 200) {
+        let response = await user_data_value.json();
+        if (response['two_factor_required']) {
+          hideOverlay();
+          let passed = await submitSecondFactor(response['challenge_token']);
+          if (!passed) {
+            return;
+          }
+        }
 
         hideOverlay();
         window.location.replace("/boards");  
 
-      } else if (login_result == 400) {
+      } else if (login_result == 400 || login_result == 429) {
         let response = await user_data_value.json();
-        let message = response['message'];
+        let message = response['detail'];
         hideOverlay();
         alert(message);
 
@@ -62,6 +92,10 @@ jQuery('document').ready(async function(){
       
     });
 
+    jQuery('#sso-button').on('click', function(){
+      window.location.href = '/oidc/login';
+    });
+
     $(".forgot-password").click(async function() {
       $("#forgotPasswordModal").css("display", "block");
     });
@@ -86,7 +120,8 @@ jQuery('document').ready(async function(){
       let forgot_password_request = await fetch('/forgot_password', {
         method: 'PUT',
         headers: {
-            'Content-Type': 'application/json;charset=utf-8'
+            'Content-Type': 'application/json;charset=utf-8', 
+            'X-CSRF-Token': getCookieValue('x-csrf')
         },
         body: JSON.stringify({
           "email": email
@@ -97,7 +132,7 @@ jQuery('document').ready(async function(){
 
       if (forgot_password_status == 200) {
         hideOverlay();
-        alert(`Temporary password sent to email address: ${email}`);
+        alert(`Password reset link sent to email address: ${email}`);
       } else {
         hideOverlay();
         alert('Out of service. Please try later.');
@@ -129,8 +164,6 @@ jQuery('document').ready(async function(){
 
       jQuery('#registration-submit').on('click', async function(){
 
-        if ((password.length >= 10) && (password.length <= 64)) {
-
           showOverlay();
           let new_user_credentials = {
               "name": username,
@@ -140,7 +173,8 @@ jQuery('document').ready(async function(){
           let user_registration_result = await fetch('/create_user', {
             method: 'POST',
             headers: {
-                'Content-Type': 'application/json;charset=utf-8'
+                'Content-Type': 'application/json;charset=utf-8', 
+                'X-CSRF-Token': getCookieValue('x-csrf')
             },
             body: JSON.stringify(new_user_credentials)
           });
@@ -151,29 +185,60 @@ jQuery('document').ready(async function(){
             alert("You've successfully registrated.\nCheck verification message, we've sent to \nyour email and finish your authentification.");
             
             $("#registerModal").css("display", "none");
-          } else if (user_registration_status == 400) {
+          } else if (user_registration_status == 400 || user_registration_status == 409) {
 
             hideOverlay();
-            let message = "Invalid credentials";
-            alert(message);
+            let user_registration_response = await user_registration_result.json();
+            alert(user_registration_response['detail']);
           } else {
             hideOverlay();
             alert("Unexpected issue happened. \nPlease try later.");
           } 
-
-        } else {
-          if (password.length < 10) {
-            alert("Password length couldn't be less than 10 signs");
-          } else {
-            alert("Password length couldn't be greater than 64 signs");
-          }
-        }
       });
 
     });
   });
 });
 
+async function submitSecondFactor(challenge_token) {
+  let code = prompt('Enter the code from your authenticator app or a recovery code');
+  showOverlay();
+  let second_factor_body = {"challenge_token": challenge_token};
+  if (/^[0-9]{6}$/.test((code || '').trim())) {
+    second_factor_body['code'] = code.trim();
+  } else {
+    second_factor_body['recovery_code'] = code || '';
+  }
+  let second_factor_request = await fetch('/authorization/second_factor', {
+    method: 'POST',
+    headers: {
+        'Content-Type': 'application/json;charset=utf-8', 
+        'X-CSRF-Token': getCookieValue('x-csrf')
+    },
+    body: JSON.stringify(second_factor_body)
+  });
+  hideOverlay();
+  if (second_factor_request.status != 200) {
+    let second_factor_response = await second_factor_request.json();
+    alert(second_factor_response['detail']);
+    return false;
+  }
+  return true;
+}
+
+function ssoErrorMessage(sso_error) {
+  switch (sso_error) {
+    case 'email_not_verified':
+      return 'Your email is not verified by the single sign-on provider.';
+    case 'account_inactive':
+      return 'Your account is deleted or not active.';
+    case 'unavailable':
+      return 'Single sign-on is unavailable now. \nPlease try later.';
+    default:
+      return 'Single sign-on failed. \nPlease try again.';
+  }
+}
+
 function timeSleep(ms) {
   return new Promise(resolve => setTimeout(resolve, ms));
 }
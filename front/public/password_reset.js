@@ -0,0 +1,53 @@
+$(document).ready(function() {
+
+    $("#reset-password-form").submit(async function(event) {
+        event.preventDefault();
+
+        const newPasswordInput = document.getElementById("new-password-input");
+        const repeatPasswordInput = document.getElementById("new-password-input2");
+        const newPassword = newPasswordInput.value;
+
+        if (newPassword != repeatPasswordInput.value) {
+            alert("Repeated password is not equal to new password!");
+            newPasswordInput.value = '';
+            repeatPasswordInput.value = '';
+            return;
+        }
+
+        showOverlay();
+        let reset_request = await fetch(`/reset_password/${resetToken}`, {
+            method: 'PUT',
+            headers: {
+                'Content-Type': 'application/json;charset=utf-8', 
+                'X-CSRF-Token': getCookieValue('x-csrf')
+            },
+            body: JSON.stringify({
+                "new_password": newPassword
+            })
+        });
+        hideOverlay();
+
+        if (reset_request.status == 200) {
+            alert("Password updated. Please log in with the new password.");
+            window.location.href = '/';
+        } else if (reset_request.status == 400) {
+            let response = await reset_request.json();
+            alert(response.detail);
+        } else {
+            alert("Something goes wrong.\nPlease try later.");
+        }
+    });
+});
+
+function getCookieValue(cookieName) {
+    const cookie = document.cookie.match('(^|;)\\s*' + cookieName + '\\s*=\\s*([^;]+)');
+    return cookie ? cookie.pop() : '';
+}
+
+function showOverlay() {
+    document.getElementById("overlay").style.display = "flex";
+}
+
+function hideOverlay() {
+    document.getElementById("overlay").style.display = "none";
+}
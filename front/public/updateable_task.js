@@ -1,6 +1,6 @@
 $(document).ready(async function() {
 
-    if (theCookieExist('x-auth')) {
+    if (theCookieExist('x-csrf')) {
 
         const titleElement = document.getElementById('title');
         const descriptionElement = document.getElementById('description');
@@ -18,12 +18,12 @@ $(document).ready(async function() {
         $("#board-link").attr("href", newURL);
 
 
-        let token = getCookieValue('x-auth');
+        let csrf_token = getCookieValue('x-csrf');
         var task_request_result = await fetch(`/task/${task_id}`, {
             method: 'GET',
             headers: {
                 'Content-Type': 'application/json;charset=utf-8', 
-                'Authorization': 'Bearer ' + token,
+                'X-CSRF-Token': csrf_token,
                 'BoardId': board_id
             }
         });
@@ -130,7 +130,7 @@ $(document).ready(async function() {
                     method: 'PUT',
                     headers: {
                         'Content-Type': 'application/json;charset=utf-8', 
-                        'Authorization': 'Bearer ' + token,
+                        'X-CSRF-Token': csrf_token,
                     },
                     body: JSON.stringify(task_update_body)
                 });
@@ -178,7 +178,7 @@ $(document).ready(async function() {
                     method: 'DELETE',
                     headers: {
                         'Content-Type': 'application/json;charset=utf-8', 
-                        'Authorization': 'Bearer ' + token
+                        'X-CSRF-Token': csrf_token
                     },
                     body: JSON.stringify({
                         "id": parseInt(task_id, 10), 
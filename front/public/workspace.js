@@ -1,6 +1,6 @@
 $(document).ready(async function() {
 
-  if (theCookieExist('x-auth')) {
+  if (theCookieExist('x-csrf')) {
 
     const startTitleInput = document.getElementById('board-title');
     const startDescriptionInput = document.getElementById('board-description');
@@ -8,12 +8,12 @@ $(document).ready(async function() {
     startTitleInput.value = "";
     startDescriptionInput.value = "";
 
-    let token = getCookieValue('x-auth');
+    let csrf_token = getCookieValue('x-csrf');
     let boards_data_request = await fetch('/user_boards', {
       method: 'GET',
       headers: {
           'Content-Type': 'application/json;charset=utf-8', 
-          'Authorization': 'Bearer ' + token
+          'X-CSRF-Token': csrf_token
       }
 
     });
@@ -74,7 +74,7 @@ $(document).ready(async function() {
           method: 'POST',
           headers: {
               'Content-Type': 'application/json;charset=utf-8', 
-              'Authorization': 'Bearer ' + token
+              'X-CSRF-Token': csrf_token
           },
           body: JSON.stringify(new_board_data)
 
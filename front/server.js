@@ -32,6 +32,22 @@ app.get('/show_task/:board_id/:task_id', (req, res) => {
 	res.render('updateable_task', data);
 });
 
+app.get('/password_reset/:reset_token', (req, res) => {
+
+	const data = {
+	  "reset_token": req.params.reset_token
+	};
+	res.render('password_reset', data);
+});
+
+app.get('/device_report/:report_token', (req, res) => {
+
+	const data = {
+	  "report_token": req.params.report_token
+	};
+	res.render('device_report', data);
+});
+
 app.get('/account', (req, res) => {
 
 	res.render('account');